@@ -0,0 +1,207 @@
+use std::{fmt::Display, fs::File, path::Path, sync::{Arc, Mutex}};
+
+use napi::{bindgen_prelude::*, Task};
+use napi_derive::napi;
+use ncd::{CurlConfig, CurlNCDReadAccessor, NCDBuild, NCDBuildConfig, NCDFlatConfig, NCDFlatSource, NCDReadAccessor, NCDReader, StdNCDReadAccessor};
+
+fn to_napi<E: Display>(e: E) -> Error {
+    Error::from_reason(e.to_string())
+}
+
+fn make_accessor(path: &str) -> Result<Box<dyn NCDReadAccessor>> {
+    Ok(if path.contains("//") {
+        Box::new(CurlNCDReadAccessor::new(&CurlConfig::new(),path).map_err(to_napi)?)
+    } else {
+        let file = File::open(Path::new(path)).map_err(to_napi)?;
+        Box::new(StdNCDReadAccessor::new(file).map_err(to_napi)?)
+    })
+}
+
+fn open_reader(path: &str) -> Result<NCDReader> {
+    NCDReader::new_box(make_accessor(path)?).map_err(to_napi)
+}
+
+/* an NCDReader seeks its accessor, so a get needs one to itself: rather than queue every get
+ * behind one reader, each worker thread running a get at once takes an idle reader, opening
+ * another if there's none */
+struct ReaderPool {
+    path: String,
+    idle: Mutex<Vec<NCDReader>>
+}
+
+impl ReaderPool {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let idle = self.idle.lock().map_err(to_napi)?.pop();
+        let mut reader = match idle {
+            Some(reader) => reader,
+            None => open_reader(&self.path)?
+        };
+        let value = reader.get(key).map_err(to_napi)?;
+        self.idle.lock().map_err(to_napi)?.push(reader);
+        Ok(value)
+    }
+}
+
+/// An opened ncd file. Concurrent `get`s run side by side on libuv's worker threads, each on a
+/// reader of its own: the file is opened again (for http, another connection) the first time
+/// more gets are in flight at once than before, and those readers are kept for later gets.
+#[napi]
+pub struct Reader {
+    pool: Arc<ReaderPool>
+}
+
+#[napi]
+impl Reader {
+    /// Open an ncd file. Paths containing `//` are fetched over http.
+    #[napi(factory)]
+    pub fn open(path: String) -> Result<Reader> {
+        let reader = open_reader(&path)?;
+        Ok(Reader { pool: Arc::new(ReaderPool { path, idle: Mutex::new(vec![reader]) }) })
+    }
+
+    /// Look up a key, resolving to the value or null if absent.
+    #[napi(ts_return_type="Promise<Buffer | null>")]
+    pub fn get(&self, key: Buffer) -> AsyncTask<GetTask> {
+        AsyncTask::new(GetTask { pool: self.pool.clone(), key: key.to_vec() })
+    }
+}
+
+pub struct GetTask {
+    pool: Arc<ReaderPool>,
+    key: Vec<u8>
+}
+
+impl Task for GetTask {
+    type Output = Option<Vec<u8>>;
+    type JsValue = Option<Buffer>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        self.pool.get(&self.key)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.map(|v| v.into()))
+    }
+}
+
+#[napi(object)]
+pub struct BuildOptions {
+    pub field: Option<u32>,
+    pub delimiter: Option<String>,
+    pub comment: Option<String>,
+    pub careful: Option<bool>,
+    pub page_size: Option<u32>
+}
+
+fn make_build_config(options: &BuildOptions) -> NCDBuildConfig {
+    let mut config = NCDBuildConfig::new();
+    if options.careful.unwrap_or(false) {
+        config = config
+            .target_page_size(16384)
+            .heap_wiggle_room(1.1)
+            .target_load_factor(0.75)
+            .rebuild_page_factor(1.1);
+    }
+    if let Some(page_size) = options.page_size {
+        config = config.target_page_size(page_size);
+    }
+    config
+}
+
+pub struct BuildTask {
+    input: String,
+    output: String,
+    options: BuildOptions
+}
+
+fn build_file(input: &Path, output: &Path, options: &BuildOptions) -> Result<()> {
+    let flat_config = NCDFlatConfig::new()
+        .index(options.field.unwrap_or(1) as usize)
+        .separator(options.delimiter.clone())
+        .comment_char(options.comment.clone());
+    let build_config = make_build_config(options);
+    let source = NCDFlatSource::new(input,&flat_config).map_err(to_napi)?;
+    let mut builder = NCDBuild::new(&build_config,&source,output).map_err(to_napi)?;
+    while !builder.attempt(|_,_| {}).map_err(to_napi)? {}
+    Ok(())
+}
+
+impl Task for BuildTask {
+    type Output = ();
+    type JsValue = ();
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        build_file(Path::new(&self.input),Path::new(&self.output),&self.options)
+    }
+
+    fn resolve(&mut self, _env: Env, _output: Self::Output) -> Result<Self::JsValue> {
+        Ok(())
+    }
+}
+
+fn no_options() -> BuildOptions {
+    BuildOptions { field: None, delimiter: None, comment: None, careful: None, page_size: None }
+}
+
+/// Build an ncd file from a flat file, resolving when the build succeeds.
+#[napi(ts_return_type="Promise<void>")]
+pub fn build(input: String, output: String, options: Option<BuildOptions>) -> AsyncTask<BuildTask> {
+    AsyncTask::new(BuildTask { input, output, options: options.unwrap_or_else(no_options) })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path, sync::{Arc, Mutex}, thread};
+
+    use super::{build_file, no_options, open_reader, BuildOptions, ReaderPool};
+
+    fn build(dir: &Path, data: &str, options: &BuildOptions) -> String {
+        let input = dir.join("genes.txt");
+        let output = dir.join("genes.ncd");
+        fs::write(&input,data).unwrap();
+        build_file(&input,&output,options).unwrap();
+        output.to_string_lossy().to_string()
+    }
+
+    fn pool(path: &str) -> ReaderPool {
+        ReaderPool { path: path.to_string(), idle: Mutex::new(vec![open_reader(path).unwrap()]) }
+    }
+
+    #[test]
+    fn test_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build(dir.path(),"BRCA2 chr13\nTP53 chr17\n",&no_options());
+        assert_eq!(Some(b"chr17".to_vec()),pool(&path).get(b"TP53").unwrap());
+        let options = BuildOptions { field: Some(2), delimiter: Some(",".to_string()), comment: Some("#".to_string()), careful: Some(true), page_size: None };
+        let path = build(dir.path(),"# genes\nchr13,BRCA2\n",&options);
+        let pool = pool(&path);
+        assert_eq!(Some(b"chr13".to_vec()),pool.get(b"BRCA2").unwrap());
+        assert_eq!(None,pool.get(b"# genes").unwrap());
+        assert!(build_file(&dir.path().join("missing.txt"),&dir.path().join("x.ncd"),&no_options()).is_err());
+    }
+
+    #[test]
+    fn test_reader_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        let data : String = (0..100).map(|i| format!("key{} value{}\n",i,i)).collect();
+        let pool = Arc::new(pool(&build(dir.path(),&data,&no_options())));
+        /* one at a time, the one reader does */
+        assert_eq!(Some(b"value1".to_vec()),pool.get(b"key1").unwrap());
+        assert_eq!(None,pool.get(b"missing").unwrap());
+        assert_eq!(1,pool.idle.lock().unwrap().len());
+        let threads : Vec<_> = (0..4).map(|t| {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                for i in (t..100).step_by(4) {
+                    assert_eq!(Some(format!("value{}",i).into_bytes()),pool.get(format!("key{}",i).as_bytes()).unwrap());
+                }
+            })
+        }).collect();
+        for thread in threads { thread.join().unwrap(); }
+        /* a reader is opened for each get in flight at once, and all are back when they're done */
+        let idle = pool.idle.lock().unwrap().len();
+        assert!(idle >= 1 && idle <= 4,"{}",idle);
+        let broken = ReaderPool { path: dir.path().join("missing.ncd").to_string_lossy().to_string(), idle: Mutex::new(vec![]) };
+        assert!(broken.get(b"key1").is_err());
+    }
+}
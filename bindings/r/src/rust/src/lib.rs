@@ -0,0 +1,79 @@
+use std::{fmt::Display, fs::File, path::Path};
+
+use extendr_api::prelude::*;
+use ncd::{CurlConfig, CurlNCDReadAccessor, NCDReadAccessor, NCDReader, StdNCDReadAccessor};
+
+fn to_r<E: Display>(e: E) -> Error {
+    Error::Other(e.to_string())
+}
+
+fn make_accessor(path: &str) -> Result<Box<dyn NCDReadAccessor>> {
+    Ok(if path.contains("//") {
+        Box::new(CurlNCDReadAccessor::new(&CurlConfig::new(),path).map_err(to_r)?)
+    } else {
+        let file = File::open(Path::new(path)).map_err(to_r)?;
+        Box::new(StdNCDReadAccessor::new(file).map_err(to_r)?)
+    })
+}
+
+/* an R string can't hold arbitrary bytes, so a value which isn't UTF-8 is an error rather than
+ * quietly coming back with replacement characters */
+fn value_string(key: &str, value: Vec<u8>) -> Result<String> {
+    String::from_utf8(value).map_err(|_| Error::Other(format!("the value for {} is not valid UTF-8",key)))
+}
+
+struct NcdFile {
+    reader: NCDReader
+}
+
+#[extendr]
+impl NcdFile {
+    fn open(path: &str) -> Result<Self> {
+        Ok(NcdFile { reader: NCDReader::new_box(make_accessor(path)?).map_err(to_r)? })
+    }
+
+    fn get(&mut self, key: &str) -> Result<Option<String>> {
+        let value = self.reader.get(key.as_bytes()).map_err(to_r)?;
+        value.map(|v| value_string(key,v)).transpose()
+    }
+}
+
+extendr_module! {
+    mod ncd;
+    impl NcdFile;
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use ncd::{NCDBuild, NCDBuildConfig, NCDFlatConfig, NCDFlatSource};
+
+    use super::{value_string, NcdFile};
+
+    fn build(dir: &Path) -> String {
+        let input = dir.join("genes.txt");
+        let output = dir.join("genes.ncd");
+        fs::write(&input,"BRCA2 chr13\nTP53 chr17\n").unwrap();
+        let source = NCDFlatSource::new(&input,&NCDFlatConfig::new()).unwrap();
+        let mut builder = NCDBuild::new(&NCDBuildConfig::new(),&source,&output).unwrap();
+        while !builder.attempt(|_,_| {}).unwrap() {}
+        output.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = NcdFile::open(&build(dir.path())).unwrap();
+        assert_eq!(Some("chr13".to_string()),file.get("BRCA2").unwrap());
+        assert_eq!(Some("chr17".to_string()),file.get("TP53").unwrap());
+        assert_eq!(None,file.get("MISSING").unwrap());
+        assert!(NcdFile::open(&dir.path().join("missing.ncd").to_string_lossy()).is_err());
+    }
+
+    #[test]
+    fn test_value_string() {
+        assert_eq!("chr13",value_string("BRCA2",b"chr13".to_vec()).unwrap());
+        assert!(value_string("BRCA2",vec![0xFF,0x00]).is_err());
+    }
+}
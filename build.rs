@@ -0,0 +1,24 @@
+use std::{fs, process::Command};
+
+/* for --version: neither is fatal, as a source tarball has no .git and may have no Cargo.lock */
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let hash = Command::new("git").args(&["rev-parse","--short","HEAD"]).output().ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok());
+    if let Some(hash) = hash {
+        println!("cargo:rustc-env=NCD_TOOLS_GIT_HASH={}",hash.trim());
+    }
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line == "name = \"ncd\"" {
+            if let Some(version) = lines.next().and_then(|l| l.strip_prefix("version = \"")) {
+                println!("cargo:rustc-env=NCD_VERSION={}",version.trim_end_matches('"'));
+            }
+            break;
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::{fs::File, io::{self, BufWriter, Read, Write}, path::Path, sync::Arc};
+
+use crate::sources::counting::Tally;
+
+/// One row of the `--accounting` report.
+pub struct AccountingRow {
+    pub input: String,
+    pub lines: u64,
+    pub tally: Arc<Tally>
+}
+
+impl AccountingRow {
+    pub fn stored(&self) -> u64 { self.tally.stored() }
+
+    /* whatever the parser didn't hand on: blanks, comments and malformed lines, or where there
+     * are no lines to go by, the records the source reported leaving out */
+    pub fn skipped(&self) -> u64 {
+        self.lines.saturating_sub(self.tally.ingested()).max(self.tally.skipped())
+    }
+}
+
+pub fn count_lines(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0;65536];
+    let mut lines = 0;
+    let mut last = b'\n';
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 { break; }
+        lines += buffer[0..n].iter().filter(|b| **b == b'\n').count() as u64;
+        last = buffer[n-1];
+    }
+    if last != b'\n' { lines += 1; }
+    Ok(lines)
+}
+
+/// What one build attempt wrote, and how long it took.
+#[derive(Clone,Debug,PartialEq)]
+pub struct AttemptCost {
+    pub bytes: u64,
+    pub seconds: f64
+}
+
+/* only the last attempt succeeds: everything before it was rewritten from scratch */
+pub fn amplification_report(attempts: &[AttemptCost]) -> Option<String> {
+    let last = attempts.last().filter(|_| attempts.len() > 1)?;
+    let written : u64 = attempts.iter().map(|a| a.bytes).sum();
+    let lost : f64 = attempts[..attempts.len()-1].iter().map(|a| a.seconds).sum();
+    let amplification = if last.bytes > 0 { written as f64 / last.bytes as f64 } else { 0. };
+    Some(format!("Write amplification: {} attempts wrote {} bytes for a {} byte file ({:.2}x); {:.1}s lost to {} failed attempts",
+        attempts.len(),written,last.bytes,amplification,lost,attempts.len()-1))
+}
+
+pub fn write_accounting<W: Write>(out: W, rows: &[AccountingRow]) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    writeln!(out,"input\tlines\tskipped\tingested\tdropped\tdeduplicated\tstored")?;
+    for row in rows {
+        writeln!(out,"{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            row.input,row.lines,row.skipped(),row.tally.ingested(),
+            row.tally.dropped(),row.tally.deduplicated(),row.stored())?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use ncd::NCDValueSource;
+
+    use crate::sources::{counting::{CountingSource, Tally}, memory::MemorySource};
+    use super::{amplification_report, count_lines, write_accounting, AccountingRow, AttemptCost};
+
+    #[test]
+    fn test_count_lines() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        assert_eq!(0,count_lines(file.path()).unwrap());
+        file.write_all(b"a 1\n\n# x\nb 2").unwrap();
+        assert_eq!(4,count_lines(file.path()).unwrap());
+    }
+
+    #[test]
+    fn test_accounting() {
+        let tally = Tally::new();
+        let source = CountingSource::new(Box::new(MemorySource::new(vec![
+            (b"a".to_vec(),b"1".to_vec()),
+            (b"b".to_vec(),b"2".to_vec())
+        ])),&tally);
+        assert_eq!(2,source.iter().count());
+        assert_eq!(2,source.iter().count());
+        tally.add_deduplicated();
+        let mut out = vec![];
+        write_accounting(&mut out,&[AccountingRow { input: "in.txt".to_string(), lines: 5, tally }]).unwrap();
+        assert_eq!("input\tlines\tskipped\tingested\tdropped\tdeduplicated\tstored\nin.txt\t5\t3\t2\t0\t1\t1\n",String::from_utf8(out).unwrap());
+        /* with no lines to go by, the skips the source reported */
+        let tally = Tally::new();
+        tally.skip("no key").unwrap();
+        assert_eq!(1,AccountingRow { input: "in.rdb".to_string(), lines: 0, tally: tally.clone() }.skipped());
+        tally.set_strict(true);
+        assert_eq!("strict: no key",tally.skip("no key").unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_amplification() {
+        assert_eq!(None,amplification_report(&[]));
+        assert_eq!(None,amplification_report(&[AttemptCost { bytes: 100, seconds: 1. }]));
+        let attempts = [
+            AttemptCost { bytes: 100, seconds: 1.5 },
+            AttemptCost { bytes: 150, seconds: 2. },
+            AttemptCost { bytes: 200, seconds: 3. }
+        ];
+        assert_eq!(Some("Write amplification: 3 attempts wrote 450 bytes for a 200 byte file (2.25x); 3.5s lost to 2 failed attempts".to_string()),amplification_report(&attempts));
+    }
+}
@@ -0,0 +1,248 @@
+use std::{cell::Cell, io::{self, Read, Seek, SeekFrom}, path::Path, time::Duration};
+
+use curl::easy::Easy;
+use zip::CompressionMethod;
+
+/// A `Read + Seek` view of `len` bytes starting at `start` of some larger stream, so an ncd file
+/// stored inside an archive can be read in place.
+pub struct Window<T> {
+    inner: T,
+    start: u64,
+    len: u64,
+    pos: u64
+}
+
+impl<T: Read+Seek> Window<T> {
+    pub fn new(mut inner: T, start: u64, len: u64) -> io::Result<Window<T>> {
+        inner.seek(SeekFrom::Start(start))?;
+        Ok(Window { inner, start, len, pos: 0 })
+    }
+}
+
+impl<T: Read+Seek> Read for Window<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.len.saturating_sub(self.pos);
+        let want = (buf.len() as u64).min(left) as usize;
+        if want == 0 { return Ok(0); }
+        let n = self.inner.read(&mut buf[..want])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<T: Read+Seek> Seek for Window<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => n as i128,
+            SeekFrom::End(n) => self.len as i128 + n as i128,
+            SeekFrom::Current(n) => self.pos as i128 + n as i128
+        };
+        if target < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,"seek before start of archive member"));
+        }
+        self.pos = target as u64;
+        self.inner.seek(SeekFrom::Start(self.start+self.pos))?;
+        Ok(self.pos)
+    }
+}
+
+const READ_AHEAD : u64 = 64*1024;
+
+/// A `Read + Seek` of a file on an HTTP server, fetched a block at a time with range requests,
+/// so archives can be searched and their members read in place remotely too. A server which
+/// ignores ranges sends the whole file, which is then kept and read from memory.
+pub struct HttpFile {
+    url: String,
+    connect_timeout: Option<Duration>,
+    len: u64,
+    pos: u64,
+    block_start: u64,
+    block: Vec<u8>
+}
+
+fn http_error(url: &str, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::Other,format!("{}: {}",url,e))
+}
+
+/* the total length from a Content-Range header, `bytes 0-99/1234` */
+fn content_range_len(line: &[u8]) -> Option<u64> {
+    let line = std::str::from_utf8(line).ok()?;
+    let (name,value) = line.split_once(':')?;
+    if !name.trim().eq_ignore_ascii_case("content-range") { return None; }
+    value.trim().rsplit_once('/')?.1.parse().ok()
+}
+
+impl HttpFile {
+    pub fn open(url: &str, connect_timeout: Option<Duration>) -> io::Result<HttpFile> {
+        let mut file = HttpFile { url: url.to_string(), connect_timeout, len: 0, pos: 0, block_start: 0, block: vec![] };
+        file.fetch(0)?;
+        Ok(file)
+    }
+
+    /* the block at `start`, which also tells us the length of the file */
+    fn fetch(&mut self, start: u64) -> io::Result<()> {
+        let mut easy = Easy::new();
+        let mut setup = || -> Result<(),curl::Error> {
+            easy.url(&self.url)?;
+            easy.follow_location(true)?;
+            easy.fail_on_error(true)?;
+            if let Some(timeout) = self.connect_timeout { easy.connect_timeout(timeout)?; }
+            easy.range(&format!("{}-{}",start,start+READ_AHEAD-1))
+        };
+        setup().map_err(|e| http_error(&self.url,e))?;
+        let len = Cell::new(None);
+        let mut block = vec![];
+        {
+            let mut transfer = easy.transfer();
+            let mut perform = || -> Result<(),curl::Error> {
+                transfer.header_function(|line| {
+                    if let Some(n) = content_range_len(line) { len.set(Some(n)); }
+                    true
+                })?;
+                transfer.write_function(|data| { block.extend_from_slice(data); Ok(data.len()) })?;
+                transfer.perform()
+            };
+            perform().map_err(|e| http_error(&self.url,e))?;
+        }
+        match easy.response_code().map_err(|e| http_error(&self.url,e))? {
+            206 => {
+                self.len = len.get().ok_or_else(|| http_error(&self.url,"partial content without a Content-Range"))?;
+                self.block_start = start;
+            },
+            _ => {
+                self.len = block.len() as u64;
+                self.block_start = 0;
+            }
+        }
+        self.block = block;
+        Ok(())
+    }
+
+    fn block_end(&self) -> u64 { self.block_start + self.block.len() as u64 }
+}
+
+impl Read for HttpFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() { return Ok(0); }
+        if self.pos < self.block_start || self.pos >= self.block_end() {
+            self.fetch(self.pos)?;
+            if self.pos < self.block_start || self.pos >= self.block_end() {
+                return Err(http_error(&self.url,format!("no data at offset {}",self.pos)));
+            }
+        }
+        let from = (self.pos - self.block_start) as usize;
+        let n = buf.len().min(self.block.len() - from);
+        buf[..n].copy_from_slice(&self.block[from..from+n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for HttpFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => n as i128,
+            SeekFrom::End(n) => self.len as i128 + n as i128,
+            SeekFrom::Current(n) => self.pos as i128 + n as i128
+        };
+        if target < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,"seek before start of file"));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
+
+fn not_found(archive: &str, member: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound,format!("No member {} in {}",member,archive))
+}
+
+/// Offset and length of an uncompressed member of a zip file, read from `input`, with `archive`
+/// naming it in errors.
+pub fn locate_zip_member<R: Read+Seek>(input: R, archive: &str, member: &str) -> io::Result<(u64,u64)> {
+    let mut zip = zip::ZipArchive::new(input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData,e))?;
+    let entry = zip.by_name(member).map_err(|_| not_found(archive,member))?;
+    if entry.compression() != CompressionMethod::Stored {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("{} is compressed in {}: it must be stored (zip -0) to be read in place",member,archive)));
+    }
+    Ok((entry.data_start(),entry.size()))
+}
+
+/// Offset and length of a member of an uncompressed tar file, read from `input`, with `archive`
+/// naming it in errors. Members' contents are seeked past, not read.
+pub fn locate_tar_member<R: Read+Seek>(input: R, archive: &str, member: &str) -> io::Result<(u64,u64)> {
+    let mut tar = tar::Archive::new(input);
+    for entry in tar.entries_with_seek()? {
+        let entry = entry?;
+        if entry.path()?.as_ref() == Path::new(member) {
+            return Ok((entry.raw_file_position(),entry.size()));
+        }
+    }
+    Err(not_found(archive,member))
+}
+
+/// Splits `archive.zip!inner.ncd` into its archive and member parts.
+pub fn split_archive_path(path: &str) -> Option<(&str,&str)> {
+    let (archive,member) = path.split_once('!')?;
+    if archive.is_empty() || member.is_empty() { return None; }
+    Some((archive,member))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, io::{Cursor, Read, Seek, SeekFrom, Write}};
+
+    use super::{content_range_len, locate_tar_member, locate_zip_member, split_archive_path, Window};
+
+    #[test]
+    fn test_window() {
+        let mut window = Window::new(Cursor::new(b"0123456789".to_vec()),2,5).unwrap();
+        let mut out = String::new();
+        window.read_to_string(&mut out).unwrap();
+        assert_eq!("23456",out);
+        assert_eq!(1,window.seek(SeekFrom::End(-4)).unwrap());
+        let mut buf = [0;2];
+        window.read_exact(&mut buf).unwrap();
+        assert_eq!(b"34",&buf);
+        assert!(window.seek(SeekFrom::Current(-10)).is_err());
+    }
+
+    #[test]
+    fn test_content_range_len() {
+        assert_eq!(Some(1234),content_range_len(b"Content-Range: bytes 0-99/1234\r\n"));
+        assert_eq!(None,content_range_len(b"Content-Range: bytes 0-99/*\r\n"));
+        assert_eq!(None,content_range_len(b"Content-Length: 100\r\n"));
+    }
+
+    #[test]
+    fn test_split_archive_path() {
+        assert_eq!(Some(("a.zip","b/c.ncd")),split_archive_path("a.zip!b/c.ncd"));
+        assert_eq!(None,split_archive_path("a.ncd"));
+        assert_eq!(None,split_archive_path("a.zip!"));
+    }
+
+    #[test]
+    fn test_locate() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("a.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("x.ncd",options).unwrap();
+        zip.write_all(b"hello").unwrap();
+        zip.finish().unwrap();
+        let (start,len) = locate_zip_member(File::open(&zip_path).unwrap(),"a.zip","x.ncd").unwrap();
+        assert_eq!(b"hello",&std::fs::read(&zip_path).unwrap()[start as usize..(start+len) as usize]);
+        assert!(locate_zip_member(File::open(&zip_path).unwrap(),"a.zip","y.ncd").is_err());
+        let tar_path = dir.path().join("a.tar");
+        let mut tar = tar::Builder::new(std::fs::File::create(&tar_path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_cksum();
+        tar.append_data(&mut header,"x.ncd",&b"hello"[..]).unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+        let (start,len) = locate_tar_member(File::open(&tar_path).unwrap(),"a.tar","x.ncd").unwrap();
+        assert_eq!(b"hello",&std::fs::read(&tar_path).unwrap()[start as usize..(start+len) as usize]);
+    }
+}
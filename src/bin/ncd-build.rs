@@ -1,8 +1,40 @@
-use std::{fmt::Display, fs::File, io, path::Path, process};
+use std::{fs::{self, File}, io::{self, BufRead, BufReader}, path::{Path, PathBuf}, sync::Arc, time::{Duration, Instant}};
 
-use clap::{App, Arg, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches};
+use encoding_rs::Encoding;
 use infer::Infer;
 use ncd::{NCDBuild, NCDBuildConfig, NCDFlatConfig, NCDFlatSource, NCDValueSource};
+use ncd_tools::accounting::{amplification_report, count_lines, write_accounting, AccountingRow, AttemptCost};
+use ncd_tools::sources::{bdb::{looks_like_bdb, BdbSource}, blocks::{BlocksConfig, BlocksSource}, cbor::CborSource, cdb::CdbSource, concat::ConcatSource, counting::{CountingSource, Tally}, csv::{CsvConfig, CsvSource}, dir::{DirConfig, DirSource}, dump::DumpSource, dup::{DupPolicy, DupSource}, fasta::{FastaConfig, FastaSource}, fixed::{parse_columns, FixedConfig, FixedSource, Span}, gff::{GffConfig, GffSource}, http_json::{is_http_url, HttpJsonConfig, HttpJsonSource}, is_redis_url, is_sql_url, json::{looks_like_json_object, JsonConfig, JsonSource}, jsonl::{JsonLinesConfig, JsonLinesSource}, kvjson::KvJsonSource, longkey::{LongKeyPolicy, LongKeySource}, msgpack::MsgpackSource, obo::{OboConfig, OboSource}, properties::PropertiesSource, protobuf::{ProtobufConfig, ProtobufSource}, rdb::{RdbConfig, RdbOther, RdbSource}, records::{RecordsConfig, RecordsSource}, skip::SkipSource, spool::{SpoolSource, Spooler}, sst::SstSource, strict::StrictSource, tar::TarSource, transform::TransformSource, validate::{json_validator, utf8_validator, InvalidPolicy, ValidateSource}, vcf::{VcfConfig, VcfKey, VcfSource}, xml::{KeyPath, XmlConfig, XmlSource}, yaml::{YamlConfig, YamlNested, YamlSource}, zip::ZipSource};
+#[cfg(feature="arrow")]
+use ncd_tools::sources::arrow::{ArrowConfig, ArrowSource};
+#[cfg(feature="avro")]
+use ncd_tools::sources::avro::{AvroConfig, AvroSource};
+#[cfg(feature="lmdb")]
+use ncd_tools::sources::lmdb::{LmdbConfig, LmdbSource};
+#[cfg(feature="parquet")]
+use ncd_tools::sources::parquet::{ParquetConfig, ParquetSource};
+#[cfg(feature="redis")]
+use ncd_tools::sources::redis::{RedisConfig, RedisSource};
+#[cfg(feature="sql")]
+use ncd_tools::sources::sql::{SqlConfig, SqlSource};
+#[cfg(feature="expr")]
+use ncd_tools::expr::compile_transform;
+use ncd_tools::download::DownloadConfig;
+use ncd_tools::encoding::KeyEncoding;
+use ncd_tools::input::{parse_encoding, same_file, Compression, Input};
+use ncd_tools::messages::{warning, Msg};
+use ncd_tools::pipe::ValuePipe;
+use ncd_tools::precheck::Precheck;
+use ncd_tools::prepare::{split_fields, split_quoted_fields, CommentMode, Crlf, Fields, Predicate, Prepare, RowFilter};
+use ncd_tools::progress::{Progress, ProgressSource};
+use ncd_tools::resolve::{normalize_key, KeyNormalize};
+use ncd_tools::s3::is_s3_url;
+use ncd_tools::shard::{manifest_path, parse_size, sha256_file, shard_of, shard_path, Manifest, ShardEntry, ShardSource};
+use ncd_tools::error::{die_msg, die_on_error, matches_or_die, remove_on_exit, set_error_attempt, set_error_context, set_error_format, ErrorFormat};
+use ncd_tools::version::{exit_if_version, features, version_args, INPUT_FORMATS};
+use regex::bytes::Regex;
+use tempfile::NamedTempFile;
 
 fn looks_like_utf8(bytes: &[u8]) -> bool {
     for b in bytes {
@@ -22,72 +54,418 @@ fn looks_like_utf8(bytes: &[u8]) -> bool {
 
 #[derive(Debug)]
 enum Format {
-    Flat
+    Flat,
+    Csv,
+    Json,
+    JsonLines,
+    KvJson,
+    Fasta,
+    Vcf,
+    Gff,
+    Dir,
+    Tar,
+    Zip,
+    Msgpack,
+    Cbor,
+    Parquet,
+    Avro,
+    Yaml,
+    Properties,
+    Cdb,
+    Bdb,
+    Lmdb,
+    Redis,
+    Sql,
+    Rdb,
+    Sst,
+    HttpJson,
+    Fixed,
+    Blocks,
+    Obo,
+    Xml,
+    Protobuf,
+    Arrow,
+    NcdDump
 }
 
 impl Format {
-    fn from_cli(name: &str, path: &str) -> Format {
-        match name {
+    /* one arm for each of version::INPUT_FORMATS */
+    fn from_name(name: &str) -> Option<Format> {
+        Some(match name {
             "flat" => Format::Flat,
-            "guess" => {
-                if let Some(format) = guess_format(path) {
-                    format
-                } else {
-                    die(format!("unknown file format for {}",path));                    
-                }
-            },
-            _ => {
-                die(format!("unknown file format for {}",path));
-            }
+            "csv" => Format::Csv,
+            "json" => Format::Json,
+            "jsonl" => Format::JsonLines,
+            "kvjson" => Format::KvJson,
+            "fasta" => Format::Fasta,
+            "vcf" => Format::Vcf,
+            "gff" => Format::Gff,
+            "dir" => Format::Dir,
+            "tar" => Format::Tar,
+            "zip" => Format::Zip,
+            "msgpack" => Format::Msgpack,
+            "cbor" => Format::Cbor,
+            "parquet" => Format::Parquet,
+            "avro" => Format::Avro,
+            "yaml" => Format::Yaml,
+            "properties" => Format::Properties,
+            "cdb" => Format::Cdb,
+            "bdb" => Format::Bdb,
+            "lmdb" => Format::Lmdb,
+            "redis" => Format::Redis,
+            "sql" => Format::Sql,
+            "rdb" => Format::Rdb,
+            "sst" => Format::Sst,
+            "http-json" => Format::HttpJson,
+            "fixed" => Format::Fixed,
+            "blocks" => Format::Blocks,
+            "obo" => Format::Obo,
+            "xml" => Format::Xml,
+            "protobuf" => Format::Protobuf,
+            "arrow" => Format::Arrow,
+            "ncd-dump" => Format::NcdDump,
+            _ => { return None; }
+        })
+    }
+
+    fn from_cli(name: &str, input: &Input) -> Format {
+        let path = input.name();
+        let format = if name == "guess" {
+            guess_format(input).unwrap_or_else(|| die_msg(Msg::UnknownFormat,&[&path]))
+        } else {
+            Format::from_name(name).unwrap_or_else(|| die_msg(Msg::UnknownFormat,&[&path]))
+        };
+        format.check_feature()
+    }
+
+    /* a guessed format as much as a named one: each feature is named after its format */
+    fn check_feature(self) -> Format {
+        if let Some(feature) = self.feature().filter(|f| !features().contains(f)) {
+            die_msg(Msg::NoFormatFeature,&[&feature,&feature]);
+        }
+        self
+    }
+
+    /* the cargo feature the reader needs, as listed in version::FORMAT_FEATURES */
+    fn feature(&self) -> Option<&'static str> {
+        match self {
+            Format::Parquet => Some("parquet"),
+            Format::Avro => Some("avro"),
+            Format::Lmdb => Some("lmdb"),
+            Format::Redis => Some("redis"),
+            Format::Sql => Some("sql"),
+            Format::Arrow => Some("arrow"),
+            _ => None
         }
     }
 
     fn from_mime_type(mime_type: &str) -> Option<Format> {
         match mime_type {
             "text/plain" => Some(Format::Flat),
+            "application/json" => Some(Format::Json),
+            "application/x-berkeley-db" => Some(Format::Bdb),
             _ => None
         }
     }
 
-    fn to_source(&self, path: &str, flat_config: &NCDFlatConfig) -> io::Result<Box<dyn NCDValueSource>> {
+    /* one record per physical line, so that line filters can't cut a record apart and lines
+     * count records: anything multi-line, binary or not a file isn't */
+    fn is_line_based(&self) -> bool {
+        matches!(self,Format::Flat | Format::KvJson | Format::JsonLines | Format::Vcf | Format::Gff)
+    }
+
+    /* sources which leave records out report them to `tally`, which fails them under --strict */
+    fn to_source(&self, path: &str, matches: &ArgMatches, tally: &Arc<Tally>) -> io::Result<Box<dyn NCDValueSource>> {
         Ok(match self {
             Format::Flat => {
-                Box::new(NCDFlatSource::new(Path::new(path),flat_config)?)
+                let index = key_index(Path::new(path),matches)?;
+                let source : Box<dyn NCDValueSource> = match (record_separator(matches),matches.is_present("quote-aware")) {
+                    (Some(terminator),_) => Box::new(RecordsSource::new(Path::new(path),&make_records_config(matches,terminator,index))?),
+                    (None,true) => Box::new(RecordsSource::new(Path::new(path),&make_records_config(matches,b'\n',index))?),
+                    (None,false) => Box::new(NCDFlatSource::new(Path::new(path),&make_flat_config(matches,index))?)
+                };
+                if matches.is_present("header") { Box::new(SkipSource::new(source,1)) } else { source }
+            },
+            Format::Csv => {
+                Box::new(CsvSource::new(Path::new(path),&make_csv_config(matches))?)
+            },
+            Format::Json => {
+                Box::new(JsonSource::new(Path::new(path),&make_json_config(matches))?)
+            },
+            Format::JsonLines => {
+                Box::new(JsonLinesSource::new(Path::new(path),&make_jsonl_config(matches))?)
+            },
+            Format::KvJson => {
+                Box::new(KvJsonSource::new(Path::new(path))?)
+            },
+            Format::Fasta => {
+                Box::new(FastaSource::new(Path::new(path),&make_fasta_config(matches))?)
+            },
+            Format::Vcf => {
+                Box::new(VcfSource::new(Path::new(path),&make_vcf_config(matches).tally(tally))?)
+            },
+            Format::Gff => {
+                Box::new(GffSource::new(Path::new(path),&make_gff_config(matches).tally(tally))?)
+            },
+            Format::Dir => {
+                Box::new(DirSource::new(Path::new(path),&make_dir_config(matches))?)
+            },
+            Format::Tar => {
+                Box::new(TarSource::new(Path::new(path))?)
+            },
+            Format::Zip => {
+                Box::new(ZipSource::new(Path::new(path))?)
+            },
+            Format::Msgpack => {
+                Box::new(MsgpackSource::new(Path::new(path))?)
+            },
+            Format::Cbor => {
+                Box::new(CborSource::new(Path::new(path))?)
+            },
+            #[cfg(feature="parquet")]
+            Format::Parquet => {
+                Box::new(ParquetSource::new(Path::new(path),&make_parquet_config(matches).tally(tally))?)
+            },
+            #[cfg(feature="avro")]
+            Format::Avro => {
+                Box::new(AvroSource::new(Path::new(path),&make_avro_config(matches).tally(tally))?)
+            },
+            Format::Yaml => {
+                Box::new(YamlSource::new(Path::new(path),&make_yaml_config(matches))?)
+            },
+            Format::Properties => {
+                Box::new(PropertiesSource::new(Path::new(path))?)
+            },
+            Format::Cdb => {
+                Box::new(CdbSource::new(Path::new(path))?)
+            },
+            Format::Bdb => {
+                Box::new(BdbSource::new(Path::new(path))?)
+            },
+            #[cfg(feature="lmdb")]
+            Format::Lmdb => {
+                Box::new(LmdbSource::new(Path::new(path),&make_lmdb_config(matches))?)
+            },
+            #[cfg(feature="redis")]
+            Format::Redis => {
+                Box::new(RedisSource::new(path,&make_redis_config(matches).tally(tally))?)
             },
+            #[cfg(feature="sql")]
+            Format::Sql => {
+                Box::new(SqlSource::new(path,&make_sql_config(matches).tally(tally))?)
+            },
+            Format::Rdb => {
+                Box::new(RdbSource::new(Path::new(path),&make_rdb_config(matches).tally(tally))?)
+            },
+            Format::Sst => {
+                Box::new(SstSource::new(Path::new(path))?)
+            },
+            Format::HttpJson => {
+                Box::new(HttpJsonSource::new(path,&make_http_json_config(matches))?)
+            },
+            Format::Fixed => {
+                Box::new(FixedSource::new(Path::new(path),&make_fixed_config(matches))?)
+            },
+            Format::Blocks => {
+                Box::new(BlocksSource::new(Path::new(path),&make_blocks_config(matches).tally(tally))?)
+            },
+            Format::Obo => {
+                Box::new(OboSource::new(Path::new(path),&make_obo_config(matches))?)
+            },
+            Format::Xml => {
+                Box::new(XmlSource::new(Path::new(path),&make_xml_config(matches))?)
+            },
+            Format::Protobuf => {
+                Box::new(ProtobufSource::new(Path::new(path),&make_protobuf_config(matches))?)
+            },
+            #[cfg(feature="arrow")]
+            Format::Arrow => {
+                Box::new(ArrowSource::new(Path::new(path),&make_arrow_config(matches).tally(tally))?)
+            },
+            Format::NcdDump => {
+                Box::new(DumpSource::new(Path::new(path))?)
+            },
+            /* formats not built in were turned away by check_feature */
+            #[cfg(not(all(feature="arrow",feature="avro",feature="lmdb",feature="parquet",feature="redis",feature="sql")))]
+            _ => unreachable!()
         })
     }
 }
 
-fn guess_format(path: &str) -> Option<Format> {
+/* by extension first: the name is the user's, whereas the path may be a spooled copy */
+fn guess_format(input: &Input) -> Option<Format> {
+    if input.is_dir() {
+        if input.path().join("data.mdb").exists() {
+            return Some(Format::Lmdb);
+        }
+        return Some(Format::Dir);
+    }
+    if is_redis_url(input.name()) {
+        return Some(Format::Redis);
+    }
+    if is_sql_url(input.name()) {
+        return Some(Format::Sql);
+    }
+    let mut lower = input.name().to_lowercase();
+    if is_http_url(&lower) || is_s3_url(&lower) {
+        lower.truncate(lower.find(|c| c == '?' || c == '#').unwrap_or(lower.len()));
+    }
+    for ext in &[".gz",".zst",".xz"] {
+        if lower.ends_with(ext) { lower.truncate(lower.len()-ext.len()); }
+    }
+    if lower.ends_with(".csv") {
+        return Some(Format::Csv);
+    }
+    if lower.ends_with(".jsonl") || lower.ends_with(".ndjson") {
+        return Some(Format::JsonLines);
+    }
+    if lower.ends_with(".kvjson") {
+        return Some(Format::KvJson);
+    }
+    if [".fa",".fasta",".faa",".fna"].iter().any(|ext| lower.ends_with(ext)) {
+        return Some(Format::Fasta);
+    }
+    if lower.ends_with(".vcf") {
+        return Some(Format::Vcf);
+    }
+    if lower.ends_with(".obo") {
+        return Some(Format::Obo);
+    }
+    if [".gff",".gff3",".gtf"].iter().any(|ext| lower.ends_with(ext)) {
+        return Some(Format::Gff);
+    }
+    if lower.ends_with(".tar") || lower.ends_with(".tgz") {
+        return Some(Format::Tar);
+    }
+    if lower.ends_with(".zip") {
+        return Some(Format::Zip);
+    }
+    if lower.ends_with(".msgpack") || lower.ends_with(".mpk") {
+        return Some(Format::Msgpack);
+    }
+    if lower.ends_with(".cbor") || lower.ends_with(".cbors") {
+        return Some(Format::Cbor);
+    }
+    if lower.ends_with(".parquet") {
+        return Some(Format::Parquet);
+    }
+    if lower.ends_with(".avro") {
+        return Some(Format::Avro);
+    }
+    if [".arrow",".arrows",".feather",".ipc"].iter().any(|ext| lower.ends_with(ext)) {
+        return Some(Format::Arrow);
+    }
+    if lower.ends_with(".yaml") || lower.ends_with(".yml") {
+        return Some(Format::Yaml);
+    }
+    if lower.ends_with(".properties") || lower.ends_with(".env") {
+        return Some(Format::Properties);
+    }
+    if lower.ends_with(".cdb") {
+        return Some(Format::Cdb);
+    }
+    if lower.ends_with(".mdb") {
+        return Some(Format::Lmdb);
+    }
+    if lower.ends_with(".rdb") {
+        return Some(Format::Rdb);
+    }
+    if lower.ends_with(".sst") || lower.ends_with(".ldb") {
+        return Some(Format::Sst);
+    }
+    if lower.ends_with(".xml") {
+        return Some(Format::Xml);
+    }
     let mut inferer = Infer::new();
+    inferer.add("application/x-berkeley-db",".db",|bytes| {
+        looks_like_bdb(bytes)
+    });
+    inferer.add("application/json",".json",|bytes| {
+        looks_like_json_object(bytes)
+    });
     inferer.add("text/plain",".txt",|bytes| {
         looks_like_utf8(bytes)
     });
-    let value = die_on_error(inferer.get_from_path(path));
+    let value = die_on_error(inferer.get_from_path(input.path()));
     value.and_then(|value| Format::from_mime_type(value.mime_type()))
 }
 
-fn die<E: Display>(value: E) -> ! {
-    eprintln!("{}",value);
-    process::exit(1);
+/* --field is a number, or with --header a column name */
+fn field_name<'a>(matches: &'a ArgMatches) -> Option<&'a str> {
+    let name = matches.value_of("field").filter(|f| str_to_u32(f).is_err());
+    if let Some(name) = name {
+        if !matches.is_present("header") {
+            die_msg(Msg::FieldNameNeedsHeader,&[&name]);
+        }
+    }
+    name
 }
 
-fn die_on_error<T,E: Display>(value: Result<T,E>) -> T {
-    match value {
-        Ok(v) => v,
-        Err(e) => die(e)
+/* the flat source's key field. Prepare has already moved any --key-fields/--value-fields key
+ * to the front (finding a named column itself), else a name is looked up in the header here */
+fn key_index(path: &Path, matches: &ArgMatches) -> io::Result<usize> {
+    if uses_fields(matches) {
+        Ok(1)
+    } else if let Some(name) = field_name(matches) {
+        header_index(path,name,matches)
+    } else {
+        Ok(die_on_error(str_to_u32(matches.value_of("field").unwrap())) as usize)
+    }
+}
+
+/* with --header the flat source reads the header as its first record: look up the key column in
+ * it here, passing over the lines the flat source would */
+fn header_index(path: &Path, name: &str, matches: &ArgMatches) -> io::Result<usize> {
+    let separator = matches.value_of("delimiter").map(|s| s.to_string());
+    let comments : Vec<&str> = matches.values_of("comment").into_iter().flatten().collect();
+    let terminator = record_separator(matches).unwrap_or(b'\n');
+    for line in BufReader::new(File::open(path)?).split(terminator) {
+        let line = line?;
+        let line = if terminator == b'\n' { line.strip_suffix(b"\r").unwrap_or(&line) } else { &line };
+        if line.iter().all(|b| b.is_ascii_whitespace()) || comments.iter().any(|c| line.starts_with(c.as_bytes())) {
+            continue;
+        }
+        let fields = if matches.is_present("quote-aware") { split_quoted_fields(line,&separator) } else { split_fields(line,&separator) };
+        match fields.iter().position(|f| *f == name.as_bytes()) {
+            Some(index) => { return Ok(index+1); },
+            None => die_msg(Msg::NoSuchColumn,&[&name])
+        }
+    }
+    /* no header at all: an empty file, with no key to pick */
+    Ok(1)
+}
+
+fn make_download_config(matches: &ArgMatches) -> DownloadConfig {
+    let mut config = DownloadConfig::new();
+    if let Some(timeout) = matches.value_of("timeout") {
+        config = config.connect_timeout(Some(Duration::from_millis(die_on_error(str_to_u32(timeout)) as u64)));
     }
+    config.retries(die_on_error(str_to_u32(matches.value_of("retries").unwrap())))
 }
 
-fn make_flat_config(matches: &ArgMatches) -> NCDFlatConfig {
-    let field = die_on_error(str_to_u32(matches.value_of("field").unwrap()));
+/* http-json fetches its pages itself: any other http(s) input, or an s3:// one, is a file to
+ * download first */
+fn open_input(name: &str, compression: Compression, encoding: Option<&'static Encoding>, prepare: &Prepare, matches: &ArgMatches) -> io::Result<Input> {
+    if is_http_url(name) && matches.value_of("format") != Some("http-json") {
+        Input::download(name,&make_download_config(matches),compression,encoding,prepare)
+    } else if is_s3_url(name) {
+        Input::download_s3(name,&make_download_config(matches),compression,encoding,prepare)
+    } else {
+        Input::open(name,compression,encoding,prepare)
+    }
+}
+
+fn make_flat_config(matches: &ArgMatches, index: usize) -> NCDFlatConfig {
     let separator = matches.value_of("delimiter").map(|s| s.to_string());
     let skip_blank = !matches.is_present("keep-blank");
-    let comment = matches.value_of("comment").map(|s| s.to_string());
-    let inline_comments = matches.is_present("inline-comments");
+    let (comment,inline_comments) = if comments_need_prepare(matches) {
+        (None,false)
+    } else {
+        (matches.value_of("comment").map(|s| s.to_string()),comment_mode(matches) == CommentMode::Anywhere)
+    };
     let trim_tail = !matches.is_present("keep-tail");
     NCDFlatConfig::new()
-        .index(field as usize)
+        .index(index)
         .separator(separator)
         .skip_blank(skip_blank)
         .comment_char(comment)
@@ -95,6 +473,376 @@ fn make_flat_config(matches: &ArgMatches) -> NCDFlatConfig {
         .trim_tail(trim_tail)
 }
 
+/* a NUL can't be passed as an argument, so it's written \0 */
+fn parse_record_separator(value: &str) -> Result<u8,String> {
+    match value {
+        "\\0" => Ok(0),
+        "\\n" => Ok(b'\n'),
+        "\\t" => Ok(b'\t'),
+        _ if value.len() == 1 => Ok(value.as_bytes()[0]),
+        _ => Err(format!("record separator must be a single byte, \\0, \\n or \\t, not '{}'",value))
+    }
+}
+
+fn record_separator(matches: &ArgMatches) -> Option<u8> {
+    if matches.is_present("null") { return Some(0); }
+    matches.value_of("record-separator").map(|s| die_on_error(parse_record_separator(s)))
+}
+
+/* the flat source only splits on newlines and on every separator: other record separators, and
+ * fields which may be quoted, read with RecordsSource, which takes the same field options */
+fn make_records_config(matches: &ArgMatches, terminator: u8, index: usize) -> RecordsConfig {
+    RecordsConfig::new()
+        .terminator(terminator)
+        .index(index)
+        .separator(matches.value_of("delimiter").map(|s| s.to_string()))
+        .skip_blank(!matches.is_present("keep-blank"))
+        .comment(matches.value_of("comment").map(|s| s.to_string()))
+        .inline_comments(comment_mode(matches) == CommentMode::Anywhere)
+        .trim_tail(!matches.is_present("keep-tail"))
+        .quote_aware(matches.is_present("quote-aware"))
+}
+
+fn single_byte(matches: &ArgMatches, name: &str) -> Option<u8> {
+    matches.value_of(name).map(|s| {
+        if s.len() != 1 {
+            die_msg(Msg::NotSingleByte,&[&name,&s]);
+        }
+        s.as_bytes()[0]
+    })
+}
+
+fn comment_mode(matches: &ArgMatches) -> CommentMode {
+    match matches.value_of("comment-mode") {
+        Some("indented") => CommentMode::Indented,
+        Some("anywhere") => CommentMode::Anywhere,
+        _ if matches.is_present("inline-comments") => CommentMode::Anywhere,
+        _ => CommentMode::Start
+    }
+}
+
+/* NCDFlatConfig takes one comment string, at the start of the line or anywhere: the rest is up to
+ * Prepare, which also has to strip comments before it picks out value fields */
+fn comments_need_prepare(matches: &ArgMatches) -> bool {
+    let count = matches.values_of("comment").map(|v| v.count()).unwrap_or(0);
+    count > 1 || (count > 0 && (comment_mode(matches) == CommentMode::Indented || uses_fields(matches)))
+}
+
+fn uses_fields(matches: &ArgMatches) -> bool {
+    matches.is_present("key-fields") || matches.is_present("value-fields")
+}
+
+fn parse_spans(s: &str) -> Result<Vec<Span>,String> {
+    s.split(',').map(Span::parse).collect()
+}
+
+fn make_fields(matches: &ArgMatches) -> Option<Fields> {
+    if !uses_fields(matches) { return None; }
+    let separator = matches.value_of("delimiter").map(|s| s.to_string());
+    /* a named --field is found in the header as it goes by */
+    let (key,key_name) = match (matches.value_of("key-fields"),field_name(matches)) {
+        (Some(key),_) => (key,None),
+        (None,Some(name)) => ("1",Some(name.to_string())),
+        (None,None) => (matches.value_of("field").unwrap(),None)
+    };
+    let mut fields = Fields::new(die_on_error(parse_spans(key)),separator)
+        .key_name(key_name)
+        .value(matches.value_of("value-fields").map(|v| die_on_error(parse_spans(v))));
+    if let Some(join) = matches.value_of("key-join") {
+        fields = fields.key_join(join.to_string());
+    }
+    if let Some(join) = matches.value_of("value-delimiter") {
+        fields = fields.joiner(join.to_string());
+    }
+    Some(fields)
+}
+
+fn uses_row_filter(matches: &ArgMatches) -> bool {
+    matches.is_present("filter") || matches.is_present("where")
+}
+
+fn make_row_filter(matches: &ArgMatches) -> Option<RowFilter> {
+    if !uses_row_filter(matches) { return None; }
+    let patterns = matches.values_of("filter").into_iter().flatten().map(|p| die_on_error(Regex::new(p))).collect();
+    let predicates = matches.values_of("where").into_iter().flatten().map(|p| die_on_error(Predicate::parse(p))).collect();
+    let separator = matches.value_of("delimiter").map(|s| s.to_string());
+    Some(RowFilter::new(separator).patterns(patterns).predicates(predicates))
+}
+
+fn make_prepare(matches: &ArgMatches) -> Prepare {
+    let mut prepare = Prepare::new().crlf(Crlf::from_cli(matches.value_of("crlf").unwrap()));
+    if comments_need_prepare(matches) {
+        let comments = matches.values_of("comment").unwrap().map(|s| s.to_string()).collect();
+        prepare = prepare.comments(comments).comment_mode(comment_mode(matches));
+    }
+    if let Some(lines) = matches.value_of("skip-lines") {
+        prepare = prepare.skip_lines(die_on_error(str_to_u32(lines)) as usize);
+    }
+    prepare.filter(make_row_filter(matches)).fields(make_fields(matches))
+}
+
+fn make_csv_config(matches: &ArgMatches) -> CsvConfig {
+    /* a named --field is the key column, which the csv source finds in the header itself */
+    let key_column = matches.value_of("key-column").or_else(|| field_name(matches)).map(|s| s.to_string());
+    let field = if key_column.is_some() { 1 } else { die_on_error(str_to_u32(matches.value_of("field").unwrap())) };
+    let header = matches.is_present("header");
+    let delimiter = single_byte(matches,"delimiter").unwrap_or(b',');
+    let quote = single_byte(matches,"quote").unwrap_or(b'"');
+    let escape = single_byte(matches,"escape");
+    CsvConfig::new()
+        .index(field as usize)
+        .key_column(key_column)
+        .header(header)
+        .delimiter(delimiter)
+        .quote(quote)
+        .escape(escape)
+}
+
+fn make_json_config(matches: &ArgMatches) -> JsonConfig {
+    JsonConfig::new()
+        .compact(matches.is_present("json-compact"))
+}
+
+fn make_jsonl_config(matches: &ArgMatches) -> JsonLinesConfig {
+    let mut config = JsonLinesConfig::new()
+        .value_path(matches.value_of("value-path").map(|s| s.to_string()));
+    if let Some(key_path) = matches.value_of("key-path") {
+        config = config.key_path(key_path.to_string());
+    }
+    config
+}
+
+#[cfg(feature="expr")]
+fn add_expressions(source: Box<dyn NCDValueSource>, matches: &ArgMatches, tally: &Arc<Tally>) -> io::Result<Box<dyn NCDValueSource>> {
+    if !matches.is_present("key-expr") && !matches.is_present("value-expr") {
+        return Ok(source);
+    }
+    let mut source = TransformSource::new(source).tally(tally);
+    if let Some(expr) = matches.value_of("key-expr") {
+        source = source.map_key(compile_transform(expr)?);
+    }
+    if let Some(expr) = matches.value_of("value-expr") {
+        source = source.map_value(compile_transform(expr)?);
+    }
+    Ok(Box::new(source))
+}
+
+#[cfg(not(feature="expr"))]
+fn add_expressions(source: Box<dyn NCDValueSource>, matches: &ArgMatches, _tally: &Arc<Tally>) -> io::Result<Box<dyn NCDValueSource>> {
+    if matches.is_present("key-expr") || matches.is_present("value-expr") {
+        die_msg(Msg::NoExprFeature,&[]);
+    }
+    Ok(source)
+}
+
+fn add_key_decoding(source: Box<dyn NCDValueSource>, matches: &ArgMatches) -> Box<dyn NCDValueSource> {
+    let encoding = KeyEncoding::from_cli(matches.value_of("key-encoding").unwrap());
+    if encoding == KeyEncoding::Raw {
+        return source;
+    }
+    Box::new(TransformSource::new(source).map_key(Box::new(move |key,_| {
+        encoding.decode(key).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData,e))
+    })))
+}
+
+fn make_key_normalization(matches: &ArgMatches) -> Vec<KeyNormalize> {
+    matches.values_of("normalize-key").map(|v| v.filter_map(KeyNormalize::from_cli).collect()).unwrap_or(vec![])
+}
+
+fn add_key_normalization(source: Box<dyn NCDValueSource>, matches: &ArgMatches) -> Box<dyn NCDValueSource> {
+    let steps = make_key_normalization(matches);
+    if steps.is_empty() {
+        return source;
+    }
+    Box::new(TransformSource::new(source).map_key(Box::new(move |key,_| Ok(Some(normalize_key(&steps,key.to_vec()))))))
+}
+
+fn add_validators(source: Box<dyn NCDValueSource>, matches: &ArgMatches, tally: &Arc<Tally>) -> Box<dyn NCDValueSource> {
+    if !matches.is_present("validate-json") && !matches.is_present("validate-utf8") {
+        return source;
+    }
+    let policy = if matches.value_of("on-invalid") == Some("skip") { InvalidPolicy::Skip } else { InvalidPolicy::Error };
+    let mut source = ValidateSource::new(source,policy).tally(tally);
+    if matches.is_present("validate-utf8") {
+        source = source.validator("UTF-8",utf8_validator());
+    }
+    if matches.is_present("validate-json") {
+        source = source.validator("JSON",json_validator());
+    }
+    Box::new(source)
+}
+
+/* in a set, a key seen twice is still just there: keep one unless told otherwise */
+fn dup_policy<'a>(matches: &'a ArgMatches) -> Option<&'a str> {
+    matches.value_of("dup-policy").or(if matches.is_present("set-mode") { Some("first") } else { None })
+}
+
+fn add_set_mode(source: Box<dyn NCDValueSource>, matches: &ArgMatches) -> Box<dyn NCDValueSource> {
+    if !matches.is_present("set-mode") {
+        return source;
+    }
+    let marker = matches.value_of("set-marker").unwrap_or("").as_bytes().to_vec();
+    Box::new(TransformSource::new(source).map_value(Box::new(move |_,_| Ok(Some(marker.clone())))))
+}
+
+fn make_value_pipes(matches: &ArgMatches) -> Vec<ValuePipe> {
+    matches.values_of("value-pipe").map(|v| v.map(|p| die_on_error(ValuePipe::parse(p))).collect()).unwrap_or(vec![])
+}
+
+/* after validation, so that it's the input which was checked, not eg its gzipped form */
+fn add_value_pipes(source: Box<dyn NCDValueSource>, matches: &ArgMatches) -> Box<dyn NCDValueSource> {
+    let pipes = make_value_pipes(matches);
+    if pipes.is_empty() {
+        return source;
+    }
+    Box::new(TransformSource::new(source).map_value(Box::new(move |_,value| {
+        let mut value = value.to_vec();
+        for pipe in &pipes {
+            value = pipe.apply(&value)?;
+        }
+        Ok(Some(value))
+    })))
+}
+
+fn make_fasta_config(matches: &ArgMatches) -> FastaConfig {
+    FastaConfig::new()
+        .description(matches.is_present("fasta-description"))
+}
+
+fn make_vcf_config(matches: &ArgMatches) -> VcfConfig {
+    let key = match matches.value_of("vcf-key") {
+        Some("locus") => VcfKey::Locus,
+        _ => VcfKey::Id
+    };
+    VcfConfig::new()
+        .key(key)
+}
+
+fn make_gff_config(matches: &ArgMatches) -> GffConfig {
+    GffConfig::new()
+        .attribute(matches.value_of("gff-attribute").unwrap().to_string())
+}
+
+fn make_dir_config(matches: &ArgMatches) -> DirConfig {
+    let exclude = matches.values_of("dir-exclude").map(|v| v.map(|s| s.to_string()).collect()).unwrap_or(vec![]);
+    DirConfig::new()
+        .follow_symlinks(matches.is_present("dir-follow-symlinks"))
+        .exclude(exclude)
+}
+
+#[cfg(feature="parquet")]
+fn make_parquet_config(matches: &ArgMatches) -> ParquetConfig {
+    ParquetConfig::new()
+        .key_col(matches.value_of("key-col").unwrap().to_string())
+        .value_col(matches.value_of("value-col").unwrap().to_string())
+}
+
+#[cfg(feature="arrow")]
+fn make_arrow_config(matches: &ArgMatches) -> ArrowConfig {
+    ArrowConfig::new()
+        .key_col(matches.value_of("key-col").unwrap().to_string())
+        .value_col(matches.value_of("value-col").unwrap().to_string())
+}
+
+#[cfg(feature="avro")]
+fn make_avro_config(matches: &ArgMatches) -> AvroConfig {
+    let mut config = AvroConfig::new()
+        .value_path(matches.value_of("value-path").map(|s| s.to_string()));
+    if let Some(key_path) = matches.value_of("key-path") {
+        config = config.key_path(key_path.to_string());
+    }
+    config
+}
+
+fn make_yaml_config(matches: &ArgMatches) -> YamlConfig {
+    let nested = match matches.value_of("yaml-nested") {
+        Some("yaml") => YamlNested::Yaml,
+        _ => YamlNested::Json
+    };
+    YamlConfig::new()
+        .nested(nested)
+}
+
+#[cfg(feature="lmdb")]
+fn make_lmdb_config(matches: &ArgMatches) -> LmdbConfig {
+    LmdbConfig::new()
+        .db(matches.value_of("lmdb-db").map(|s| s.to_string()))
+}
+
+#[cfg(feature="redis")]
+fn make_redis_config(matches: &ArgMatches) -> RedisConfig {
+    RedisConfig::new()
+        .pattern(matches.value_of("scan-pattern").unwrap().to_string())
+}
+
+#[cfg(feature="sql")]
+fn make_sql_config(matches: &ArgMatches) -> SqlConfig {
+    match matches.value_of("query") {
+        Some(query) => SqlConfig::new().query(query.to_string()),
+        None => die_msg(Msg::SqlNeedsQuery,&[])
+    }
+}
+
+fn make_rdb_config(matches: &ArgMatches) -> RdbConfig {
+    let other = match matches.value_of("rdb-other") {
+        Some("json") => RdbOther::Json,
+        _ => RdbOther::Skip
+    };
+    RdbConfig::new()
+        .other(other)
+}
+
+fn make_http_json_config(matches: &ArgMatches) -> HttpJsonConfig {
+    let mut config = HttpJsonConfig::new()
+        .items_path(matches.value_of("items-path").unwrap_or("").to_string())
+        .next_path(matches.value_of("next-path").map(|s| s.to_string()))
+        .value_path(matches.value_of("value-path").map(|s| s.to_string()));
+    if let Some(key_path) = matches.value_of("key-path") {
+        config = config.key_path(key_path.to_string());
+    }
+    config
+}
+
+fn make_fixed_config(matches: &ArgMatches) -> FixedConfig {
+    let columns = matches.value_of("columns").unwrap_or_else(|| die_msg(Msg::RequiredWith,&[&"columns",&"fixed"]));
+    let (key,value) = die_on_error(parse_columns(columns));
+    FixedConfig::new()
+        .key(key)
+        .value(value)
+}
+
+fn make_xml_config(matches: &ArgMatches) -> XmlConfig {
+    let record = matches.value_of("xml-record").unwrap_or_else(|| die_msg(Msg::RequiredWith,&[&"xml-record",&"xml"]));
+    let key = die_on_error(KeyPath::parse(matches.value_of("xml-key").unwrap()));
+    XmlConfig::new()
+        .record(record.to_string())
+        .key(key)
+}
+
+fn make_protobuf_config(matches: &ArgMatches) -> ProtobufConfig {
+    let descriptors = matches.value_of("proto-desc").unwrap_or_else(|| die_msg(Msg::RequiredWith,&[&"proto-desc",&"protobuf"]));
+    let mut config = ProtobufConfig::new()
+        .descriptors(PathBuf::from(descriptors))
+        .message(matches.value_of("proto-message").map(|s| s.to_string()));
+    if let Some(key) = matches.value_of("proto-key") {
+        config = config.key(key.to_string());
+    }
+    config
+}
+
+fn make_blocks_config(matches: &ArgMatches) -> BlocksConfig {
+    let mut config = BlocksConfig::new();
+    if let Some(field) = matches.value_of("record-key") {
+        config = config.record_key(field.to_string());
+    }
+    config
+}
+
+fn make_obo_config(matches: &ArgMatches) -> OboConfig {
+    OboConfig::new()
+        .stanzas(matches.values_of("obo-stanza").unwrap().map(|s| s.to_string()).collect())
+}
+
 fn make_careful_config() -> NCDBuildConfig {
     NCDBuildConfig::new()
         .target_page_size(16384)
@@ -135,47 +883,144 @@ fn str_to_u32(s: &str) -> Result<u32,String> {
     s.parse::<u32>().map_err(|e| format!("Invalid integer: {}",e))
 }
 
+fn str_to_u64(s: &str) -> Result<u64,String> {
+    s.parse::<u64>().map_err(|e| format!("Invalid integer: {}",e))
+}
+
 fn str_to_f64(s: &str) -> Result<f64,String> {
     s.parse::<f64>().map_err(|e| format!("Invalid floating-point number: {}",e))
 }
 
 pub fn make_app() -> App<'static,'static> {
-    App::new("ncd file builder").version("0.0.1")
+    App::new("ncd file builder").version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::DisableVersion)
+        .args(&version_args())
         .author("Dan Sheppard <dan@ebi.ac.uk")
         .about("Builds ncd files from a variety of sources")
         .arg(Arg::with_name("INPUT")
-            .help("input file to convert")
+            .help("input file to convert, with any --input after it (- for stdin, a redis:// URL, a postgres:// or mysql:// DSN with --query, or an http(s):// URL to download, or for http-json to page through, or an s3://BUCKET/KEY object)")
             .index(1)
-            .required(true)
+            .required_unless("version")
         )
         .arg(Arg::with_name("OUTPUT")
             .help("output file to create")
             .index(2)
-            .required(true)
+            .required_unless("version")
+        )
+        .arg(Arg::with_name("input")
+            .long("--input")
+            .takes_value(true)
+            .help("another input file, its records after INPUT's, may be repeated: as PATH=PREFIX, PREFIX is put in front of each of its keys")
+            .multiple(true)
+            .number_of_values(1)
         )
         .arg(Arg::with_name("format")
             .short("-t")
             .long("--type")
             .help("specify input file format (optional: will guess)")
             .takes_value(true)
-            .possible_value("flat")
-            .possible_value("gdbm")
+            .possible_values(INPUT_FORMATS)
             .possible_value("guess")
             .default_value("guess")
         )
+        .arg(Arg::with_name("timeout")
+            .long("--timeout")
+            .takes_value(true)
+            .help("connect timeout for http(s) and s3:// input (ms)")
+            .validator(|v| str_to_u32(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("retries")
+            .long("--retries")
+            .takes_value(true)
+            .help("for http(s) and s3:// input, times to retry a download which breaks off, resuming where it stopped where the server allows")
+            .default_value("3")
+            .validator(|v| str_to_u32(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("input-encoding")
+            .long("--input-encoding")
+            .takes_value(true)
+            .help("convert text input from this encoding (latin1, windows-1252, utf-16le, shift_jis, ...) to UTF-8 first (default is to take it as it is)")
+            .validator(|v| parse_encoding(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("compress-in")
+            .long("--compress-in")
+            .takes_value(true)
+            .help("decompress input first (default will guess from its first bytes)")
+            .possible_value("none")
+            .possible_value("gzip")
+            .possible_value("zstd")
+            .possible_value("xz")
+            .possible_value("auto")
+            .default_value("auto")
+        )
         .arg(Arg::with_name("field")
             .short("-f")
             .long("--field")
             .takes_value(true)
-            .help("when using separated file, which field to use (first is 1)")
+            .help("when using separated file, which field to use (first is 1), or with --header the name of its column")
             .default_value("1")
-            .validator(|v| str_to_u32(&v).map(|_| ()))
+            .validator(|v| if v.is_empty() { Err("empty field".to_string()) } else { Ok(()) })
         )
         .arg(Arg::with_name("delimiter")
             .short("-d")
             .long("--delimiter")
             .takes_value(true)
-            .help("when using separated file, which delimiter to use (default is arbitrary whitespace)")
+            .help("when using separated file, which delimiter to use (default is arbitrary whitespace, or comma for csv)")
+        )
+        .arg(Arg::with_name("filter")
+            .long("--filter")
+            .takes_value(true)
+            .help("keep only lines matching this regex, may be repeated (lines must match them all)")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v| Regex::new(&v).map(|_| ()).map_err(|e| e.to_string()))
+        )
+        .arg(Arg::with_name("where")
+            .long("--where")
+            .takes_value(true)
+            .help("keep only lines where fieldN OP VALUE holds (OP is == != < <= > >= =~ !~), fields split as for --delimiter, may be repeated")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v| Predicate::parse(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("value-fields")
+            .long("--value-fields")
+            .takes_value(true)
+            .help("when using separated file, store only these fields as the value (numbered like --field, eg 3,5-7 or 4-)")
+            .validator(|v| parse_spans(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("value-delimiter")
+            .long("--value-delimiter")
+            .takes_value(true)
+            .help("join value fields with this when using --key-fields or --value-fields (default the --delimiter, or a space)")
+        )
+        .arg(Arg::with_name("key-fields")
+            .long("--key-fields")
+            .takes_value(true)
+            .help("when using separated file, build the key from these fields instead of --field (eg 1,2), the value being the other fields unless --value-fields")
+            .validator(|v| parse_spans(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("key-join")
+            .long("--key-join")
+            .takes_value(true)
+            .help("join --key-fields with this (default the --delimiter, or a space)")
+            .requires("key-fields")
+        )
+        .arg(Arg::with_name("record-separator")
+            .long("--record-separator")
+            .takes_value(true)
+            .help("when using separated file, records end with this byte rather than a newline, so values can hold newlines (\\0 for NUL, \\n, \\t)")
+            .validator(|v| parse_record_separator(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("null")
+            .short("-0")
+            .long("--null")
+            .help("when using separated file, records end with NUL, as from find -print0 (short for --record-separator '\\0')")
+            .conflicts_with("record-separator")
+        )
+        .arg(Arg::with_name("quote-aware")
+            .long("--quote-aware")
+            .help("when using separated file, don't split fields on -d (or whitespace) between double quotes, so a quoted field can hold it; a field wholly in quotes loses them")
         )
         .arg(Arg::with_name("keep-blank")
             .short("-B")
@@ -186,7 +1031,9 @@ pub fn make_app() -> App<'static,'static> {
             .short("-C")
             .long("--comment")
             .takes_value(true)
-            .help("when using separated file, treat as comment character (default is none)")
+            .help("when using separated file, treat as comment character, may be repeated (default is none)")
+            .multiple(true)
+            .number_of_values(1)
         )
         .arg(Arg::with_name("inline-comments")
             .short("-I")
@@ -194,36 +1041,230 @@ pub fn make_app() -> App<'static,'static> {
             .help("when using separated file, strip trailing comments (default is none)")
             .requires("comment")
         )
+        .arg(Arg::with_name("comment-mode")
+            .long("--comment-mode")
+            .takes_value(true)
+            .help("when using separated file, comments only at the start of a line, also after leading whitespace, or anywhere (same as -I)")
+            .possible_value("start")
+            .possible_value("indented")
+            .possible_value("anywhere")
+            .requires("comment")
+        )
+        .arg(Arg::with_name("crlf")
+            .long("--crlf")
+            .takes_value(true)
+            .help("when using separated file, strip the \\r of Windows line endings: auto does if the first line has one (a UTF-8 BOM is always stripped)")
+            .possible_value("auto")
+            .possible_value("keep")
+            .possible_value("strip")
+            .default_value("auto")
+        )
         .arg(Arg::with_name("keep-tail")
             .short("-T")
             .long("--keep-tail")
             .help("when using separated file, don't strip trailing whitespace (default is none)")
         )
-        .arg(Arg::with_name("careful")
-            .short("-c")
-            .long("--careful")
-            .help("Use careful settings for building (will take longer but probably result in smaller file)")
+        .arg(Arg::with_name("header")
+            .long("--header")
+            .help("when using csv or separated file, the first row (after --skip-lines) is a header, which --field can name columns from (default is no header)")
         )
-        .arg(Arg::with_name("page-size")
-            .short("-p")
-            .long("--page-size")
+        .arg(Arg::with_name("skip-lines")
+            .long("--skip-lines")
             .takes_value(true)
-            .help("target page size in bytes (requests will be of this size) (default 32768, careful 16384)")
+            .help("ignore this many lines at the start of the input, before anything else")
             .validator(|v| str_to_u32(&v).map(|_| ()))
         )
-        .arg(Arg::with_name("load-factor")
-            .long("--load-factor")
+        .arg(Arg::with_name("key-column")
+            .long("--key-column")
             .takes_value(true)
-            .help("target hash-table load factor (requests will be of this size) (default 0.5, careful 0.75)")
-            .validator(|v| str_to_f64(&v).map(|_| ()))
+            .help("when using csv, use the header column with this name as the key (implies --header)")
         )
-        .arg(Arg::with_name("heap-wiggle")
-            .long("--heap-wiggle")
+        .arg(Arg::with_name("quote")
+            .long("--quote")
             .takes_value(true)
-            .help("heap-figgle room (requests will be of this size) (default 1.25, careful 1.1)")
-            .validator(|v| str_to_f64(&v).map(|_| ()))
+            .help("when using csv, the quote character (default is \")")
         )
-        .arg(Arg::with_name("min-entries")
+        .arg(Arg::with_name("escape")
+            .long("--escape")
+            .takes_value(true)
+            .help("when using csv, the escape character within quotes (default is doubling the quote)")
+        )
+        .arg(Arg::with_name("json-compact")
+            .long("--json-compact")
+            .help("when using json, store non-string values as compact json (default is to reject them)")
+        )
+        .arg(Arg::with_name("yaml-nested")
+            .long("--yaml-nested")
+            .takes_value(true)
+            .help("when using yaml, how to store sequence and mapping values")
+            .possible_value("json")
+            .possible_value("yaml")
+            .default_value("json")
+        )
+        .arg(Arg::with_name("key-path")
+            .long("--key-path")
+            .takes_value(true)
+            .help("when using jsonl, avro or http-json, dotted path to the key in each record (default is id)")
+        )
+        .arg(Arg::with_name("value-path")
+            .long("--value-path")
+            .takes_value(true)
+            .help("when using jsonl, avro or http-json, dotted path to the value in each record (default is the whole record)")
+        )
+        .arg(Arg::with_name("rdb-other")
+            .long("--rdb-other")
+            .takes_value(true)
+            .help("rdb: skip keys which aren't strings, or store them as JSON")
+            .possible_value("skip")
+            .possible_value("json")
+            .default_value("skip")
+        )
+        .arg(Arg::with_name("items-path")
+            .long("--items-path")
+            .takes_value(true)
+            .help("http-json: dotted path to the array of records in each page (default is the whole page)")
+        )
+        .arg(Arg::with_name("next-path")
+            .long("--next-path")
+            .takes_value(true)
+            .help("http-json: dotted path to the next page's URL in each page (default is a single page)")
+        )
+        .arg(Arg::with_name("fasta-description")
+            .long("--fasta-description")
+            .help("when using fasta, keep the description line before the sequence (default is sequence only)")
+        )
+        .arg(Arg::with_name("vcf-key")
+            .long("--vcf-key")
+            .takes_value(true)
+            .help("when using vcf, key on the ID column (falling back to locus for '.') or on chrom:pos:ref:alt")
+            .possible_value("id")
+            .possible_value("locus")
+            .default_value("id")
+        )
+        .arg(Arg::with_name("gff-attribute")
+            .long("--gff-attribute")
+            .takes_value(true)
+            .help("when using gff or gtf, attribute to key features on (features without it are skipped)")
+            .default_value("ID")
+        )
+        .arg(Arg::with_name("dir-follow-symlinks")
+            .long("--dir-follow-symlinks")
+            .help("when using a directory, follow symlinks (default is to skip them)")
+        )
+        .arg(Arg::with_name("dir-exclude")
+            .long("--dir-exclude")
+            .takes_value(true)
+            .help("when using a directory, skip paths matching this glob, may be repeated")
+            .multiple(true)
+            .number_of_values(1)
+        )
+        .arg(Arg::with_name("key-col")
+            .long("--key-col")
+            .takes_value(true)
+            .help("when using parquet or arrow, column to take keys from")
+            .default_value("key")
+        )
+        .arg(Arg::with_name("value-col")
+            .long("--value-col")
+            .takes_value(true)
+            .help("when using parquet or arrow, column to take values from")
+            .default_value("value")
+        )
+        .arg(Arg::with_name("columns")
+            .long("--columns")
+            .takes_value(true)
+            .help("fixed: key and value byte columns, 1-based and inclusive (eg 1-12,13-80 or 1-12,13-)")
+        )
+        .arg(Arg::with_name("xml-record")
+            .long("--xml-record")
+            .takes_value(true)
+            .help("xml: name of the repeated element to store, one record each (eg entry)")
+        )
+        .arg(Arg::with_name("xml-key")
+            .long("--xml-key")
+            .takes_value(true)
+            .help("xml: where the key is in each record: @attr, child/path, child/@attr or . for its own text")
+            .default_value("@id")
+        )
+        .arg(Arg::with_name("proto-desc")
+            .long("--proto-desc")
+            .takes_value(true)
+            .help("protobuf: descriptor set for the messages (from protoc --descriptor_set_out)")
+        )
+        .arg(Arg::with_name("proto-message")
+            .long("--proto-message")
+            .takes_value(true)
+            .help("protobuf: message type in the stream, if the descriptor set has several")
+        )
+        .arg(Arg::with_name("proto-key")
+            .long("--proto-key")
+            .takes_value(true)
+            .help("protobuf: field holding the key, with dots for nested messages (default id)")
+        )
+        .arg(Arg::with_name("record-key")
+            .long("--record-key")
+            .takes_value(true)
+            .help("blocks: header field whose value is the key (default: the first field of each block)")
+        )
+        .arg(Arg::with_name("obo-stanza")
+            .long("--obo-stanza")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .default_value("Term")
+            .help("obo: stanza type to store, keyed by id (repeatable, eg Term and Typedef)")
+        )
+        .arg(Arg::with_name("lmdb-db")
+            .long("--lmdb-db")
+            .takes_value(true)
+            .help("lmdb: read this named database rather than the unnamed one")
+        )
+        .arg(Arg::with_name("scan-pattern")
+            .long("--scan-pattern")
+            .takes_value(true)
+            .help("redis: only snapshot keys matching this SCAN pattern")
+            .default_value("*")
+        )
+        .arg(Arg::with_name("query")
+            .long("--query")
+            .takes_value(true)
+            .help("sql: the query to snapshot, giving the key then the value in each row (INPUT is a postgres:// or mysql:// DSN)")
+        )
+        .arg(Arg::with_name("key-expr")
+            .long("--key-expr")
+            .takes_value(true)
+            .help("rhai script giving each record's new key from key and value, or () to drop it (needs the expr feature)")
+        )
+        .arg(Arg::with_name("value-expr")
+            .long("--value-expr")
+            .takes_value(true)
+            .help("rhai script giving each record's new value from key and value, or () to drop it (needs the expr feature)")
+        )
+        .arg(Arg::with_name("careful")
+            .short("-c")
+            .long("--careful")
+            .help("Use careful settings for building (will take longer but probably result in smaller file)")
+        )
+        .arg(Arg::with_name("page-size")
+            .short("-p")
+            .long("--page-size")
+            .takes_value(true)
+            .help("target page size in bytes (requests will be of this size) (default 32768, careful 16384)")
+            .validator(|v| str_to_u32(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("load-factor")
+            .long("--load-factor")
+            .takes_value(true)
+            .help("target hash-table load factor (requests will be of this size) (default 0.5, careful 0.75)")
+            .validator(|v| str_to_f64(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("heap-wiggle")
+            .long("--heap-wiggle")
+            .takes_value(true)
+            .help("heap-figgle room (requests will be of this size) (default 1.25, careful 1.1)")
+            .validator(|v| str_to_f64(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("min-entries")
             .long("--min-entries")
             .takes_value(true)
             .help("minimum entries per page (default 100)")
@@ -250,40 +1291,508 @@ pub fn make_app() -> App<'static,'static> {
             .possible_value("2")
             .possible_value("4")
         )
+        .arg(Arg::with_name("force")
+            .long("--force")
+            .help("allow OUTPUT to be the same file as INPUT, rebuilding it in place through a temporary file")
+        )
+        .arg(Arg::with_name("strict")
+            .long("--strict")
+            .help("fail the build on duplicate keys (left after any --dup-policy), empty values, or records the format would otherwise skip or key by a fallback (default is to store or skip them): add --validate-utf8 to insist on UTF-8 values")
+        )
+        .arg(Arg::with_name("key-encoding")
+            .long("--key-encoding")
+            .takes_value(true)
+            .help("how keys are written in the input: hex or base64 are decoded to the binary keys they stand for (look them up with the same --key-encoding)")
+            .possible_value("raw")
+            .possible_value("hex")
+            .possible_value("base64")
+            .default_value("raw")
+        )
+        .arg(Arg::with_name("normalize-key")
+            .long("--normalize-key")
+            .takes_value(true)
+            .help("canonicalize every key, may be repeated to apply several in order (look up with the same --normalize-key)")
+            .possible_value("lower")
+            .possible_value("upper")
+            .possible_value("nfc")
+            .possible_value("nfkc")
+            .possible_value("casefold")
+            .possible_value("trim")
+            .multiple(true)
+            .number_of_values(1)
+        )
+        .arg(Arg::with_name("validate-json")
+            .long("--validate-json")
+            .help("every value must be a complete JSON document")
+        )
+        .arg(Arg::with_name("validate-utf8")
+            .long("--validate-utf8")
+            .help("every value must be valid UTF-8")
+        )
+        .arg(Arg::with_name("on-invalid")
+            .long("--on-invalid")
+            .takes_value(true)
+            .help("for values failing --validate-*: error fails the build naming the record, skip leaves them out (counted as dropped)")
+            .possible_value("error")
+            .possible_value("skip")
+            .default_value("error")
+        )
+        .arg(Arg::with_name("value-pipe")
+            .long("--value-pipe")
+            .takes_value(true)
+            .help("pass every value through gzip, base64, json-minify or else this shell command (value on stdin, replacement on stdout), after any --validate-*; may be repeated to apply several in order")
+            .validator(|v| ValuePipe::parse(&v).map(|_| ()))
+            .multiple(true)
+            .number_of_values(1)
+        )
+        .arg(Arg::with_name("long-keys")
+            .long("--long-keys")
+            .takes_value(true)
+            .help("for keys over --max-key-len: reject fails early naming the record, hash stores them as sha256:HEX (lookups need --hash-keys-over)")
+            .possible_value("reject")
+            .possible_value("hash")
+        )
+        .arg(Arg::with_name("max-key-len")
+            .long("--max-key-len")
+            .takes_value(true)
+            .help("longest key in bytes allowed by --long-keys")
+            .default_value("1024")
+            .validator(|v| str_to_u32(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("set-mode")
+            .long("--set-mode")
+            .help("store keys only, for membership tests: every value is empty (or --set-marker), and a repeated key is kept once unless --dup-policy says otherwise")
+            .conflicts_with_all(&["value-pipe","value-fields","value-expr"])
+        )
+        .arg(Arg::with_name("set-marker")
+            .long("--set-marker")
+            .takes_value(true)
+            .help("with --set-mode, the value stored for every key (default empty)")
+            .requires("set-mode")
+        )
+        .arg(Arg::with_name("dup-policy")
+            .long("--dup-policy")
+            .takes_value(true)
+            .help("for keys seen more than once: error fails naming both records, first or last keeps that value, concat[:SEP] joins them all with SEP (default ,)")
+            .validator(|v| DupPolicy::parse(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("precheck-duplicates")
+            .long("--precheck-duplicates")
+            .help("before building, estimate duplicate keys and distinct key count from a quick pass over the input")
+        )
+        .arg(Arg::with_name("precheck-limit")
+            .long("--precheck-limit")
+            .takes_value(true)
+            .help("stop the precheck pass after this many records")
+            .default_value("10000000")
+        )
+        .arg(Arg::with_name("expect-keys")
+            .long("--expect-keys")
+            .takes_value(true)
+            .help("warn if the precheck finds a distinct key count far from this")
+        )
+        .arg(Arg::with_name("fail-if-empty")
+            .long("--fail-if-empty")
+            .help("fail if the input has no records (default is to build an empty file)")
+        )
+        .arg(Arg::with_name("progress")
+            .long("--progress")
+            .help("show the attempt, pass, records and bytes read so far and an ETA for the pass on stderr, when it's a terminal")
+        )
+        .arg(Arg::with_name("max-output-size")
+            .long("--max-output-size")
+            .takes_value(true)
+            .help("if the output would be bigger than this (eg 50G), shard it into OUTPUT.0, OUTPUT.1, ... listed in OUTPUT.manifest.json")
+            .validator(|v| parse_size(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("accounting")
+            .long("--accounting")
+            .takes_value(true)
+            .help("write per-input record counts (read, skipped, stored, ...) as tsv to this file")
+        )
+        .arg(Arg::with_name("error-format")
+            .long("--error-format")
+            .takes_value(true)
+            .help("how to report errors on stderr")
+            .possible_value("text")
+            .possible_value("json")
+            .default_value("text")
+        )
+    }
+
+/* with --progress, lines go out around the progress line rather than through it */
+fn say(progress: Option<&Arc<Progress>>, line: &str) {
+    match progress {
+        Some(progress) => progress.println(line),
+        None => println!("{}",line)
+    }
+}
+
+fn build_file(build_config: &NCDBuildConfig, source: &dyn NCDValueSource, path: &Path, progress: Option<&Arc<Progress>>) {
+    let mut builder = die_on_error(NCDBuild::new(build_config,source,path));
+    let mut attempt = 0;
+    let mut costs = vec![];
+    loop {
+        attempt += 1;
+        set_error_attempt(attempt);
+        if let Some(progress) = progress { progress.start_attempt(attempt); }
+        say(progress,&format!("Attempting to build: {}",builder.describe_attempt()));
+        let start = Instant::now();
+        let success = die_on_error(builder.attempt(|records,time| {
+            say(progress,&format!("  wrote {:.2}M records in {:.1}s",records/1000000,time));
+        }));
+        costs.push(AttemptCost { bytes: file_size(path), seconds: start.elapsed().as_secs_f64() });
+        say(progress,&format!("  {}",builder.result()));
+        if success { break }
+    }
+    if let Some(report) = amplification_report(&costs) {
+        say(progress,&report);
+    }
+}
+
+fn file_size(path: &Path) -> u64 {
+    die_on_error(fs::metadata(path)).len()
+}
+
+/* roughly what ncd adds per record for its hash table and lengths: erring high only costs a
+ * shard more than needed, erring low a rebuild */
+const RECORD_OVERHEAD : u64 = 32;
+
+/* the records and bytes of keys and values in one pass, to size the output before building it */
+fn count_pass(source: &dyn NCDValueSource) -> io::Result<(u64,u64)> {
+    let (mut records,mut bytes) = (0,0);
+    for record in source.iter() {
+        let (key,value) = record?;
+        records += 1;
+        bytes += (key.len() + value.len()) as u64;
+    }
+    Ok((records,bytes))
+}
+
+fn estimate_size(records: u64, bytes: u64) -> u64 {
+    bytes + records*RECORD_OVERHEAD
+}
+
+fn shard_count(size: u64, max: u64) -> usize {
+    (size / max + (size % max != 0) as u64).max(2) as usize
+}
+
+/* the key and size of the biggest record in a spool, to blame for a shard which won't shrink */
+fn largest_record(spool: &Path) -> io::Result<(Vec<u8>,u64)> {
+    let mut largest = (vec![],0);
+    for record in SpoolSource::new(spool).iter() {
+        let (key,value) = record?;
+        let size = (key.len()+value.len()) as u64;
+        if size >= largest.1 { largest = (key,size); }
+    }
+    Ok(largest)
+}
+
+/* one pass over `source`, each record spooled for the shard of `count` it hashes to */
+fn spool_shards(source: &dyn NCDValueSource, count: usize) -> io::Result<Vec<NamedTempFile>> {
+    let mut spools = vec![];
+    let mut spoolers = vec![];
+    for _ in 0..count {
+        let spool = NamedTempFile::new()?;
+        remove_on_exit(spool.path());
+        spoolers.push(Spooler::new(spool.reopen()?));
+        spools.push(spool);
+    }
+    for record in source.iter() {
+        let (key,value) = record?;
+        spoolers[shard_of(&key,count)].add(&key,&value)?;
+    }
+    for spooler in spoolers {
+        spooler.finish()?;
     }
+    Ok(spools)
+}
+
+/* the pipeline runs once, into a spool per shard, and each shard is built from its spool. If
+ * one comes out too big the count doubles and the new spools are split from the old ones, so
+ * the pipeline still isn't run again. A shard of one record, or one no smaller than the shard
+ * it was split from, isn't going to fit however many there are, so that stops the build */
+fn build_sharded(build_config: &NCDBuildConfig, source: &dyn NCDValueSource, output: &Path, size: u64, max: u64, progress: Option<&Arc<Progress>>) -> Manifest {
+    let mut count = shard_count(size,max);
+    say(progress,&format!("Output is about {} bytes, over the maximum of {}: building {} shards",size,max,count));
+    if let Some(progress) = progress { progress.set_stage("spooling shards"); }
+    let mut spools = die_on_error(spool_shards(source,count));
+    let mut last_over = None;
+    loop {
+        let mut shards = vec![];
+        for (index,spool) in spools.iter().enumerate() {
+            let path = shard_path(output,index);
+            set_error_context("build",Some(&*path.to_string_lossy()));
+            let mut spooled : Box<dyn NCDValueSource> = Box::new(SpoolSource::new(spool.path()));
+            if let Some(progress) = progress {
+                progress.set_stage(&format!("shard {} of {}",index+1,count));
+                spooled = Box::new(ProgressSource::new(spooled,progress));
+            }
+            let shard = ShardSource::new(spooled.as_ref(),index,count);
+            build_file(build_config,&shard,&path,progress);
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            let sha256 = die_on_error(sha256_file(&path));
+            shards.push(ShardEntry { index, path: name, size: file_size(&path), records: shard.records(), sha256, samples: shard.samples() });
+            if shards[index].size > max { break; }
+        }
+        if shards.len() == count {
+            return Manifest { shard_count: count, shards };
+        }
+        for index in 0..shards.len() {
+            die_on_error(fs::remove_file(shard_path(output,index)));
+        }
+        let over = &shards[shards.len()-1];
+        let stuck = last_over.map(|(n,index,size)| over.index % n == index && over.size >= size).unwrap_or(false);
+        if over.records <= 1 || stuck {
+            let (key,size) = die_on_error(largest_record(spools[over.index].path()));
+            die_msg(Msg::RecordTooBigForShard,&[&String::from_utf8_lossy(&key),&size,&max]);
+        }
+        last_over = Some((count,over.index,over.size));
+        count *= 2;
+        say(progress,&format!("A shard is over the maximum of {}: splitting into {} shards",max,count));
+        if let Some(progress) = progress { progress.set_stage("splitting shards"); }
+        let mut old : Box<dyn NCDValueSource> = Box::new(ConcatSource::new(spools.iter().map(|spool| {
+            Box::new(SpoolSource::new(spool.path())) as Box<dyn NCDValueSource>
+        }).collect()));
+        if let Some(progress) = progress { old = Box::new(ProgressSource::new(old,progress)); }
+        spools = die_on_error(spool_shards(old.as_ref(),count));
+    }
+}
+
+/* --input PATH=PREFIX splits at the last =, unless the whole thing is a file which exists */
+fn split_input(value: &str) -> (&str,&str) {
+    if Path::new(value).exists() {
+        return (value,"");
+    }
+    value.rsplit_once('=').unwrap_or((value,""))
+}
+
+/* INPUT followed by any --input, each with the prefix for its keys, empty for none. INPUT and
+ * OUTPUT stay a fixed pair of positionals so that options can go between them.
+ */
+fn input_specs<'a>(matches: &'a ArgMatches) -> Vec<(&'a str,&'a str)> {
+    let mut specs : Vec<_> = matches.value_of("INPUT").into_iter().map(|name| (name,"")).collect();
+    specs.extend(matches.values_of("input").into_iter().flatten().map(split_input));
+    specs
+}
+
+fn add_key_prefix(source: Box<dyn NCDValueSource>, prefix: &str) -> Box<dyn NCDValueSource> {
+    if prefix.is_empty() {
+        return source;
+    }
+    let prefix = prefix.as_bytes().to_vec();
+    Box::new(TransformSource::new(source).map_key(Box::new(move |key,_| Ok(Some([&prefix[..],key].concat())))))
+}
+
+/* everything up to where the records of all the inputs are combined */
+fn input_source(input: &Input, format: &Format, prefix: &str, matches: &ArgMatches, tally: &Arc<Tally>) -> Box<dyn NCDValueSource> {
+    let mut source : Box<dyn NCDValueSource> = Box::new(CountingSource::new(die_on_error(format.to_source(&input.path_str(),matches,tally)),tally));
+    source = add_key_decoding(source,matches);
+    source = die_on_error(add_expressions(source,matches,tally));
+    source = add_key_normalization(source,matches);
+    source = add_key_prefix(source,prefix);
+    source = add_set_mode(source,matches);
+    source = add_validators(source,matches,tally);
+    add_value_pipes(source,matches)
+}
 
 fn main() {
     let app = make_app();
-    let matches = app.get_matches();
-    let flat_config = make_flat_config(&matches);
+    let matches = matches_or_die(app.get_matches_safe());
+    exit_if_version("ncd-build",&matches);
+    set_error_format(ErrorFormat::from_cli(matches.value_of("error-format").unwrap()));
     let mut build_config = if matches.is_present("careful") { make_careful_config() } else { NCDBuildConfig::new() };
     modify_build_config(&mut build_config,&matches);
-    let input = matches.value_of("INPUT").unwrap();
-    let input_path = Path::new(input);
-    if !input_path.exists() {
-        die(&format!("File does not exist: {}",input));
+    let specs = input_specs(&matches);
+    let input_names : Vec<&str> = specs.iter().map(|(name,_)| *name).collect();
+    let compression = Compression::from_cli(matches.value_of("compress-in").unwrap());
+    let encoding = matches.value_of("input-encoding").map(|label| die_on_error(parse_encoding(label)));
+    let prepare = make_prepare(&matches);
+    if record_separator(&matches).is_some() && prepare.is_active() {
+        die_msg(Msg::RecordSepWithLines,&[]);
+    }
+    if matches.is_present("quote-aware") && prepare.is_active() {
+        die_msg(Msg::QuoteAwareWithLines,&[]);
     }
+    let mut inputs : Vec<Input> = input_names.iter().map(|name| {
+        set_error_context("input",Some(name));
+        die_on_error(open_input(name,compression,encoding,&prepare,&matches))
+    }).collect();
     let output = matches.value_of("OUTPUT").unwrap();
     let output_path = Path::new(output);
-    if File::create(output_path).is_err() {
-        die(&format!("Cannot create output file: {}",output));
+    set_error_context("output",Some(output));
+    let clobbered = input_names.iter().find(|name| same_file(Path::new(name),output_path));
+    if let Some(input_name) = clobbered {
+        if !matches.is_present("force") {
+            die_msg(Msg::OutputIsInput,&[&output,input_name]);
+        }
     }
-    let format = Format::from_cli(matches.value_of("format").unwrap(),matches.value_of("INPUT").unwrap());
-    let source = die_on_error(format.to_source(&input,&flat_config));
-    let mut builder = die_on_error(NCDBuild::new(&build_config,source.as_ref(),&output_path));
-    loop {
-        println!("Attempting to build: {}",builder.describe_attempt());
-        let success = die_on_error(builder.attempt(|records,time| {
-            println!("  wrote {:.2}M records in {:.1}s",records/1000000,time);
-        }));
-        println!("  {}",builder.result());
-        if success { break }
+    /* in place, the input has to survive the build: build beside it and rename over it at the end */
+    let in_place_file = if clobbered.is_some() {
+        let dir = output_path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let file = NamedTempFile::new_in(dir).unwrap_or_else(|_| die_msg(Msg::CannotCreateBeside,&[&output]));
+        remove_on_exit(file.path());
+        Some(file)
+    } else {
+        if File::create(output_path).is_err() {
+            die_msg(Msg::CannotCreateOutput,&[&output]);
+        }
+        None
+    };
+    let build_path = in_place_file.as_ref().map(|f| f.path()).unwrap_or(output_path);
+    let formats : Vec<Format> = inputs.iter().map(|input| {
+        set_error_context("input",Some(input.name()));
+        let format = Format::from_cli(matches.value_of("format").unwrap(),input);
+        if uses_fields(&matches) && !matches!(format,Format::Flat) {
+            die_msg(Msg::FieldsNeedFlat,&[]);
+        }
+        if uses_row_filter(&matches) && !format.is_line_based() {
+            die_msg(Msg::FilterNeedsLines,&[]);
+        }
+        if matches.is_present("skip-lines") && !format.is_line_based() {
+            die_msg(Msg::SkipNeedsLines,&[]);
+        }
+        if encoding.is_some() && !format.is_line_based() {
+            die_msg(Msg::EncodingNeedsLines,&[]);
+        }
+        if record_separator(&matches).is_some() && !matches!(format,Format::Flat) {
+            die_msg(Msg::RecordSepNeedsFlat,&[]);
+        }
+        format
+    }).collect();
+    /* prepared input has had its line endings fixed already */
+    if !prepare.is_active() && record_separator(&matches).is_none() {
+        for (input,format) in inputs.iter_mut().zip(formats.iter()) {
+            if matches!(format,Format::Flat) {
+                set_error_context("input",Some(input.name()));
+                die_on_error(input.fix_line_endings(&prepare));
+            }
+        }
+    }
+    let tallies : Vec<Arc<Tally>> = inputs.iter().map(|_| Tally::new()).collect();
+    for tally in &tallies {
+        tally.set_strict(matches.is_present("strict"));
+    }
+    let mut sources = vec![];
+    for (((input,format),tally),(_,prefix)) in inputs.iter().zip(formats.iter()).zip(tallies.iter()).zip(specs.iter()) {
+        set_error_context("input",Some(input.name()));
+        sources.push(input_source(input,format,prefix,&matches,tally));
+    }
+    let mut source : Box<dyn NCDValueSource> = if sources.len() == 1 { sources.remove(0) } else { Box::new(ConcatSource::new(sources)) };
+    let all_names = input_names.join(", ");
+    if let Some(policy) = matches.value_of("long-keys") {
+        let policy = if policy == "hash" { LongKeyPolicy::Hash } else { LongKeyPolicy::Reject };
+        let max = die_on_error(str_to_u32(matches.value_of("max-key-len").unwrap())) as usize;
+        source = Box::new(LongKeySource::new(source,max,policy));
+    }
+    if let Some(policy) = dup_policy(&matches) {
+        let mut dup = DupSource::new(source,die_on_error(DupPolicy::parse(policy)));
+        /* with several inputs, a folded record isn't down to any one of them */
+        if tallies.len() == 1 {
+            dup = dup.tally(&tallies[0]);
+        }
+        source = Box::new(dup);
+    }
+    if matches.is_present("strict") {
+        source = Box::new(StrictSource::new(source));
+    }
+    let max_size = matches.value_of("max-output-size").map(|s| die_on_error(parse_size(s)));
+    if matches.is_present("precheck-duplicates") {
+        let limit = die_on_error(str_to_u64(matches.value_of("precheck-limit").unwrap()));
+        let expected = matches.value_of("expect-keys").map(|n| die_on_error(str_to_u64(n)));
+        let check = die_on_error(Precheck::run(source.as_ref(),limit));
+        println!("{}",check.summary());
+        for warning in check.warnings(expected) {
+            println!("{}",warning);
+        }
+    }
+    let progress = if matches.is_present("progress") { Some(Progress::new()) } else { None };
+    if let Some(progress) = &progress {
+        source = Box::new(ProgressSource::new(source,progress));
+    }
+    set_error_context("build",Some(output));
+    /* with a maximum, a counting pass sizes the output first, so an output which is going to be
+     * too big is sharded from the start rather than built whole and then again in pieces */
+    let estimate = max_size.map(|_| {
+        if let Some(progress) = &progress { progress.set_stage("counting"); }
+        let (records,bytes) = die_on_error(count_pass(source.as_ref()));
+        estimate_size(records,bytes)
+    });
+    let over = |size: u64| max_size.map(|max| size > max).unwrap_or(false);
+    let shard_size = match estimate {
+        Some(estimate) if over(estimate) => Some(estimate),
+        _ => {
+            if let Some(progress) = &progress { progress.set_stage(""); }
+            build_file(&build_config,source.as_ref(),build_path,progress.as_ref());
+            /* the estimate was low: the file's size is a better one */
+            Some(file_size(build_path)).filter(|size| over(*size))
+        }
+    };
+    /* after filtering, as of the pass just made: an expression which drops everything leaves
+     * nothing to build from */
+    if tallies.iter().all(|tally| tally.stored() == 0) {
+        if matches.is_present("fail-if-empty") {
+            if build_path.exists() {
+                die_on_error(fs::remove_file(build_path));
+            }
+            die_msg(Msg::NoRecords,&[&all_names]);
+        }
+        say(progress.as_ref(),&warning(Msg::EmptyBuild,&[&all_names]));
+    }
+    if let (Some(max_size),Some(size)) = (max_size,shard_size) {
+        if build_path.exists() {
+            die_on_error(fs::remove_file(build_path));
+        }
+        let manifest = build_sharded(&build_config,source.as_ref(),output_path,size,max_size,progress.as_ref());
+        let manifest_path = manifest_path(output_path);
+        set_error_context("output",Some(&*manifest_path.to_string_lossy()));
+        die_on_error(manifest.write(&manifest_path));
+        say(progress.as_ref(),&format!("Wrote {} shards, listed in {}",manifest.shards.len(),manifest_path.display()));
+    }
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    if let Some(file) = in_place_file {
+        if file.path().exists() {
+            die_on_error(file.persist(output_path).map_err(|e| e.error));
+        }
+    }
+    if let Some(accounting) = matches.value_of("accounting") {
+        set_error_context("output",Some(accounting));
+        let rows : Vec<AccountingRow> = inputs.iter().zip(formats.iter()).zip(tallies.into_iter()).map(|((input,format),tally)| {
+            /* only one-record-per-line input has lines to compare the records with: for anything
+             * else a line count would be meaningless, so the records as read are all there is */
+            let by_line = format.is_line_based() && record_separator(&matches).is_none();
+            let lines = if by_line { die_on_error(count_lines(input.path())) } else { tally.ingested() };
+            AccountingRow { input: input.name().to_string(), lines, tally }
+        }).collect();
+        die_on_error(File::create(accounting).and_then(|file| write_accounting(file,&rows)));
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{looks_like_utf8, make_app, make_careful_config, make_flat_config, modify_build_config};
+    use std::path::Path;
+
+    use clap::ArgMatches;
+    use ncd::NCDFlatConfig;
+    use ncd_tools::{pipe::ValuePipe, prepare::{CommentMode, Crlf}, resolve::KeyNormalize, sources::{fixed::Span, rdb::RdbOther, vcf::VcfKey, xml::KeyPath, yaml::YamlNested}, version::INPUT_FORMATS};
+    use crate::{dup_policy, field_name, header_index, input_specs, key_index, looks_like_utf8, make_app, make_blocks_config, make_careful_config, make_csv_config, make_dir_config, make_fixed_config, make_flat_config, make_gff_config, make_http_json_config, make_json_config, make_jsonl_config, make_key_normalization, make_obo_config, make_prepare, make_protobuf_config, make_rdb_config, make_records_config, make_value_pipes, make_vcf_config, make_xml_config, make_yaml_config, modify_build_config, record_separator, Format};
+    #[cfg(feature="avro")]
+    use crate::make_avro_config;
+    #[cfg(feature="lmdb")]
+    use crate::make_lmdb_config;
+    #[cfg(feature="parquet")]
+    use crate::make_parquet_config;
+    #[cfg(feature="redis")]
+    use crate::make_redis_config;
+    #[cfg(feature="sql")]
+    use crate::make_sql_config;
+
+    /* the key index of a numbered field, which needs no file to find */
+    fn flat_config(matches: &ArgMatches) -> NCDFlatConfig {
+        make_flat_config(matches,key_index(Path::new("unused"),matches).unwrap())
+    }
 
     #[test]
     fn test_looks_like_utf8() {
@@ -304,13 +1813,24 @@ mod test {
         assert_eq!(looks_like_utf8(&[0x21,0xC0,0x21,0xF3,0x90,0x90,0x90]),false);
     }
 
+    #[test]
+    fn test_input_formats() {
+        for name in INPUT_FORMATS {
+            assert!(Format::from_name(name).is_some(),"{}",name);
+        }
+        assert!(Format::from_name("guess").is_none());
+        assert!(Format::from_name("gdbm").is_none());
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","-t","gdbm"].iter()).is_err());
+    }
+
     // XXX pr gdbm print
     // XXX verbose
     #[test]
     fn test_flat_config() {
         let app = make_app();
         let matches = app.get_matches_from(["file","x","y"].iter());
-        let config = make_flat_config(&matches);
+        let config = flat_config(&matches);
         assert_eq!(1,*config.get_index());
         assert_eq!(None,*config.get_separator());
         assert_eq!(true,*config.get_skip_blank());
@@ -327,7 +1847,7 @@ mod test {
             "-I",
             "-T"
         ].iter());
-        let config = make_flat_config(&matches);
+        let config = flat_config(&matches);
         assert_eq!(2,*config.get_index());
         assert_eq!(Some("\t".to_string()),*config.get_separator());
         assert_eq!(false,*config.get_skip_blank());
@@ -336,6 +1856,435 @@ mod test {
         assert_eq!(false,*config.get_trim_tail());        
     }
 
+    #[test]
+    fn test_comments() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-C","#"].iter());
+        assert_eq!(Some("#".to_string()),*flat_config(&matches).get_comment_char());
+        assert_eq!(false,make_prepare(&matches).is_active());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-C","#","--comment-mode","anywhere"].iter());
+        assert_eq!(true,*flat_config(&matches).get_inline_comments());
+        assert_eq!(false,make_prepare(&matches).is_active());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-C","#","-C",";"].iter());
+        let config = flat_config(&matches);
+        assert_eq!(None,*config.get_comment_char());
+        assert_eq!(false,*config.get_inline_comments());
+        let prepare = make_prepare(&matches);
+        assert_eq!(vec!["#".to_string(),";".to_string()],*prepare.get_comments());
+        assert_eq!(CommentMode::Start,*prepare.get_comment_mode());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","--comment","#","--comment-mode","indented"].iter());
+        assert_eq!(None,*flat_config(&matches).get_comment_char());
+        assert_eq!(CommentMode::Indented,*make_prepare(&matches).get_comment_mode());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-C","#","--value-fields","3"].iter());
+        assert_eq!(None,*flat_config(&matches).get_comment_char());
+        assert_eq!(true,make_prepare(&matches).is_active());
+    }
+
+    #[test]
+    fn test_row_filter() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y"].iter());
+        assert!(make_prepare(&matches).get_filter().is_none());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","--filter","^chr","--where","field3 != \".\"","--where","field2 > 0"].iter());
+        let prepare = make_prepare(&matches);
+        let filter = prepare.get_filter().as_ref().unwrap();
+        assert_eq!(1,filter.get_patterns().len());
+        assert_eq!(2,filter.get_predicates().len());
+        assert!(filter.keep(b"chr1 5 A"));
+        assert!(!filter.keep(b"chr1 0 A"));
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--where","third != ."].iter()).is_err());
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--filter","("].iter()).is_err());
+    }
+
+    #[test]
+    fn test_header_field() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file,b"# made today\n\nid\tchrom\tref\nrs1\tchr1\tA\n").unwrap();
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","--header","-f","ref","-d","\t","-C","#"].iter());
+        assert_eq!(Some("ref"),field_name(&matches));
+        assert_eq!(3,header_index(file.path(),"ref",&matches).unwrap());
+        assert_eq!(3,key_index(file.path(),&matches).unwrap());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","--header","-f","ref","--value-fields","1"].iter());
+        assert_eq!(1,key_index(file.path(),&matches).unwrap());
+        let prepare = make_prepare(&matches);
+        assert_eq!(Some("ref".to_string()),*prepare.get_fields().as_ref().unwrap().get_key_name());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","csv","--header","-f","ref","--skip-lines","2","--crlf","strip"].iter());
+        assert_eq!(Some("ref".to_string()),*make_csv_config(&matches).get_key_column());
+        assert_eq!(2,*make_prepare(&matches).get_skip_lines());
+        assert_eq!(Crlf::Strip,*make_prepare(&matches).get_crlf());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-f","2"].iter());
+        assert_eq!(None,field_name(&matches));
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--skip-lines","many"].iter()).is_err());
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--long-keys","hash","--max-key-len","lots"].iter()).is_err());
+    }
+
+    #[test]
+    fn test_record_separator() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y"].iter());
+        assert_eq!(None,record_separator(&matches));
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-0","-f","2","-d",","].iter());
+        assert_eq!(Some(0),record_separator(&matches));
+        let config = make_records_config(&matches,0,key_index(Path::new("unused"),&matches).unwrap());
+        assert_eq!(2,*config.get_index());
+        assert_eq!(Some(",".to_string()),*config.get_separator());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","--record-separator","\\t"].iter());
+        assert_eq!(Some(b'\t'),record_separator(&matches));
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--record-separator","ab"].iter()).is_err());
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","-0","--record-separator",";"].iter()).is_err());
+    }
+
+    #[test]
+    fn test_set_mode() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y"].iter());
+        assert_eq!(None,dup_policy(&matches));
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","--set-mode"].iter());
+        assert_eq!(Some("first"),dup_policy(&matches));
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","--set-mode","--dup-policy","error"].iter());
+        assert_eq!(Some("error"),dup_policy(&matches));
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--set-marker","1"].iter()).is_err());
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--set-mode","--value-pipe","gzip"].iter()).is_err());
+    }
+
+    #[test]
+    fn test_value_pipes() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y"].iter());
+        assert!(make_value_pipes(&matches).is_empty());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","--value-pipe","json-minify","--value-pipe","xz -c"].iter());
+        assert_eq!(vec![ValuePipe::JsonMinify,ValuePipe::Command("xz -c".to_string())],make_value_pipes(&matches));
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--value-pipe"," "].iter()).is_err());
+    }
+
+    #[test]
+    fn test_input_specs() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y"].iter());
+        assert_eq!(vec![("x","")],input_specs(&matches));
+        assert_eq!(Some("y"),matches.value_of("OUTPUT"));
+        let app = make_app();
+        let matches = app.get_matches_from(["file","b=c","-t","csv","y","--input","d"].iter());
+        assert_eq!(vec![("b=c",""),("d","")],input_specs(&matches));
+        assert_eq!(Some("y"),matches.value_of("OUTPUT"));
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","--input","genes.tsv=gene:","--input","a=b.tsv=tx:","y"].iter());
+        assert_eq!(vec![("x",""),("genes.tsv","gene:"),("a=b.tsv","tx:")],input_specs(&matches));
+        assert_eq!(Some("y"),matches.value_of("OUTPUT"));
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","y"].iter()).is_err());
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","--input","x","y"].iter()).is_err());
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","a","b","y"].iter()).is_err());
+    }
+
+    #[test]
+    fn test_dup_policy() {
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--dup-policy","concat:;"].iter()).is_ok());
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--dup-policy","newest"].iter()).is_err());
+    }
+
+    #[test]
+    fn test_key_normalization() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y"].iter());
+        assert!(make_key_normalization(&matches).is_empty());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","--normalize-key","trim","--normalize-key","lower"].iter());
+        assert_eq!(vec![KeyNormalize::Trim,KeyNormalize::Lower],make_key_normalization(&matches));
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--normalize-key","title"].iter()).is_err());
+    }
+
+    #[test]
+    fn test_value_fields() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-f","2"].iter());
+        assert_eq!(2,*flat_config(&matches).get_index());
+        assert!(make_prepare(&matches).get_fields().is_none());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-f","2","--value-fields","3,5-7","--value-delimiter","|"].iter());
+        assert_eq!(1,*flat_config(&matches).get_index());
+        assert_eq!("|",make_prepare(&matches).get_fields().as_ref().unwrap().get_joiner());
+        let app = make_app();
+        assert!(app.get_matches_from_safe(["file","x","y","--value-fields","0"].iter()).is_err());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","--key-fields","1,2","--key-join",":"].iter());
+        assert_eq!(1,*flat_config(&matches).get_index());
+        let prepare = make_prepare(&matches);
+        let fields = prepare.get_fields().as_ref().unwrap();
+        assert_eq!(":",fields.get_key_join());
+        assert!(fields.get_value().is_none());
+    }
+
+    #[test]
+    fn test_csv_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","csv"].iter());
+        let config = make_csv_config(&matches);
+        assert_eq!(1,*config.get_index());
+        assert_eq!(None,*config.get_key_column());
+        assert_eq!(false,*config.get_header());
+        assert_eq!(b',',*config.get_delimiter());
+        assert_eq!(b'"',*config.get_quote());
+        assert_eq!(None,*config.get_escape());
+        let app = make_app();
+        let matches = app.get_matches_from([
+            "file","x","y",
+            "-t","csv",
+            "-f","3",
+            "-d",";",
+            "--header",
+            "--key-column","id",
+            "--quote","'",
+            "--escape","\\",
+        ].iter());
+        let config = make_csv_config(&matches);
+        assert_eq!(3,*config.get_index());
+        assert_eq!(Some("id".to_string()),*config.get_key_column());
+        assert_eq!(true,*config.get_header());
+        assert_eq!(b';',*config.get_delimiter());
+        assert_eq!(b'\'',*config.get_quote());
+        assert_eq!(Some(b'\\'),*config.get_escape());
+    }
+
+    #[test]
+    fn test_json_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","json"].iter());
+        assert_eq!(false,*make_json_config(&matches).get_compact());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","json","--json-compact"].iter());
+        assert_eq!(true,*make_json_config(&matches).get_compact());
+    }
+
+    #[test]
+    fn test_jsonl_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","jsonl"].iter());
+        let config = make_jsonl_config(&matches);
+        assert_eq!("id",*config.get_key_path());
+        assert_eq!(None,*config.get_value_path());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","jsonl","--key-path","gene.id","--value-path","gene"].iter());
+        let config = make_jsonl_config(&matches);
+        assert_eq!("gene.id",*config.get_key_path());
+        assert_eq!(Some("gene".to_string()),*config.get_value_path());
+    }
+
+    #[test]
+    #[cfg(feature="avro")]
+    fn test_avro_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","avro"].iter());
+        let config = make_avro_config(&matches);
+        assert_eq!("id",*config.get_key_path());
+        assert_eq!(None,*config.get_value_path());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","avro","--key-path","gene.id","--value-path","seq"].iter());
+        let config = make_avro_config(&matches);
+        assert_eq!("gene.id",*config.get_key_path());
+        assert_eq!(Some("seq".to_string()),*config.get_value_path());
+    }
+
+    #[test]
+    fn test_vcf_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","vcf"].iter());
+        assert_eq!(VcfKey::Id,*make_vcf_config(&matches).get_key());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","vcf","--vcf-key","locus"].iter());
+        assert_eq!(VcfKey::Locus,*make_vcf_config(&matches).get_key());
+    }
+
+    #[test]
+    fn test_gff_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","gff"].iter());
+        assert_eq!("ID",make_gff_config(&matches).get_attribute());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","gff","--gff-attribute","gene_id"].iter());
+        assert_eq!("gene_id",make_gff_config(&matches).get_attribute());
+    }
+
+    #[test]
+    fn test_dir_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","dir"].iter());
+        let config = make_dir_config(&matches);
+        assert!(!*config.get_follow_symlinks());
+        assert!(config.get_exclude().is_empty());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","dir","--dir-follow-symlinks","--dir-exclude","*.tmp","--dir-exclude",".git"].iter());
+        let config = make_dir_config(&matches);
+        assert!(*config.get_follow_symlinks());
+        assert_eq!(vec!["*.tmp".to_string(),".git".to_string()],*config.get_exclude());
+    }
+
+    #[test]
+    #[cfg(feature="parquet")]
+    fn test_parquet_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","parquet"].iter());
+        let config = make_parquet_config(&matches);
+        assert_eq!("key",config.get_key_col());
+        assert_eq!("value",config.get_value_col());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","parquet","--key-col","gene_id","--value-col","symbol"].iter());
+        let config = make_parquet_config(&matches);
+        assert_eq!("gene_id",config.get_key_col());
+        assert_eq!("symbol",config.get_value_col());
+    }
+
+    #[test]
+    fn test_yaml_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","yaml"].iter());
+        assert_eq!(YamlNested::Json,*make_yaml_config(&matches).get_nested());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","yaml","--yaml-nested","yaml"].iter());
+        assert_eq!(YamlNested::Yaml,*make_yaml_config(&matches).get_nested());
+    }
+
+    #[test]
+    fn test_http_json_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","https://x","y","-t","http-json"].iter());
+        let config = make_http_json_config(&matches);
+        assert_eq!("",config.get_items_path());
+        assert_eq!(None,*config.get_next_path());
+        assert_eq!("id",config.get_key_path());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","https://x","y","-t","http-json","--items-path",".items","--next-path",".next","--key-path",".gene_id"].iter());
+        let config = make_http_json_config(&matches);
+        assert_eq!(".items",config.get_items_path());
+        assert_eq!(Some(".next".to_string()),*config.get_next_path());
+        assert_eq!(".gene_id",config.get_key_path());
+    }
+
+    #[test]
+    fn test_xml_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","xml","--xml-record","entry"].iter());
+        let config = make_xml_config(&matches);
+        assert_eq!("entry",config.get_record());
+        assert_eq!(KeyPath::parse("@id").unwrap(),*config.get_key());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","xml","--xml-record","entry","--xml-key","accession"].iter());
+        assert_eq!(KeyPath::parse("accession").unwrap(),*make_xml_config(&matches).get_key());
+    }
+
+    #[test]
+    fn test_protobuf_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","protobuf","--proto-desc","genes.pb"].iter());
+        let config = make_protobuf_config(&matches);
+        assert_eq!(Path::new("genes.pb"),config.get_descriptors());
+        assert_eq!(None,*config.get_message());
+        assert_eq!("id",config.get_key());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","protobuf","--proto-desc","genes.pb","--proto-message","Gene","--proto-key","locus.start"].iter());
+        let config = make_protobuf_config(&matches);
+        assert_eq!(Some("Gene".to_string()),*config.get_message());
+        assert_eq!("locus.start",config.get_key());
+    }
+
+    #[test]
+    fn test_blocks_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","blocks"].iter());
+        assert_eq!("",make_blocks_config(&matches).get_record_key());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","blocks","--record-key","Package"].iter());
+        assert_eq!("Package",make_blocks_config(&matches).get_record_key());
+    }
+
+    #[test]
+    fn test_obo_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","go.obo","y"].iter());
+        assert_eq!(&vec!["Term".to_string()],make_obo_config(&matches).get_stanzas());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","go.obo","y","--obo-stanza","Term","--obo-stanza","Typedef"].iter());
+        assert_eq!(&vec!["Term".to_string(),"Typedef".to_string()],make_obo_config(&matches).get_stanzas());
+    }
+
+    #[test]
+    fn test_fixed_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","fixed","--columns","1-12,13-80"].iter());
+        let config = make_fixed_config(&matches);
+        assert_eq!(Span::parse("1-12").unwrap(),*config.get_key());
+        assert_eq!(Span::parse("13-80").unwrap(),*config.get_value());
+    }
+
+    #[test]
+    #[cfg(feature="lmdb")]
+    fn test_lmdb_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","lmdb"].iter());
+        assert_eq!(None,*make_lmdb_config(&matches).get_db());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","lmdb","--lmdb-db","genes"].iter());
+        assert_eq!(Some("genes".to_string()),*make_lmdb_config(&matches).get_db());
+    }
+
+    #[test]
+    fn test_rdb_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","rdb"].iter());
+        assert_eq!(RdbOther::Skip,*make_rdb_config(&matches).get_other());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","x","y","-t","rdb","--rdb-other","json"].iter());
+        assert_eq!(RdbOther::Json,*make_rdb_config(&matches).get_other());
+    }
+
+    #[test]
+    #[cfg(feature="redis")]
+    fn test_redis_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","redis://localhost","y"].iter());
+        assert_eq!("*",make_redis_config(&matches).get_pattern());
+        let app = make_app();
+        let matches = app.get_matches_from(["file","redis://localhost","y","-t","redis","--scan-pattern","gene:*"].iter());
+        assert_eq!("gene:*",make_redis_config(&matches).get_pattern());
+    }
+
+    #[test]
+    #[cfg(feature="sql")]
+    fn test_sql_config() {
+        let app = make_app();
+        let matches = app.get_matches_from(["file","postgres://localhost/genes","y","--query","SELECT id, name FROM gene"].iter());
+        assert_eq!("SELECT id, name FROM gene",make_sql_config(&matches).get_query());
+        assert_eq!(1000,make_sql_config(&matches).get_batch());
+    }
+
     #[test]
     fn test_build_config() {
         let config = make_careful_config();
@@ -1,27 +1,32 @@
-use clap::{App, Arg, ArgMatches};
-use std::{fmt::Display, fs::File, io::{self, Write}, path::Path, process, time::Duration};
+use clap::{App, AppSettings, Arg, ArgMatches};
+use std::{fs::File, io::{self, Read, Seek, Write}, path::Path, process, time::Duration};
+use serde_json::Value;
 use ncd::{CurlConfig, CurlNCDReadAccessor, NCDReader, NCDReadAccessor, StdNCDReadAccessor};
-
-fn die<E: Display>(value: E) -> ! {
-    eprintln!("{}",value);
-    process::exit(1);
-}
-
-fn die_on_error<T,E: Display>(value: Result<T,E>) -> T {
-    match value {
-        Ok(v) => v,
-        Err(e) => die(e)
-    }
-}
+use ncd_tools::archive::{locate_tar_member, locate_zip_member, split_archive_path, HttpFile, Window};
+use ncd_tools::shard::{shard_of, Manifest};
+use ncd_tools::resolve::{resolve, AliasResolver, DirectResolver, KeyNormalize, KeyResolver, LongKeyResolver, NormalizingResolver};
+use ncd_tools::encoding::KeyEncoding;
+use ncd_tools::messages::Msg;
+use ncd_tools::error::{die, die_msg, die_on_error, matches_or_die, set_error_context, set_error_format, ErrorFormat};
+use ncd_tools::version::{exit_if_version, version_args};
 
 enum Source {
     File,
-    Http
+    Http,
+    Zip,
+    Tar,
+    Manifest
 }
 
 fn guess_source(path: &str) -> Source {
-    if path.contains("//") {
+    if path.to_lowercase().contains(".zip!") {
+        Source::Zip
+    } else if path.to_lowercase().contains(".tar!") {
+        Source::Tar
+    } else if path.contains("//") {
         Source::Http
+    } else if path.to_lowercase().ends_with(".manifest.json") {
+        Source::Manifest
     } else {
         Source::File
     }
@@ -32,16 +37,19 @@ impl Source {
         match arg {
             Some("file") => Source::File,
             Some("http") => Source::Http,
+            Some("zip") => Source::Zip,
+            Some("tar") => Source::Tar,
+            Some("manifest") => Source::Manifest,
             _ => guess_source(path)
         }
     }
 
-    fn make_accessor(&self, path: &str, curl_config: &CurlConfig) -> io::Result<Box<dyn NCDReadAccessor>> {
+    fn make_accessor(&self, path: &str, curl_config: &CurlConfig, connect_timeout: Option<Duration>) -> io::Result<Box<dyn NCDReadAccessor>> {
         Ok(match self {
-            Source::File => {
+            Source::File | Source::Manifest => {
                 let file_path = Path::new(path);
                 if !file_path.exists() {
-                   die(format!("No such file: {}",path)); 
+                   die_msg(Msg::NoSuchFile,&[&path]); 
                 }        
                 let file = File::open(file_path)?;
                 Box::new(StdNCDReadAccessor::new(file)?)
@@ -49,45 +57,202 @@ impl Source {
             Source::Http => {
                 // TODO configurable
                 Box::new(CurlNCDReadAccessor::new(curl_config,path)?)
+            },
+            Source::Zip | Source::Tar => {
+                let (archive,member) = match split_archive_path(path) {
+                    Some(parts) => parts,
+                    None => die_msg(Msg::ExpectedMember,&[&path])
+                };
+                if archive.contains("//") {
+                    let mut input = HttpFile::open(archive,connect_timeout)?;
+                    let (start,len) = self.locate_member(&mut input,archive,member)?;
+                    Box::new(StdNCDReadAccessor::new(Window::new(input,start,len)?)?)
+                } else {
+                    let archive_path = Path::new(archive);
+                    if !archive_path.exists() {
+                       die_msg(Msg::NoSuchFile,&[&archive]);
+                    }
+                    let mut input = File::open(archive_path)?;
+                    let (start,len) = self.locate_member(&mut input,archive,member)?;
+                    Box::new(StdNCDReadAccessor::new(Window::new(input,start,len)?)?)
+                }
             }
         })
     }
+
+    fn locate_member<R: Read+Seek>(&self, input: R, archive: &str, member: &str) -> io::Result<(u64,u64)> {
+        match self {
+            Source::Zip => locate_zip_member(input,archive,member),
+            _ => locate_tar_member(input,archive,member)
+        }
+    }
 }
 
 fn str_to_u32(s: &str) -> Result<u32,String> {
     s.parse::<u32>().map_err(|e| format!("Invalid integer: {}",e))
 }
 
+/// A step of a jq-style path: `.name`, `."any name"`, `["any name"]` or `[N]` (negative counting
+/// from the end).
+#[derive(Debug,PartialEq)]
+enum Step {
+    Field(String),
+    Index(i64)
+}
+
+fn parse_quoted(chars: &[char], pos: &mut usize, path: &str) -> Result<String,String> {
+    let mut out = String::new();
+    *pos += 1;
+    while let Some(c) = chars.get(*pos) {
+        *pos += 1;
+        match c {
+            '"' => { return Ok(out); },
+            '\\' => { out.push(*chars.get(*pos).ok_or_else(|| format!("Invalid path (unfinished escape): {}",path))?); *pos += 1; },
+            c => { out.push(*c); }
+        }
+    }
+    Err(format!("Invalid path (unfinished quote): {}",path))
+}
+
+fn parse_extract(path: &str) -> Result<Vec<Step>,String> {
+    let chars : Vec<char> = path.trim().chars().collect();
+    if chars.first() != Some(&'.') {
+        return Err(format!("Invalid path (should start with .): {}",path));
+    }
+    let mut out = vec![];
+    let mut pos = 0;
+    while pos < chars.len() {
+        match chars[pos] {
+            '.' if chars.get(pos+1) == Some(&'"') => {
+                pos += 1;
+                out.push(Step::Field(parse_quoted(&chars,&mut pos,path)?));
+            },
+            '.' => {
+                pos += 1;
+                let start = pos;
+                while pos < chars.len() && chars[pos] != '.' && chars[pos] != '[' { pos += 1; }
+                let name : String = chars[start..pos].iter().collect();
+                if !name.is_empty() {
+                    out.push(Step::Field(name));
+                } else if start > 1 || chars.get(pos) == Some(&'.') {
+                    return Err(format!("Invalid path (empty field name): {}",path));
+                }
+            },
+            '[' if chars.get(pos+1) == Some(&'"') => {
+                pos += 1;
+                out.push(Step::Field(parse_quoted(&chars,&mut pos,path)?));
+                if chars.get(pos) != Some(&']') { return Err(format!("Invalid path (expected ]): {}",path)); }
+                pos += 1;
+            },
+            '[' => {
+                let end = chars[pos..].iter().position(|c| *c == ']').ok_or_else(|| format!("Invalid path (expected ]): {}",path))? + pos;
+                let index : String = chars[pos+1..end].iter().collect();
+                out.push(Step::Index(index.trim().parse().map_err(|_| format!("Invalid path (bad index {}): {}",index,path))?));
+                pos = end+1;
+            },
+            _ => { return Err(format!("Invalid path: {}",path)); }
+        }
+    }
+    Ok(out)
+}
+
+fn extract<'a>(value: &'a Value, path: &[Step]) -> Option<&'a Value> {
+    let mut here = value;
+    for step in path {
+        here = match (here,step) {
+            (Value::Object(map),Step::Field(name)) => map.get(name)?,
+            (Value::Array(list),Step::Index(index)) => {
+                let index = if *index < 0 { list.len() as i64 + index } else { *index };
+                if index < 0 { return None; }
+                list.get(index as usize)?
+            },
+            _ => { return None; }
+        };
+    }
+    Some(here)
+}
+
+/* None if the value isn't JSON and there's no path which needs it to be */
+fn render_json(value: &[u8], pretty: bool, path: Option<&[Step]>) -> Result<Option<String>,String> {
+    let json : Value = match serde_json::from_slice(value) {
+        Ok(json) => json,
+        Err(_) if path.is_none() => { return Ok(None); },
+        Err(e) => { return Err(format!("Value is not JSON, so can't --extract from it: {}",e)); }
+    };
+    let json = match path {
+        Some(path) => extract(&json,path).ok_or_else(|| "Nothing at the --extract path in the value".to_string())?,
+        None => &json
+    };
+    let text = if pretty { serde_json::to_string_pretty(json) } else { serde_json::to_string(json) };
+    Ok(Some(text.map_err(|e| e.to_string())? + "\n"))
+}
+
+fn make_connect_timeout(matches: &ArgMatches) -> Option<Duration> {
+    matches.value_of("timeout").map(|timeout| Duration::from_millis(die_on_error(str_to_u32(timeout)) as u64))
+}
+
 fn make_curl_config(matches: &ArgMatches) -> CurlConfig {
     let mut config = CurlConfig::new();
-    if let Some(timeout) = matches.value_of("timeout") {
-        let timeout = die_on_error(str_to_u32(timeout));
-        config = config.connect_timeout(Duration::from_millis(timeout as u64));
+    if let Some(timeout) = make_connect_timeout(matches) {
+        config = config.connect_timeout(timeout);
     }
     config
 }
 
+/* --normalize and --casefold are shorthand for the first steps; any --normalize-key follow */
+fn make_key_normalization(matches: &ArgMatches) -> Vec<KeyNormalize> {
+    let mut steps : Vec<_> = matches.value_of("normalize").and_then(KeyNormalize::from_cli).into_iter().collect();
+    if matches.is_present("casefold") {
+        steps.push(KeyNormalize::Casefold);
+    }
+    steps.extend(matches.values_of("normalize-key").into_iter().flatten().filter_map(KeyNormalize::from_cli));
+    steps
+}
+
+/* normalization goes outside aliases so that alias targets get it too, and hashing outermost.
+ * Without `aliases` it's the keys KEY itself stands for, for telling when an alias matched */
+fn make_resolver(matches: &ArgMatches, aliases: bool) -> Box<dyn KeyResolver> {
+    let mut resolver : Box<dyn KeyResolver> = Box::new(DirectResolver);
+    if let Some(aliases) = matches.value_of("alias-file").filter(|_| aliases) {
+        set_error_context("open",Some(aliases));
+        resolver = Box::new(die_on_error(AliasResolver::new(resolver).load(Path::new(aliases))));
+    }
+    let steps = make_key_normalization(matches);
+    if !steps.is_empty() {
+        resolver = Box::new(NormalizingResolver::new(resolver,&steps));
+    }
+    if let Some(max) = matches.value_of("hash-keys-over") {
+        resolver = Box::new(LongKeyResolver::new(resolver,die_on_error(str_to_u32(max)) as usize));
+    }
+    resolver
+}
+
 pub fn make_app() -> App<'static,'static> {
-    App::new("ncd file lookcup").version("0.0.1")
+    App::new("ncd file lookcup").version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::DisableVersion)
+        .args(&version_args())
         .author("Dan Sheppard <dan@ebi.ac.uk")
         .about("Looks up data in ncd files (locally or remotely)")
         .arg(Arg::with_name("KEY")
             .help("input file to convert")
             .index(1)
-            .required(true)
+            .required_unless("version")
         )
         .arg(Arg::with_name("PATH")
             .help("output file to create")
             .index(2)
-            .required(true)
+            .required_unless("version")
         )
         .arg(Arg::with_name("source")
             .short("-s")
             .long("--source")
-            .help("specify source type (optional: will guess)")
+            .help("specify source type, zip and tar taking ARCHIVE!MEMBER paths (ARCHIVE a file or URL), manifest a sharded build's .manifest.json (optional: will guess)")
             .takes_value(true)
             .possible_value("file")
             .possible_value("http")
+            .possible_value("zip")
+            .possible_value("tar")
+            .possible_value("manifest")
             .possible_value("guess")
             .default_value("guess")
         )
@@ -97,22 +262,177 @@ pub fn make_app() -> App<'static,'static> {
             .help("specify timeout for remote methods (ms)")
             .takes_value(true)
         )
+        .arg(Arg::with_name("key-encoding")
+            .long("--key-encoding")
+            .help("KEY is written as hex or base64 of the binary key, to match a file built with the same --key-encoding")
+            .takes_value(true)
+            .possible_value("raw")
+            .possible_value("hex")
+            .possible_value("base64")
+            .default_value("raw")
+        )
+        .arg(Arg::with_name("pretty")
+            .long("--pretty")
+            .help("if the value is JSON, pretty-print it (other values are output as they are)")
+        )
+        .arg(Arg::with_name("extract")
+            .long("--extract")
+            .help("output only this part of a JSON value, as a jq-style path (eg .transcripts[0].id)")
+            .takes_value(true)
+            .validator(|v| parse_extract(&v).map(|_| ()))
+        )
+        .arg(Arg::with_name("alias-file")
+            .short("-a")
+            .long("--alias-file")
+            .help("tsv of FROM<tab>TO aliases to try if KEY itself is missing, reporting on stderr which matched")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("normalize")
+            .long("--normalize")
+            .help("unicode-normalize KEY before lookup (the same as --normalize-key nfc or nfkc, applied first)")
+            .takes_value(true)
+            .possible_value("nfc")
+            .possible_value("nfkc")
+        )
+        .arg(Arg::with_name("casefold")
+            .long("--casefold")
+            .help("case-fold KEY after any --normalize (the same as --normalize-key casefold), to match a file built with --normalize-key casefold")
+        )
+        .arg(Arg::with_name("normalize-key")
+            .long("--normalize-key")
+            .takes_value(true)
+            .help("canonicalize KEY before lookup, to match a file built with the same --normalize-key, may be repeated")
+            .possible_value("lower")
+            .possible_value("upper")
+            .possible_value("nfc")
+            .possible_value("nfkc")
+            .possible_value("casefold")
+            .possible_value("trim")
+            .multiple(true)
+            .number_of_values(1)
+        )
+        .arg(Arg::with_name("hash-keys-over")
+            .long("--hash-keys-over")
+            .help("look up keys over this many bytes by their hash, to match a file built with --long-keys hash")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("error-format")
+            .long("--error-format")
+            .takes_value(true)
+            .help("how to report errors on stderr")
+            .possible_value("text")
+            .possible_value("json")
+            .default_value("text")
+        )
     }
 
+fn open_reader(source_type: &Source, path: &str, curl_config: &CurlConfig, connect_timeout: Option<Duration>) -> NCDReader {
+    set_error_context("open",Some(path));
+    let accessor = die_on_error(source_type.make_accessor(path,curl_config,connect_timeout));
+    let reader = die_on_error(NCDReader::new_box(accessor));
+    set_error_context("lookup",Some(path));
+    reader
+}
+
 fn main() {
     let app = make_app();
-    let matches = app.get_matches();
+    let matches = matches_or_die(app.get_matches_safe());
+    exit_if_version("ncd-lookup",&matches);
+    set_error_format(ErrorFormat::from_cli(matches.value_of("error-format").unwrap()));
     let path = matches.value_of("PATH").unwrap();
-    let key =  matches.value_of("KEY").unwrap().as_bytes();
+    let encoding = KeyEncoding::from_cli(matches.value_of("key-encoding").unwrap());
+    let key = die_on_error(encoding.decode(matches.value_of("KEY").unwrap().as_bytes()));
+    let key = &key[..];
     let source_type = Source::new(matches.value_of("source"),path);
     let curl_config = make_curl_config(&matches);
-    let accessor = die_on_error(source_type.make_accessor(path,&curl_config));
-    let mut reader = die_on_error(NCDReader::new_box(accessor));
-    let value = die_on_error(reader.get(key));
-    if let Some(value) = value.as_ref() {
+    let connect_timeout = make_connect_timeout(&matches);
+    let resolver = make_resolver(&matches,true);
+    let value = if let Source::Manifest = source_type {
+        /* candidates can hash to different shards */
+        set_error_context("open",Some(path));
+        let manifest = die_on_error(Manifest::load(Path::new(path)));
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        die_on_error(resolve(resolver.as_ref(),key,|k| {
+            let shard = manifest.shard_for(k).unwrap_or_else(|| {
+                die(format!("The manifest lists no shard {} of {}",shard_of(k,manifest.shard_count),manifest.shard_count))
+            });
+            open_reader(&source_type,&dir.join(&shard.path).to_string_lossy(),&curl_config,connect_timeout).get(k)
+        }))
+    } else {
+        let mut reader = open_reader(&source_type,path,&curl_config,connect_timeout);
+        die_on_error(resolve(resolver.as_ref(),key,|k| reader.get(k)))
+    };
+    if let Some((matched,value)) = value.as_ref() {
+        /* a normalized or hashed KEY is still KEY: only report what the alias table gave */
+        if matches.is_present("alias-file") && !make_resolver(&matches,false).candidates(key).contains(matched) {
+            eprintln!("matched alias: {}",String::from_utf8_lossy(matched));
+        }
+        let value = &value[..];
+        let path = matches.value_of("extract").map(|p| die_on_error(parse_extract(p)));
+        if matches.is_present("pretty") || path.is_some() {
+            if let Some(text) = die_on_error(render_json(value,matches.is_present("pretty"),path.as_deref())) {
+                die_on_error(io::stdout().write_all(text.as_bytes()));
+                process::exit(0);
+            }
+        }
         die_on_error(io::stdout().write_all(value));
         process::exit(0);
     } else {
         process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use ncd_tools::resolve::KeyNormalize;
+    use crate::{make_app, make_key_normalization, make_resolver, parse_extract, render_json, Step};
+
+    #[test]
+    fn test_parse_extract() {
+        assert_eq!(Ok(vec![]),parse_extract("."));
+        assert_eq!(Ok(vec![Step::Field("transcripts".to_string()),Step::Index(0),Step::Field("id".to_string())]),parse_extract(".transcripts[0].id"));
+        assert_eq!(Ok(vec![Step::Index(-1),Step::Field("a b".to_string()),Step::Field("c\"".to_string())]),parse_extract(".[-1].\"a b\"[\"c\\\"\"]"));
+        assert!(parse_extract("transcripts").is_err());
+        assert!(parse_extract(".a..b").is_err());
+        assert!(parse_extract(".a.").is_err());
+        assert!(parse_extract(".a[x]").is_err());
+        assert!(parse_extract(".a[0").is_err());
+    }
+
+    #[test]
+    fn test_render_json() {
+        let value = br#"{"id":"TP53","transcripts":[{"id":"T1"},{"id":"T2"}]}"#;
+        assert_eq!(Ok(Some("\"T2\"\n".to_string())),render_json(value,false,Some(&parse_extract(".transcripts[-1].id").unwrap()[..])));
+        assert_eq!(Ok(Some("{\n  \"id\": \"T1\"\n}\n".to_string())),render_json(value,true,Some(&parse_extract(".transcripts[0]").unwrap()[..])));
+        assert_eq!(Ok(None),render_json(b"not json",true,None));
+        assert!(render_json(b"not json",true,Some(&[][..])).is_err());
+        assert!(render_json(value,true,Some(&parse_extract(".missing").unwrap()[..])).is_err());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let matches = make_app().get_matches_from(["lookup","k","x.ncd"].iter());
+        assert!(make_key_normalization(&matches).is_empty());
+        assert_eq!(vec![b"Cafe\xcc\x81".to_vec()],make_resolver(&matches,true).candidates("Cafe\u{301}".as_bytes()));
+        let matches = make_app().get_matches_from(["lookup","k","x.ncd","--normalize-key","nfc","--normalize-key","lower"].iter());
+        assert_eq!(vec!["caf\u{e9}".as_bytes().to_vec()],make_resolver(&matches,true).candidates("Cafe\u{301}".as_bytes()));
+        let matches = make_app().get_matches_from(["lookup","k","x.ncd","--normalize-key","trim","--normalize-key","upper"].iter());
+        assert_eq!(vec![b"BRCA1".to_vec()],make_resolver(&matches,true).candidates(b" Brca1 "));
+        let matches = make_app().get_matches_from(["lookup","k","x.ncd","--normalize","nfc","--casefold"].iter());
+        assert_eq!(vec![KeyNormalize::Nfc,KeyNormalize::Casefold],make_key_normalization(&matches));
+        assert_eq!(vec![b"strasse".to_vec()],make_resolver(&matches,true).candidates("STRA\u{df}E".as_bytes()));
+        let matches = make_app().get_matches_from(["lookup","k","x.ncd","--casefold","--normalize-key","trim"].iter());
+        assert_eq!(vec![KeyNormalize::Casefold,KeyNormalize::Trim],make_key_normalization(&matches));
+        assert!(make_app().get_matches_from_safe(["lookup","k","x.ncd","--normalize","title"].iter()).is_err());
+    }
+
+    #[test]
+    fn test_alias_candidates() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file,b"FANCD1\tbrca2\n").unwrap();
+        let path = file.path().to_str().unwrap();
+        let matches = make_app().get_matches_from(["lookup","k","x.ncd","-a",path,"--normalize-key","upper"].iter());
+        assert_eq!(vec![b"FANCD1".to_vec(),b"BRCA2".to_vec()],make_resolver(&matches,true).candidates(b"FANCD1"));
+        assert_eq!(vec![b"FANCD1".to_vec()],make_resolver(&matches,false).candidates(b"FANCD1"));
+    }
+}
@@ -0,0 +1,82 @@
+use clap::{App, AppSettings, Arg};
+use std::{fs::{self, File}, io::Write, path::Path, process};
+use ncd_tools::error::{die_msg, die_on_error, matches_or_die, set_error_context, set_error_format, ErrorFormat};
+use ncd_tools::messages::Msg;
+use ncd_tools::verify::{attestation, sign, verify_manifest};
+use ncd_tools::version::{exit_if_version, version_args};
+
+pub fn make_app() -> App<'static,'static> {
+    App::new("ncd release verifier").version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::DisableVersion)
+        .args(&version_args())
+        .author("Dan Sheppard <dan@ebi.ac.uk")
+        .about("Verifies the shards of a sharded ncd build against its manifest")
+        .arg(Arg::with_name("manifest")
+            .short("-m")
+            .long("--manifest")
+            .help("manifest to verify (OUTPUT.manifest.json from ncd-build --max-output-size)")
+            .takes_value(true)
+            .required_unless("version")
+        )
+        .arg(Arg::with_name("attestation")
+            .short("-a")
+            .long("--attestation")
+            .help("if everything verifies, write an attestation (json) to this file")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("sign-key")
+            .long("--sign-key")
+            .help("file holding a secret to sign the attestation with (hmac-sha256)")
+            .takes_value(true)
+            .requires("attestation")
+        )
+        .arg(Arg::with_name("error-format")
+            .long("--error-format")
+            .takes_value(true)
+            .help("how to report errors on stderr")
+            .possible_value("text")
+            .possible_value("json")
+            .default_value("text")
+        )
+    }
+
+fn main() {
+    let app = make_app();
+    let matches = matches_or_die(app.get_matches_safe());
+    exit_if_version("ncd-verify",&matches);
+    set_error_format(ErrorFormat::from_cli(matches.value_of("error-format").unwrap()));
+    let manifest_path = Path::new(matches.value_of("manifest").unwrap());
+    set_error_context("open",matches.value_of("manifest"));
+    let (manifest,check) = die_on_error(verify_manifest(manifest_path));
+    for problem in &check.problems {
+        println!("FAIL\t{}\t{}",manifest_path.display(),problem);
+    }
+    for shard in &check.shards {
+        if shard.ok() {
+            println!("ok\t{}",shard.path);
+        }
+        for problem in &shard.problems {
+            println!("FAIL\t{}\t{}",shard.path,problem);
+        }
+    }
+    if !check.ok() {
+        let failed = check.shards.iter().filter(|c| !c.ok()).count();
+        eprintln!("{} of {} shards failed verification, with {} problems in the manifest itself",failed,check.shards.len(),check.problems.len());
+        process::exit(1);
+    }
+    if let Some(out) = matches.value_of("attestation") {
+        let mut record = die_on_error(attestation(manifest_path,&manifest));
+        if let Some(key_path) = matches.value_of("sign-key") {
+            set_error_context("open",Some(key_path));
+            let mut key = die_on_error(fs::read(key_path));
+            while key.last() == Some(&b'\n') || key.last() == Some(&b'\r') { key.pop(); }
+            if key.is_empty() {
+                die_msg(Msg::EmptySigningKey,&[&key_path]);
+            }
+            die_on_error(sign(&mut record,&key));
+        }
+        set_error_context("output",Some(out));
+        let text = die_on_error(serde_json::to_string_pretty(&record));
+        die_on_error(File::create(out).and_then(|mut file| writeln!(file,"{}",text)));
+    }
+}
@@ -0,0 +1,127 @@
+use std::{cell::Cell, fs::File, io::{self, Seek, SeekFrom, Write}, thread, time::Duration};
+
+use curl::easy::{Easy, List};
+
+/// How `download` talks to the server. A transfer which breaks off, or a 5xx response, is
+/// retried up to `retries` times, resuming where it stopped if the server takes ranges.
+#[derive(Clone,Debug)]
+pub struct DownloadConfig {
+    connect_timeout: Option<Duration>,
+    retries: u32
+}
+
+impl DownloadConfig {
+    pub fn new() -> DownloadConfig {
+        DownloadConfig {
+            connect_timeout: None,
+            retries: 3
+        }
+    }
+}
+
+chain!(connect_timeout,get_connect_timeout,Option<Duration>,DownloadConfig);
+chain!(retries,get_retries,u32,DownloadConfig);
+
+fn status_code(line: &[u8]) -> Option<u32> {
+    let line = std::str::from_utf8(line).ok()?;
+    if !line.starts_with("HTTP/") { return None; }
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/* why an attempt failed, with the last HTTP status seen */
+struct Failure {
+    error: curl::Error,
+    write_error: Option<io::Error>,
+    status: u32
+}
+
+impl From<curl::Error> for Failure {
+    fn from(error: curl::Error) -> Failure { Failure { error, write_error: None, status: 0 } }
+}
+
+/* one request for what's after the `got` bytes so far: a server ignoring the range sends it all
+ * again, so then start over */
+fn attempt(url: &str, out: &mut File, config: &DownloadConfig, headers: &[String], got: &mut u64) -> Result<(),Failure> {
+    let from = *got;
+    let mut easy = Easy::new();
+    let setup = || -> Result<(),curl::Error> {
+        easy.url(url)?;
+        let mut list = List::new();
+        for header in headers { list.append(header)?; }
+        easy.http_headers(list)?;
+        easy.follow_location(true)?;
+        easy.fail_on_error(true)?;
+        if let Some(timeout) = config.connect_timeout { easy.connect_timeout(timeout)?; }
+        if from > 0 { easy.resume_from(from)?; }
+        Ok(())
+    };
+    setup()?;
+    let status = Cell::new(0);
+    let mut started = false;
+    let mut write_error = None;
+    let result = {
+        let mut transfer = easy.transfer();
+        transfer.header_function(|line| {
+            if let Some(code) = status_code(line) { status.set(code); }
+            true
+        })?;
+        transfer.write_function(|data| {
+            let written = (|| -> io::Result<()> {
+                if !started {
+                    started = true;
+                    if status.get() != 206 {
+                        *got = 0;
+                        out.set_len(0)?;
+                    }
+                    out.seek(SeekFrom::Start(*got))?;
+                }
+                out.write_all(data)
+            })();
+            match written {
+                Ok(()) => { *got += data.len() as u64; Ok(data.len()) },
+                Err(e) => { write_error = Some(e); Ok(0) }
+            }
+        })?;
+        transfer.perform()
+    };
+    result.map_err(|error| Failure { error, write_error, status: status.get() })
+}
+
+/// Streams `url` into `out`, retrying and resuming as `config` says. Returns the length.
+pub fn download(url: &str, out: &mut File, config: &DownloadConfig) -> io::Result<u64> {
+    download_with(url,out,config,&|| Ok(vec![]))
+}
+
+/// As `download`, sending the `headers` made afresh for each attempt, as signed requests expire.
+pub fn download_with(url: &str, out: &mut File, config: &DownloadConfig, headers: &dyn Fn() -> io::Result<Vec<String>>) -> io::Result<u64> {
+    let mut got = 0;
+    let mut tries = 0;
+    loop {
+        let failure = match attempt(url,out,config,&headers()?,&mut got) {
+            Ok(()) => { return Ok(got); },
+            Err(failure) => failure
+        };
+        if let Some(e) = failure.write_error {
+            return Err(e);
+        }
+        /* 4xx won't get better by asking again */
+        let transient = !failure.error.is_http_returned_error() || failure.status >= 500;
+        if !transient || tries >= config.retries {
+            return Err(io::Error::new(io::ErrorKind::Other,format!("{}: {}",url,failure.error)));
+        }
+        tries += 1;
+        thread::sleep(Duration::from_millis(100*tries as u64));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::status_code;
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(Some(206),status_code(b"HTTP/1.1 206 Partial Content\r\n"));
+        assert_eq!(Some(200),status_code(b"HTTP/2 200\r\n"));
+        assert_eq!(None,status_code(b"Content-Length: 200\r\n"));
+    }
+}
@@ -0,0 +1,59 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// How a key is written in text input or on the command line, for keys which are really binary.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum KeyEncoding {
+    Raw,
+    Hex,
+    Base64
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None
+    }
+}
+
+pub fn from_hex(text: &[u8]) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 { return None; }
+    text.chunks(2).map(|pair| Some(hex_digit(pair[0])? << 4 | hex_digit(pair[1])?)).collect()
+}
+
+impl KeyEncoding {
+    pub fn from_cli(value: &str) -> KeyEncoding {
+        match value {
+            "hex" => KeyEncoding::Hex,
+            "base64" => KeyEncoding::Base64,
+            _ => KeyEncoding::Raw
+        }
+    }
+
+    /// The key's actual bytes.
+    pub fn decode(&self, key: &[u8]) -> Result<Vec<u8>,String> {
+        let show = || String::from_utf8_lossy(key).to_string();
+        match self {
+            KeyEncoding::Raw => Ok(key.to_vec()),
+            KeyEncoding::Hex => from_hex(key).ok_or_else(|| format!("key is not valid hex: {}",show())),
+            KeyEncoding::Base64 => STANDARD.decode(key).map_err(|e| format!("key is not valid base64 ({}): {}",e,show()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KeyEncoding;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Ok(b"a\nb".to_vec()),KeyEncoding::Raw.decode(b"a\nb"));
+        assert_eq!(Ok(vec![0x00,0xFF,0xab]),KeyEncoding::Hex.decode(b"00ffAB"));
+        assert!(KeyEncoding::Hex.decode(b"0").is_err());
+        assert!(KeyEncoding::Hex.decode(b"zz").is_err());
+        assert_eq!(Ok(vec![0x00,0xFF]),KeyEncoding::Base64.decode(b"AP8="));
+        assert!(KeyEncoding::Base64.decode(b"!!").is_err());
+        assert_eq!(KeyEncoding::Hex,KeyEncoding::from_cli("hex"));
+    }
+}
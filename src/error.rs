@@ -0,0 +1,164 @@
+use std::{env, fmt::Display, fs, path::{Path, PathBuf}, process, sync::Mutex};
+
+use clap::ArgMatches;
+
+use crate::messages::{text, Msg};
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum ErrorFormat {
+    Text,
+    Json
+}
+
+impl ErrorFormat {
+    pub fn from_cli(name: &str) -> ErrorFormat {
+        match name {
+            "json" => ErrorFormat::Json,
+            _ => ErrorFormat::Text
+        }
+    }
+}
+
+/* What the binary was doing when it died, reported alongside the error in json mode */
+struct ErrorContext {
+    format: ErrorFormat,
+    kind: &'static str,
+    path: Option<String>,
+    attempt: Option<u32>
+}
+
+static CONTEXT: Mutex<ErrorContext> = Mutex::new(ErrorContext {
+    format: ErrorFormat::Text,
+    kind: "usage",
+    path: None,
+    attempt: None
+});
+
+/* process::exit skips destructors, so temporary files have to be cleaned up by hand */
+static REMOVE_ON_EXIT: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+
+pub fn remove_on_exit(path: &Path) {
+    REMOVE_ON_EXIT.lock().unwrap().push(path.to_path_buf());
+}
+
+pub fn set_error_format(format: ErrorFormat) {
+    CONTEXT.lock().unwrap().format = format;
+}
+
+pub fn set_error_context(kind: &'static str, path: Option<&str>) {
+    let mut context = CONTEXT.lock().unwrap();
+    context.kind = kind;
+    context.path = path.map(|s| s.to_string());
+}
+
+pub fn set_error_attempt(attempt: u32) {
+    CONTEXT.lock().unwrap().attempt = Some(attempt);
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}",c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn format_error(message: &str, id: Option<&str>, context: &ErrorContext) -> String {
+    match context.format {
+        ErrorFormat::Text => match id {
+            Some(id) => format!("{}: {}",id,message),
+            None => message.to_string()
+        },
+        ErrorFormat::Json => {
+            let mut out = format!("{{\"error\":{}",json_string(message));
+            if let Some(id) = id {
+                out.push_str(&format!(",\"id\":{}",json_string(id)));
+            }
+            out.push_str(&format!(",\"kind\":{}",json_string(context.kind)));
+            if let Some(path) = &context.path {
+                out.push_str(&format!(",\"path\":{}",json_string(path)));
+            }
+            if let Some(attempt) = context.attempt {
+                out.push_str(&format!(",\"attempt\":{}",attempt));
+            }
+            out.push('}');
+            out
+        }
+    }
+}
+
+fn exit_with(message: String, id: Option<&str>) -> ! {
+    let line = match CONTEXT.lock() {
+        Ok(context) => format_error(&message,id,&context),
+        Err(_) => message
+    };
+    eprintln!("{}",line);
+    if let Ok(paths) = REMOVE_ON_EXIT.lock() {
+        for path in paths.iter() {
+            fs::remove_file(path).ok();
+        }
+    }
+    process::exit(1);
+}
+
+/// Dies with an error which comes from elsewhere and has no message ID.
+pub fn die<E: Display>(value: E) -> ! {
+    exit_with(value.to_string(),None)
+}
+
+/// Dies with a catalogued message, reported with its ID.
+pub fn die_msg(msg: Msg, args: &[&dyn Display]) -> ! {
+    exit_with(text(msg,args),Some(msg.id()))
+}
+
+pub fn die_on_error<T,E: Display>(value: Result<T,E>) -> T {
+    match value {
+        Ok(v) => v,
+        Err(e) => die(e)
+    }
+}
+
+/* clap reports its own errors before --error-format has been parsed, so peek at the raw arguments */
+fn args_want_json() -> bool {
+    let args : Vec<String> = env::args().collect();
+    args.iter().any(|a| a == "--error-format=json") ||
+        args.windows(2).any(|w| w[0] == "--error-format" && w[1] == "json")
+}
+
+pub fn matches_or_die<'a>(matches: clap::Result<ArgMatches<'a>>) -> ArgMatches<'a> {
+    match matches {
+        Ok(matches) => matches,
+        Err(e) if e.use_stderr() && args_want_json() => {
+            set_error_format(ErrorFormat::Json);
+            die(e.message.trim());
+        },
+        Err(e) => e.exit()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_error, ErrorContext, ErrorFormat};
+
+    #[test]
+    fn test_format_error() {
+        let mut context = ErrorContext { format: ErrorFormat::Text, kind: "input", path: Some("a\"b".to_string()), attempt: None };
+        assert_eq!("gone",format_error("gone",None,&context));
+        assert_eq!("NCD-E010: gone",format_error("gone",Some("NCD-E010"),&context));
+        context.format = ErrorFormat::Json;
+        assert_eq!("{\"error\":\"gone\\n\",\"kind\":\"input\",\"path\":\"a\\\"b\"}",format_error("gone\n",None,&context));
+        context.path = None;
+        context.attempt = Some(2);
+        assert_eq!("{\"error\":\"x\\u0001\",\"kind\":\"input\",\"attempt\":2}",format_error("x\u{1}",None,&context));
+        assert_eq!("{\"error\":\"gone\",\"id\":\"NCD-E010\",\"kind\":\"input\",\"attempt\":2}",format_error("gone",Some("NCD-E010"),&context));
+    }
+}
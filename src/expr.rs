@@ -0,0 +1,45 @@
+use std::io;
+
+use rhai::{Dynamic, Engine, Scope, AST};
+
+use crate::sources::{invalid_data, transform::Transform};
+
+/// Compiles a rhai script into a `Transform`. The script sees `key` and `value` as
+/// strings. Its final value is the replacement, or `()` to drop the record.
+pub fn compile_transform(expr: &str) -> io::Result<Transform> {
+    let engine = Engine::new();
+    let ast : AST = engine.compile(expr).map_err(|e| invalid_data(format!("bad expression '{}': {}",expr,e)))?;
+    let text = expr.to_string();
+    Ok(Box::new(move |key,value| {
+        let mut scope = Scope::new();
+        scope.push("key",String::from_utf8_lossy(key).to_string());
+        scope.push("value",String::from_utf8_lossy(value).to_string());
+        let result : Dynamic = engine.eval_ast_with_scope(&mut scope,&ast)
+            .map_err(|e| invalid_data(format!("expression '{}' failed on key {}: {}",text,String::from_utf8_lossy(key),e)))?;
+        Ok(if result.is_unit() {
+            None
+        } else if result.is_string() {
+            Some(result.into_string().unwrap_or_default().into_bytes())
+        } else {
+            Some(result.to_string().into_bytes())
+        })
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::compile_transform;
+
+    #[test]
+    fn test_compile_transform() {
+        let upper = compile_transform("key.to_upper()").unwrap();
+        assert_eq!(Some(b"BRCA2".to_vec()),upper(b"brca2",b"x").unwrap());
+        let cond = compile_transform("if value == \"\" { () } else { key + \":\" + value }").unwrap();
+        assert_eq!(None,cond(b"a",b"").unwrap());
+        assert_eq!(Some(b"a:b".to_vec()),cond(b"a",b"b").unwrap());
+        let num = compile_transform("value.len()").unwrap();
+        assert_eq!(Some(b"3".to_vec()),num(b"a",b"abc").unwrap());
+        assert!(compile_transform("key +").is_err());
+        assert!(compile_transform("key.nosuch()").unwrap()(b"a",b"b").is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use std::{fs::File, io::{self, Read, Seek, SeekFrom}, path::Path, thread, time::Duration};
+
+use ncd::{NCDReader, StdNCDReadAccessor};
+
+/// How often a `FaultInjectingAccessor` misbehaves, each a probability per read, and the seed
+/// which makes a run of faults repeatable.
+#[derive(Clone,Debug)]
+pub struct FaultConfig {
+    seed: u64,
+    short_reads: f64,
+    delays: f64,
+    delay: Duration,
+    bit_flips: f64,
+    errors: f64
+}
+
+impl FaultConfig {
+    pub fn new() -> FaultConfig {
+        FaultConfig {
+            seed: 0,
+            short_reads: 0.,
+            delays: 0.,
+            delay: Duration::from_millis(10),
+            bit_flips: 0.,
+            errors: 0.
+        }
+    }
+}
+
+chain!(seed,get_seed,u64,FaultConfig);
+chain!(short_reads,get_short_reads,f64,FaultConfig);
+chain!(delays,get_delays,f64,FaultConfig);
+chain!(delay,get_delay,Duration,FaultConfig);
+chain!(bit_flips,get_bit_flips,f64,FaultConfig);
+chain!(errors,get_errors,f64,FaultConfig);
+
+/* splitmix64: small, and the same sequence for a seed everywhere */
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0. && ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+/// A `Read + Seek` which passes through to `inner` but, as `FaultConfig` says, sleeps before a
+/// read, fails it with a transient `TimedOut` error, returns fewer bytes than asked for or
+/// flips a bit of what it returns. Wrapped in a `StdNCDReadAccessor` (as `reader` does) it
+/// puts readers through the faults a flaky disk or network would, the same ones on every run
+/// with the same seed. Seeks are passed through untouched.
+pub struct FaultInjectingAccessor<T> {
+    inner: T,
+    config: FaultConfig,
+    rng: Rng
+}
+
+impl<T: Read+Seek> FaultInjectingAccessor<T> {
+    pub fn new(inner: T, config: &FaultConfig) -> FaultInjectingAccessor<T> {
+        FaultInjectingAccessor { inner, config: config.clone(), rng: Rng(config.seed) }
+    }
+}
+
+impl FaultInjectingAccessor<File> {
+    /// An `NCDReader` of the ncd file at `path`, reading it through the faults of `config`.
+    pub fn reader(path: &Path, config: &FaultConfig) -> io::Result<NCDReader> {
+        let accessor = StdNCDReadAccessor::new(FaultInjectingAccessor::new(File::open(path)?,config))?;
+        NCDReader::new_box(Box::new(accessor)).map_err(|e| io::Error::new(io::ErrorKind::Other,e.to_string()))
+    }
+}
+
+impl<T: Read+Seek> Read for FaultInjectingAccessor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.rng.chance(self.config.delays) {
+            thread::sleep(self.config.delay);
+        }
+        if self.rng.chance(self.config.errors) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut,"injected fault"));
+        }
+        let mut want = buf.len();
+        if want > 1 && self.rng.chance(self.config.short_reads) {
+            want = 1 + (self.rng.next() % (want as u64 - 1)) as usize;
+        }
+        let n = self.inner.read(&mut buf[..want])?;
+        if n > 0 && self.rng.chance(self.config.bit_flips) {
+            let bit = self.rng.next() % (n as u64 * 8);
+            buf[(bit / 8) as usize] ^= 1 << (bit % 8);
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Read+Seek> Seek for FaultInjectingAccessor<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::{Cursor, ErrorKind, Read}, time::{Duration, Instant}};
+
+    use ncd::NCDBuildConfig;
+
+    use crate::writer::NCDWriter;
+    use super::{FaultConfig, FaultInjectingAccessor};
+
+    fn data() -> Vec<u8> { (0..=255).cycle().take(4096).collect() }
+
+    /* what each of `reads` reads of 64 bytes gave: the bytes, or the error's kind */
+    fn run(config: &FaultConfig, reads: usize) -> Vec<Result<Vec<u8>,ErrorKind>> {
+        let mut faulty = FaultInjectingAccessor::new(Cursor::new(data()),config);
+        (0..reads).map(|_| {
+            let mut buf = [0;64];
+            faulty.read(&mut buf).map(|n| buf[..n].to_vec()).map_err(|e| e.kind())
+        }).collect()
+    }
+
+    #[test]
+    fn test_no_faults() {
+        let mut out = vec![];
+        FaultInjectingAccessor::new(Cursor::new(data()),&FaultConfig::new()).read_to_end(&mut out).unwrap();
+        assert_eq!(data(),out);
+    }
+
+    #[test]
+    fn test_seeded() {
+        let config = FaultConfig::new().short_reads(0.3).bit_flips(0.3).errors(0.3);
+        assert_eq!(run(&config.clone().seed(7),50),run(&config.clone().seed(7),50));
+        assert_ne!(run(&config.clone().seed(7),50),run(&config.seed(8),50));
+    }
+
+    #[test]
+    fn test_faults() {
+        assert!(run(&FaultConfig::new().errors(1.),3).iter().all(|r| r == &Err(ErrorKind::TimedOut)));
+        for read in run(&FaultConfig::new().short_reads(1.),20) {
+            let read = read.unwrap();
+            assert!(!read.is_empty() && read.len() < 64);
+        }
+        let expected = data();
+        for (i,read) in run(&FaultConfig::new().bit_flips(1.),20).into_iter().enumerate() {
+            let flipped : u32 = read.unwrap().iter().zip(&expected[i*64..]).map(|(a,b)| (a ^ b).count_ones()).sum();
+            assert_eq!(1,flipped);
+        }
+        let start = Instant::now();
+        run(&FaultConfig::new().delays(1.).delay(Duration::from_millis(2)),3);
+        assert!(start.elapsed() >= Duration::from_millis(6));
+    }
+
+    #[test]
+    fn test_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("genes.ncd");
+        let mut writer = NCDWriter::create(&path,NCDBuildConfig::new()).unwrap();
+        writer.insert(b"BRCA2",b"chr13").unwrap();
+        writer.finish().unwrap();
+        let mut reader = FaultInjectingAccessor::reader(&path,&FaultConfig::new().delays(0.5).delay(Duration::from_millis(1))).unwrap();
+        assert_eq!(Some(b"chr13".to_vec()),reader.get(b"BRCA2").unwrap());
+        let failing = FaultInjectingAccessor::reader(&path,&FaultConfig::new().errors(1.));
+        assert!(failing.is_err() || failing.unwrap().get(b"BRCA2").is_err());
+    }
+}
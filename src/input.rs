@@ -0,0 +1,262 @@
+use std::{fs::{self, File}, io::{self, BufRead, BufReader, Read}, path::{Path, PathBuf}};
+
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use flate2::read::MultiGzDecoder;
+use tempfile::NamedTempFile;
+use xz2::read::XzDecoder;
+
+use crate::{download::{download, DownloadConfig}, error::remove_on_exit, prepare::Prepare, s3::download_object, sources::{http_json::is_http_url, is_redis_url, is_sql_url}};
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+    Auto
+}
+
+impl Compression {
+    pub fn from_cli(name: &str) -> Compression {
+        match name {
+            "none" => Compression::None,
+            "gzip" => Compression::Gzip,
+            "zstd" => Compression::Zstd,
+            "xz" => Compression::Xz,
+            _ => Compression::Auto
+        }
+    }
+
+    pub fn from_magic(bytes: &[u8]) -> Compression {
+        if bytes.starts_with(&[0x1F,0x8B]) {
+            Compression::Gzip
+        } else if bytes.starts_with(&[0x28,0xB5,0x2F,0xFD]) {
+            Compression::Zstd
+        } else if bytes.starts_with(&[0xFD,b'7',b'z',b'X',b'Z',0x00]) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+
+    fn decoder<'a>(&self, input: Box<dyn BufRead + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(input)),
+            Compression::Zstd => Box::new(zstd::Decoder::with_buffer(input)?),
+            Compression::Xz => Box::new(XzDecoder::new_multi_decoder(input)),
+            _ => Box::new(input)
+        })
+    }
+}
+
+/// The text encoding named by `label` (`latin1`, `windows-1252`, `utf-16le`, ...), using the
+/// WHATWG names and aliases.
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding,String> {
+    Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| format!("unknown text encoding: {}",label))
+}
+
+/// An input file to build from. `-` (stdin), compressed files, files in another text encoding
+/// (converted to UTF-8, with U+FFFD for anything invalid) and files needing a `Prepare` pass
+/// are spooled to a temporary file, as the build may make several passes over its input and the
+/// sources need a plain file. The temporary file is removed when the `Input` is dropped.
+/// Directories, and the redis, sql and http URLs which sources fetch for themselves, are passed
+/// through untouched: see `download` for http URLs to read as files.
+pub struct Input {
+    name: String,
+    path: PathBuf,
+    _spool: Option<NamedTempFile>
+}
+
+fn spool<R: Read>(mut input: R, prepare: &Prepare) -> io::Result<NamedTempFile> {
+    let mut spool = NamedTempFile::new()?;
+    remove_on_exit(spool.path());
+    if prepare.is_active() {
+        prepare.run(BufReader::new(input),spool.as_file_mut())?;
+    } else {
+        io::copy(&mut input,spool.as_file_mut())?;
+    }
+    Ok(spool)
+}
+
+impl Input {
+    pub fn open(name: &str, compression: Compression, encoding: Option<&'static Encoding>, prepare: &Prepare) -> io::Result<Input> {
+        let path = Path::new(name);
+        let mut input : Box<dyn BufRead> = if is_redis_url(name) || is_sql_url(name) || is_http_url(name) {
+            return Ok(Input { name: name.to_string(), path: path.to_path_buf(), _spool: None });
+        } else if name == "-" {
+            Box::new(BufReader::new(io::stdin()))
+        } else if !path.exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound,format!("File does not exist: {}",name)));
+        } else if path.is_dir() {
+            return Ok(Input { name: name.to_string(), path: path.to_path_buf(), _spool: None });
+        } else {
+            Box::new(BufReader::new(File::open(path)?))
+        };
+        let compression = match compression {
+            Compression::Auto => Compression::from_magic(input.fill_buf()?),
+            c => c
+        };
+        if name != "-" && compression == Compression::None && encoding.is_none() && !prepare.is_active() {
+            return Ok(Input { name: name.to_string(), path: path.to_path_buf(), _spool: None });
+        }
+        let mut input = compression.decoder(input)?;
+        if let Some(encoding) = encoding {
+            input = Box::new(DecodeReaderBytesBuilder::new().encoding(Some(encoding)).build(input));
+        }
+        let spool = spool(input,prepare)
+            .map_err(|e| io::Error::new(e.kind(),format!("Cannot read {}: {}",name,e)))?;
+        Ok(Input { name: name.to_string(), path: spool.path().to_path_buf(), _spool: Some(spool) })
+    }
+
+    /// An http(s) URL downloaded to a temporary file, then opened as any local file would be.
+    pub fn download(url: &str, config: &DownloadConfig, compression: Compression, encoding: Option<&'static Encoding>, prepare: &Prepare) -> io::Result<Input> {
+        Input::fetch(url,|file| download(url,file,config),compression,encoding,prepare)
+    }
+
+    /// An `s3://BUCKET/KEY` object, downloaded as for `download`.
+    pub fn download_s3(url: &str, config: &DownloadConfig, compression: Compression, encoding: Option<&'static Encoding>, prepare: &Prepare) -> io::Result<Input> {
+        Input::fetch(url,|file| download_object(url,file,config),compression,encoding,prepare)
+    }
+
+    fn fetch<F>(url: &str, fetch: F, compression: Compression, encoding: Option<&'static Encoding>, prepare: &Prepare) -> io::Result<Input>
+            where F: FnOnce(&mut File) -> io::Result<u64> {
+        let mut file = NamedTempFile::new()?;
+        remove_on_exit(file.path());
+        fetch(file.as_file_mut())?;
+        let local = file.path().to_string_lossy().to_string();
+        let mut input = Input::open(&local,compression,encoding,prepare)
+            .map_err(|e| io::Error::new(e.kind(),e.to_string().replace(&local,url)))?;
+        input.name = url.to_string();
+        if input._spool.is_none() {
+            input._spool = Some(file);
+        }
+        Ok(input)
+    }
+
+    /// Spools the input again to strip a BOM and `\r`s, if `prepare` finds it needs it. This is
+    /// for input which only turns out to be flat once it's open, so wasn't prepared.
+    pub fn fix_line_endings(&mut self, prepare: &Prepare) -> io::Result<()> {
+        if !self.path.is_file() { return Ok(()); }
+        let mut input = BufReader::new(File::open(&self.path)?);
+        if !prepare.fixes_line_endings(input.fill_buf()?) { return Ok(()); }
+        let mut spool = NamedTempFile::new()?;
+        remove_on_exit(spool.path());
+        prepare.run(input,spool.as_file_mut())
+            .map_err(|e| io::Error::new(e.kind(),format!("Cannot read {}: {}",self.name,e)))?;
+        self.path = spool.path().to_path_buf();
+        self._spool = Some(spool);
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str { &self.name }
+    pub fn path(&self) -> &Path { &self.path }
+    pub fn is_dir(&self) -> bool { self.path.is_dir() }
+
+    /* the sources take &str paths */
+    pub fn path_str(&self) -> String { self.path.to_string_lossy().to_string() }
+}
+
+/// True if both paths exist and are the same file however they are reached, through symlinks,
+/// `..` or hard links.
+#[cfg(unix)]
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a),fs::metadata(b)) {
+        (Ok(a),Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false
+    }
+}
+
+#[cfg(not(unix))]
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a),fs::canonicalize(b)) {
+        (Ok(a),Ok(b)) => a == b,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use crate::prepare::{Crlf, Prepare};
+    use super::{parse_encoding, same_file, Compression, Input};
+
+    fn round_trip(data: &[u8], compression: Compression) -> Vec<u8> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(data).unwrap();
+        let name = file.path().to_string_lossy().to_string();
+        let input = Input::open(&name,compression,None,&Prepare::new()).unwrap();
+        std::fs::read(input.path()).unwrap()
+    }
+
+    #[test]
+    fn test_compression() {
+        let plain = b"a 1\nb 2\n".to_vec();
+        let mut gz = flate2::write::GzEncoder::new(vec![],flate2::Compression::default());
+        gz.write_all(&plain).unwrap();
+        let gz = gz.finish().unwrap();
+        let zst = zstd::encode_all(&plain[..],0).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(vec![],6);
+        xz.write_all(&plain).unwrap();
+        let xz = xz.finish().unwrap();
+        assert_eq!(Compression::Gzip,Compression::from_magic(&gz));
+        assert_eq!(Compression::Zstd,Compression::from_magic(&zst));
+        assert_eq!(Compression::Xz,Compression::from_magic(&xz));
+        assert_eq!(Compression::None,Compression::from_magic(&plain));
+        assert_eq!(plain,round_trip(&gz,Compression::Auto));
+        assert_eq!(plain,round_trip(&zst,Compression::Auto));
+        assert_eq!(plain,round_trip(&xz,Compression::Xz));
+        assert_eq!(plain,round_trip(&plain,Compression::Auto));
+        assert_eq!(xz,round_trip(&xz,Compression::None));
+    }
+
+    #[test]
+    fn test_encoding() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"caf\xE9 1\n\x80 2\n").unwrap();
+        let name = file.path().to_string_lossy().to_string();
+        let input = Input::open(&name,Compression::None,Some(parse_encoding("latin1").unwrap()),&Prepare::new()).unwrap();
+        assert_eq!("caf\u{E9} 1\n\u{20AC} 2\n",std::fs::read_to_string(input.path()).unwrap());
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"a\x00 \x00\x01\x01\n\x00").unwrap();
+        let name = file.path().to_string_lossy().to_string();
+        let input = Input::open(&name,Compression::None,Some(parse_encoding("utf-16le").unwrap()),&Prepare::new()).unwrap();
+        assert_eq!("a \u{101}\n",std::fs::read_to_string(input.path()).unwrap());
+        assert_eq!("windows-1252",parse_encoding("Latin1").unwrap().name().to_lowercase());
+        assert!(parse_encoding("klingon").is_err());
+    }
+
+    #[test]
+    fn test_fix_line_endings() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"\xEF\xBB\xBFa 1\r\nb 2\r\n").unwrap();
+        let name = file.path().to_string_lossy().to_string();
+        let mut input = Input::open(&name,Compression::None,None,&Prepare::new()).unwrap();
+        assert_eq!(file.path(),input.path());
+        input.fix_line_endings(&Prepare::new()).unwrap();
+        assert_ne!(file.path(),input.path());
+        assert_eq!(b"a 1\nb 2\n".to_vec(),std::fs::read(input.path()).unwrap());
+        let mut input = Input::open(&name,Compression::None,None,&Prepare::new()).unwrap();
+        input.fix_line_endings(&Prepare::new().crlf(Crlf::Keep).strip_bom(false)).unwrap();
+        assert_eq!(file.path(),input.path());
+    }
+
+    #[test]
+    fn test_same_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.tsv");
+        std::fs::write(&path,b"a 1\n").unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        assert!(same_file(&path,&dir.path().join("sub/../data.tsv")));
+        #[cfg(unix)]
+        {
+            std::fs::hard_link(&path,dir.path().join("linked.tsv")).unwrap();
+            assert!(same_file(&path,&dir.path().join("linked.tsv")));
+        }
+        std::fs::write(dir.path().join("other.tsv"),b"a 1\n").unwrap();
+        assert!(!same_file(&path,&dir.path().join("other.tsv")));
+        assert!(!same_file(&path,&dir.path().join("missing.ncd")));
+    }
+}
@@ -0,0 +1,24 @@
+#[macro_use]
+mod util;
+
+pub mod accounting;
+pub mod archive;
+pub mod download;
+pub mod encoding;
+pub mod error;
+#[cfg(feature="expr")]
+pub mod expr;
+pub mod fault;
+pub mod input;
+pub mod messages;
+pub mod pipe;
+pub mod precheck;
+pub mod prepare;
+pub mod progress;
+pub mod resolve;
+pub mod s3;
+pub mod shard;
+pub mod sources;
+pub mod verify;
+pub mod version;
+pub mod writer;
@@ -0,0 +1,137 @@
+use std::{collections::HashMap, env, fmt::Display, fs, sync::Mutex};
+
+/* each entry is a variant, its stable ID and the English text, with {} for each argument */
+macro_rules! catalog {
+    ($($name:ident $id:literal $text:literal),* $(,)?) => {
+        /// A user-facing CLI message. The ID stays the same across versions however the
+        /// English changes, so scripts should match on that.
+        #[derive(Clone,Copy,Debug,PartialEq)]
+        pub enum Msg { $($name),* }
+
+        impl Msg {
+            pub fn id(&self) -> &'static str {
+                match self { $(Msg::$name => $id),* }
+            }
+
+            pub fn english(&self) -> &'static str {
+                match self { $(Msg::$name => $text),* }
+            }
+
+            pub fn all() -> &'static [Msg] {
+                &[$(Msg::$name),*]
+            }
+        }
+    }
+}
+
+catalog! {
+    UnknownFormat "NCD-E001" "unknown file format for {}",
+    NotSingleByte "NCD-E002" "--{} must be a single byte, not '{}'",
+    NoExprFeature "NCD-E003" "--key-expr and --value-expr need ncd-build to be built with the expr feature",
+    RequiredWith "NCD-E004" "--{} is required with -t {}",
+    OutputIsInput "NCD-E005" "Output {} is the same file as input {}: refusing to overwrite it (use --force to rebuild it in place)",
+    CannotCreateOutput "NCD-E006" "Cannot create output file: {}",
+    CannotCreateBeside "NCD-E007" "Cannot create output file beside: {}",
+    FieldsNeedFlat "NCD-E008" "--key-fields and --value-fields only apply to flat input",
+    NoRecords "NCD-E009" "No records to build from in {}",
+    NoSuchFile "NCD-E010" "No such file: {}",
+    ExpectedMember "NCD-E012" "Expected ARCHIVE!MEMBER: {}",
+    EmptySigningKey "NCD-E013" "Empty signing key: {}",
+    FilterNeedsLines "NCD-E014" "--filter and --where only apply to line-based input",
+    SkipNeedsLines "NCD-E015" "--skip-lines only applies to line-based input",
+    FieldNameNeedsHeader "NCD-E016" "--field {} is a column name, which needs --header",
+    NoSuchColumn "NCD-E017" "no column {} in the header",
+    RecordSepNeedsFlat "NCD-E018" "--record-separator only applies to flat input",
+    RecordSepWithLines "NCD-E019" "--record-separator can't be combined with options which work on lines (--skip-lines, --filter, --where, --key-fields, --value-fields or several --comment)",
+    EncodingNeedsLines "NCD-E020" "--input-encoding only applies to line-based input",
+    SqlNeedsQuery "NCD-E021" "sql input needs --query",
+    NoFormatFeature "NCD-E022" "-t {} needs ncd-build to be built with the {} feature",
+    RecordTooBigForShard "NCD-E023" "The record for key {} is {} bytes, so no number of shards can keep each within --max-output-size {}",
+    QuoteAwareWithLines "NCD-E024" "--quote-aware can't be combined with options which rewrite lines (--skip-lines, --filter, --where, --key-fields, --value-fields or several --comment)",
+    EmptyBuild "NCD-W001" "no records in {}: building an empty file",
+}
+
+/* loaded on first use: translations only ever come from the one file */
+static TRANSLATIONS: Mutex<Option<HashMap<String,String>>> = Mutex::new(None);
+
+/// Reads `ID<tab>TEXT` lines, the text being a translation of the message with that ID. Blank
+/// lines and lines starting `#` are skipped, as are lines for IDs which don't exist.
+pub fn parse_catalog(data: &str) -> HashMap<String,String> {
+    let mut out = HashMap::new();
+    for line in data.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') { continue; }
+        if let Some((id,text)) = line.split_once('\t') {
+            if Msg::all().iter().any(|m| m.id() == id) {
+                out.insert(id.to_string(),text.to_string());
+            }
+        }
+    }
+    out
+}
+
+/* a catalog which can't be read falls back to English rather than hiding the real error */
+fn translation(msg: Msg) -> Option<String> {
+    let mut translations = TRANSLATIONS.lock().ok()?;
+    let catalog = translations.get_or_insert_with(|| {
+        env::var_os("NCD_MESSAGES").and_then(|path| fs::read_to_string(path).ok()).map(|data| parse_catalog(&data)).unwrap_or_default()
+    });
+    catalog.get(msg.id()).cloned()
+}
+
+/// Fills `{}` placeholders in turn. `{0}`, `{1}`, ... pick an argument by position, so a
+/// translation can reorder them.
+pub fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::new();
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') { Some(end) => start+end, None => break };
+        out.push_str(&rest[..start]);
+        let index = &rest[start+1..end];
+        let arg = if index.is_empty() { next += 1; args.get(next-1) } else { index.parse::<usize>().ok().and_then(|i| args.get(i)) };
+        match arg {
+            Some(arg) => { out.push_str(&arg.to_string()); },
+            None => { out.push_str(&rest[start..=end]); }
+        }
+        rest = &rest[end+1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The text of `msg` with its arguments, from the `NCD_MESSAGES` catalog if it has it, else in
+/// English. The ID isn't included.
+pub fn text(msg: Msg, args: &[&dyn Display]) -> String {
+    let template = translation(msg).unwrap_or_else(|| msg.english().to_string());
+    fill(&template,args)
+}
+
+/// A warning line for stdout, with its ID.
+pub fn warning(msg: Msg, args: &[&dyn Display]) -> String {
+    format!("Warning: {}: {}",msg.id(),text(msg,args))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::{fill, parse_catalog, Msg};
+
+    #[test]
+    fn test_catalog() {
+        let ids : HashSet<_> = Msg::all().iter().map(|m| m.id()).collect();
+        assert_eq!(Msg::all().len(),ids.len());
+        assert_eq!("NCD-E006",Msg::CannotCreateOutput.id());
+        let catalog = parse_catalog("# de\nNCD-E006\tAusgabedatei kann nicht erstellt werden: {}\r\nNCD-E999\tnope\nbroken\n");
+        assert_eq!(1,catalog.len());
+        assert_eq!("Ausgabedatei kann nicht erstellt werden: {}",catalog["NCD-E006"]);
+    }
+
+    #[test]
+    fn test_fill() {
+        assert_eq!("--sep must be a single byte, not 'ab'",fill(Msg::NotSingleByte.english(),&[&"sep",&"ab"]));
+        assert_eq!("ab 2 1",fill("ab {1} {0}",&[&1,&2]));
+        assert_eq!("x {} {9} {",fill("x {} {9} {",&[]));
+    }
+}
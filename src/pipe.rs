@@ -0,0 +1,129 @@
+use std::{io::{self, Read, Write}, process::{Command, Stdio}, thread};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::{write::GzEncoder, Compression};
+
+/// A step values are passed through before they're stored: one of the built-ins, or anything
+/// else as a shell command with the value on stdin and its replacement read from stdout. The
+/// build may take several passes over its input, so a command should give the same output for
+/// the same value each time.
+#[derive(Clone,Debug,PartialEq)]
+pub enum ValuePipe {
+    Gzip,
+    Base64,
+    JsonMinify,
+    Command(String)
+}
+
+impl ValuePipe {
+    pub fn parse(value: &str) -> Result<ValuePipe,String> {
+        Ok(match value.trim() {
+            "" => { return Err("empty --value-pipe command".to_string()); },
+            "gzip" => ValuePipe::Gzip,
+            "base64" => ValuePipe::Base64,
+            "json-minify" => ValuePipe::JsonMinify,
+            command => ValuePipe::Command(command.to_string())
+        })
+    }
+
+    pub fn apply(&self, value: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            /* the default header has no name or time in it, so the output is the same each pass */
+            ValuePipe::Gzip => {
+                let mut encoder = GzEncoder::new(vec![],Compression::default());
+                encoder.write_all(value)?;
+                encoder.finish()
+            },
+            ValuePipe::Base64 => Ok(STANDARD.encode(value).into_bytes()),
+            ValuePipe::JsonMinify => {
+                serde_json::from_slice::<serde_json::Value>(value)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData,format!("value is not JSON: {}",e)))?;
+                Ok(minify_json(value))
+            },
+            ValuePipe::Command(command) => run_command(command,value)
+        }
+    }
+}
+
+/* drops whitespace outside strings, leaving keys in their order and numbers as written */
+pub(crate) fn minify_json(json: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(json.len());
+    let (mut in_string,mut escaped) = (false,false);
+    for &b in json {
+        if in_string {
+            out.push(b);
+            if escaped { escaped = false; } else if b == b'\\' { escaped = true; } else if b == b'"' { in_string = false; }
+        } else if b == b'"' {
+            in_string = true;
+            out.push(b);
+        } else if !matches!(b,b' '|b'\t'|b'\n'|b'\r') {
+            out.push(b);
+        }
+    }
+    out
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(&["/C",command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(&["-c",command]);
+        shell
+    }
+}
+
+/* stdin is written from another thread, as the command may fill its stdout before it's read all of stdin */
+fn run_command(command: &str, value: &[u8]) -> io::Result<Vec<u8>> {
+    let failed = |e: io::Error| io::Error::new(e.kind(),format!("--value-pipe '{}': {}",command,e));
+    let mut child = shell(command).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::inherit()).spawn().map_err(failed)?;
+    let mut stdin = child.stdin.take().unwrap();
+    let value = value.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&value));
+    let mut out = vec![];
+    child.stdout.take().unwrap().read_to_end(&mut out).map_err(failed)?;
+    let status = child.wait().map_err(failed)?;
+    /* a command which doesn't read its input gives a broken pipe: its status is what counts */
+    let written = writer.join().unwrap_or(Ok(()));
+    if !status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other,format!("--value-pipe '{}' failed: {}",command,status)));
+    }
+    match written {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(failed(e)),
+        _ => Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use super::ValuePipe;
+
+    #[test]
+    fn test_builtins() {
+        let gzipped = ValuePipe::Gzip.apply(b"hello").unwrap();
+        assert_eq!(gzipped,ValuePipe::Gzip.apply(b"hello").unwrap());
+        let mut out = String::new();
+        flate2::read::GzDecoder::new(&gzipped[..]).read_to_string(&mut out).unwrap();
+        assert_eq!("hello",out);
+        assert_eq!(b"aGVsbG8=".to_vec(),ValuePipe::Base64.apply(b"hello").unwrap());
+        assert_eq!(b"{\"b\":[1.0,2],\"a\":\"x \\\" y\"}".to_vec(),ValuePipe::JsonMinify.apply(b"{ \"b\" : [ 1.0,\n 2 ], \"a\": \"x \\\" y\" }").unwrap());
+        assert!(ValuePipe::JsonMinify.apply(b"{").is_err());
+        assert_eq!(ValuePipe::Command("tr a-z A-Z".to_string()),ValuePipe::parse(" tr a-z A-Z ").unwrap());
+        assert!(ValuePipe::parse("").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command() {
+        let upper = ValuePipe::parse("tr a-z A-Z").unwrap();
+        assert_eq!(b"HELLO\n".to_vec(),upper.apply(b"hello\n").unwrap());
+        let big = vec![b'x';1<<20];
+        assert_eq!(big.len(),ValuePipe::parse("cat").unwrap().apply(&big).unwrap().len());
+        assert_eq!(b"fixed".to_vec(),ValuePipe::parse("printf fixed").unwrap().apply(&big).unwrap());
+        assert!(ValuePipe::parse("exit 3").unwrap().apply(b"x").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::hash_map::DefaultHasher, hash::Hasher, io};
+
+use ncd::NCDValueSource;
+
+const HLL_BITS : u32 = 14;
+const BLOOM_HASHES : u64 = 7;
+const BLOOM_BITS_PER_KEY : u64 = 10;
+
+/* a fixed-key hasher, so the same keys always land in the same buckets */
+fn hash(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(key);
+    hasher.finish()
+}
+
+/// Distinct-count estimate in 16k registers, good to a percent or so.
+pub struct HyperLogLog {
+    registers: Vec<u8>
+}
+
+impl HyperLogLog {
+    pub fn new() -> HyperLogLog {
+        HyperLogLog { registers: vec![0;1 << HLL_BITS] }
+    }
+
+    pub fn add(&mut self, key: &[u8]) {
+        let hash = hash(key);
+        let index = (hash >> (64-HLL_BITS)) as usize;
+        let rank = ((hash << HLL_BITS) | (1 << (HLL_BITS-1))).leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1. + 1.079 / m);
+        let sum : f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+}
+
+/// Set membership with about a 1% false positive rate at `capacity` keys.
+pub struct Bloom {
+    bits: Vec<u64>,
+    size: u64
+}
+
+impl Bloom {
+    pub fn new(capacity: u64) -> Bloom {
+        let size = (capacity.max(1) * BLOOM_BITS_PER_KEY + 63) / 64 * 64;
+        Bloom { bits: vec![0;(size/64) as usize], size }
+    }
+
+    /// Adds `key`, returning true if it was (probably) there already.
+    pub fn insert(&mut self, key: &[u8]) -> bool {
+        let hash = hash(key);
+        let (h1,h2) = (hash & 0xFFFFFFFF,(hash >> 32) | 1);
+        let mut present = true;
+        for i in 0..BLOOM_HASHES {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.size;
+            let (word,mask) = ((bit / 64) as usize,1u64 << (bit % 64));
+            present &= self.bits[word] & mask != 0;
+            self.bits[word] |= mask;
+        }
+        present
+    }
+}
+
+/// Results of a quick pass over (up to `limit`) keys before a build.
+pub struct Precheck {
+    pub records: u64,
+    pub duplicates: u64,
+    pub distinct: f64,
+    /// True if the whole input fitted within the limit.
+    pub complete: bool
+}
+
+impl Precheck {
+    pub fn run(source: &dyn NCDValueSource, limit: u64) -> io::Result<Precheck> {
+        let mut hll = HyperLogLog::new();
+        let mut bloom = Bloom::new(limit);
+        let (mut records,mut duplicates) = (0,0);
+        let mut complete = true;
+        for entry in source.iter() {
+            if records >= limit { complete = false; break; }
+            let (key,_) = entry?;
+            hll.add(&key);
+            if bloom.insert(&key) { duplicates += 1; }
+            records += 1;
+        }
+        Ok(Precheck { records, duplicates, distinct: hll.estimate(), complete })
+    }
+
+    pub fn summary(&self) -> String {
+        format!("Precheck: {} {}records, about {:.0} distinct keys, about {} duplicates",
+            self.records,if self.complete { "" } else { "sampled " },self.distinct,self.duplicates)
+    }
+
+    /* the bloom filter alone accounts for around 1% */
+    pub fn warnings(&self, expected: Option<u64>) -> Vec<String> {
+        let mut out = vec![];
+        if self.records > 0 && self.duplicates as f64 / self.records as f64 > 0.05 {
+            out.push(format!("Warning: about {:.0}% of keys look like duplicates",100. * self.duplicates as f64 / self.records as f64));
+        }
+        if let Some(expected) = expected {
+            let expected = expected as f64;
+            if self.distinct > expected * 2. {
+                out.push(format!("Warning: about {:.0} distinct keys{}, but {} were expected",
+                    self.distinct,if self.complete { "" } else { " already" },expected));
+            } else if self.complete && self.distinct < expected / 2. {
+                out.push(format!("Warning: only about {:.0} distinct keys, but {} were expected",self.distinct,expected));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sources::memory::MemorySource;
+    use super::{Bloom, HyperLogLog, Precheck};
+
+    #[test]
+    fn test_hll() {
+        let mut hll = HyperLogLog::new();
+        assert_eq!(0.,hll.estimate());
+        for i in 0..100000 { hll.add(format!("key{}",i).as_bytes()); hll.add(format!("key{}",i).as_bytes()); }
+        let estimate = hll.estimate();
+        assert!(estimate > 97000. && estimate < 103000.,"{}",estimate);
+    }
+
+    #[test]
+    fn test_bloom() {
+        let mut bloom = Bloom::new(10000);
+        assert!(!bloom.insert(b"a"));
+        assert!(bloom.insert(b"a"));
+        let false_positives = (0..10000).filter(|i| bloom.insert(format!("k{}",i).as_bytes())).count();
+        assert!(false_positives < 200,"{}",false_positives);
+    }
+
+    #[test]
+    fn test_precheck() {
+        let entries = (0..1000).map(|i| (format!("k{}",i % 500).into_bytes(),b"v".to_vec())).collect();
+        let source = MemorySource::new(entries);
+        let check = Precheck::run(&source,10000).unwrap();
+        assert!(check.complete);
+        assert_eq!(1000,check.records);
+        assert!(check.duplicates >= 500);
+        let warnings = check.warnings(Some(10000));
+        assert_eq!(2,warnings.len());
+        assert!(check.warnings(None).len() == 1);
+        let check = Precheck::run(&source,100).unwrap();
+        assert!(!check.complete);
+        assert_eq!(100,check.records);
+        assert!(check.warnings(Some(10000)).is_empty());
+    }
+}
@@ -0,0 +1,474 @@
+use std::{cmp::Ordering, io::{self, BufRead, BufWriter, Write}};
+
+use regex::bytes::Regex;
+
+use crate::messages::{text, Msg};
+use crate::sources::{fixed::Span, invalid_data};
+
+/// Where a comment string has to be for the rest of the line to be a comment.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum CommentMode {
+    /// Only at the very start of a line.
+    Start,
+    /// At the start of a line or after leading whitespace.
+    Indented,
+    /// Anywhere, stripping trailing comments.
+    Anywhere
+}
+
+/// What to do with a `\r` before each `\n`, as written on Windows.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Crlf {
+    /// Strip it if the first line ends with one.
+    Auto,
+    Keep,
+    Strip
+}
+
+impl Crlf {
+    pub fn from_cli(value: &str) -> Crlf {
+        match value {
+            "keep" => Crlf::Keep,
+            "strip" => Crlf::Strip,
+            _ => Crlf::Auto
+        }
+    }
+}
+
+const BOM : &[u8] = b"\xEF\xBB\xBF";
+
+/// Splits a line as the flat source does: a `separator` of `None` means runs of spaces and tabs.
+pub fn split_fields<'a>(line: &'a [u8], separator: &Option<String>) -> Vec<&'a [u8]> {
+    match separator {
+        Some(sep) if !sep.is_empty() => {
+            let sep = sep.as_bytes();
+            let mut out = vec![];
+            let mut start = 0;
+            let mut at = 0;
+            while at + sep.len() <= line.len() {
+                if &line[at..at+sep.len()] == sep {
+                    out.push(&line[start..at]);
+                    at += sep.len();
+                    start = at;
+                } else {
+                    at += 1;
+                }
+            }
+            out.push(&line[start..]);
+            out
+        },
+        _ => line.split(|b| *b == b' ' || *b == b'\t').filter(|f| !f.is_empty()).collect()
+    }
+}
+
+/// As `split_fields`, but a separator between double quotes doesn't split, so on whitespace
+/// `a "b c" d` is three fields. A field wholly in quotes loses them, including `""` as an empty
+/// field: any other quotes are kept as they are. An unclosed quote runs to the end of the line.
+pub fn split_quoted_fields<'a>(line: &'a [u8], separator: &Option<String>) -> Vec<&'a [u8]> {
+    let sep = separator.as_deref().filter(|s| !s.is_empty()).map(|s| s.as_bytes());
+    let mut out = vec![];
+    let mut start = 0;
+    let mut at = 0;
+    let mut quoted = false;
+    while at < line.len() {
+        let len = match sep {
+            _ if line[at] == b'"' => { quoted = !quoted; 0 },
+            _ if quoted => 0,
+            Some(sep) if line[at..].starts_with(sep) => sep.len(),
+            None if line[at] == b' ' || line[at] == b'\t' => 1,
+            _ => 0
+        };
+        if len > 0 {
+            out.push(&line[start..at]);
+            at += len;
+            start = at;
+        } else {
+            at += 1;
+        }
+    }
+    out.push(&line[start..]);
+    out.into_iter().filter(|f| sep.is_some() || !f.is_empty()).map(|f| {
+        if f.len() >= 2 && f[0] == b'"' && f[f.len()-1] == b'"' { &f[1..f.len()-1] } else { f }
+    }).collect()
+}
+
+/// Fields of each line to build the key and value from, as spans of 1-based field numbers (see
+/// `Span`). Lines are rewritten as the key fields joined by `key_join`, the separator and the
+/// value fields joined by `joiner`, so the flat source should then take its key from field 1.
+/// Without `value` spans the value is every field not in the key. Lines missing a key field
+/// are left alone. With a `key_name` the key is instead the column of that name in the first
+/// line (the header), and input without one is an error.
+#[derive(Clone,Debug)]
+pub struct Fields {
+    key: Vec<Span>,
+    key_name: Option<String>,
+    key_join: String,
+    value: Option<Vec<Span>>,
+    separator: Option<String>,
+    joiner: String
+}
+
+impl Fields {
+    /// A `separator` of `None` means runs of spaces and tabs.
+    pub fn new(key: Vec<Span>, separator: Option<String>) -> Fields {
+        let joiner = separator.clone().unwrap_or_else(|| " ".to_string());
+        Fields { key, key_name: None, key_join: joiner.clone(), value: None, separator, joiner }
+    }
+
+    /* a key_name becomes the key span of its column in `header` */
+    fn resolve(&mut self, header: &[u8]) -> io::Result<()> {
+        if let Some(name) = self.key_name.take() {
+            let index = split_fields(header,&self.separator).iter().position(|f| *f == name.as_bytes())
+                .ok_or_else(|| invalid_data(format!("{}: {}",Msg::NoSuchColumn.id(),text(Msg::NoSuchColumn,&[&name]))))?;
+            self.key = vec![Span::single(index)];
+        }
+        Ok(())
+    }
+
+    fn select(&self, line: &[u8]) -> Option<Vec<u8>> {
+        let fields = split_fields(line,&self.separator);
+        let mut key = vec![];
+        for span in &self.key {
+            let indexes = span.indexes(fields.len());
+            if indexes.is_empty() { return None; }
+            key.extend(indexes);
+        }
+        let value : Vec<usize> = match &self.value {
+            Some(spans) => spans.iter().flat_map(|span| span.indexes(fields.len())).collect(),
+            None => (0..fields.len()).filter(|i| !key.contains(i)).collect()
+        };
+        let join = |indexes: &[usize], with: &str| indexes.iter().map(|i| fields[*i]).collect::<Vec<_>>().join(with.as_bytes());
+        let mut out = join(&key,&self.key_join);
+        out.extend_from_slice(self.separator.as_deref().unwrap_or(" ").as_bytes());
+        out.extend(join(&value,&self.joiner));
+        Some(out)
+    }
+}
+
+chain!(key_name,get_key_name,Option<String>,Fields);
+chain!(key_join,get_key_join,String,Fields);
+chain!(value,get_value,Option<Vec<Span>>,Fields);
+chain!(joiner,get_joiner,String,Fields);
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+enum Op { Eq, Ne, Lt, Le, Gt, Ge, Match, NotMatch }
+
+/* longest first, so <= isn't read as < */
+const OPS : &[(&str,Op)] = &[
+    ("==",Op::Eq),("!=",Op::Ne),("<=",Op::Le),(">=",Op::Ge),("=~",Op::Match),("!~",Op::NotMatch),("<",Op::Lt),(">",Op::Gt)
+];
+
+/// A test on one field of a line, `fieldN OP VALUE` with N counting from 1. `==` and `!=`
+/// compare exactly, `<`, `<=`, `>` and `>=` compare as numbers when both sides are numbers and
+/// as bytes otherwise, and `=~` and `!~` match VALUE as a regex. VALUE may be double-quoted,
+/// with `\"` and `\\` escapes. A line without field N fails the test.
+#[derive(Clone,Debug)]
+pub struct Predicate {
+    field: usize,
+    op: Op,
+    value: Vec<u8>,
+    regex: Option<Regex>
+}
+
+fn unquote(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push(chars.next()?),
+            '"' => { return None; },
+            c => out.push(c)
+        }
+    }
+    Some(out)
+}
+
+fn compare(a: &[u8], b: &[u8]) -> Ordering {
+    let number = |v: &[u8]| std::str::from_utf8(v).ok().and_then(|s| s.trim().parse::<f64>().ok());
+    match (number(a),number(b)) {
+        (Some(a),Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => a.cmp(b)
+    }
+}
+
+impl Predicate {
+    pub fn parse(text: &str) -> Result<Predicate,String> {
+        let bad = || format!("bad predicate '{}': expected fieldN OP VALUE",text);
+        let rest = text.trim().strip_prefix("field").ok_or_else(bad)?;
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let field = rest[..digits].parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(bad)?;
+        let rest = rest[digits..].trim_start();
+        let (name,op) = OPS.iter().find(|(name,_)| rest.starts_with(name)).ok_or_else(bad)?;
+        let value = rest[name.len()..].trim();
+        let value = if value.starts_with('"') { unquote(value).ok_or_else(bad)? } else { value.to_string() };
+        let regex = match op {
+            Op::Match | Op::NotMatch => Some(Regex::new(&value).map_err(|e| format!("bad predicate '{}': {}",text,e))?),
+            _ => None
+        };
+        Ok(Predicate { field, op: *op, value: value.into_bytes(), regex })
+    }
+
+    fn test(&self, fields: &[&[u8]]) -> bool {
+        let field = match fields.get(self.field-1) { Some(f) => *f, None => { return false; } };
+        match self.op {
+            Op::Eq => field == &self.value[..],
+            Op::Ne => field != &self.value[..],
+            Op::Lt => compare(field,&self.value) == Ordering::Less,
+            Op::Le => compare(field,&self.value) != Ordering::Greater,
+            Op::Gt => compare(field,&self.value) == Ordering::Greater,
+            Op::Ge => compare(field,&self.value) != Ordering::Less,
+            Op::Match => self.regex.as_ref().map(|r| r.is_match(field)).unwrap_or(false),
+            Op::NotMatch => self.regex.as_ref().map(|r| !r.is_match(field)).unwrap_or(false)
+        }
+    }
+}
+
+/// Which lines to keep: those matching every pattern and passing every predicate, with fields
+/// split as for `Fields`.
+#[derive(Clone,Debug)]
+pub struct RowFilter {
+    patterns: Vec<Regex>,
+    predicates: Vec<Predicate>,
+    separator: Option<String>
+}
+
+impl RowFilter {
+    /// A `separator` of `None` means runs of spaces and tabs.
+    pub fn new(separator: Option<String>) -> RowFilter {
+        RowFilter { patterns: vec![], predicates: vec![], separator }
+    }
+
+    pub fn keep(&self, line: &[u8]) -> bool {
+        if !self.patterns.iter().all(|p| p.is_match(line)) { return false; }
+        if self.predicates.is_empty() { return true; }
+        let fields = split_fields(line,&self.separator);
+        self.predicates.iter().all(|p| p.test(&fields))
+    }
+}
+
+chain!(patterns,get_patterns,Vec<Regex>,RowFilter);
+chain!(predicates,get_predicates,Vec<Predicate>,RowFilter);
+
+/// Line-level clean-up of flat input, for things `NCDFlatConfig` can't express: Windows line
+/// endings and a UTF-8 BOM, skipping leading lines, comments, then row filtering, then field
+/// selection. It's applied while the input is spooled, so the flat source only ever sees the
+/// cleaned lines. Line endings alone don't make it active: see `fixes_line_endings`.
+#[derive(Clone,Debug)]
+pub struct Prepare {
+    crlf: Crlf,
+    strip_bom: bool,
+    skip_lines: usize,
+    comments: Vec<String>,
+    comment_mode: CommentMode,
+    filter: Option<RowFilter>,
+    fields: Option<Fields>
+}
+
+impl Prepare {
+    pub fn new() -> Prepare {
+        Prepare {
+            crlf: Crlf::Auto,
+            strip_bom: true,
+            skip_lines: 0,
+            comments: vec![],
+            comment_mode: CommentMode::Start,
+            filter: None,
+            fields: None
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.skip_lines > 0 || !self.comments.is_empty() || self.filter.is_some() || self.fields.is_some()
+    }
+
+    /// Whether input starting with `head` needs its BOM or `\r`s stripping, for input which
+    /// isn't otherwise prepared.
+    pub fn fixes_line_endings(&self, head: &[u8]) -> bool {
+        (self.strip_bom && head.starts_with(BOM)) || match self.crlf {
+            Crlf::Keep => false,
+            Crlf::Strip => head.contains(&b'\r'),
+            Crlf::Auto => head.iter().position(|b| *b == b'\n').map(|at| at > 0 && head[at-1] == b'\r').unwrap_or(false)
+        }
+    }
+
+    fn comment_at(&self, line: &[u8]) -> Option<usize> {
+        let starts = |at: usize| self.comments.iter().any(|c| line[at..].starts_with(c.as_bytes()));
+        match self.comment_mode {
+            CommentMode::Start => if starts(0) { Some(0) } else { None },
+            CommentMode::Indented => {
+                let at = line.iter().position(|b| *b != b' ' && *b != b'\t').unwrap_or(line.len());
+                if starts(at) { Some(0) } else { None }
+            },
+            CommentMode::Anywhere => (0..line.len()).find(|at| starts(*at))
+        }
+    }
+
+    /// The cleaned line, or `None` if it should be dropped.
+    pub fn line<'a>(&self, line: &'a [u8]) -> Option<&'a [u8]> {
+        match self.comment_at(line) {
+            Some(0) => None,
+            Some(at) => Some(&line[..at]),
+            None => Some(line)
+        }
+    }
+
+    pub fn run<R: BufRead, W: Write>(&self, mut input: R, out: W) -> io::Result<()> {
+        let mut out = BufWriter::new(out);
+        let mut line = vec![];
+        let mut number = 0;
+        let mut strip_cr = self.crlf == Crlf::Strip;
+        let mut fields = self.fields.clone();
+        loop {
+            line.clear();
+            if input.read_until(b'\n',&mut line)? == 0 { break; }
+            number += 1;
+            let mut body = line.strip_suffix(b"\n").unwrap_or(&line);
+            if number == 1 {
+                if self.strip_bom { body = body.strip_prefix(BOM).unwrap_or(body); }
+                if self.crlf == Crlf::Auto { strip_cr = body.ends_with(b"\r"); }
+            }
+            if strip_cr { body = body.strip_suffix(b"\r").unwrap_or(body); }
+            if number <= self.skip_lines { continue; }
+            if let Some(body) = self.line(body) {
+                if let Some(filter) = &self.filter {
+                    if !filter.keep(body) { continue; }
+                }
+                if let Some(fields) = fields.as_mut().filter(|f| f.key_name.is_some()) {
+                    if !body.iter().all(|b| b.is_ascii_whitespace()) { fields.resolve(body)?; }
+                }
+                match fields.as_ref().and_then(|f| f.select(body)) {
+                    Some(selected) => out.write_all(&selected)?,
+                    None => out.write_all(body)?
+                }
+                out.write_all(b"\n")?;
+            }
+        }
+        out.flush()
+    }
+}
+
+chain!(crlf,get_crlf,Crlf,Prepare);
+chain!(strip_bom,get_strip_bom,bool,Prepare);
+chain!(skip_lines,get_skip_lines,usize,Prepare);
+chain!(comments,get_comments,Vec<String>,Prepare);
+chain!(comment_mode,get_comment_mode,CommentMode,Prepare);
+chain!(filter,get_filter,Option<RowFilter>,Prepare);
+chain!(fields,get_fields,Option<Fields>,Prepare);
+
+#[cfg(test)]
+mod test {
+    use regex::bytes::Regex;
+
+    use crate::sources::fixed::Span;
+    use super::{split_fields, split_quoted_fields, CommentMode, Crlf, Fields, Predicate, Prepare, RowFilter};
+
+    fn run(prepare: &Prepare, data: &str) -> String {
+        let mut out = vec![];
+        prepare.run(data.as_bytes(),&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_split_quoted_fields() {
+        let fields = |line: &str, sep: Option<&str>| split_quoted_fields(line.as_bytes(),&sep.map(|s| s.to_string())).iter().map(|f| String::from_utf8_lossy(f).to_string()).collect::<Vec<_>>();
+        assert_eq!(vec!["a","b c","d"],fields("a  \"b c\"\td",None));
+        assert_eq!(vec!["gene_id","\"ENSG 1\";"],fields("gene_id \"ENSG 1\";",None));
+        assert_eq!(vec!["x","","y"],fields("x \"\" y",None));
+        assert_eq!(vec!["a","b,c",""],fields("a,\"b,c\",",Some(",")));
+        assert_eq!(vec!["a","\"b c"],fields("a \"b c",None));
+        /* without quotes, just as split_fields */
+        assert_eq!(split_fields(b"a  b\tc",&None),split_quoted_fields(b"a  b\tc",&None));
+    }
+
+    #[test]
+    fn test_comments() {
+        let data = "# a\n  ; b\nc 1 # x\nd 2 ; y\n";
+        let prepare = Prepare::new().comments(vec!["#".to_string(),";".to_string()]);
+        assert_eq!("  ; b\nc 1 # x\nd 2 ; y\n",run(&prepare,data));
+        let prepare = prepare.comment_mode(CommentMode::Indented);
+        assert_eq!("c 1 # x\nd 2 ; y\n",run(&prepare,data));
+        let prepare = prepare.comment_mode(CommentMode::Anywhere);
+        assert_eq!("c 1 \nd 2 \n",run(&prepare,data));
+        let prepare = Prepare::new().comments(vec!["//".to_string()]).comment_mode(CommentMode::Anywhere);
+        assert_eq!("a /x\nb \n",run(&prepare,"a /x\nb // y"));
+        assert!(!Prepare::new().is_active());
+        let prepare = Prepare::new().skip_lines(2).comments(vec!["#".to_string()]);
+        assert_eq!("c 1 # x\n",run(&prepare,"# a\nb\n# c\nc 1 # x\n"));
+        assert_eq!("",run(&prepare,"a\n"));
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let data = "\u{FEFF}a 1\r\nb 2\r\nc 3\r";
+        let prepare = Prepare::new();
+        assert_eq!("a 1\nb 2\nc 3\n",run(&prepare,data));
+        assert!(prepare.fixes_line_endings(data.as_bytes()));
+        assert!(prepare.fixes_line_endings(b"a 1\r\nb"));
+        assert!(!prepare.fixes_line_endings(b"a 1\nb\r\n"));
+        assert_eq!("a\nb\r\n",run(&prepare,"a\nb\r\n"));
+        let prepare = Prepare::new().crlf(Crlf::Strip);
+        assert_eq!("a\nb\n",run(&prepare,"a\nb\r\n"));
+        let prepare = Prepare::new().crlf(Crlf::Keep).strip_bom(false);
+        assert!(!prepare.fixes_line_endings(data.as_bytes()));
+        assert_eq!("\u{FEFF}a 1\r\nb 2\r\nc 3\r\n",run(&prepare,data));
+        let prepare = Prepare::new().comments(vec!["#".to_string()]).skip_lines(1);
+        assert_eq!("b\n",run(&prepare,"\u{FEFF}x\r\n#\r\nb\r\n"));
+    }
+
+    #[test]
+    fn test_fields() {
+        let spans = |s: &str| s.split(',').map(|s| Span::parse(s).unwrap()).collect::<Vec<_>>();
+        let fields = Fields::new(spans("2"),None).value(Some(spans("3,5-"))).joiner("|".to_string());
+        let prepare = Prepare::new().fields(Some(fields));
+        assert!(prepare.is_active());
+        assert_eq!("k a|c|d\nonly\n\nk \n",run(&prepare,"x k  a\tb c d\nonly\n\nb k\n"));
+        let fields = Fields::new(spans("1"),Some("::".to_string())).value(Some(spans("3,5-"))).joiner(",".to_string());
+        let prepare = Prepare::new().fields(Some(fields));
+        assert_eq!("k::3,5\nk::\n",run(&prepare,"k::2::3::4::5\nk\n"));
+        let prepare = prepare.comments(vec!["#".to_string()]).comment_mode(CommentMode::Anywhere);
+        assert_eq!("k::3 \n",run(&prepare,"k::2::3 # x::4\n"));
+        let fields = Fields::new(spans("2,1"),Some("\t".to_string())).key_join(":".to_string());
+        let prepare = Prepare::new().fields(Some(fields));
+        assert_eq!("1:chr1\tA\tG\nchr2\n",run(&prepare,"chr1\t1\tA\tG\nchr2\n"));
+        let fields = Fields::new(spans("1"),Some("\t".to_string())).key_name(Some("ref".to_string())).value(Some(spans("1")));
+        let prepare = Prepare::new().fields(Some(fields.clone())).skip_lines(1);
+        assert_eq!("ref\tid\nA\trs1\n",run(&prepare,"made today\nid\tchrom\tref\nrs1\tchr1\tA\n"));
+        let mut out = vec![];
+        let error = Prepare::new().fields(Some(fields)).run(&b"id\tchrom\nrs1\tchr1\n"[..],&mut out).unwrap_err();
+        assert_eq!("NCD-E017: no column ref in the header",error.to_string());
+    }
+
+    #[test]
+    fn test_predicates() {
+        let test = |text: &str, fields: &[&str]| {
+            let fields : Vec<&[u8]> = fields.iter().map(|f| f.as_bytes()).collect();
+            Predicate::parse(text).unwrap().test(&fields)
+        };
+        assert!(test("field3 != \".\"",&["chr1","10","A"]));
+        assert!(!test("field3 != \".\"",&["chr1","10","."]));
+        assert!(!test("field3 != \".\"",&["chr1","10"]));
+        assert!(test("field2>=9.5",&["chr1","10"]));
+        assert!(!test("field2 < 9",&["chr1","10"]));
+        assert!(test("field1 < chr2",&["chr10"]));
+        assert!(test("field1 =~ ^chr[0-9]+$",&["chr10"]));
+        assert!(test("field1 !~ _alt",&["chr10"]));
+        assert!(test("field1 == \"a \\\"b\\\"\"",&["a \"b\""]));
+        for bad in &["3 != .","field0 == a","field1 ~ a","field1 == \"a","field1 =~ ("] {
+            assert!(Predicate::parse(bad).is_err(),"{}",bad);
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = RowFilter::new(Some("\t".to_string()))
+            .patterns(vec![Regex::new("^chr").unwrap()])
+            .predicates(vec![Predicate::parse("field3 != \".\"").unwrap()]);
+        let prepare = Prepare::new().filter(Some(filter)).comments(vec!["#".to_string()]);
+        assert!(prepare.is_active());
+        assert_eq!("chr1\t1\tA\n",run(&prepare,"#chr\t0\tX\nchr1\t1\tA\nchr2\t2\t.\nscaffold\t3\tC\n"));
+        let fields = Fields::new(vec![Span::parse("2").unwrap()],Some("\t".to_string()));
+        let prepare = prepare.fields(Some(fields));
+        assert_eq!("1\tchr1\tA\n",run(&prepare,"chr1\t1\tA\nchr2\t2\t.\n"));
+    }
+}
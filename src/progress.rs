@@ -0,0 +1,156 @@
+use std::{sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}}, time::Duration};
+
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use ncd::NCDValueSource;
+
+use crate::sources::SourceIter;
+
+/* how many records go by between redraws: the bar ticks itself in between */
+const UPDATE_EVERY : u64 = 4096;
+
+const COUNTING : &str = "{spinner} {prefix}: {pos} records, {msg} [{elapsed_precise}]";
+const KNOWN : &str = "{spinner} {prefix}: [{bar:30}] {pos}/{len} records, {msg}, pass ETA {eta} [{elapsed_precise}]";
+
+/// A progress line on stderr for `--progress`: the stage (eg which shard), the attempt and pass
+/// the build is on, and the records and bytes of this pass so far. Each attempt makes several
+/// passes over the input, so once one pass has finished its record count is the total, and the
+/// line gains a bar and an ETA for the pass. Nothing is drawn unless stderr is a terminal.
+pub struct Progress {
+    bar: ProgressBar,
+    stage: Mutex<String>,
+    attempt: AtomicU64,
+    pass: AtomicU64,
+    total: AtomicU64
+}
+
+impl Progress {
+    pub fn new() -> Arc<Progress> {
+        let bar = ProgressBar::with_draw_target(None,ProgressDrawTarget::stderr());
+        bar.set_style(ProgressStyle::with_template(COUNTING).expect("bad progress template"));
+        bar.enable_steady_tick(Duration::from_millis(250));
+        Arc::new(Progress { bar, stage: Mutex::new(String::new()), attempt: AtomicU64::new(0), pass: AtomicU64::new(0), total: AtomicU64::new(0) })
+    }
+
+    /// Starts on something with a different number of records, such as the next shard: until
+    /// an attempt starts, passes are shown as just `stage`.
+    pub fn set_stage(&self, stage: &str) {
+        *self.stage.lock().unwrap() = stage.to_string();
+        self.attempt.store(0,Ordering::Relaxed);
+        self.pass.store(0,Ordering::Relaxed);
+        self.total.store(0,Ordering::Relaxed);
+        self.bar.set_style(ProgressStyle::with_template(COUNTING).expect("bad progress template"));
+    }
+
+    pub fn start_attempt(&self, attempt: u32) {
+        self.attempt.store(attempt as u64,Ordering::Relaxed);
+        self.pass.store(0,Ordering::Relaxed);
+    }
+
+    /// Prints `line` on stdout without tearing the progress line.
+    pub fn println(&self, line: &str) {
+        self.bar.suspend(|| println!("{}",line));
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+
+    fn start_pass(&self) {
+        let pass = self.pass.fetch_add(1,Ordering::Relaxed) + 1;
+        let stage = self.stage.lock().unwrap().clone();
+        let prefix = match (self.attempt.load(Ordering::Relaxed),stage.is_empty()) {
+            (0,_) => stage,
+            (attempt,true) => format!("attempt {}, pass {}",attempt,pass),
+            (attempt,false) => format!("{}, attempt {}, pass {}",stage,attempt,pass)
+        };
+        self.bar.set_prefix(prefix);
+        self.bar.set_position(0);
+        self.bar.reset_eta();
+    }
+
+    fn update(&self, records: u64, bytes: u64) {
+        self.bar.set_position(records);
+        self.bar.set_message(HumanBytes(bytes).to_string());
+    }
+
+    fn end_pass(&self, records: u64, bytes: u64) {
+        self.update(records,bytes);
+        if self.total.fetch_max(records,Ordering::Relaxed) < records {
+            self.bar.set_length(records);
+            self.bar.set_style(ProgressStyle::with_template(KNOWN).expect("bad progress template").progress_chars("=> "));
+        }
+    }
+}
+
+/// Reports each pass over `inner` to a `Progress`.
+pub struct ProgressSource {
+    inner: Box<dyn NCDValueSource>,
+    progress: Arc<Progress>
+}
+
+impl ProgressSource {
+    pub fn new(inner: Box<dyn NCDValueSource>, progress: &Arc<Progress>) -> ProgressSource {
+        ProgressSource { inner, progress: progress.clone() }
+    }
+}
+
+struct ProgressIterator<'a> {
+    inner: SourceIter<'a>,
+    progress: &'a Progress,
+    records: u64,
+    bytes: u64
+}
+
+impl<'a> Iterator for ProgressIterator<'a> {
+    type Item = std::io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.inner.next();
+        match &entry {
+            Some(Ok((key,value))) => {
+                self.records += 1;
+                self.bytes += (key.len() + value.len()) as u64;
+                if self.records % UPDATE_EVERY == 0 { self.progress.update(self.records,self.bytes); }
+            },
+            Some(Err(_)) => {},
+            None => { self.progress.end_pass(self.records,self.bytes); }
+        }
+        entry
+    }
+}
+
+impl NCDValueSource for ProgressSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        self.progress.start_pass();
+        Box::new(ProgressIterator { inner: self.inner.iter(), progress: &self.progress, records: 0, bytes: 0 })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use crate::sources::memory::MemorySource;
+    use super::{Progress, ProgressSource};
+
+    #[test]
+    fn test_progress_source() {
+        let progress = Progress::new();
+        let records = vec![(b"a".to_vec(),b"1".to_vec()),(b"b".to_vec(),b"22".to_vec())];
+        let source = ProgressSource::new(Box::new(MemorySource::new(records.clone())),&progress);
+        progress.start_attempt(1);
+        assert_eq!(records,source.iter().collect::<Result<Vec<_>,_>>().unwrap());
+        assert_eq!(Some(2),progress.bar.length());
+        assert_eq!(2,progress.bar.position());
+        assert_eq!(2,source.iter().count());
+        assert_eq!(2,progress.pass.load(std::sync::atomic::Ordering::Relaxed));
+        progress.set_stage("shard 1 of 2");
+        assert_eq!(0,progress.pass.load(std::sync::atomic::Ordering::Relaxed));
+        assert_eq!(2,source.iter().count());
+        assert_eq!("shard 1 of 2",progress.bar.prefix());
+        progress.start_attempt(1);
+        assert_eq!(2,source.iter().count());
+        assert_eq!("shard 1 of 2, attempt 1, pass 1",progress.bar.prefix());
+        progress.finish();
+    }
+}
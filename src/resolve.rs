@@ -0,0 +1,221 @@
+use std::{collections::HashMap, fs::File, io::{self, BufRead, BufReader}, path::Path};
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::sources::{invalid_data, longkey::hashed_key};
+
+/// Turns a query key into the keys to try, in order. Resolvers stack by wrapping one another, so
+/// `ncd-lookup` and a server can share the same chain.
+pub trait KeyResolver {
+    fn candidates(&self, key: &[u8]) -> Vec<Vec<u8>>;
+}
+
+/// The key as given.
+pub struct DirectResolver;
+
+impl KeyResolver for DirectResolver {
+    fn candidates(&self, key: &[u8]) -> Vec<Vec<u8>> {
+        vec![key.to_vec()]
+    }
+}
+
+/// Tries whatever `inner` tries, followed by the aliases of each of those keys.
+pub struct AliasResolver {
+    inner: Box<dyn KeyResolver>,
+    aliases: HashMap<Vec<u8>,Vec<Vec<u8>>>
+}
+
+impl AliasResolver {
+    pub fn new(inner: Box<dyn KeyResolver>) -> AliasResolver {
+        AliasResolver { inner, aliases: HashMap::new() }
+    }
+
+    pub fn add(&mut self, from: &[u8], to: &[u8]) {
+        self.aliases.entry(from.to_vec()).or_insert_with(Vec::new).push(to.to_vec());
+    }
+
+    /// Reads `FROM<tab>TO` lines, one alias per line. A key may have several aliases, tried in
+    /// file order. Blank lines and lines starting `#` are skipped.
+    pub fn load(mut self, path: &Path) -> io::Result<AliasResolver> {
+        let file = File::open(path).map_err(|e| io::Error::new(e.kind(),format!("Cannot open {}: {}",path.display(),e)))?;
+        for (i,line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() || line.starts_with('#') { continue; }
+            match line.split_once('\t') {
+                Some((from,to)) if !from.is_empty() && !to.is_empty() => { self.add(from.as_bytes(),to.as_bytes()); },
+                _ => { return Err(invalid_data(format!("{}: line {}: expected FROM<tab>TO",path.display(),i+1))); }
+            }
+        }
+        Ok(self)
+    }
+}
+
+impl KeyResolver for AliasResolver {
+    fn candidates(&self, key: &[u8]) -> Vec<Vec<u8>> {
+        let direct = self.inner.candidates(key);
+        let aliases = direct.iter().flat_map(|k| self.aliases.get(k).into_iter().flatten().cloned()).collect::<Vec<_>>();
+        let mut out = direct;
+        for alias in aliases {
+            if !out.contains(&alias) { out.push(alias); }
+        }
+        out
+    }
+}
+
+/// One step of key canonicalization, as given to `ncd-build --normalize-key`. Steps apply in
+/// the order given.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum KeyNormalize {
+    Lower,
+    Upper,
+    Nfc,
+    Nfkc,
+    /// Unicode full case folding (so `ß` matches `SS`), for caseless rather than lowercase keys.
+    Casefold,
+    Trim
+}
+
+impl KeyNormalize {
+    pub fn from_cli(value: &str) -> Option<KeyNormalize> {
+        match value {
+            "lower" => Some(KeyNormalize::Lower),
+            "upper" => Some(KeyNormalize::Upper),
+            "nfc" => Some(KeyNormalize::Nfc),
+            "nfkc" => Some(KeyNormalize::Nfkc),
+            "casefold" => Some(KeyNormalize::Casefold),
+            "trim" => Some(KeyNormalize::Trim),
+            _ => None
+        }
+    }
+}
+
+/// Applies each step to `key` in turn. Keys which aren't UTF-8 are passed through untouched.
+pub fn normalize_key(steps: &[KeyNormalize], key: Vec<u8>) -> Vec<u8> {
+    let mut text = match String::from_utf8(key) {
+        Ok(text) => text,
+        Err(e) => { return e.into_bytes(); }
+    };
+    for step in steps {
+        text = match step {
+            KeyNormalize::Lower => text.to_lowercase(),
+            KeyNormalize::Upper => text.to_uppercase(),
+            KeyNormalize::Nfc => text.nfc().collect(),
+            KeyNormalize::Nfkc => text.nfkc().collect(),
+            KeyNormalize::Casefold => caseless::default_case_fold_str(&text),
+            KeyNormalize::Trim => text.trim().to_string()
+        };
+    }
+    text.into_bytes()
+}
+
+/// Rewrites whatever `inner` tries to match keys canonicalized at build time. Keys which aren't
+/// UTF-8 are passed through untouched.
+pub struct NormalizingResolver {
+    inner: Box<dyn KeyResolver>,
+    steps: Vec<KeyNormalize>
+}
+
+impl NormalizingResolver {
+    /// The same steps as the file was built with.
+    pub fn new(inner: Box<dyn KeyResolver>, steps: &[KeyNormalize]) -> NormalizingResolver {
+        NormalizingResolver { inner, steps: steps.to_vec() }
+    }
+}
+
+impl KeyResolver for NormalizingResolver {
+    fn candidates(&self, key: &[u8]) -> Vec<Vec<u8>> {
+        let mut out = vec![];
+        for candidate in self.inner.candidates(key) {
+            let candidate = normalize_key(&self.steps,candidate);
+            if !out.contains(&candidate) { out.push(candidate); }
+        }
+        out
+    }
+}
+
+/// Rewrites whatever `inner` tries which is over `max` bytes to its hashed form, to match a file
+/// built with `--long-keys hash`.
+pub struct LongKeyResolver {
+    inner: Box<dyn KeyResolver>,
+    max: usize
+}
+
+impl LongKeyResolver {
+    pub fn new(inner: Box<dyn KeyResolver>, max: usize) -> LongKeyResolver {
+        LongKeyResolver { inner, max }
+    }
+}
+
+impl KeyResolver for LongKeyResolver {
+    fn candidates(&self, key: &[u8]) -> Vec<Vec<u8>> {
+        self.inner.candidates(key).into_iter().map(|k| if k.len() > self.max { hashed_key(&k) } else { k }).collect()
+    }
+}
+
+/// Looks up each candidate in turn with `get`, returning the first key that matched and its value.
+#[cfg_attr(feature="trace",tracing::instrument(skip_all,fields(key=%String::from_utf8_lossy(key))))]
+pub fn resolve<F,E>(resolver: &dyn KeyResolver, key: &[u8], mut get: F) -> Result<Option<(Vec<u8>,Vec<u8>)>,E>
+        where F: FnMut(&[u8]) -> Result<Option<Vec<u8>>,E> {
+    for candidate in resolver.candidates(key) {
+        #[cfg(feature="trace")]
+        let _span = tracing::debug_span!("get",candidate=%String::from_utf8_lossy(&candidate)).entered();
+        if let Some(value) = get(&candidate)? {
+            return Ok(Some((candidate,value)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, io::Write};
+
+    use crate::sources::longkey::hashed_key;
+    use super::{normalize_key, resolve, AliasResolver, DirectResolver, KeyNormalize, KeyResolver, LongKeyResolver, NormalizingResolver};
+
+    #[test]
+    fn test_aliases() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"# old symbol\tcurrent\nBRCC1\tBRCA1\nFANCD1\tBRCA2\nFANCD1\tENSG00000139618\n").unwrap();
+        let resolver = AliasResolver::new(Box::new(DirectResolver)).load(file.path()).unwrap();
+        assert_eq!(vec![b"FANCD1".to_vec(),b"BRCA2".to_vec(),b"ENSG00000139618".to_vec()],resolver.candidates(b"FANCD1"));
+        assert_eq!(vec![b"TP53".to_vec()],resolver.candidates(b"TP53"));
+        let data : HashMap<&[u8],&[u8]> = vec![(&b"BRCA2"[..],&b"13"[..]),(&b"TP53"[..],&b"17"[..])].into_iter().collect();
+        let get = |k: &[u8]| -> Result<Option<Vec<u8>>,()> { Ok(data.get(k).map(|v| v.to_vec())) };
+        assert_eq!(Ok(Some((b"BRCA2".to_vec(),b"13".to_vec()))),resolve(&resolver,b"FANCD1",get));
+        assert_eq!(Ok(Some((b"TP53".to_vec(),b"17".to_vec()))),resolve(&resolver,b"TP53",get));
+        assert_eq!(Ok(None),resolve(&resolver,b"BRCC1",get));
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"BRCC1 BRCA1\n").unwrap();
+        assert!(AliasResolver::new(Box::new(DirectResolver)).load(file.path()).is_err());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let decomposed = "Cafe\u{301}";
+        let resolver = NormalizingResolver::new(Box::new(DirectResolver),&[KeyNormalize::Nfc]);
+        assert_eq!(vec!["Caf\u{e9}".as_bytes().to_vec()],resolver.candidates(decomposed.as_bytes()));
+        let resolver = NormalizingResolver::new(Box::new(DirectResolver),&[KeyNormalize::Nfkc,KeyNormalize::Lower]);
+        assert_eq!(vec!["caf\u{e9} 2".as_bytes().to_vec()],resolver.candidates("Caf\u{e9} \u{2082}".as_bytes()));
+        assert_eq!(vec![b"\xff".to_vec()],resolver.candidates(b"\xff"));
+        let steps = [KeyNormalize::Trim,KeyNormalize::Upper];
+        assert_eq!(b"BRCA1".to_vec(),normalize_key(&steps,b" brca1\t".to_vec()));
+        assert_eq!(b" \xff".to_vec(),normalize_key(&steps,b" \xff".to_vec()));
+        let resolver = NormalizingResolver::new(Box::new(DirectResolver),&steps);
+        assert_eq!(vec![b"BRCA1".to_vec()],resolver.candidates(b"Brca1 "));
+        assert_eq!(b"strasse".to_vec(),normalize_key(&[KeyNormalize::Casefold],"STRA\u{df}E".as_bytes().to_vec()));
+        assert_eq!(normalize_key(&[KeyNormalize::Casefold],"Stra\u{df}e".as_bytes().to_vec()),normalize_key(&[KeyNormalize::Casefold],b"STRASSE".to_vec()));
+        assert_eq!("\u{3c3}".as_bytes().to_vec(),normalize_key(&[KeyNormalize::Casefold],"\u{3c2}".as_bytes().to_vec()));
+        assert_eq!(Some(KeyNormalize::Nfkc),KeyNormalize::from_cli("nfkc"));
+        assert_eq!(Some(KeyNormalize::Casefold),KeyNormalize::from_cli("casefold"));
+        assert_eq!(None,KeyNormalize::from_cli("title"));
+    }
+
+    #[test]
+    fn test_long_keys() {
+        let resolver = LongKeyResolver::new(Box::new(DirectResolver),4);
+        assert_eq!(vec![b"abcd".to_vec()],resolver.candidates(b"abcd"));
+        assert_eq!(vec![hashed_key(b"abcde")],resolver.candidates(b"abcde"));
+    }
+}
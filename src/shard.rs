@@ -0,0 +1,265 @@
+use std::{fs::File, io::{self, BufReader, BufWriter, Write}, path::{Path, PathBuf}, sync::{Mutex, atomic::{AtomicU64, Ordering}}};
+
+use ncd::NCDValueSource;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::sources::{invalid_data, SourceIter};
+
+/// Parses sizes like `50G`, `512M`, `100k` or a plain byte count (binary multiples).
+pub fn parse_size(s: &str) -> Result<u64,String> {
+    let s = s.trim();
+    let (digits,multiplier) = match s.char_indices().last() {
+        Some((i,c)) if c.is_ascii_alphabetic() => {
+            let multiplier = match c.to_ascii_uppercase() {
+                'K' => 1u64 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => { return Err(format!("Invalid size suffix (expected K, M, G or T): {}",s)); }
+            };
+            (&s[..i],multiplier)
+        },
+        _ => (s,1)
+    };
+    let n = digits.parse::<u64>().map_err(|e| format!("Invalid size: {}: {}",s,e))?;
+    let size = n.checked_mul(multiplier).ok_or_else(|| format!("Size too large: {}",s))?;
+    if size == 0 { return Err(format!("Size must be more than 0: {}",s)); }
+    Ok(size)
+}
+
+/* FNV-1a: stable across platforms and releases, unlike std's hasher */
+fn fnv1a64(key: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for b in key {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn shard_of(key: &[u8], count: usize) -> usize {
+    (fnv1a64(key) % count as u64) as usize
+}
+
+/* keys kept per shard in the manifest, for ncd-verify to check the routing with */
+const SAMPLES : usize = 4;
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}",b)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 { return None; }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i+2)?,16).ok()).collect()
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(File::open(path)?),&mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+pub fn shard_path(output: &Path, index: usize) -> PathBuf {
+    let mut name = output.as_os_str().to_os_string();
+    name.push(format!(".{}",index));
+    PathBuf::from(name)
+}
+
+pub fn manifest_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_os_string();
+    name.push(".manifest.json");
+    PathBuf::from(name)
+}
+
+/// Passes on only the records of `inner` which hash to shard `index` of `count`.
+pub struct ShardSource<'a> {
+    inner: &'a dyn NCDValueSource,
+    index: usize,
+    count: usize,
+    records: AtomicU64,
+    samples: Mutex<Vec<Vec<u8>>>
+}
+
+impl<'a> ShardSource<'a> {
+    pub fn new(inner: &'a dyn NCDValueSource, index: usize, count: usize) -> ShardSource<'a> {
+        ShardSource { inner, index, count, records: AtomicU64::new(0), samples: Mutex::new(vec![]) }
+    }
+
+    /// Records passed on in the most recent pass.
+    pub fn records(&self) -> u64 { self.records.load(Ordering::Relaxed) }
+
+    /// The first few keys passed on in the most recent pass.
+    pub fn samples(&self) -> Vec<Vec<u8>> { self.samples.lock().unwrap().clone() }
+}
+
+impl<'a> NCDValueSource for ShardSource<'a> {
+    fn iter<'b>(&'b self) -> SourceIter<'b> {
+        self.records.store(0,Ordering::Relaxed);
+        self.samples.lock().unwrap().clear();
+        Box::new(self.inner.iter().filter(move |record| {
+            match record {
+                Ok((key,_)) if shard_of(key,self.count) != self.index => false,
+                Ok((key,_)) => {
+                    if self.records.fetch_add(1,Ordering::Relaxed) < SAMPLES as u64 {
+                        self.samples.lock().unwrap().push(key.clone());
+                    }
+                    true
+                },
+                Err(_) => true
+            }
+        }))
+    }
+}
+
+/// One shard of a manifest: `index` is the shard number keys hash to, and `samples` a few of
+/// the keys stored in it.
+#[derive(Clone,Debug,PartialEq)]
+pub struct ShardEntry {
+    pub index: usize,
+    pub path: String,
+    pub size: u64,
+    pub records: u64,
+    pub sha256: String,
+    pub samples: Vec<Vec<u8>>
+}
+
+/// Index of a sharded build. Shard paths are relative to the manifest's directory and a key
+/// lives in the shard with index `shard_of(key,shard_count)`. `shard_count` is recorded rather
+/// than taken from the list, so that a shard dropped from the list is noticed rather than
+/// silently sending keys to the wrong shard.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Manifest {
+    pub shard_count: usize,
+    pub shards: Vec<ShardEntry>
+}
+
+impl Manifest {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "version": 1,
+            "hash": "fnv1a64",
+            "shard_count": self.shard_count,
+            "shards": self.shards.iter().map(|s| json!({
+                "index": s.index,
+                "path": s.path,
+                "size": s.size,
+                "records": s.records,
+                "sha256": s.sha256,
+                "samples": s.samples.iter().map(|k| to_hex(k)).collect::<Vec<_>>()
+            })).collect::<Vec<_>>()
+        })
+    }
+
+    pub fn from_json(value: &Value) -> io::Result<Manifest> {
+        match value.get("version").and_then(|v| v.as_u64()) {
+            Some(1) => {},
+            Some(v) => { return Err(invalid_data(format!("manifest: unsupported version {}",v))); },
+            None => { return Err(invalid_data("manifest: missing version")); }
+        }
+        if value.get("hash").and_then(|h| h.as_str()) != Some("fnv1a64") {
+            return Err(invalid_data("manifest: unknown or missing hash"));
+        }
+        let shard_count = value.get("shard_count").and_then(|c| c.as_u64()).filter(|c| *c > 0)
+            .ok_or_else(|| invalid_data("manifest: missing shard_count"))? as usize;
+        let shards = value.get("shards").and_then(|s| s.as_array()).ok_or_else(|| invalid_data("manifest: missing shards"))?;
+        let shards = shards.iter().map(|shard| {
+            Some(ShardEntry {
+                index: shard.get("index")?.as_u64()? as usize,
+                path: shard.get("path")?.as_str()?.to_string(),
+                size: shard.get("size")?.as_u64()?,
+                records: shard.get("records")?.as_u64()?,
+                sha256: shard.get("sha256")?.as_str()?.to_string(),
+                samples: shard.get("samples")?.as_array()?.iter().map(|k| from_hex(k.as_str()?)).collect::<Option<Vec<_>>>()?
+            })
+        }).collect::<Option<Vec<_>>>().ok_or_else(|| invalid_data("manifest: bad shard entry"))?;
+        if shards.is_empty() {
+            return Err(invalid_data("manifest: no shards"));
+        }
+        Ok(Manifest { shard_count, shards })
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut out,&self.to_json()).map_err(invalid_data)?;
+        writeln!(out)?;
+        out.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Manifest> {
+        let value : Value = serde_json::from_reader(BufReader::new(File::open(path)?))
+            .map_err(|e| invalid_data(format!("{}: {}",path.display(),e)))?;
+        Manifest::from_json(&value)
+    }
+
+    /// The shard holding `key`, or None if the manifest has lost it.
+    pub fn shard_for(&self, key: &[u8]) -> Option<&ShardEntry> {
+        let index = shard_of(key,self.shard_count);
+        self.shards.iter().find(|s| s.index == index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use ncd::NCDValueSource;
+
+    use crate::sources::memory::MemorySource;
+    use super::{from_hex, manifest_path, parse_size, sha256_file, shard_of, shard_path, to_hex, Manifest, ShardEntry, ShardSource};
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(Ok(50<<30),parse_size("50G"));
+        assert_eq!(Ok(512<<20),parse_size("512m"));
+        assert_eq!(Ok(1000),parse_size("1000"));
+        assert!(parse_size("5X").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("0K").is_err());
+    }
+
+    #[test]
+    fn test_shards() {
+        let entries = (0..100).map(|i| (format!("k{}",i).into_bytes(),b"v".to_vec())).collect();
+        let source = MemorySource::new(entries);
+        let mut total = 0;
+        for index in 0..3 {
+            let shard = ShardSource::new(&source,index,3);
+            let keys : Vec<_> = shard.iter().map(|e| e.unwrap().0).collect();
+            assert!(keys.iter().all(|k| shard_of(k,3) == index));
+            assert_eq!(keys.len() as u64,shard.records());
+            assert_eq!(keys[..4].to_vec(),shard.samples());
+            total += keys.len();
+        }
+        assert_eq!(100,total);
+        assert_eq!(Path::new("out.ncd.2"),shard_path(Path::new("out.ncd"),2));
+        assert_eq!(Path::new("out.ncd.manifest.json"),manifest_path(Path::new("out.ncd")));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let mut manifest = Manifest { shard_count: 2, shards: vec![
+            ShardEntry { index: 0, path: "out.ncd.0".to_string(), size: 10, records: 2, sha256: "00".to_string(), samples: vec![b"a".to_vec()] },
+            ShardEntry { index: 1, path: "out.ncd.1".to_string(), size: 20, records: 3, sha256: "11".to_string(), samples: vec![vec![0,255]] }
+        ]};
+        assert_eq!(manifest,Manifest::from_json(&manifest.to_json()).unwrap());
+        assert_eq!(Some(&manifest.shards[shard_of(b"BRCA2",2)]),manifest.shard_for(b"BRCA2"));
+        let lost = shard_of(b"BRCA2",2);
+        manifest.shards.retain(|s| s.index != lost);
+        assert_eq!(None,manifest.shard_for(b"BRCA2"));
+        assert!(Manifest::from_json(&serde_json::json!({"version":1,"hash":"fnv1a64","shards":[]})).is_err());
+        assert!(Manifest::from_json(&serde_json::json!({"version":1,"hash":"md5","shards":[]})).is_err());
+        assert!(Manifest::from_json(&serde_json::json!({"version":2,"hash":"fnv1a64","shards":[]})).is_err());
+    }
+
+    #[test]
+    fn test_sha256() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(),b"abc").unwrap();
+        assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",sha256_file(file.path()).unwrap());
+        assert_eq!(Some(vec![0,171,255]),from_hex(&to_hex(&[0,171,255])));
+        assert_eq!(None,from_hex("abc"));
+        assert_eq!(None,from_hex("zz"));
+    }
+}
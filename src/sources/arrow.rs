@@ -0,0 +1,204 @@
+use std::{collections::VecDeque, fs::File, io::{self, BufReader, Read, Seek, SeekFrom}, path::{Path, PathBuf}, sync::Arc};
+
+use arrow::{array::{Array, BinaryArray, LargeBinaryArray, LargeStringArray, StringArray}, error::ArrowError, ipc::reader::{FileReader, StreamReader}, record_batch::{RecordBatch, RecordBatchReader}, util::display::{ArrayFormatter, FormatOptions}};
+use ncd::NCDValueSource;
+
+use super::{check_exists, counting::{report_skip, Tally}, invalid_data, SourceIter};
+
+const FILE_MAGIC : &[u8] = b"ARROW1";
+
+/// Rows of an Arrow IPC file (including Feather v2) or stream, read a record batch at a time.
+/// The key is taken from column `key_col` and the value from `value_col`, as for parquet:
+/// string and binary columns are stored as their bytes and anything else as its display form.
+/// Rows with a null key are skipped (and reported to any `tally`), and a null value is stored
+/// empty.
+#[derive(Clone,Debug)]
+pub struct ArrowConfig {
+    key_col: String,
+    value_col: String,
+    tally: Option<Arc<Tally>>
+}
+
+impl ArrowConfig {
+    pub fn new() -> ArrowConfig {
+        ArrowConfig {
+            key_col: "key".to_string(),
+            value_col: "value".to_string(),
+            tally: None
+        }
+    }
+
+    /// Report rows skipped for a null key to `tally`.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> ArrowConfig {
+        self.tally = Some(tally.clone());
+        self
+    }
+}
+
+chain!(key_col,get_key_col,String,ArrowConfig);
+chain!(value_col,get_value_col,String,ArrowConfig);
+
+pub struct ArrowSource {
+    path: PathBuf,
+    config: ArrowConfig
+}
+
+impl ArrowSource {
+    pub fn new(path: &Path, config: &ArrowConfig) -> io::Result<ArrowSource> {
+        check_exists(path)?;
+        Ok(ArrowSource { path: path.to_path_buf(), config: config.clone() })
+    }
+
+    /* the file format starts with its magic, the stream format goes straight into messages */
+    fn batches(&self) -> io::Result<(Box<dyn RecordBatchReader>,usize,usize)> {
+        let mut file = BufReader::new(File::open(&self.path)?);
+        let mut magic = [0;6];
+        let is_file = file.read_exact(&mut magic).is_ok() && magic == FILE_MAGIC;
+        file.seek(SeekFrom::Start(0))?;
+        let reader : Box<dyn RecordBatchReader> = if is_file {
+            Box::new(FileReader::try_new(file,None).map_err(invalid_data)?)
+        } else {
+            Box::new(StreamReader::try_new(file,None).map_err(invalid_data)?)
+        };
+        let schema = reader.schema();
+        let mut index = vec![];
+        for column in &[&self.config.key_col,&self.config.value_col] {
+            index.push(schema.index_of(column).map_err(|_| {
+                let columns = schema.fields().iter().map(|f| f.name().to_string()).collect::<Vec<_>>();
+                invalid_data(format!("no such column: {} (have {})",column,columns.join(", ")))
+            })?);
+        }
+        Ok((reader,index[0],index[1]))
+    }
+}
+
+fn column_bytes(array: &dyn Array) -> Result<Vec<Option<Vec<u8>>>,ArrowError> {
+    let any = array.as_any();
+    let cell = |i: usize, bytes: &[u8]| if array.is_null(i) { None } else { Some(bytes.to_vec()) };
+    if let Some(a) = any.downcast_ref::<StringArray>() {
+        return Ok((0..a.len()).map(|i| cell(i,a.value(i).as_bytes())).collect());
+    }
+    if let Some(a) = any.downcast_ref::<LargeStringArray>() {
+        return Ok((0..a.len()).map(|i| cell(i,a.value(i).as_bytes())).collect());
+    }
+    if let Some(a) = any.downcast_ref::<BinaryArray>() {
+        return Ok((0..a.len()).map(|i| cell(i,a.value(i))).collect());
+    }
+    if let Some(a) = any.downcast_ref::<LargeBinaryArray>() {
+        return Ok((0..a.len()).map(|i| cell(i,a.value(i))).collect());
+    }
+    let formatter = ArrayFormatter::try_new(array,&FormatOptions::default())?;
+    Ok((0..array.len()).map(|i| {
+        if array.is_null(i) { None } else { Some(formatter.value(i).to_string().into_bytes()) }
+    }).collect())
+}
+
+/* None for a row with a null key */
+fn batch_entries(batch: &RecordBatch, key: usize, value: usize) -> Result<VecDeque<Option<(Vec<u8>,Vec<u8>)>>,ArrowError> {
+    let keys = column_bytes(batch.column(key).as_ref())?;
+    let values = column_bytes(batch.column(value).as_ref())?;
+    Ok(keys.into_iter().zip(values.into_iter()).map(|(k,v)| k.map(|k| (k,v.unwrap_or_default()))).collect())
+}
+
+struct ArrowIterator<'a> {
+    batches: Box<dyn RecordBatchReader>,
+    key: usize,
+    value: usize,
+    config: &'a ArrowConfig,
+    row: usize,
+    pending: VecDeque<Option<(Vec<u8>,Vec<u8>)>>
+}
+
+impl<'a> Iterator for ArrowIterator<'a> {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                self.row += 1;
+                match entry {
+                    Some(entry) => { return Some(Ok(entry)); },
+                    None => {
+                        let what = format!("row {}: null {}",self.row,self.config.key_col);
+                        if let Err(e) = report_skip(&self.config.tally,what) { return Some(Err(e)); }
+                        continue;
+                    }
+                }
+            }
+            let batch = match self.batches.next()? {
+                Ok(batch) => batch,
+                Err(e) => { return Some(Err(invalid_data(e))); }
+            };
+            match batch_entries(&batch,self.key,self.value) {
+                Ok(entries) => { self.pending = entries; },
+                Err(e) => { return Some(Err(invalid_data(e))); }
+            }
+        }
+    }
+}
+
+impl NCDValueSource for ArrowSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match self.batches() {
+            Ok((batches,key,value)) => Box::new(ArrowIterator { batches, key, value, config: &self.config, row: 0, pending: VecDeque::new() }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{array::{ArrayRef, BinaryArray, Int64Array, StringArray}, datatypes::{DataType, Field, Schema}, ipc::writer::{FileWriter, StreamWriter}, record_batch::RecordBatch};
+    use ncd::NCDValueSource;
+
+    use crate::sources::counting::Tally;
+    use super::{ArrowConfig, ArrowSource};
+
+    fn batches() -> (Arc<Schema>,Vec<RecordBatch>) {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id",DataType::Utf8,true),
+            Field::new("count",DataType::Int64,true),
+            Field::new("blob",DataType::Binary,true)
+        ]));
+        let batch = |ids: Vec<Option<&str>>, counts: Vec<Option<i64>>, blobs: Vec<Option<&[u8]>>| {
+            RecordBatch::try_new(schema.clone(),vec![
+                Arc::new(StringArray::from(ids)) as ArrayRef,
+                Arc::new(Int64Array::from(counts)) as ArrayRef,
+                Arc::new(BinaryArray::from(blobs)) as ArrayRef
+            ]).unwrap()
+        };
+        let first = batch(vec![Some("a"),Some("b")],vec![Some(42),None],vec![None,Some(&[0xFF,0x00][..])]);
+        let second = batch(vec![None,Some("c")],vec![Some(1),Some(-7)],vec![Some(&b"x"[..]),Some(&b"y"[..])]);
+        (schema,vec![first,second])
+    }
+
+    fn read(path: &std::path::Path, value: &str) -> Vec<(Vec<u8>,Vec<u8>)> {
+        let config = ArrowConfig::new().key_col("id".to_string()).value_col(value.to_string());
+        ArrowSource::new(path,&config).unwrap().iter().map(|e| e.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_arrow() {
+        let (schema,batches) = batches();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut writer = FileWriter::try_new(file.reopen().unwrap(),&schema).unwrap();
+        for batch in &batches { writer.write(batch).unwrap(); }
+        writer.finish().unwrap();
+        let stream = tempfile::NamedTempFile::new().unwrap();
+        let mut writer = StreamWriter::try_new(stream.reopen().unwrap(),&schema).unwrap();
+        for batch in &batches { writer.write(batch).unwrap(); }
+        writer.finish().unwrap();
+        for path in &[file.path(),stream.path()] {
+            assert_eq!(vec![(b"a".to_vec(),b"42".to_vec()),(b"b".to_vec(),vec![]),(b"c".to_vec(),b"-7".to_vec())],read(path,"count"));
+            assert_eq!(vec![(b"a".to_vec(),vec![]),(b"b".to_vec(),vec![0xFF,0x00]),(b"c".to_vec(),b"y".to_vec())],read(path,"blob"));
+            let tally = Tally::new();
+            tally.set_strict(true);
+            let source = ArrowSource::new(path,&ArrowConfig::new().key_col("id".to_string()).value_col("count".to_string()).tally(&tally)).unwrap();
+            assert_eq!("strict: row 3: null id",source.iter().find_map(|e| e.err()).unwrap().to_string());
+            let source = ArrowSource::new(path,&ArrowConfig::new()).unwrap();
+            assert!(source.iter().next().unwrap().is_err());
+        }
+    }
+}
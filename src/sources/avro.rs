@@ -0,0 +1,149 @@
+use std::{convert::TryFrom, fs::File, io::{self, BufReader}, path::{Path, PathBuf}, sync::Arc};
+
+use apache_avro::{types::Value, Reader};
+use ncd::NCDValueSource;
+
+use super::{check_exists, counting::{report_skip, Tally}, invalid_data, SourceIter};
+
+/// Records of an Avro object container file, read with the schema embedded in the file. The key
+/// is the field at dotted path `key_path`. The value is the field at `value_path` if given,
+/// otherwise the whole record. String, bytes and fixed values are stored as their bytes, anything
+/// else as compact JSON. Records with a null or missing key are skipped, and reported to any
+/// `tally`.
+#[derive(Clone,Debug)]
+pub struct AvroConfig {
+    key_path: String,
+    value_path: Option<String>,
+    tally: Option<Arc<Tally>>
+}
+
+impl AvroConfig {
+    pub fn new() -> AvroConfig {
+        AvroConfig {
+            key_path: "id".to_string(),
+            value_path: None,
+            tally: None
+        }
+    }
+
+    /// Report records skipped for want of a key to `tally`.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> AvroConfig {
+        self.tally = Some(tally.clone());
+        self
+    }
+}
+
+chain!(key_path,get_key_path,String,AvroConfig);
+chain!(value_path,get_value_path,Option<String>,AvroConfig);
+
+pub struct AvroSource {
+    path: PathBuf,
+    config: AvroConfig
+}
+
+impl AvroSource {
+    pub fn new(path: &Path, config: &AvroConfig) -> io::Result<AvroSource> {
+        check_exists(path)?;
+        Ok(AvroSource { path: path.to_path_buf(), config: config.clone() })
+    }
+}
+
+fn unwrap_union(value: Value) -> Value {
+    match value {
+        Value::Union(_,inner) => unwrap_union(*inner),
+        v => v
+    }
+}
+
+fn lookup_path(value: Value, path: &str) -> Option<Value> {
+    let mut here = unwrap_union(value);
+    for part in path.split('.').filter(|p| !p.is_empty()) {
+        here = match here {
+            Value::Record(fields) => fields.into_iter().find(|(name,_)| name == part)?.1,
+            Value::Map(mut map) => map.remove(part)?,
+            _ => { return None; }
+        };
+        here = unwrap_union(here);
+    }
+    Some(here)
+}
+
+fn avro_bytes(value: Value) -> io::Result<Option<Vec<u8>>> {
+    Ok(match value {
+        Value::Null => None,
+        Value::String(s) | Value::Enum(_,s) => Some(s.into_bytes()),
+        Value::Bytes(b) | Value::Fixed(_,b) => Some(b),
+        v => {
+            let json = serde_json::Value::try_from(v).map_err(invalid_data)?;
+            Some(serde_json::to_vec(&json).map_err(invalid_data)?)
+        }
+    })
+}
+
+fn record_entry(record: Value, config: &AvroConfig) -> io::Result<Option<(Vec<u8>,Vec<u8>)>> {
+    let key = match lookup_path(record.clone(),&config.key_path).map(avro_bytes).transpose()?.flatten() {
+        Some(key) => key,
+        None => { return Ok(None); }
+    };
+    let value = match &config.value_path {
+        Some(path) => lookup_path(record,path).map(avro_bytes).transpose()?.flatten().unwrap_or_default(),
+        None => avro_bytes(record)?.unwrap_or_default()
+    };
+    Ok(Some((key,value)))
+}
+
+impl NCDValueSource for AvroSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let reader = match File::open(&self.path).and_then(|f| Reader::new(BufReader::new(f)).map_err(invalid_data)) {
+            Ok(reader) => reader,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        Box::new(reader.enumerate().filter_map(move |(i,record)| {
+            match record.map_err(invalid_data).and_then(|r| record_entry(r,&self.config)) {
+                Ok(None) => report_skip(&self.config.tally,format!("record {}: no {}",i+1,self.config.key_path)).err().map(Err),
+                entry => entry.transpose()
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use apache_avro::{types::Record, Schema, Writer};
+    use ncd::NCDValueSource;
+
+    use crate::sources::counting::Tally;
+    use super::{AvroConfig, AvroSource};
+
+    #[test]
+    fn test_avro() {
+        let schema = Schema::parse_str(r#"{"type":"record","name":"gene","fields":[
+            {"name":"id","type":["null","string"]},
+            {"name":"start","type":"long"},
+            {"name":"seq","type":"bytes"}
+        ]}"#).unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut writer = Writer::new(&schema,file.reopen().unwrap());
+        for (id,start) in &[(Some("BRCA2"),32315474_i64),(None,1)] {
+            let mut record = Record::new(&schema).unwrap();
+            record.put("id",id.map(|s| s.to_string()));
+            record.put("start",*start);
+            record.put("seq",vec![0xFF_u8,0x00]);
+            writer.append(record).unwrap();
+        }
+        writer.into_inner().unwrap();
+        let tally = Tally::new();
+        let source = AvroSource::new(file.path(),&AvroConfig::new().value_path(Some("seq".to_string())).tally(&tally)).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"BRCA2".to_vec(),vec![0xFF,0x00])],out);
+        assert_eq!(1,tally.skipped());
+        tally.set_strict(true);
+        assert_eq!("strict: record 2: no id",source.iter().find_map(|e| e.err()).unwrap().to_string());
+        let source = AvroSource::new(file.path(),&AvroConfig::new().key_path("start".to_string()).value_path(Some("id".to_string()))).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"32315474".to_vec(),b"BRCA2".to_vec()),(b"1".to_vec(),vec![])],out);
+        let source = AvroSource::new(file.path(),&AvroConfig::new()).unwrap();
+        let (_,value) = source.iter().next().unwrap().unwrap();
+        assert_eq!(br#"{"id":"BRCA2","seq":[255,0],"start":32315474}"#.to_vec(),value);
+    }
+}
@@ -0,0 +1,295 @@
+use std::{collections::{HashSet, VecDeque}, fs::File, io::{self, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+const BTREE_MAGIC : u32 = 0x053162;
+const HASH_MAGIC : u32 = 0x061561;
+const PAGE_HEADER : usize = 26;
+
+const P_HASH_UNSORTED : u8 = 2;
+const P_LBTREE : u8 = 5;
+const P_OVERFLOW : u8 = 7;
+const P_HASH : u8 = 13;
+
+const B_KEYDATA : u8 = 1;
+const B_OVERFLOW : u8 = 3;
+const B_DELETE : u8 = 0x80;
+const H_KEYDATA : u8 = 1;
+const H_OFFPAGE : u8 = 3;
+
+/// True if `bytes` (the start of a file) carry a Berkeley DB btree or hash magic number.
+pub fn looks_like_bdb(bytes: &[u8]) -> bool {
+    bytes.len() >= 16 && {
+        let magic = [bytes[12],bytes[13],bytes[14],bytes[15]];
+        [u32::from_le_bytes(magic),u32::from_be_bytes(magic)].iter().any(|m| *m == BTREE_MAGIC || *m == HASH_MAGIC)
+    }
+}
+
+/// Berkeley DB btree and hash files, read page by page without libdb. Pairs come out in page
+/// order, with overflow items followed to their pages. Encrypted files and sorted duplicate sets
+/// aren't supported.
+pub struct BdbSource {
+    path: PathBuf
+}
+
+impl BdbSource {
+    pub fn new(path: &Path) -> io::Result<BdbSource> {
+        check_exists(path)?;
+        Ok(BdbSource { path: path.to_path_buf() })
+    }
+}
+
+struct Pages {
+    file: File,
+    file_size: u64,
+    page_size: usize,
+    big_endian: bool
+}
+
+impl Pages {
+    fn open(path: &Path) -> io::Result<(Pages,u64)> {
+        let mut file = File::open(path)?;
+        let mut meta = [0;72];
+        file.read_exact(&mut meta).map_err(|_| invalid_data("bdb: truncated metadata page"))?;
+        let magic = [meta[12],meta[13],meta[14],meta[15]];
+        let big_endian = match (u32::from_le_bytes(magic),u32::from_be_bytes(magic)) {
+            (BTREE_MAGIC,_) | (HASH_MAGIC,_) => false,
+            (_,BTREE_MAGIC) | (_,HASH_MAGIC) => true,
+            _ => { return Err(invalid_data("bdb: not a btree or hash database")); }
+        };
+        let file_size = file.metadata()?.len();
+        let mut pages = Pages { file, file_size, page_size: 0, big_endian };
+        pages.page_size = pages.u32(&meta,20) as usize;
+        if pages.page_size < 512 || pages.page_size > 65536 {
+            return Err(invalid_data(format!("bdb: bad page size {}",pages.page_size)));
+        }
+        if meta[24] != 0 {
+            return Err(invalid_data("bdb: encrypted databases are not supported"));
+        }
+        /* a file cut short would otherwise just end early, with its last pages missing */
+        let page_size = pages.page_size as u64;
+        if file_size < 2*page_size || file_size % page_size != 0 {
+            return Err(invalid_data(format!("bdb: truncated: {} bytes is not a whole number of {} byte pages",file_size,page_size)));
+        }
+        Ok((pages,file_size/page_size))
+    }
+
+    fn u16(&self, page: &[u8], at: usize) -> usize {
+        let bytes = [page[at],page[at+1]];
+        (if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }) as usize
+    }
+
+    fn u32(&self, page: &[u8], at: usize) -> u32 {
+        let bytes = [page[at],page[at+1],page[at+2],page[at+3]];
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+
+    fn read(&mut self, number: u64) -> io::Result<Vec<u8>> {
+        let mut page = vec![0;self.page_size];
+        self.file.seek(SeekFrom::Start(number*self.page_size as u64))?;
+        self.file.read_exact(&mut page)?;
+        Ok(page)
+    }
+
+    fn overflow(&mut self, mut number: u64, total: usize) -> io::Result<Vec<u8>> {
+        /* the length is the page's word for it */
+        let mut out = Vec::with_capacity(total.min(self.file_size as usize));
+        let mut seen = HashSet::new();
+        while out.len() < total {
+            if number == 0 { return Err(invalid_data("bdb: overflow chain ends early")); }
+            /* a page seen twice, or one adding nothing, would go round forever */
+            if !seen.insert(number) { return Err(invalid_data(format!("bdb: overflow chain loops back to page {}",number))); }
+            let page = self.read(number)?;
+            if page[25] != P_OVERFLOW { return Err(invalid_data(format!("bdb: page {} is not an overflow page",number))); }
+            let len = self.u16(&page,22).min(self.page_size-PAGE_HEADER).min(total-out.len());
+            if len == 0 { return Err(invalid_data(format!("bdb: overflow page {} is empty",number))); }
+            out.extend_from_slice(&page[PAGE_HEADER..PAGE_HEADER+len]);
+            number = self.u32(&page,16) as u64;
+        }
+        Ok(out)
+    }
+
+    fn slice<'a>(&self, page: &'a [u8], start: usize, len: usize) -> io::Result<&'a [u8]> {
+        page.get(start..start+len).ok_or_else(|| invalid_data("bdb: item runs off the page"))
+    }
+
+    fn btree_item(&mut self, page: &[u8], at: usize) -> io::Result<Option<Vec<u8>>> {
+        let kind = *page.get(at+2).ok_or_else(|| invalid_data("bdb: bad item offset"))?;
+        if kind & B_DELETE != 0 { return Ok(None); }
+        match kind {
+            B_KEYDATA => {
+                let len = self.u16(page,at);
+                Ok(Some(self.slice(page,at+3,len)?.to_vec()))
+            },
+            B_OVERFLOW => {
+                let item = self.slice(page,at,12)?;
+                let (number,total) = (self.u32(item,4) as u64,self.u32(item,8) as usize);
+                Ok(Some(self.overflow(number,total)?))
+            },
+            other => Err(invalid_data(format!("bdb: unsupported btree item type {}",other)))
+        }
+    }
+
+    fn hash_item(&mut self, item: &[u8]) -> io::Result<Vec<u8>> {
+        match item.first() {
+            Some(&H_KEYDATA) => Ok(item[1..].to_vec()),
+            Some(&H_OFFPAGE) if item.len() >= 12 => {
+                let (number,total) = (self.u32(item,4) as u64,self.u32(item,8) as usize);
+                self.overflow(number,total)
+            },
+            Some(other) => Err(invalid_data(format!("bdb: unsupported hash item type {}",other))),
+            None => Err(invalid_data("bdb: empty hash item"))
+        }
+    }
+
+    fn pairs(&mut self, number: u64) -> io::Result<Vec<(Vec<u8>,Vec<u8>)>> {
+        let page = self.read(number)?;
+        let entries = self.u16(&page,20);
+        if PAGE_HEADER + 2*entries > self.page_size {
+            return Err(invalid_data(format!("bdb: {} entries can't fit on a page",entries)));
+        }
+        let offsets = (0..entries).map(|i| self.u16(&page,PAGE_HEADER+2*i)).collect::<Vec<_>>();
+        let mut out = vec![];
+        match page[25] {
+            P_LBTREE => {
+                for pair in offsets.chunks(2).filter(|p| p.len() == 2) {
+                    let key = self.btree_item(&page,pair[0])?;
+                    let value = self.btree_item(&page,pair[1])?;
+                    if let (Some(key),Some(value)) = (key,value) { out.push((key,value)); }
+                }
+            },
+            P_HASH | P_HASH_UNSORTED => {
+                for i in (0..entries).step_by(2).filter(|i| i+1 < entries) {
+                    let end = |i: usize| if i == 0 { self.page_size } else { offsets[i-1] };
+                    let key = self.slice(&page,offsets[i],end(i).saturating_sub(offsets[i]))?.to_vec();
+                    let value = self.slice(&page,offsets[i+1],end(i+1).saturating_sub(offsets[i+1]))?.to_vec();
+                    out.push((self.hash_item(&key)?,self.hash_item(&value)?));
+                }
+            },
+            _ => {}
+        }
+        Ok(out)
+    }
+}
+
+struct BdbIterator {
+    pages: Pages,
+    next_page: u64,
+    page_count: u64,
+    pending: VecDeque<(Vec<u8>,Vec<u8>)>
+}
+
+impl Iterator for BdbIterator {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            if self.next_page >= self.page_count { return None; }
+            let number = self.next_page;
+            self.next_page += 1;
+            match self.pages.pairs(number) {
+                Ok(pairs) => { self.pending.extend(pairs); },
+                Err(e) => {
+                    self.next_page = self.page_count;
+                    return Some(Err(invalid_data(format!("page {}: {}",number,e))));
+                }
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+impl NCDValueSource for BdbSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match Pages::open(&self.path) {
+            Ok((pages,page_count)) => Box::new(BdbIterator { pages, next_page: 1, page_count, pending: VecDeque::new() }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use super::{looks_like_bdb, BdbSource, BTREE_MAGIC, B_KEYDATA, B_OVERFLOW, P_LBTREE, P_OVERFLOW};
+
+    fn first_error(source: &BdbSource) -> String {
+        source.iter().find_map(|e| e.err()).unwrap().to_string()
+    }
+
+    const PAGE : usize = 512;
+
+    fn header(page: &mut [u8], number: u32, next: u32, entries: u16, hf_offset: u16, kind: u8) {
+        page[8..12].copy_from_slice(&number.to_le_bytes());
+        page[16..20].copy_from_slice(&next.to_le_bytes());
+        page[20..22].copy_from_slice(&entries.to_le_bytes());
+        page[22..24].copy_from_slice(&hf_offset.to_le_bytes());
+        page[25] = kind;
+    }
+
+    /* a meta page, one btree leaf holding `a`=`1` and `big`=(overflow), and the overflow page */
+    fn btree_file(big: &[u8]) -> Vec<u8> {
+        let mut meta = vec![0;PAGE];
+        meta[12..16].copy_from_slice(&BTREE_MAGIC.to_le_bytes());
+        meta[20..24].copy_from_slice(&(PAGE as u32).to_le_bytes());
+        meta[25] = 9;
+        let mut leaf = vec![0;PAGE];
+        let mut items : Vec<Vec<u8>> = vec![];
+        for data in &[&b"a"[..],&b"1"[..],&b"big"[..]] {
+            let mut item = (data.len() as u16).to_le_bytes().to_vec();
+            item.push(B_KEYDATA);
+            item.extend_from_slice(data);
+            items.push(item);
+        }
+        let mut overflow_item = vec![0,0,B_OVERFLOW,0];
+        overflow_item.extend_from_slice(&2u32.to_le_bytes());
+        overflow_item.extend_from_slice(&(big.len() as u32).to_le_bytes());
+        items.push(overflow_item);
+        let mut at = PAGE;
+        for (i,item) in items.iter().enumerate() {
+            at -= item.len();
+            leaf[at..at+item.len()].copy_from_slice(item);
+            leaf[26+2*i..28+2*i].copy_from_slice(&(at as u16).to_le_bytes());
+        }
+        header(&mut leaf,1,0,items.len() as u16,at as u16,P_LBTREE);
+        let mut overflow = vec![0;PAGE];
+        header(&mut overflow,2,0,1,big.len() as u16,P_OVERFLOW);
+        overflow[26..26+big.len()].copy_from_slice(big);
+        [meta,leaf,overflow].concat()
+    }
+
+    #[test]
+    fn test_bdb() {
+        let big = vec![0xAB;300];
+        let data = btree_file(&big);
+        assert!(looks_like_bdb(&data));
+        assert!(!looks_like_bdb(b"a\t1\nb\t2\n01234567"));
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(),&data).unwrap();
+        let source = BdbSource::new(file.path()).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"a".to_vec(),b"1".to_vec()),(b"big".to_vec(),big)],out);
+        std::fs::write(file.path(),&data[..100]).unwrap();
+        assert!(first_error(&source).starts_with("bdb: truncated"));
+        std::fs::write(file.path(),&data[..2*PAGE+100]).unwrap();
+        assert!(first_error(&source).starts_with("bdb: truncated"));
+        std::fs::write(file.path(),vec![0;PAGE]).unwrap();
+        assert!(source.iter().next().unwrap().is_err());
+        let mut bad = data.clone();
+        bad[PAGE+20..PAGE+22].copy_from_slice(&300u16.to_le_bytes());
+        std::fs::write(file.path(),&bad).unwrap();
+        assert_eq!("page 1: bdb: 300 entries can't fit on a page",first_error(&source));
+        /* an overflow page holding less than the item's length and linking to itself */
+        let mut looped = data.clone();
+        looped[2*PAGE+16..2*PAGE+20].copy_from_slice(&2u32.to_le_bytes());
+        looped[2*PAGE+22..2*PAGE+24].copy_from_slice(&100u16.to_le_bytes());
+        std::fs::write(file.path(),&looped).unwrap();
+        assert!(first_error(&source).ends_with("bdb: overflow chain loops back to page 2"));
+        looped[2*PAGE+22..2*PAGE+24].copy_from_slice(&0u16.to_le_bytes());
+        std::fs::write(file.path(),&looped).unwrap();
+        assert!(first_error(&source).ends_with("bdb: overflow page 2 is empty"));
+    }
+}
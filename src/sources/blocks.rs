@@ -0,0 +1,154 @@
+use std::{fs::File, io::{self, BufRead, BufReader, Lines}, path::{Path, PathBuf}, sync::Arc};
+
+use ncd::NCDValueSource;
+
+use super::{check_exists, counting::{report_skip, Tally}, invalid_data, SourceIter};
+
+/// Blank-line separated blocks of RFC 822 style `Field: value` headers, as in Debian control
+/// files. Each block is stored whole, keyed by the value of `record_key` (matched ignoring case,
+/// with any continuation lines folded in), or of its first field if `record_key` is empty.
+/// Blocks without the field are skipped, and reported to any `tally`. Lines starting `#` are
+/// comments and left out.
+#[derive(Clone,Debug)]
+pub struct BlocksConfig {
+    record_key: String,
+    tally: Option<Arc<Tally>>
+}
+
+impl BlocksConfig {
+    pub fn new() -> BlocksConfig {
+        BlocksConfig {
+            record_key: "".to_string(),
+            tally: None
+        }
+    }
+
+    /// Report blocks skipped for want of the field to `tally`.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> BlocksConfig {
+        self.tally = Some(tally.clone());
+        self
+    }
+}
+
+chain!(record_key,get_record_key,String,BlocksConfig);
+
+pub struct BlocksSource {
+    path: PathBuf,
+    config: BlocksConfig
+}
+
+impl BlocksSource {
+    pub fn new(path: &Path, config: &BlocksConfig) -> io::Result<BlocksSource> {
+        check_exists(path)?;
+        Ok(BlocksSource { path: path.to_path_buf(), config: config.clone() })
+    }
+}
+
+/* the value of `field` in a block, continuation lines joined with single spaces */
+fn field_value(lines: &[String], field: &str, start: usize) -> io::Result<Option<String>> {
+    let mut out : Option<String> = None;
+    for (i,line) in lines.iter().enumerate() {
+        if line.starts_with(|c: char| c == ' ' || c == '\t') {
+            if i == 0 { return Err(invalid_data(format!("line {}: continuation line with no field before it",start))); }
+            if let Some(out) = out.as_mut() {
+                let more = line.trim();
+                if more != "." && !more.is_empty() { out.push(' '); out.push_str(more); }
+            }
+            continue;
+        }
+        if out.is_some() { break; }
+        let (name,value) = line.split_once(':')
+            .ok_or_else(|| invalid_data(format!("line {}: expected Field: value",start+i)))?;
+        if field.is_empty() || name.trim().eq_ignore_ascii_case(field) {
+            out = Some(value.trim().to_string());
+        }
+    }
+    Ok(out)
+}
+
+struct BlocksIterator<'a> {
+    lines: Lines<BufReader<File>>,
+    line: usize,
+    config: &'a BlocksConfig
+}
+
+impl<'a> BlocksIterator<'a> {
+    /* the next block's lines, less comments, and the line number it starts on */
+    fn block(&mut self) -> io::Result<Option<(Vec<String>,usize)>> {
+        let mut out = vec![];
+        let mut start = 0;
+        for line in self.lines.by_ref() {
+            let line = line?;
+            self.line += 1;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                if out.is_empty() { continue; } else { break; }
+            }
+            if line.starts_with('#') { continue; }
+            if out.is_empty() { start = self.line; }
+            out.push(line.to_string());
+        }
+        Ok(if out.is_empty() { None } else { Some((out,start)) })
+    }
+}
+
+impl<'a> Iterator for BlocksIterator<'a> {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (lines,start) = match self.block() {
+                Ok(Some(block)) => block,
+                Ok(None) => { return None; },
+                Err(e) => { return Some(Err(e)); }
+            };
+            match field_value(&lines,&self.config.record_key,start) {
+                Ok(Some(key)) => { return Some(Ok((key.into_bytes(),lines.join("\n").into_bytes()))); },
+                Ok(None) => {
+                    let what = format!("line {}: block has no {} field",start,self.config.record_key);
+                    if let Err(e) = report_skip(&self.config.tally,what) { return Some(Err(e)); }
+                },
+                Err(e) => { return Some(Err(e)); }
+            }
+        }
+    }
+}
+
+impl NCDValueSource for BlocksSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match File::open(&self.path) {
+            Ok(file) => Box::new(BlocksIterator { lines: BufReader::new(file).lines(), line: 0, config: &self.config }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sources::{counting::Tally, fixture};
+    use super::{BlocksConfig, BlocksSource};
+
+    fn parse(data: &str, record_key: &str) -> Result<Vec<(String,String)>,String> {
+        fixture::parse(data.as_bytes(),|path| BlocksSource::new(path,&BlocksConfig::new().record_key(record_key.to_string())))
+    }
+
+    #[test]
+    fn test_blocks() {
+        let data = "\n# comment\nPackage: zlib1g\nVersion: 1:1.2.13\nDescription: compression library\n more text\n .\n\n\n\
+                    Source: x\nPackage: libc6\n\nVersion: 2\n";
+        assert_eq!(Ok(vec![
+            ("zlib1g".to_string(),"Package: zlib1g\nVersion: 1:1.2.13\nDescription: compression library\n more text\n .".to_string()),
+            ("libc6".to_string(),"Source: x\nPackage: libc6".to_string())
+        ]),parse(data,"package"));
+        assert_eq!(Ok(vec![
+            ("compression library more text".to_string(),"Package: zlib1g\nVersion: 1:1.2.13\nDescription: compression library\n more text\n .".to_string())
+        ]),parse(data,"Description"));
+        assert_eq!(Ok(vec!["zlib1g".to_string(),"x".to_string(),"2".to_string()]),parse(data,"").map(|v| v.into_iter().map(|(k,_)| k).collect()));
+        assert_eq!(Err("line 2: expected Field: value".to_string()),parse("A: 1\nnot a header\n","A"));
+        assert!(parse(" leading\nA: 1\n","A").is_err());
+        let tally = Tally::new();
+        tally.set_strict(true);
+        let config = BlocksConfig::new().record_key("package".to_string()).tally(&tally);
+        assert_eq!(Err("strict: line 13: block has no package field".to_string()),fixture::parse(data.as_bytes(),|path| BlocksSource::new(path,&config)));
+    }
+}
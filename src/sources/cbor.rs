@@ -0,0 +1,134 @@
+use std::{fs::File, io::{self, BufRead, BufReader}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+use ciborium::value::Value;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+/// A top-level CBOR map, or a CBOR sequence of maps and `[key, value]` arrays. Keys must be byte
+/// or text strings, taken as raw bytes. Byte and text string values are also stored raw, while
+/// any other value is stored re-encoded as CBOR. The self-described CBOR tag is ignored.
+pub struct CborSource {
+    path: PathBuf
+}
+
+impl CborSource {
+    pub fn new(path: &Path) -> io::Result<CborSource> {
+        check_exists(path)?;
+        Ok(CborSource { path: path.to_path_buf() })
+    }
+}
+
+fn raw_bytes(value: Value) -> Result<Vec<u8>,Value> {
+    match value {
+        Value::Bytes(b) => Ok(b),
+        Value::Text(s) => Ok(s.into_bytes()),
+        other => Err(other)
+    }
+}
+
+fn pair(key: Value, value: Value, number: usize) -> io::Result<(Vec<u8>,Vec<u8>)> {
+    let key = raw_bytes(key).map_err(|k| invalid_data(format!("item {}: key must be a byte or text string, not {:?}",number,k)))?;
+    let value = match raw_bytes(value) {
+        Ok(v) => v,
+        Err(v) => {
+            let mut out = vec![];
+            ciborium::ser::into_writer(&v,&mut out).map_err(|e| invalid_data(e.to_string()))?;
+            out
+        }
+    };
+    Ok((key,value))
+}
+
+const SELF_DESCRIBED : u64 = 55799;
+
+fn pairs(item: Value, number: usize) -> Vec<io::Result<(Vec<u8>,Vec<u8>)>> {
+    match item {
+        Value::Tag(SELF_DESCRIBED,inner) => pairs(*inner,number),
+        Value::Map(entries) => entries.into_iter().map(|(k,v)| pair(k,v,number)).collect(),
+        Value::Array(mut kv) if kv.len() == 2 => {
+            let value = kv.pop().unwrap();
+            vec![pair(kv.pop().unwrap(),value,number)]
+        },
+        _ => vec![Err(invalid_data(format!("item {}: expected a map or a [key, value] array",number)))]
+    }
+}
+
+struct CborIterator {
+    input: BufReader<File>,
+    number: usize
+}
+
+impl Iterator for CborIterator {
+    type Item = (usize,io::Result<Value>);
+
+    fn next(&mut self) -> Option<(usize,io::Result<Value>)> {
+        self.number += 1;
+        match self.input.fill_buf() {
+            Ok(b) if b.is_empty() => { return None; },
+            Err(e) => { return Some((self.number,Err(e))); },
+            Ok(_) => {}
+        }
+        let number = self.number;
+        Some((number,ciborium::de::from_reader(&mut self.input)
+            .map_err(|e| invalid_data(format!("item {}: {}",number,e)))))
+    }
+}
+
+impl NCDValueSource for CborSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        let items = CborIterator { input: BufReader::new(file), number: 0 };
+        Box::new(items.flat_map(|(number,item)| {
+            match item {
+                Ok(item) => pairs(item,number),
+                Err(e) => vec![Err(e)]
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use ciborium::value::Value;
+    use ncd::NCDValueSource;
+
+    use super::CborSource;
+
+    fn write_items(items: &[Value]) -> (tempfile::NamedTempFile,CborSource) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for item in items {
+            ciborium::ser::into_writer(item,&mut file).unwrap();
+        }
+        file.flush().unwrap();
+        let source = CborSource::new(file.path()).unwrap();
+        (file,source)
+    }
+
+    #[test]
+    fn test_cbor() {
+        let map = Value::Map(vec![
+            (Value::Bytes(vec![0xFF,0x00]),Value::Bytes(vec![1,2])),
+            (Value::Text("b".to_string()),Value::Integer(7.into()))
+        ]);
+        let (_file,source) = write_items(&[Value::Tag(55799,Box::new(map))]);
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(vec![0xFF,0x00],vec![1,2]),(b"b".to_vec(),vec![0x07])],out);
+        let sequence = vec![
+            Value::Array(vec![Value::Text("x".to_string()),Value::Text("1".to_string())]),
+            Value::Array(vec![Value::Text("y".to_string()),Value::Text("2".to_string())])
+        ];
+        let (_file,source) = write_items(&sequence);
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"x".to_vec(),b"1".to_vec()),(b"y".to_vec(),b"2".to_vec())],out);
+        let (_file,source) = write_items(&[Value::Array(vec![Value::Integer(1.into()),Value::Text("1".to_string())])]);
+        assert!(source.iter().next().unwrap().is_err());
+        let (_file,source) = write_items(&[Value::Bool(true)]);
+        assert!(source.iter().next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use std::{fs::File, io::{self, BufReader, Read}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+const HEADER_SIZE : u64 = 2048;
+
+/// djb's cdb. Records are read in file order, straight after the 2048-byte header and up to the
+/// first hash table, so keys and values come through byte-for-byte and repeated keys repeat.
+pub struct CdbSource {
+    path: PathBuf
+}
+
+impl CdbSource {
+    pub fn new(path: &Path) -> io::Result<CdbSource> {
+        check_exists(path)?;
+        Ok(CdbSource { path: path.to_path_buf() })
+    }
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut bytes = [0;4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/* the lengths are only bounded by the tables' offset, so read what is there rather than
+ * allocating them up front */
+fn read_bytes<R: Read>(input: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    input.by_ref().take(len).read_to_end(&mut out)?;
+    if (out.len() as u64) < len {
+        return Err(invalid_data("cdb: truncated record"));
+    }
+    Ok(out)
+}
+
+struct CdbIterator {
+    input: BufReader<File>,
+    pos: u64,
+    end: u64
+}
+
+impl CdbIterator {
+    fn open(path: &Path) -> io::Result<CdbIterator> {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut end = u64::MAX;
+        for _ in 0..256 {
+            let table = read_u32(&mut input).map_err(|_| invalid_data("cdb: truncated header"))? as u64;
+            read_u32(&mut input).map_err(|_| invalid_data("cdb: truncated header"))?;
+            end = end.min(table);
+        }
+        if end < HEADER_SIZE {
+            return Err(invalid_data("cdb: hash table inside header"));
+        }
+        Ok(CdbIterator { input, pos: HEADER_SIZE, end })
+    }
+
+    fn record(&mut self) -> io::Result<(Vec<u8>,Vec<u8>)> {
+        let key_len = read_u32(&mut self.input)? as u64;
+        let value_len = read_u32(&mut self.input)? as u64;
+        self.pos += 8 + key_len + value_len;
+        if self.pos > self.end {
+            return Err(invalid_data("cdb: record runs into the hash tables"));
+        }
+        let key = read_bytes(&mut self.input,key_len)?;
+        let value = read_bytes(&mut self.input,value_len)?;
+        Ok((key,value))
+    }
+}
+
+impl Iterator for CdbIterator {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.end { return None; }
+        let record = self.record();
+        if record.is_err() { self.pos = self.end; }
+        Some(record)
+    }
+}
+
+impl NCDValueSource for CdbSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match CdbIterator::open(&self.path) {
+            Ok(records) => Box::new(records),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use super::CdbSource;
+
+    /* records and the header only: the hash tables themselves aren't read */
+    fn cdb(records: &[(&[u8],&[u8])]) -> Vec<u8> {
+        let mut body = vec![];
+        for (key,value) in records {
+            body.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            body.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            body.extend_from_slice(key);
+            body.extend_from_slice(value);
+        }
+        let tables = (2048 + body.len()) as u32;
+        let mut out = vec![];
+        for _ in 0..256 {
+            out.extend_from_slice(&tables.to_le_bytes());
+            out.extend_from_slice(&0u32.to_le_bytes());
+        }
+        out.extend(body);
+        out
+    }
+
+    #[test]
+    fn test_cdb() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(),cdb(&[(b"a",b"1"),(b"\xff\x00",b""),(b"a",b"2")])).unwrap();
+        let source = CdbSource::new(file.path()).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"a".to_vec(),b"1".to_vec()),(vec![0xFF,0x00],vec![]),(b"a".to_vec(),b"2".to_vec())],out);
+        let mut data = cdb(&[(b"key",b"value")]);
+        data.truncate(2048+8+2);
+        std::fs::write(file.path(),data).unwrap();
+        assert_eq!("cdb: truncated record",source.iter().next().unwrap().unwrap_err().to_string());
+        let mut data = cdb(&[]);
+        for i in 0..256 { data[i*8..i*8+4].copy_from_slice(&u32::MAX.to_le_bytes()); }
+        data.extend_from_slice(&[0xFF,0xFF,0xFF,0x7F,0,0,0,0]);
+        std::fs::write(file.path(),data).unwrap();
+        assert_eq!("cdb: truncated record",source.iter().next().unwrap().unwrap_err().to_string());
+        std::fs::write(file.path(),b"short").unwrap();
+        assert!(source.iter().next().unwrap().is_err());
+    }
+}
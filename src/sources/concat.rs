@@ -0,0 +1,38 @@
+use ncd::NCDValueSource;
+
+use super::SourceIter;
+
+/// The records of each source in turn, as a single source.
+pub struct ConcatSource {
+    sources: Vec<Box<dyn NCDValueSource>>
+}
+
+impl ConcatSource {
+    pub fn new(sources: Vec<Box<dyn NCDValueSource>>) -> ConcatSource {
+        ConcatSource { sources }
+    }
+}
+
+impl NCDValueSource for ConcatSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        Box::new(self.sources.iter().flat_map(|source| source.iter()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use crate::sources::memory::MemorySource;
+    use super::ConcatSource;
+
+    #[test]
+    fn test_concat() {
+        let first = MemorySource::new(vec![(b"a".to_vec(),b"1".to_vec()),(b"b".to_vec(),b"2".to_vec())]);
+        let second = MemorySource::new(vec![(b"a".to_vec(),b"3".to_vec())]);
+        let source = ConcatSource::new(vec![Box::new(first),Box::new(MemorySource::new(vec![])),Box::new(second)]);
+        let values : Vec<_> = source.iter().map(|e| e.unwrap().1).collect();
+        assert_eq!(vec![b"1".to_vec(),b"2".to_vec(),b"3".to_vec()],values);
+        assert_eq!(0,ConcatSource::new(vec![]).iter().count());
+    }
+}
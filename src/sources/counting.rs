@@ -0,0 +1,91 @@
+use std::{io, sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}}};
+
+use ncd::NCDValueSource;
+
+use super::{invalid_data, SourceIter};
+
+/// Per-input record counts, shared between the wrappers which update them and the report.
+#[derive(Debug,Default)]
+pub struct Tally {
+    ingested: AtomicU64,
+    dropped: AtomicU64,
+    deduplicated: AtomicU64,
+    skipped: AtomicU64,
+    strict: AtomicBool
+}
+
+impl Tally {
+    pub fn new() -> Arc<Tally> { Arc::new(Tally::default()) }
+
+    /* each build attempt is a fresh pass so only the last one counts */
+    pub fn reset(&self) {
+        self.ingested.store(0,Ordering::Relaxed);
+        self.dropped.store(0,Ordering::Relaxed);
+        self.deduplicated.store(0,Ordering::Relaxed);
+        self.skipped.store(0,Ordering::Relaxed);
+    }
+
+    /// Fail on what sources would otherwise quietly leave out or guess at, for `--strict`.
+    pub fn set_strict(&self, strict: bool) { self.strict.store(strict,Ordering::Relaxed); }
+
+    /* something the source coped with: fine, unless strict */
+    pub fn tolerate(&self, what: &str) -> io::Result<()> {
+        if self.strict.load(Ordering::Relaxed) {
+            return Err(invalid_data(format!("strict: {}",what)));
+        }
+        Ok(())
+    }
+
+    /* a record the source couldn't hand on */
+    pub fn skip(&self, what: &str) -> io::Result<()> {
+        self.tolerate(what)?;
+        self.skipped.fetch_add(1,Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn add_dropped(&self) { self.dropped.fetch_add(1,Ordering::Relaxed); }
+    pub fn add_deduplicated(&self) { self.deduplicated.fetch_add(1,Ordering::Relaxed); }
+
+    pub fn ingested(&self) -> u64 { self.ingested.load(Ordering::Relaxed) }
+    pub fn dropped(&self) -> u64 { self.dropped.load(Ordering::Relaxed) }
+    pub fn deduplicated(&self) -> u64 { self.deduplicated.load(Ordering::Relaxed) }
+    pub fn skipped(&self) -> u64 { self.skipped.load(Ordering::Relaxed) }
+
+    /* what made it through to the build on the last pass */
+    pub fn stored(&self) -> u64 {
+        self.ingested().saturating_sub(self.dropped()+self.deduplicated())
+    }
+}
+
+/// For sources given a tally in their config: report a record left out, if there's a tally.
+pub fn report_skip(tally: &Option<Arc<Tally>>, what: String) -> io::Result<()> {
+    tally.as_ref().map(|tally| tally.skip(&what)).unwrap_or(Ok(()))
+}
+
+/// As `report_skip`, for a record taken in by falling back on something.
+pub fn report_tolerated(tally: &Option<Arc<Tally>>, what: String) -> io::Result<()> {
+    tally.as_ref().map(|tally| tally.tolerate(&what)).unwrap_or(Ok(()))
+}
+
+/// Counts the records an input yields into a `Tally`.
+pub struct CountingSource {
+    inner: Box<dyn NCDValueSource>,
+    tally: Arc<Tally>
+}
+
+impl CountingSource {
+    pub fn new(inner: Box<dyn NCDValueSource>, tally: &Arc<Tally>) -> CountingSource {
+        CountingSource { inner, tally: tally.clone() }
+    }
+}
+
+impl NCDValueSource for CountingSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        self.tally.reset();
+        Box::new(self.inner.iter().inspect(move |entry| {
+            if entry.is_ok() {
+                self.tally.ingested.fetch_add(1,Ordering::Relaxed);
+            }
+        }))
+    }
+}
@@ -0,0 +1,148 @@
+use std::{io, path::{Path, PathBuf}};
+
+use csv::{ByteRecord, ReaderBuilder, Terminator, WriterBuilder};
+use ncd::NCDValueSource;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+/// How to parse an RFC 4180 csv file. The key is the `index`th field (first is 1) unless
+/// `key_column` names a column in the header row. The value is the remaining fields,
+/// re-quoted as needed.
+#[derive(Clone,Debug)]
+pub struct CsvConfig {
+    index: usize,
+    key_column: Option<String>,
+    header: bool,
+    delimiter: u8,
+    quote: u8,
+    escape: Option<u8>
+}
+
+impl CsvConfig {
+    pub fn new() -> CsvConfig {
+        CsvConfig {
+            index: 1,
+            key_column: None,
+            header: false,
+            delimiter: b',',
+            quote: b'"',
+            escape: None
+        }
+    }
+}
+
+chain!(index,get_index,usize,CsvConfig);
+chain!(key_column,get_key_column,Option<String>,CsvConfig);
+chain!(header,get_header,bool,CsvConfig);
+chain!(delimiter,get_delimiter,u8,CsvConfig);
+chain!(quote,get_quote,u8,CsvConfig);
+chain!(escape,get_escape,Option<u8>,CsvConfig);
+
+pub struct CsvSource {
+    path: PathBuf,
+    config: CsvConfig
+}
+
+impl CsvSource {
+    pub fn new(path: &Path, config: &CsvConfig) -> io::Result<CsvSource> {
+        check_exists(path)?;
+        Ok(CsvSource { path: path.to_path_buf(), config: config.clone() })
+    }
+
+    fn reader(&self) -> io::Result<csv::Reader<std::fs::File>> {
+        ReaderBuilder::new()
+            .delimiter(self.config.delimiter)
+            .quote(self.config.quote)
+            .escape(self.config.escape)
+            .double_quote(self.config.escape.is_none())
+            .has_headers(self.config.header || self.config.key_column.is_some())
+            .flexible(true)
+            .from_path(&self.path)
+            .map_err(csv_error)
+    }
+
+    fn key_index(&self, reader: &mut csv::Reader<std::fs::File>) -> io::Result<usize> {
+        if let Some(column) = &self.config.key_column {
+            let headers = reader.byte_headers().map_err(csv_error)?;
+            headers.iter().position(|h| h == column.as_bytes())
+                .ok_or_else(|| invalid_data(format!("no column named {} in {}",column,self.path.display())))
+        } else if self.config.index == 0 {
+            Err(invalid_data("fields are numbered from 1"))
+        } else {
+            Ok(self.config.index-1)
+        }
+    }
+}
+
+fn csv_error(e: csv::Error) -> io::Error {
+    invalid_data(e.to_string())
+}
+
+fn split_record(record: &ByteRecord, index: usize, config: &CsvConfig) -> io::Result<(Vec<u8>,Vec<u8>)> {
+    let line = record.position().map(|p| p.line()).unwrap_or(0);
+    let key = record.get(index).ok_or_else(|| invalid_data(format!("line {}: no field {}",line,index+1)))?;
+    let rest : ByteRecord = record.iter().enumerate().filter(|(i,_)| *i != index).map(|(_,f)| f).collect();
+    let mut writer = WriterBuilder::new()
+        .delimiter(config.delimiter)
+        .quote(config.quote)
+        .escape(config.escape.unwrap_or(b'\\'))
+        .double_quote(config.escape.is_none())
+        .terminator(Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    let mut value = if rest.is_empty() {
+        vec![]
+    } else {
+        writer.write_byte_record(&rest).map_err(csv_error)?;
+        writer.into_inner().map_err(|e| invalid_data(e.to_string()))?
+    };
+    if value.last() == Some(&b'\n') { value.pop(); }
+    Ok((key.to_vec(),value))
+}
+
+impl NCDValueSource for CsvSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let mut reader = match self.reader() {
+            Ok(r) => r,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        let index = match self.key_index(&mut reader) {
+            Ok(i) => i,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        Box::new(reader.into_byte_records().map(move |record| {
+            split_record(&record.map_err(csv_error)?,index,&self.config)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sources::fixture;
+    use super::{CsvConfig, CsvSource};
+
+    fn parse(data: &str, config: &CsvConfig) -> Result<Vec<(String,String)>,String> {
+        fixture::parse(data.as_bytes(),|path| CsvSource::new(path,config))
+    }
+
+    fn pairs(v: &[(&str,&str)]) -> Vec<(String,String)> {
+        v.iter().map(|(k,v)| (k.to_string(),v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_csv() {
+        let data = "id,name,note\nG1,\"BRCA2, human\",\"two\nlines\"\nG2,TP53,\n";
+        assert_eq!(Ok(pairs(&[
+            ("id","name,note"),
+            ("G1","\"BRCA2, human\",\"two\nlines\""),
+            ("G2","TP53,")
+        ])),parse(data,&CsvConfig::new()));
+        assert_eq!(Ok(pairs(&[
+            ("BRCA2, human","G1,\"two\nlines\""),
+            ("TP53","G2,")
+        ])),parse(data,&CsvConfig::new().key_column(Some("name".to_string()))));
+        assert_eq!(Ok(pairs(&[("G2","TP53,")])),parse("G1\nG2,TP53,\n",&CsvConfig::new().index(1).header(true)));
+        assert!(parse(data,&CsvConfig::new().key_column(Some("missing".to_string()))).is_err());
+        assert!(parse("a,b\nc\n",&CsvConfig::new().index(2).header(true)).is_err());
+        assert_eq!(Ok(pairs(&[("a;b","c")])),parse("'a;b';c\n",&CsvConfig::new().delimiter(b';').quote(b'\'')));
+    }
+}
@@ -0,0 +1,130 @@
+use std::{collections::HashSet, fs, io, path::{Path, PathBuf}};
+
+use glob::Pattern;
+use ncd::NCDValueSource;
+
+use super::{invalid_data, SourceIter};
+
+/// Each file under the directory is stored whole, keyed by its `/`-separated path relative to
+/// the directory. Paths matching any `exclude` glob are skipped (directories with everything under
+/// them). Symlinks are skipped unless `follow_symlinks` is set.
+#[derive(Clone,Debug)]
+pub struct DirConfig {
+    follow_symlinks: bool,
+    exclude: Vec<String>
+}
+
+impl DirConfig {
+    pub fn new() -> DirConfig {
+        DirConfig {
+            follow_symlinks: false,
+            exclude: vec![]
+        }
+    }
+}
+
+chain!(follow_symlinks,get_follow_symlinks,bool,DirConfig);
+chain!(exclude,get_exclude,Vec<String>,DirConfig);
+
+pub struct DirSource {
+    path: PathBuf,
+    follow_symlinks: bool,
+    exclude: Vec<Pattern>
+}
+
+impl DirSource {
+    pub fn new(path: &Path, config: &DirConfig) -> io::Result<DirSource> {
+        if !path.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound,format!("No such directory: {}",path.display())));
+        }
+        let exclude = config.exclude.iter().map(|glob| {
+            Pattern::new(glob).map_err(|e| invalid_data(format!("bad --dir-exclude glob {}: {}",glob,e)))
+        }).collect::<io::Result<_>>()?;
+        Ok(DirSource { path: path.to_path_buf(), follow_symlinks: config.follow_symlinks, exclude })
+    }
+
+    fn excluded(&self, key: &str) -> bool {
+        self.exclude.iter().any(|p| p.matches(key))
+    }
+
+    /* sorted, so that every pass sees the same order */
+    fn walk(&self, dir: &Path, prefix: &str, seen: &mut HashSet<PathBuf>, out: &mut Vec<(String,PathBuf)>) -> io::Result<()> {
+        if !seen.insert(fs::canonicalize(dir)?) { return Ok(()); }
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let name = entry.file_name();
+            let name = name.to_str().ok_or_else(|| {
+                invalid_data(format!("non-UTF-8 file name in {}",dir.display()))
+            })?;
+            let key = format!("{}{}",prefix,name);
+            if self.excluded(&key) { continue; }
+            let mut file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                if !self.follow_symlinks { continue; }
+                file_type = fs::metadata(entry.path())?.file_type();
+            }
+            if file_type.is_dir() {
+                self.walk(&entry.path(),&format!("{}/",key),seen,out)?;
+            } else if file_type.is_file() {
+                out.push((key,entry.path()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl NCDValueSource for DirSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let mut files = vec![];
+        if let Err(e) = self.walk(&self.path,"",&mut HashSet::new(),&mut files) {
+            return Box::new(std::iter::once(Err(e)));
+        }
+        Box::new(files.into_iter().map(|(key,path)| {
+            fs::read(&path)
+                .map(|value| (key.into_bytes(),value))
+                .map_err(|e| io::Error::new(e.kind(),format!("Cannot read {}: {}",path.display(),e)))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use ncd::NCDValueSource;
+
+    use super::{DirConfig, DirSource};
+
+    fn entries(source: &DirSource) -> Vec<(String,Vec<u8>)> {
+        source.iter().map(|e| {
+            let (k,v) = e.unwrap();
+            (String::from_utf8(k).unwrap(),v)
+        }).collect()
+    }
+
+    #[test]
+    fn test_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("img/icons")).unwrap();
+        fs::write(dir.path().join("index.html"),b"<html>").unwrap();
+        fs::write(dir.path().join("img/icons/a.png"),b"PNG").unwrap();
+        fs::write(dir.path().join("img/b.tmp"),b"tmp").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path().join("img/icons"),dir.path().join("link")).unwrap();
+        let source = DirSource::new(dir.path(),&DirConfig::new().exclude(vec!["*.tmp".to_string()])).unwrap();
+        assert_eq!(vec![
+            ("img/icons/a.png".to_string(),b"PNG".to_vec()),
+            ("index.html".to_string(),b"<html>".to_vec())
+        ],entries(&source));
+        #[cfg(unix)]
+        {
+            let source = DirSource::new(dir.path(),&DirConfig::new().follow_symlinks(true).exclude(vec!["img".to_string()])).unwrap();
+            assert_eq!(vec![
+                ("index.html".to_string(),b"<html>".to_vec()),
+                ("link/a.png".to_string(),b"PNG".to_vec())
+            ],entries(&source));
+        }
+        assert!(DirSource::new(dir.path(),&DirConfig::new().exclude(vec!["[".to_string()])).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use std::{fs::File, io::{self, BufRead, BufReader, Read, Write}, path::{Path, PathBuf}};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ncd::NCDValueSource;
+use serde_json::{json, Value};
+
+use super::{check_exists, invalid_data, SourceIter};
+
+const RAW_MAGIC : &[u8] = b"NCDDUMP\x01";
+
+/// The binary-safe dump formats, which keep every key and value exactly.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum DumpFormat {
+    /// `NCDDUMP\x01`, then for each entry a big-endian u32 key length, the key, a big-endian
+    /// u64 value length and the value.
+    Raw,
+    /// One `{"key":...,"value":...}` object per line, both base64.
+    Base64Lines
+}
+
+/// Writes entries in a `DumpFormat`, for `DumpSource` (and so `ncd-build -t ncd-dump`) to read
+/// back. None of this crate's tools write dumps: this is library API, for ncd-dump and for
+/// anything else which wants to write a backup that ncd-build can restore exactly. It is what
+/// the `writes version 1` of ncd-dump in `--version` refers to.
+pub struct DumpWriter<W: Write> {
+    out: W,
+    format: DumpFormat
+}
+
+impl<W: Write> DumpWriter<W> {
+    pub fn new(mut out: W, format: DumpFormat) -> io::Result<DumpWriter<W>> {
+        if format == DumpFormat::Raw {
+            out.write_all(RAW_MAGIC)?;
+        }
+        Ok(DumpWriter { out, format })
+    }
+
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        match self.format {
+            DumpFormat::Raw => {
+                if key.len() > u32::MAX as usize {
+                    return Err(invalid_data(format!("key of {} bytes is too long to dump",key.len())));
+                }
+                self.out.write_all(&(key.len() as u32).to_be_bytes())?;
+                self.out.write_all(key)?;
+                self.out.write_all(&(value.len() as u64).to_be_bytes())?;
+                self.out.write_all(value)
+            },
+            DumpFormat::Base64Lines => {
+                let line = json!({ "key": STANDARD.encode(key), "value": STANDARD.encode(value) });
+                writeln!(self.out,"{}",line)
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Entries from either `DumpFormat`, told apart by the raw format's magic.
+pub struct DumpSource {
+    path: PathBuf
+}
+
+impl DumpSource {
+    pub fn new(path: &Path) -> io::Result<DumpSource> {
+        check_exists(path)?;
+        Ok(DumpSource { path: path.to_path_buf() })
+    }
+}
+
+/* None at a clean end of file */
+fn read_length(reader: &mut BufReader<File>, size: usize) -> io::Result<Option<u64>> {
+    let mut bytes = [0;8];
+    let mut got = 0;
+    while got < size {
+        let n = reader.read(&mut bytes[8-size+got..])?;
+        if n == 0 {
+            if got == 0 { return Ok(None); }
+            return Err(invalid_data("truncated dump entry"));
+        }
+        got += n;
+    }
+    Ok(Some(u64::from_be_bytes(bytes)))
+}
+
+fn read_bytes(reader: &mut BufReader<File>, len: u64) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    reader.by_ref().take(len).read_to_end(&mut out)?;
+    if (out.len() as u64) < len {
+        return Err(invalid_data("truncated dump entry"));
+    }
+    Ok(out)
+}
+
+fn raw_entry(reader: &mut BufReader<File>) -> io::Result<Option<(Vec<u8>,Vec<u8>)>> {
+    let key_len = match read_length(reader,4)? { Some(len) => len, None => { return Ok(None); } };
+    let key = read_bytes(reader,key_len)?;
+    let value_len = read_length(reader,8)?.ok_or_else(|| invalid_data("truncated dump entry"))?;
+    Ok(Some((key,read_bytes(reader,value_len)?)))
+}
+
+fn base64_field(record: &Value, name: &str, number: usize) -> io::Result<Vec<u8>> {
+    let text = record.get(name).and_then(|v| v.as_str())
+        .ok_or_else(|| invalid_data(format!("line {}: no {} string",number,name)))?;
+    STANDARD.decode(text).map_err(|e| invalid_data(format!("line {}: bad base64 {}: {}",number,name,e)))
+}
+
+fn line_entry(line: &str, number: usize) -> io::Result<(Vec<u8>,Vec<u8>)> {
+    let record : Value = serde_json::from_str(line).map_err(|e| invalid_data(format!("line {}: {}",number,e)))?;
+    Ok((base64_field(&record,"key",number)?,base64_field(&record,"value",number)?))
+}
+
+impl NCDValueSource for DumpSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let mut reader = match File::open(&self.path) {
+            Ok(f) => BufReader::new(f),
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        let is_raw = match reader.fill_buf() {
+            Ok(buf) => buf.starts_with(RAW_MAGIC),
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        if is_raw {
+            reader.consume(RAW_MAGIC.len());
+            Box::new(std::iter::from_fn(move || raw_entry(&mut reader).transpose()))
+        } else {
+            Box::new(reader.lines().enumerate().filter_map(|(i,line)| {
+                let line = match line { Ok(l) => l, Err(e) => { return Some(Err(e)); } };
+                if line.trim().is_empty() { return None; }
+                Some(line_entry(&line,i+1))
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use ncd::NCDValueSource;
+
+    use super::{DumpFormat, DumpSource, DumpWriter};
+
+    #[test]
+    fn test_round_trip() {
+        let entries = vec![
+            (b"plain".to_vec(),b"value".to_vec()),
+            (vec![0,0xFF,b'\n'],vec![]),
+            (vec![],b"line\nbreak\r\n\x00".to_vec())
+        ];
+        for format in &[DumpFormat::Raw,DumpFormat::Base64Lines] {
+            let file = tempfile::NamedTempFile::new().unwrap();
+            let mut writer = DumpWriter::new(file.reopen().unwrap(),*format).unwrap();
+            for (key,value) in &entries {
+                writer.add(key,value).unwrap();
+            }
+            writer.finish().unwrap();
+            let source = DumpSource::new(file.path()).unwrap();
+            let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+            assert_eq!(entries,out);
+        }
+    }
+
+    #[test]
+    fn test_bad_dumps() {
+        for data in &[&b"NCDDUMP\x01\x00\x00\x00\x05ab"[..],b"{\"key\":\"YQ==\"}\n",b"{\"key\":\"!\",\"value\":\"\"}\n"] {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            file.write_all(data).unwrap();
+            let source = DumpSource::new(file.path()).unwrap();
+            assert!(source.iter().any(|e| e.is_err()));
+        }
+    }
+}
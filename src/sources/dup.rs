@@ -0,0 +1,163 @@
+use std::{collections::HashMap, io, sync::Arc};
+
+use ncd::NCDValueSource;
+
+use super::{counting::Tally, invalid_data, SourceIter};
+
+/// What to do when a key turns up more than once.
+#[derive(Clone,Debug,PartialEq)]
+pub enum DupPolicy {
+    /// Fail on the second occurrence, naming both records.
+    Error,
+    /// Keep the first value.
+    First,
+    /// Keep the last value.
+    Last,
+    /// Keep every value, in input order, joined with the separator.
+    Concat(Vec<u8>)
+}
+
+impl DupPolicy {
+    /// `error`, `first`, `last`, or `concat` optionally followed by `:SEP` (default `,`).
+    pub fn parse(value: &str) -> Result<DupPolicy,String> {
+        match value {
+            "error" => Ok(DupPolicy::Error),
+            "first" => Ok(DupPolicy::First),
+            "last" => Ok(DupPolicy::Last),
+            "concat" => Ok(DupPolicy::Concat(b",".to_vec())),
+            _ => match value.strip_prefix("concat:") {
+                Some(sep) => Ok(DupPolicy::Concat(sep.as_bytes().to_vec())),
+                None => Err(format!("bad duplicate policy '{}': expected error, first, last or concat[:SEP]",value))
+            }
+        }
+    }
+}
+
+/// Applies a `DupPolicy` to the keys of `inner`. `error` and `first` stream, remembering every
+/// key seen. `last` and `concat` count each key's occurrences in a first pass, then hold values
+/// only for keys which repeat, yielding each at its last occurrence.
+pub struct DupSource {
+    inner: Box<dyn NCDValueSource>,
+    policy: DupPolicy,
+    tally: Option<Arc<Tally>>
+}
+
+impl DupSource {
+    pub fn new(inner: Box<dyn NCDValueSource>, policy: DupPolicy) -> DupSource {
+        DupSource { inner, policy, tally: None }
+    }
+
+    /// Count records folded into another in `tally`, as deduplicated.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> DupSource {
+        self.tally = Some(tally.clone());
+        self
+    }
+
+    fn add_deduplicated(&self) {
+        if let Some(tally) = &self.tally { tally.add_deduplicated(); }
+    }
+
+    fn counts(&self) -> io::Result<HashMap<Vec<u8>,u64>> {
+        let mut counts = HashMap::new();
+        for entry in self.inner.iter() {
+            *counts.entry(entry?.0).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+
+    fn streaming<'a>(&'a self) -> SourceIter<'a> {
+        let mut first_seen = HashMap::new();
+        let mut record = 0;
+        Box::new(self.inner.iter().filter_map(move |entry| {
+            let (key,value) = match entry { Ok(e) => e, Err(e) => { return Some(Err(e)); } };
+            record += 1;
+            let first = match first_seen.get(&key) {
+                Some(first) => *first,
+                None => {
+                    first_seen.insert(key.clone(),record);
+                    return Some(Ok((key,value)));
+                }
+            };
+            if self.policy == DupPolicy::Error {
+                return Some(Err(invalid_data(format!("record {}: duplicate key {} (first seen at record {})",
+                    record,String::from_utf8_lossy(&key),first))));
+            }
+            self.add_deduplicated();
+            None
+        }))
+    }
+
+    fn merging<'a>(&'a self, counts: HashMap<Vec<u8>,u64>) -> SourceIter<'a> {
+        let mut pending : HashMap<Vec<u8>,(u64,Vec<u8>)> = HashMap::new();
+        Box::new(self.inner.iter().filter_map(move |entry| {
+            let (key,value) = match entry { Ok(e) => e, Err(e) => { return Some(Err(e)); } };
+            let total = counts.get(&key).cloned().unwrap_or(1);
+            if total == 1 { return Some(Ok((key,value))); }
+            let (seen,merged) = pending.entry(key.clone()).or_insert((0,vec![]));
+            match &self.policy {
+                DupPolicy::Concat(sep) if *seen > 0 => {
+                    merged.extend_from_slice(sep);
+                    merged.extend_from_slice(&value);
+                },
+                _ => { *merged = value; }
+            }
+            *seen += 1;
+            if *seen < total {
+                self.add_deduplicated();
+                return None;
+            }
+            let (_,merged) = pending.remove(&key).unwrap();
+            Some(Ok((key,merged)))
+        }))
+    }
+}
+
+impl NCDValueSource for DupSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match self.policy {
+            DupPolicy::Error | DupPolicy::First => self.streaming(),
+            DupPolicy::Last | DupPolicy::Concat(_) => match self.counts() {
+                Ok(counts) => self.merging(counts),
+                Err(e) => Box::new(std::iter::once(Err(e)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use crate::sources::{counting::Tally, memory::MemorySource};
+    use super::{DupPolicy, DupSource};
+
+    fn run(policy: &str) -> Result<Vec<(Vec<u8>,Vec<u8>)>,String> {
+        let entries = vec![
+            (b"a".to_vec(),b"1".to_vec()),
+            (b"b".to_vec(),b"2".to_vec()),
+            (b"a".to_vec(),b"3".to_vec()),
+            (b"c".to_vec(),b"4".to_vec()),
+            (b"a".to_vec(),b"5".to_vec())
+        ];
+        let source = DupSource::new(Box::new(MemorySource::new(entries)),DupPolicy::parse(policy)?);
+        source.iter().collect::<Result<Vec<_>,_>>().map_err(|e| e.to_string())
+    }
+
+    fn pairs(entries: &[(&str,&str)]) -> Vec<(Vec<u8>,Vec<u8>)> {
+        entries.iter().map(|(k,v)| (k.as_bytes().to_vec(),v.as_bytes().to_vec())).collect()
+    }
+
+    #[test]
+    fn test_dup_policy() {
+        assert_eq!(Err("record 3: duplicate key a (first seen at record 1)".to_string()),run("error"));
+        assert_eq!(Ok(pairs(&[("a","1"),("b","2"),("c","4")])),run("first"));
+        assert_eq!(Ok(pairs(&[("b","2"),("c","4"),("a","5")])),run("last"));
+        assert_eq!(Ok(pairs(&[("b","2"),("c","4"),("a","1,3,5")])),run("concat"));
+        assert_eq!(Ok(pairs(&[("b","2"),("c","4"),("a","1 | 3 | 5")])),run("concat: | "));
+        assert!(run("latest").is_err());
+        let tally = Tally::new();
+        let source = DupSource::new(Box::new(MemorySource::new(pairs(&[("a","1"),("a","2")]))),DupPolicy::Last).tally(&tally);
+        assert_eq!(1,source.iter().count());
+        assert_eq!(1,tally.deduplicated());
+    }
+}
@@ -0,0 +1,126 @@
+use std::{fs::File, io::{self, BufRead, BufReader, Lines}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+/// Each record is keyed by its ID (the first token after `>`). The value is the sequence with
+/// line breaks removed, preceded by the description line and a newline if `description` is set.
+#[derive(Clone,Debug)]
+pub struct FastaConfig {
+    description: bool
+}
+
+impl FastaConfig {
+    pub fn new() -> FastaConfig {
+        FastaConfig { description: false }
+    }
+}
+
+chain!(description,get_description,bool,FastaConfig);
+
+pub struct FastaSource {
+    path: PathBuf,
+    config: FastaConfig
+}
+
+impl FastaSource {
+    pub fn new(path: &Path, config: &FastaConfig) -> io::Result<FastaSource> {
+        check_exists(path)?;
+        Ok(FastaSource { path: path.to_path_buf(), config: config.clone() })
+    }
+}
+
+struct FastaIterator {
+    lines: Lines<BufReader<File>>,
+    header: Option<String>,
+    sequence: Vec<u8>,
+    config: FastaConfig,
+    line: usize
+}
+
+impl FastaIterator {
+    fn record(&mut self, next_header: Option<String>) -> Option<io::Result<(Vec<u8>,Vec<u8>)>> {
+        let header = std::mem::replace(&mut self.header,next_header)?;
+        let sequence = std::mem::take(&mut self.sequence);
+        let id = match header.split_whitespace().next() {
+            Some(id) => id.as_bytes().to_vec(),
+            None => { return Some(Err(invalid_data(format!("line {}: record with no ID",self.line)))); }
+        };
+        let value = if self.config.description {
+            [header.as_bytes(),b"\n",&sequence].concat()
+        } else {
+            sequence
+        };
+        Some(Ok((id,value)))
+    }
+}
+
+impl Iterator for FastaIterator {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => { return Some(Err(e)); },
+                None => { return self.record(None); }
+            };
+            self.line += 1;
+            let line = line.trim_end();
+            if let Some(header) = line.strip_prefix('>') {
+                let out = self.record(Some(header.to_string()));
+                if out.is_some() { return out; }
+            } else if line.starts_with(';') || line.is_empty() {
+                continue;
+            } else if self.header.is_none() {
+                return Some(Err(invalid_data(format!("line {}: sequence before first '>' header",self.line))));
+            } else {
+                self.sequence.extend(line.bytes().filter(|b| !b.is_ascii_whitespace()));
+            }
+        }
+    }
+}
+
+impl NCDValueSource for FastaSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match File::open(&self.path) {
+            Ok(file) => Box::new(FastaIterator {
+                lines: BufReader::new(file).lines(),
+                header: None,
+                sequence: vec![],
+                config: self.config.clone(),
+                line: 0
+            }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sources::fixture;
+    use super::{FastaConfig, FastaSource};
+
+    fn parse(data: &str, config: &FastaConfig) -> Result<Vec<(String,String)>,String> {
+        fixture::parse(data.as_bytes(),|path| FastaSource::new(path,config))
+    }
+
+    #[test]
+    fn test_fasta() {
+        let data = ">sp|P51587|BRCA2 Breast cancer type 2\nMPIGS\nKERPT\n\n>P04637 TP53\r\nMEEPQ\n>empty\n";
+        assert_eq!(Ok(vec![
+            ("sp|P51587|BRCA2".to_string(),"MPIGSKERPT".to_string()),
+            ("P04637".to_string(),"MEEPQ".to_string()),
+            ("empty".to_string(),"".to_string())
+        ]),parse(data,&FastaConfig::new()));
+        assert_eq!(Ok(vec![
+            ("sp|P51587|BRCA2".to_string(),"sp|P51587|BRCA2 Breast cancer type 2\nMPIGSKERPT".to_string()),
+            ("P04637".to_string(),"P04637 TP53\nMEEPQ".to_string()),
+            ("empty".to_string(),"empty\n".to_string())
+        ]),parse(data,&FastaConfig::new().description(true)));
+        assert_eq!(Ok(vec![]),parse("",&FastaConfig::new()));
+        assert!(parse("MPIGS\n>a\nM\n",&FastaConfig::new()).is_err());
+        assert!(parse(">\nM\n",&FastaConfig::new()).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use std::{fs::File, io::{self, BufRead, BufReader}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+/// A span of byte columns, 1-based and inclusive as written: `1-12`, `13-` (to the end of the
+/// line) or `7` (one column).
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Span {
+    start: usize,
+    end: Option<usize>
+}
+
+impl Span {
+    pub fn parse(s: &str) -> Result<Span,String> {
+        let number = |n: &str| n.trim().parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(|| format!("Invalid column range: {}",s));
+        let (start,end) = match s.split_once('-') {
+            Some((start,"")) => (number(start)?,None),
+            Some((start,end)) => (number(start)?,Some(number(end)?)),
+            None => { let n = number(s)?; (n,Some(n)) }
+        };
+        if end.map(|end| end < start).unwrap_or(false) {
+            return Err(format!("Invalid column range (ends before it starts): {}",s));
+        }
+        Ok(Span { start: start-1, end })
+    }
+
+    /// Just the field at 0-based `index`.
+    pub(crate) fn single(index: usize) -> Span {
+        Span { start: index, end: Some(index+1) }
+    }
+
+    /// The 0-based indexes this span covers out of `len`.
+    pub(crate) fn indexes(&self, len: usize) -> std::ops::Range<usize> {
+        self.start.min(len)..self.end.unwrap_or(len).min(len)
+    }
+
+    fn extract<'a>(&self, line: &'a [u8]) -> Option<&'a [u8]> {
+        if self.start >= line.len() { return None; }
+        let end = self.end.unwrap_or(line.len()).min(line.len());
+        Some(&line[self.start..end])
+    }
+}
+
+/// Parses `--columns` style `KEY,VALUE` spans, eg `1-12,13-80`.
+pub fn parse_columns(s: &str) -> Result<(Span,Span),String> {
+    match s.split(',').collect::<Vec<_>>().as_slice() {
+        [key,value] => Ok((Span::parse(key)?,Span::parse(value)?)),
+        _ => Err(format!("Expected two column ranges, key then value: {}",s))
+    }
+}
+
+/// Lines of an undelimited fixed-width file, with the key and value taken from byte spans of
+/// each line and stripped of space padding. The default spans are the whole line. Blank lines
+/// are skipped, and a line too short to reach the key is an error.
+#[derive(Clone,Debug)]
+pub struct FixedConfig {
+    key: Span,
+    value: Span
+}
+
+impl FixedConfig {
+    pub fn new() -> FixedConfig {
+        FixedConfig {
+            key: Span { start: 0, end: None },
+            value: Span { start: 0, end: None }
+        }
+    }
+}
+
+chain!(key,get_key,Span,FixedConfig);
+chain!(value,get_value,Span,FixedConfig);
+
+pub struct FixedSource {
+    path: PathBuf,
+    config: FixedConfig
+}
+
+impl FixedSource {
+    pub fn new(path: &Path, config: &FixedConfig) -> io::Result<FixedSource> {
+        check_exists(path)?;
+        Ok(FixedSource { path: path.to_path_buf(), config: config.clone() })
+    }
+}
+
+fn trim_spaces(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != b' ').unwrap_or(bytes.len());
+    let end = bytes.iter().rposition(|b| *b != b' ').map(|i| i+1).unwrap_or(start);
+    &bytes[start..end]
+}
+
+fn parse_line(line: &[u8], number: usize, config: &FixedConfig) -> io::Result<(Vec<u8>,Vec<u8>)> {
+    let key = config.key.extract(line)
+        .ok_or_else(|| invalid_data(format!("line {}: too short for the key column (length {})",number,line.len())))?;
+    let value = config.value.extract(line).unwrap_or(&[]);
+    Ok((trim_spaces(key).to_vec(),trim_spaces(value).to_vec()))
+}
+
+impl NCDValueSource for FixedSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        Box::new(BufReader::new(file).split(b'\n').enumerate().filter_map(move |(i,line)| {
+            let mut line = match line { Ok(l) => l, Err(e) => { return Some(Err(e)); } };
+            if line.last() == Some(&b'\r') { line.pop(); }
+            if trim_spaces(&line).is_empty() { return None; }
+            Some(parse_line(&line,i+1,&self.config))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use ncd::NCDValueSource;
+
+    use super::{parse_columns, FixedConfig, FixedSource, Span};
+
+    #[test]
+    fn test_columns() {
+        let (key,value) = parse_columns("1-12,13-").unwrap();
+        assert_eq!(Span { start: 0, end: Some(12) },key);
+        assert_eq!(Span { start: 12, end: None },value);
+        assert_eq!(Span { start: 6, end: Some(7) },Span::parse("7").unwrap());
+        assert!(parse_columns("1-12").is_err());
+        assert!(parse_columns("0-3,4-5").is_err());
+        assert!(parse_columns("5-3,6-").is_err());
+    }
+
+    #[test]
+    fn test_fixed() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"BRCA2     13q13.1   \r\n\n   TP53   17p13.1\nKRAS\n  \nX").unwrap();
+        let (key,value) = parse_columns("1-10,11-20").unwrap();
+        let source = FixedSource::new(file.path(),&FixedConfig::new().key(key).value(value)).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![
+            (b"BRCA2".to_vec(),b"13q13.1".to_vec()),
+            (b"TP53".to_vec(),b"17p13.1".to_vec()),
+            (b"KRAS".to_vec(),b"".to_vec()),
+            (b"X".to_vec(),b"".to_vec())
+        ],out);
+        let (key,value) = parse_columns("3-4,5-").unwrap();
+        let source = FixedSource::new(file.path(),&FixedConfig::new().key(key).value(value)).unwrap();
+        assert!(source.iter().any(|e| e.is_err()));
+    }
+}
@@ -0,0 +1,125 @@
+use std::{fs::File, io::{self, BufRead, BufReader}, path::{Path, PathBuf}, sync::Arc};
+
+use ncd::NCDValueSource;
+
+use super::{check_exists, counting::{report_skip, Tally}, invalid_data, SourceIter};
+
+/// Each feature line of a GFF3 or GTF file is stored whole, keyed by the value of `attribute`
+/// in its ninth column. Features without the attribute are skipped, and reported to any
+/// `tally`. Comma-separated GFF3 values give an entry each.
+#[derive(Clone,Debug)]
+pub struct GffConfig {
+    attribute: String,
+    tally: Option<Arc<Tally>>
+}
+
+impl GffConfig {
+    pub fn new() -> GffConfig {
+        GffConfig { attribute: "ID".to_string(), tally: None }
+    }
+
+    /// Report skipped features to `tally`.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> GffConfig {
+        self.tally = Some(tally.clone());
+        self
+    }
+}
+
+chain!(attribute,get_attribute,String,GffConfig);
+
+pub struct GffSource {
+    path: PathBuf,
+    config: GffConfig
+}
+
+impl GffSource {
+    pub fn new(path: &Path, config: &GffConfig) -> io::Result<GffSource> {
+        check_exists(path)?;
+        Ok(GffSource { path: path.to_path_buf(), config: config.clone() })
+    }
+}
+
+/* GFF3 is `name=v1,v2`, GTF is `name "v"` */
+fn attribute_values(attributes: &str, want: &str) -> Vec<String> {
+    for part in attributes.split(';') {
+        let part = part.trim();
+        if let Some((name,values)) = part.split_once('=') {
+            if name.trim() == want {
+                return values.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect();
+            }
+        } else if let Some((name,value)) = part.split_once(|c: char| c.is_whitespace()) {
+            if name == want {
+                return vec![value.trim().trim_matches('"').to_string()];
+            }
+        }
+    }
+    vec![]
+}
+
+fn gff_keys(line: &str, number: usize, attribute: &str) -> io::Result<Vec<String>> {
+    let columns : Vec<&str> = line.split('\t').collect();
+    if columns.len() != 9 {
+        return Err(invalid_data(format!("line {}: expected 9 tab-separated columns, found {}",number,columns.len())));
+    }
+    Ok(attribute_values(columns[8],attribute))
+}
+
+impl NCDValueSource for GffSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        let lines = BufReader::new(file).lines().enumerate()
+            .take_while(|(_,line)| !matches!(line,Ok(l) if l.starts_with("##FASTA")));
+        Box::new(lines.flat_map(move |(i,line)| {
+            let line = match line { Ok(l) => l, Err(e) => { return vec![Err(e)]; } };
+            let line = line.trim_end_matches('\r');
+            if line.starts_with('#') || line.is_empty() { return vec![]; }
+            match gff_keys(line,i+1,&self.config.attribute) {
+                Ok(keys) if keys.is_empty() => {
+                    let what = format!("line {}: no {} attribute",i+1,self.config.attribute);
+                    match report_skip(&self.config.tally,what) { Ok(()) => vec![], Err(e) => vec![Err(e)] }
+                },
+                Ok(keys) => keys.into_iter().map(|k| Ok((k.into_bytes(),line.as_bytes().to_vec()))).collect(),
+                Err(e) => vec![Err(e)]
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use ncd::NCDValueSource;
+
+    use crate::sources::counting::Tally;
+    use super::{attribute_values, GffConfig, GffSource};
+
+    #[test]
+    fn test_attribute_values() {
+        assert_eq!(vec!["gene1"],attribute_values("ID=gene1;Name=BRCA2","ID"));
+        assert_eq!(vec!["t1","t2"],attribute_values("ID=e1;Parent=t1,t2","Parent"));
+        assert_eq!(vec!["ENSG01"],attribute_values("gene_id \"ENSG01\"; transcript_id \"ENST01\";","gene_id"));
+        assert_eq!(Vec::<String>::new(),attribute_values("ID=gene1","Name"));
+    }
+
+    #[test]
+    fn test_gff() {
+        let data = "##gff-version 3\nchr13\tens\tgene\t1\t9\t.\t+\t.\tID=gene1;Name=BRCA2\nchr13\tens\tregion\t1\t9\t.\t+\t.\tNote=x\n##FASTA\n>chr13\nACGT\n";
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+        let tally = Tally::new();
+        let source = GffSource::new(file.path(),&GffConfig::new().attribute("Name".to_string()).tally(&tally)).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"BRCA2".to_vec(),b"chr13\tens\tgene\t1\t9\t.\t+\t.\tID=gene1;Name=BRCA2".to_vec())],out);
+        assert_eq!(1,tally.skipped());
+        tally.set_strict(true);
+        assert_eq!("strict: line 3: no Name attribute",source.iter().find_map(|e| e.err()).unwrap().to_string());
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"chr13 ens gene 1 9\n").unwrap();
+        let source = GffSource::new(file.path(),&GffConfig::new()).unwrap();
+        assert!(source.iter().next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use std::{collections::HashSet, io};
+
+use curl::easy::{Easy, List};
+use ncd::NCDValueSource;
+use serde_json::Value;
+use tempfile::NamedTempFile;
+
+use crate::error::remove_on_exit;
+use super::{invalid_data, json::json_value, jsonl::{key_bytes, lookup_path}, spool::{SpoolSource, Spooler}, SourceIter};
+
+/// Where things are in each page of a paginated JSON API, as dotted paths (a leading `.` is
+/// allowed). The records are the array at `items_path` (the whole page if empty), and the URL of
+/// the next page is the string at `next_path`: without one, only the first page is read. Keys and
+/// values are found in each record as for the jsonl format.
+#[derive(Clone,Debug)]
+pub struct HttpJsonConfig {
+    items_path: String,
+    next_path: Option<String>,
+    key_path: String,
+    value_path: Option<String>
+}
+
+impl HttpJsonConfig {
+    pub fn new() -> HttpJsonConfig {
+        HttpJsonConfig {
+            items_path: "".to_string(),
+            next_path: None,
+            key_path: "id".to_string(),
+            value_path: None
+        }
+    }
+}
+
+chain!(items_path,get_items_path,String,HttpJsonConfig);
+chain!(next_path,get_next_path,Option<String>,HttpJsonConfig);
+chain!(key_path,get_key_path,String,HttpJsonConfig);
+chain!(value_path,get_value_path,Option<String>,HttpJsonConfig);
+
+pub fn is_http_url(name: &str) -> bool {
+    name.starts_with("http://") || name.starts_with("https://")
+}
+
+/* next links may be absolute, scheme-, host- or query-relative, or relative to the directory */
+fn resolve_url(base: &str, next: &str) -> String {
+    let scheme_end = base.find("://").map(|i| i+3).unwrap_or(0);
+    let host_end = base[scheme_end..].find('/').map(|i| i+scheme_end).unwrap_or(base.len());
+    if next.contains("://") {
+        next.to_string()
+    } else if next.starts_with("//") {
+        format!("{}{}",&base[..scheme_end-2],next)
+    } else if next.starts_with('/') {
+        format!("{}{}",&base[..host_end],next)
+    } else if next.starts_with('?') {
+        format!("{}{}",base.split('?').next().unwrap(),next)
+    } else {
+        let path = base.split('?').next().unwrap();
+        let dir_end = path[host_end..].rfind('/').map(|i| i+host_end+1);
+        match dir_end {
+            Some(end) => format!("{}{}",&path[..end],next),
+            None => format!("{}/{}",path,next)
+        }
+    }
+}
+
+fn fetch(url: &str) -> io::Result<Value> {
+    let mut body = vec![];
+    let mut easy = Easy::new();
+    let mut headers = List::new();
+    headers.append("Accept: application/json")?;
+    easy.url(url)?;
+    easy.follow_location(true)?;
+    easy.fail_on_error(true)?;
+    easy.http_headers(headers)?;
+    {
+        let mut transfer = easy.transfer();
+        transfer.write_function(|data| { body.extend_from_slice(data); Ok(data.len()) })?;
+        transfer.perform().map_err(|e| io::Error::new(io::ErrorKind::Other,format!("{}: {}",url,e)))?;
+    }
+    serde_json::from_slice(&body).map_err(|e| invalid_data(format!("{}: {}",url,e)))
+}
+
+/* the records of one page, and the link to the next if there is one */
+fn page_records(page: &Value, config: &HttpJsonConfig) -> io::Result<(Vec<(Vec<u8>,Vec<u8>)>,Option<String>)> {
+    let items = lookup_path(page,&config.items_path).and_then(|v| v.as_array())
+        .ok_or_else(|| invalid_data(format!("no array of items at {}",config.items_path)))?;
+    let mut records = vec![];
+    for (i,item) in items.iter().enumerate() {
+        let key = lookup_path(item,&config.key_path)
+            .ok_or_else(|| invalid_data(format!("item {}: no key at {}",i,config.key_path)))?;
+        let key = key_bytes(key)
+            .ok_or_else(|| invalid_data(format!("item {}: key at {} is not a string or number",i,config.key_path)))?;
+        let value = if let Some(value_path) = &config.value_path {
+            let value = lookup_path(item,value_path)
+                .ok_or_else(|| invalid_data(format!("item {}: no value at {}",i,value_path)))?;
+            json_value(&String::from_utf8_lossy(&key),value.clone(),true)?
+        } else {
+            serde_json::to_vec(item).map_err(invalid_data)?
+        };
+        records.push((key,value));
+    }
+    let next = config.next_path.as_ref().and_then(|path| lookup_path(page,path))
+        .and_then(|next| next.as_str()).filter(|next| !next.is_empty()).map(|next| next.to_string());
+    Ok((records,next))
+}
+
+/// A snapshot of every page of a paginated JSON API, taken once, into a spool, so that every
+/// build attempt sees the same data. Paging stops at a page without a next link, or at a link
+/// back to a page already read.
+pub struct HttpJsonSource {
+    _spool: NamedTempFile,
+    records: SpoolSource
+}
+
+impl HttpJsonSource {
+    pub fn new(url: &str, config: &HttpJsonConfig) -> io::Result<HttpJsonSource> {
+        let spool = NamedTempFile::new()?;
+        remove_on_exit(spool.path());
+        let mut spooler = Spooler::new(spool.reopen()?);
+        let mut seen = HashSet::new();
+        let mut next = Some(url.to_string());
+        while let Some(url) = next.take() {
+            if !seen.insert(url.clone()) { break; }
+            let (records,link) = page_records(&fetch(&url)?,config)
+                .map_err(|e| invalid_data(format!("{}: {}",url,e)))?;
+            for (key,value) in records {
+                spooler.add(&key,&value)?;
+            }
+            next = link.map(|link| resolve_url(&url,&link));
+        }
+        spooler.finish()?;
+        let records = SpoolSource::new(spool.path());
+        Ok(HttpJsonSource { _spool: spool, records })
+    }
+}
+
+impl NCDValueSource for HttpJsonSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        self.records.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{page_records, resolve_url, HttpJsonConfig};
+
+    #[test]
+    fn test_page_records() {
+        let config = HttpJsonConfig::new()
+            .items_path(".data.items".to_string())
+            .next_path(Some(".links.next".to_string()));
+        let page = json!({"data":{"items":[{"id":"a","n":1},{"id":2,"n":2}]},"links":{"next":"?page=2"}});
+        let (records,next) = page_records(&page,&config).unwrap();
+        assert_eq!(vec![
+            (b"a".to_vec(),br#"{"id":"a","n":1}"#.to_vec()),
+            (b"2".to_vec(),br#"{"id":2,"n":2}"#.to_vec())
+        ],records);
+        assert_eq!(Some("?page=2".to_string()),next);
+        let page = json!({"data":{"items":[]},"links":{"next":null}});
+        assert_eq!((vec![],None),page_records(&page,&config).unwrap());
+        let config = HttpJsonConfig::new().value_path(Some("n".to_string()));
+        assert_eq!(vec![(b"a".to_vec(),b"1".to_vec())],page_records(&json!([{"id":"a","n":1}]),&config).unwrap().0);
+        assert!(page_records(&json!({"items":[]}),&config).is_err());
+        assert!(page_records(&json!([{"name":"a"}]),&config).is_err());
+    }
+
+    #[test]
+    fn test_resolve_url() {
+        let base = "https://api.example.org/v1/genes?page=1";
+        assert_eq!("https://other.org/x",resolve_url(base,"https://other.org/x"));
+        assert_eq!("https://cdn.example.org/x",resolve_url(base,"//cdn.example.org/x"));
+        assert_eq!("https://api.example.org/v2/genes",resolve_url(base,"/v2/genes"));
+        assert_eq!("https://api.example.org/v1/genes?page=2",resolve_url(base,"?page=2"));
+        assert_eq!("https://api.example.org/v1/more",resolve_url(base,"more"));
+        assert_eq!("https://api.example.org/more",resolve_url("https://api.example.org","more"));
+    }
+}
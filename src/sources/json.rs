@@ -0,0 +1,85 @@
+use std::{fs::File, io::{self, BufReader}, path::Path};
+
+use ncd::NCDValueSource;
+use serde_json::{Map, Value};
+
+use super::{invalid_data, memory::MemorySource, SourceIter};
+
+/// String values are stored as their contents. Other values are an error unless `compact` is
+/// set, in which case they're stored as compact JSON.
+#[derive(Clone,Debug)]
+pub struct JsonConfig {
+    compact: bool
+}
+
+impl JsonConfig {
+    pub fn new() -> JsonConfig {
+        JsonConfig { compact: false }
+    }
+}
+
+chain!(compact,get_compact,bool,JsonConfig);
+
+pub(crate) fn json_value(key: &str, value: Value, compact: bool) -> io::Result<Vec<u8>> {
+    match value {
+        Value::String(s) => Ok(s.into_bytes()),
+        v if compact => serde_json::to_vec(&v).map_err(invalid_data),
+        _ => Err(invalid_data(format!("value for {} is not a string (use --json-compact to store it as JSON)",key)))
+    }
+}
+
+/// Source over the members of a JSON file's top-level object.
+pub struct JsonSource {
+    entries: MemorySource
+}
+
+impl JsonSource {
+    pub fn new(path: &Path, config: &JsonConfig) -> io::Result<JsonSource> {
+        let object : Map<String,Value> = serde_json::from_reader(BufReader::new(File::open(path)?))
+            .map_err(|e| invalid_data(format!("{}: {}",path.display(),e)))?;
+        let mut entries = vec![];
+        for (key,value) in object {
+            let value = json_value(&key,value,config.compact)?;
+            entries.push((key.into_bytes(),value));
+        }
+        Ok(JsonSource { entries: MemorySource::new(entries) })
+    }
+}
+
+impl NCDValueSource for JsonSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        self.entries.iter()
+    }
+}
+
+pub fn looks_like_json_object(bytes: &[u8]) -> bool {
+    bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{')
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sources::fixture;
+    use super::{looks_like_json_object, JsonConfig, JsonSource};
+
+    fn parse(data: &str, config: &JsonConfig) -> Result<Vec<(String,String)>,String> {
+        fixture::parse(data.as_bytes(),|path| JsonSource::new(path,config))
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(Ok(vec![("a".to_string(),"x\ny".to_string())]),parse("{\"a\":\"x\\ny\"}",&JsonConfig::new()));
+        assert!(parse("{\"a\":1}",&JsonConfig::new()).is_err());
+        assert!(parse("[1,2]",&JsonConfig::new()).is_err());
+        assert_eq!(Ok(vec![
+            ("a".to_string(),"{\"b\":[1,null]}".to_string()),
+            ("c".to_string(),"s".to_string())
+        ]),parse("{ \"c\": \"s\", \"a\": { \"b\": [ 1, null ] } }",&JsonConfig::new().compact(true)));
+    }
+
+    #[test]
+    fn test_looks_like_json_object() {
+        assert!(looks_like_json_object(b"  \n{\"a\""));
+        assert!(!looks_like_json_object(b"a\tb"));
+        assert!(!looks_like_json_object(b""));
+    }
+}
@@ -0,0 +1,128 @@
+use std::{fs::File, io::{self, BufRead, BufReader}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+use serde_json::Value;
+
+use super::{check_exists, invalid_data, json::json_value, SourceIter};
+
+/// One JSON object per line. The key is found at dotted path `key_path` (`gene.id`,
+/// `xrefs.0`). The value is found at `value_path` if given (stored as for the json format),
+/// otherwise the whole line is stored as it is.
+#[derive(Clone,Debug)]
+pub struct JsonLinesConfig {
+    key_path: String,
+    value_path: Option<String>
+}
+
+impl JsonLinesConfig {
+    pub fn new() -> JsonLinesConfig {
+        JsonLinesConfig {
+            key_path: "id".to_string(),
+            value_path: None
+        }
+    }
+}
+
+chain!(key_path,get_key_path,String,JsonLinesConfig);
+chain!(value_path,get_value_path,Option<String>,JsonLinesConfig);
+
+pub(crate) fn lookup_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let mut here = value;
+    for part in path.split('.').filter(|p| !p.is_empty()) {
+        here = match here {
+            Value::Object(map) => map.get(part)?,
+            Value::Array(list) => list.get(part.parse::<usize>().ok()?)?,
+            _ => { return None; }
+        };
+    }
+    Some(here)
+}
+
+pub(crate) fn key_bytes(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::String(s) => Some(s.as_bytes().to_vec()),
+        Value::Number(n) => Some(n.to_string().into_bytes()),
+        _ => None
+    }
+}
+
+pub struct JsonLinesSource {
+    path: PathBuf,
+    config: JsonLinesConfig
+}
+
+impl JsonLinesSource {
+    pub fn new(path: &Path, config: &JsonLinesConfig) -> io::Result<JsonLinesSource> {
+        check_exists(path)?;
+        Ok(JsonLinesSource { path: path.to_path_buf(), config: config.clone() })
+    }
+}
+
+fn parse_line(line: &str, number: usize, config: &JsonLinesConfig) -> io::Result<(Vec<u8>,Vec<u8>)> {
+    let record : Value = serde_json::from_str(line).map_err(|e| invalid_data(format!("line {}: {}",number,e)))?;
+    let key = lookup_path(&record,&config.key_path)
+        .ok_or_else(|| invalid_data(format!("line {}: no key at {}",number,config.key_path)))?;
+    let key = key_bytes(key)
+        .ok_or_else(|| invalid_data(format!("line {}: key at {} is not a string or number",number,config.key_path)))?;
+    let value = if let Some(value_path) = &config.value_path {
+        let value = lookup_path(&record,value_path)
+            .ok_or_else(|| invalid_data(format!("line {}: no value at {}",number,value_path)))?;
+        json_value(&String::from_utf8_lossy(&key),value.clone(),true)?
+    } else {
+        line.as_bytes().to_vec()
+    };
+    Ok((key,value))
+}
+
+impl NCDValueSource for JsonLinesSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        Box::new(BufReader::new(file).lines().enumerate().filter_map(move |(i,line)| {
+            let line = match line { Ok(l) => l, Err(e) => { return Some(Err(e)); } };
+            let line = line.trim();
+            if line.is_empty() { return None; }
+            Some(parse_line(line,i+1,&self.config))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::sources::fixture;
+    use super::{lookup_path, JsonLinesConfig, JsonLinesSource};
+
+    fn parse(data: &str, config: &JsonLinesConfig) -> Result<Vec<(String,String)>,String> {
+        fixture::parse(data.as_bytes(),|path| JsonLinesSource::new(path,config))
+    }
+
+    #[test]
+    fn test_lookup_path() {
+        let v = json!({ "a": { "b": [ 1, { "c": "x" } ] } });
+        assert_eq!(Some(&json!("x")),lookup_path(&v,"a.b.1.c"));
+        assert_eq!(Some(&v),lookup_path(&v,""));
+        assert_eq!(None,lookup_path(&v,"a.b.2"));
+        assert_eq!(None,lookup_path(&v,"a.x"));
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let data = "{\"id\":\"G1\",\"gene\":{\"sym\":\"BRCA2\",\"n\":2}}\n\n{ \"id\": 7, \"gene\": {\"sym\":\"TP53\"} }\n";
+        assert_eq!(Ok(vec![
+            ("G1".to_string(),"{\"id\":\"G1\",\"gene\":{\"sym\":\"BRCA2\",\"n\":2}}".to_string()),
+            ("7".to_string(),"{ \"id\": 7, \"gene\": {\"sym\":\"TP53\"} }".to_string())
+        ]),parse(data,&JsonLinesConfig::new()));
+        assert_eq!(Ok(vec![
+            ("BRCA2".to_string(),"{\"n\":2,\"sym\":\"BRCA2\"}".to_string()),
+            ("TP53".to_string(),"{\"sym\":\"TP53\"}".to_string())
+        ]),parse(data,&JsonLinesConfig::new().key_path("gene.sym".to_string()).value_path(Some("gene".to_string()))));
+        assert_eq!(Err("line 3: no value at gene.n".to_string()),
+            parse(data,&JsonLinesConfig::new().value_path(Some("gene.n".to_string()))));
+        assert_eq!(Err("line 1: key at gene is not a string or number".to_string()),
+            parse(data,&JsonLinesConfig::new().key_path("gene".to_string())));
+    }
+}
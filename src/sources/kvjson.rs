@@ -0,0 +1,82 @@
+use std::{fs::File, io::{self, BufRead, BufReader, Split}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+
+use crate::pipe::minify_json;
+use super::{check_exists, invalid_data, SourceIter};
+
+/// Lines of a key, a tab, then a JSON value. Each value is checked and stored minified, and a
+/// line with no tab or with a value which isn't JSON stops the build with its line number.
+/// Blank lines are skipped.
+pub struct KvJsonSource {
+    path: PathBuf
+}
+
+impl KvJsonSource {
+    pub fn new(path: &Path) -> io::Result<KvJsonSource> {
+        check_exists(path)?;
+        Ok(KvJsonSource { path: path.to_path_buf() })
+    }
+}
+
+fn parse_line(line: &[u8], number: usize) -> io::Result<(Vec<u8>,Vec<u8>)> {
+    let tab = line.iter().position(|b| *b == b'\t')
+        .ok_or_else(|| invalid_data(format!("line {}: no tab after the key",number)))?;
+    let value = &line[tab+1..];
+    serde_json::from_slice::<serde_json::Value>(value)
+        .map_err(|e| invalid_data(format!("line {}: value is not JSON: {}",number,e)))?;
+    Ok((line[..tab].to_vec(),minify_json(value)))
+}
+
+struct KvJsonIterator {
+    lines: Split<BufReader<File>>,
+    line: usize
+}
+
+impl Iterator for KvJsonIterator {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in self.lines.by_ref() {
+            self.line += 1;
+            let mut line = match line {
+                Ok(line) => line,
+                Err(e) => { return Some(Err(e)); }
+            };
+            if line.last() == Some(&b'\r') { line.pop(); }
+            if line.iter().all(|b| b.is_ascii_whitespace()) { continue; }
+            return Some(parse_line(&line,self.line));
+        }
+        None
+    }
+}
+
+impl NCDValueSource for KvJsonSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match File::open(&self.path) {
+            Ok(file) => Box::new(KvJsonIterator { lines: BufReader::new(file).split(b'\n'), line: 0 }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sources::fixture;
+    use super::KvJsonSource;
+
+    fn parse(data: &str) -> Result<Vec<(String,String)>,String> {
+        fixture::parse(data.as_bytes(),|path| KvJsonSource::new(path))
+    }
+
+    #[test]
+    fn test_kvjson() {
+        assert_eq!(Ok(vec![
+            ("ENSG1".to_string(),"{\"name\":\"BRCA2\",\"tags\":[1,2]}".to_string()),
+            ("ENSG2".to_string(),"\"a\\tb\"".to_string()),
+            ("key with space".to_string(),"null".to_string())
+        ]),parse("ENSG1\t{ \"name\": \"BRCA2\", \"tags\": [1, 2] }\n\nENSG2\t\"a\\tb\"\r\nkey with space\tnull\n"));
+        assert_eq!(Err("line 2: no tab after the key".to_string()),parse("a\t1\nb 2\n"));
+        assert!(parse("a\t1\n\nb\t{\"x\":\n").unwrap_err().starts_with("line 3: value is not JSON"));
+    }
+}
@@ -0,0 +1,132 @@
+use std::{collections::VecDeque, io, path::Path};
+
+use lmdb::{Cursor, Database, Environment, EnvironmentFlags, RoTransaction, Transaction};
+use ncd::NCDValueSource;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+const BATCH : usize = 1000;
+
+/// Which database of the environment to read: the unnamed one unless `db` is set.
+#[derive(Clone,Debug)]
+pub struct LmdbConfig {
+    db: Option<String>
+}
+
+impl LmdbConfig {
+    pub fn new() -> LmdbConfig {
+        LmdbConfig {
+            db: None
+        }
+    }
+}
+
+chain!(db,get_db,Option<String>,LmdbConfig);
+
+fn lmdb_error(e: lmdb::Error) -> io::Error {
+    invalid_data(format!("lmdb: {}",e))
+}
+
+/// An LMDB environment, opened read-only: either its directory or (for environments made with
+/// `MDB_NOSUBDIR`) its data file. Each pass runs in a single read transaction, so it sees one
+/// consistent version of the database, in key order. `MDB_DUPSORT` databases aren't supported.
+pub struct LmdbSource {
+    env: Environment,
+    db: Database
+}
+
+impl LmdbSource {
+    pub fn new(path: &Path, config: &LmdbConfig) -> io::Result<LmdbSource> {
+        check_exists(path)?;
+        let mut flags = EnvironmentFlags::READ_ONLY;
+        if !path.is_dir() { flags |= EnvironmentFlags::NO_SUB_DIR; }
+        let env = Environment::new().set_flags(flags).set_max_dbs(256).open(path).map_err(lmdb_error)?;
+        let db = env.open_db(config.db.as_deref())
+            .map_err(|e| invalid_data(format!("lmdb: cannot open database {}: {}",config.db.as_deref().unwrap_or("(default)"),e)))?;
+        Ok(LmdbSource { env, db })
+    }
+}
+
+/* cursors borrow the transaction, so each batch opens a fresh one and picks up after `last` */
+struct LmdbIterator<'a> {
+    txn: RoTransaction<'a>,
+    db: Database,
+    last: Option<Vec<u8>>,
+    pending: VecDeque<(Vec<u8>,Vec<u8>)>,
+    done: bool
+}
+
+impl<'a> LmdbIterator<'a> {
+    fn refill(&mut self) -> lmdb::Result<()> {
+        let mut cursor = self.txn.open_ro_cursor(self.db)?;
+        let records = match &self.last {
+            Some(last) => cursor.iter_from(last),
+            None => cursor.iter_start()
+        };
+        let last = self.last.as_deref();
+        let batch = records.skip_while(|(key,_)| Some(*key) == last).take(BATCH)
+            .map(|(key,value)| (key.to_vec(),value.to_vec())).collect::<Vec<_>>();
+        if batch.is_empty() { self.done = true; }
+        if let Some((key,_)) = batch.last() { self.last = Some(key.clone()); }
+        self.pending.extend(batch);
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for LmdbIterator<'a> {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending.is_empty() && !self.done {
+            if let Err(e) = self.refill() {
+                self.done = true;
+                return Some(Err(lmdb_error(e)));
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+impl NCDValueSource for LmdbSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match self.env.begin_ro_txn() {
+            Ok(txn) => Box::new(LmdbIterator { txn, db: self.db, last: None, pending: VecDeque::new(), done: false }),
+            Err(e) => Box::new(std::iter::once(Err(lmdb_error(e))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lmdb::{DatabaseFlags, Environment, Transaction, WriteFlags};
+    use ncd::NCDValueSource;
+
+    use super::{LmdbConfig, LmdbSource};
+
+    #[test]
+    fn test_lmdb() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let env = Environment::new().set_max_dbs(4).open(dir.path()).unwrap();
+            let main = env.create_db(None,DatabaseFlags::empty()).unwrap();
+            let genes = env.create_db(Some("genes"),DatabaseFlags::empty()).unwrap();
+            let mut txn = env.begin_rw_txn().unwrap();
+            for i in 0..2500 {
+                txn.put(main,&format!("a{:05}",i),&format!("v{}",i),WriteFlags::empty()).unwrap();
+            }
+            txn.put(genes,b"BRCA2",b"13q13.1",WriteFlags::empty()).unwrap();
+            txn.commit().unwrap();
+        }
+        let source = LmdbSource::new(dir.path(),&LmdbConfig::new()).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(2501,out.len());
+        assert_eq!((b"a00000".to_vec(),b"v0".to_vec()),out[0]);
+        assert_eq!((b"a02499".to_vec(),b"v2499".to_vec()),out[2499]);
+        /* the unnamed database also lists the named ones */
+        assert_eq!(b"genes".to_vec(),out[2500].0);
+        let source = LmdbSource::new(dir.path(),&LmdbConfig::new().db(Some("genes".to_string()))).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"BRCA2".to_vec(),b"13q13.1".to_vec())],out);
+        assert!(LmdbSource::new(dir.path(),&LmdbConfig::new().db(Some("missing".to_string()))).is_err());
+    }
+}
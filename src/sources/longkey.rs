@@ -0,0 +1,77 @@
+use std::io;
+
+use ncd::NCDValueSource;
+use sha2::{Digest, Sha256};
+
+use crate::shard::to_hex;
+use super::{invalid_data, SourceIter};
+
+/// What to do with keys longer than the limit, which would otherwise fail late in the build.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum LongKeyPolicy {
+    /// Fail on the first one, saying which record it was.
+    Reject,
+    /// Store it under `hashed_key`, which lookups have to compute too.
+    Hash
+}
+
+/// `sha256:` and the hex digest of `key`: the whole digest is kept, so it doubles as the check
+/// that a lookup found the right key.
+pub fn hashed_key(key: &[u8]) -> Vec<u8> {
+    format!("sha256:{}",to_hex(&Sha256::digest(key))).into_bytes()
+}
+
+/// Applies a `LongKeyPolicy` to keys of `inner` over `max` bytes.
+pub struct LongKeySource {
+    inner: Box<dyn NCDValueSource>,
+    max: usize,
+    policy: LongKeyPolicy
+}
+
+impl LongKeySource {
+    pub fn new(inner: Box<dyn NCDValueSource>, max: usize, policy: LongKeyPolicy) -> LongKeySource {
+        LongKeySource { inner, max, policy }
+    }
+}
+
+impl NCDValueSource for LongKeySource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let mut record = 0;
+        Box::new(self.inner.iter().map(move |entry| {
+            let (key,value) = entry?;
+            record += 1;
+            if key.len() <= self.max { return Ok((key,value)); }
+            match self.policy {
+                LongKeyPolicy::Hash => Ok((hashed_key(&key),value)),
+                LongKeyPolicy::Reject => {
+                    let start = String::from_utf8_lossy(&key[..40.min(key.len())]).to_string();
+                    Err(invalid_data(format!("record {}: key is {} bytes, over the limit of {} (use --long-keys hash to store it hashed): {}...",
+                        record,key.len(),self.max,start)))
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use crate::sources::memory::MemorySource;
+    use super::{hashed_key, LongKeyPolicy, LongKeySource};
+
+    fn source(policy: LongKeyPolicy) -> LongKeySource {
+        let entries = vec![(b"short".to_vec(),b"1".to_vec()),(vec![b'A';100],b"2".to_vec())];
+        LongKeySource::new(Box::new(MemorySource::new(entries)),10,policy)
+    }
+
+    #[test]
+    fn test_long_keys() {
+        assert_eq!(b"sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_vec(),hashed_key(b"abc"));
+        let out : Vec<_> = source(LongKeyPolicy::Hash).iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"short".to_vec(),b"1".to_vec()),(hashed_key(&[b'A';100]),b"2".to_vec())],out);
+        let out : Vec<_> = source(LongKeyPolicy::Reject).iter().collect();
+        assert!(out[0].is_ok());
+        assert!(out[1].as_ref().unwrap_err().to_string().starts_with("record 2: key is 100 bytes, over the limit of 10"));
+    }
+}
@@ -0,0 +1,20 @@
+use ncd::NCDValueSource;
+
+use super::SourceIter;
+
+/// Source over key/value pairs already held in memory.
+pub struct MemorySource {
+    entries: Vec<(Vec<u8>,Vec<u8>)>
+}
+
+impl MemorySource {
+    pub fn new(entries: Vec<(Vec<u8>,Vec<u8>)>) -> MemorySource {
+        MemorySource { entries }
+    }
+}
+
+impl NCDValueSource for MemorySource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        Box::new(self.entries.iter().map(|e| Ok(e.clone())))
+    }
+}
@@ -0,0 +1,131 @@
+use std::{fs::File, io::{self, BufRead, BufReader}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+use rmpv::Value;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+/// A top-level MessagePack map, or a stream of maps and `[key, value]` arrays. Keys must be
+/// bin or str, taken as raw bytes. bin and str values are also stored raw, while any other value
+/// is stored re-encoded as MessagePack.
+pub struct MsgpackSource {
+    path: PathBuf
+}
+
+impl MsgpackSource {
+    pub fn new(path: &Path) -> io::Result<MsgpackSource> {
+        check_exists(path)?;
+        Ok(MsgpackSource { path: path.to_path_buf() })
+    }
+}
+
+fn raw_bytes(value: Value) -> Result<Vec<u8>,Value> {
+    match value {
+        Value::Binary(b) => Ok(b),
+        Value::String(s) => Ok(s.into_bytes()),
+        other => Err(other)
+    }
+}
+
+fn pair(key: Value, value: Value, number: usize) -> io::Result<(Vec<u8>,Vec<u8>)> {
+    let key = raw_bytes(key).map_err(|k| invalid_data(format!("item {}: key must be bin or str, not {}",number,k)))?;
+    let value = match raw_bytes(value) {
+        Ok(v) => v,
+        Err(v) => {
+            let mut out = vec![];
+            rmpv::encode::write_value(&mut out,&v).map_err(invalid_data)?;
+            out
+        }
+    };
+    Ok((key,value))
+}
+
+fn pairs(item: Value, number: usize) -> Vec<io::Result<(Vec<u8>,Vec<u8>)>> {
+    match item {
+        Value::Map(entries) => entries.into_iter().map(|(k,v)| pair(k,v,number)).collect(),
+        Value::Array(mut kv) if kv.len() == 2 => {
+            let value = kv.pop().unwrap();
+            vec![pair(kv.pop().unwrap(),value,number)]
+        },
+        _ => vec![Err(invalid_data(format!("item {}: expected a map or a [key, value] array",number)))]
+    }
+}
+
+struct MsgpackIterator {
+    input: BufReader<File>,
+    number: usize
+}
+
+impl Iterator for MsgpackIterator {
+    type Item = (usize,io::Result<Value>);
+
+    fn next(&mut self) -> Option<(usize,io::Result<Value>)> {
+        self.number += 1;
+        match self.input.fill_buf() {
+            Ok(b) if b.is_empty() => { return None; },
+            Err(e) => { return Some((self.number,Err(e))); },
+            Ok(_) => {}
+        }
+        let number = self.number;
+        Some((number,rmpv::decode::read_value(&mut self.input)
+            .map_err(|e| invalid_data(format!("item {}: {}",number,e)))))
+    }
+}
+
+impl NCDValueSource for MsgpackSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        let items = MsgpackIterator { input: BufReader::new(file), number: 0 };
+        Box::new(items.flat_map(|(number,item)| {
+            match item {
+                Ok(item) => pairs(item,number),
+                Err(e) => vec![Err(e)]
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use ncd::NCDValueSource;
+    use rmpv::Value;
+
+    use super::MsgpackSource;
+
+    fn write_items(items: &[Value]) -> (tempfile::NamedTempFile,MsgpackSource) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for item in items {
+            rmpv::encode::write_value(&mut file,item).unwrap();
+        }
+        file.flush().unwrap();
+        let source = MsgpackSource::new(file.path()).unwrap();
+        (file,source)
+    }
+
+    #[test]
+    fn test_msgpack() {
+        let map = Value::Map(vec![
+            (Value::Binary(vec![0xFF,0x00]),Value::Binary(vec![1,2])),
+            (Value::from("b"),Value::from(7))
+        ]);
+        let (_file,source) = write_items(&[map]);
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(vec![0xFF,0x00],vec![1,2]),(b"b".to_vec(),vec![0x07])],out);
+        let stream = vec![
+            Value::Array(vec![Value::from("x"),Value::from("1")]),
+            Value::Array(vec![Value::from("y"),Value::from("2")])
+        ];
+        let (_file,source) = write_items(&stream);
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"x".to_vec(),b"1".to_vec()),(b"y".to_vec(),b"2".to_vec())],out);
+        let (_file,source) = write_items(&[Value::Array(vec![Value::from(1),Value::from("1")])]);
+        assert!(source.iter().next().unwrap().is_err());
+        let (_file,source) = write_items(&[Value::from(1)]);
+        assert!(source.iter().next().unwrap().is_err());
+    }
+}
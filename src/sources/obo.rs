@@ -0,0 +1,143 @@
+use std::{fs::File, io::{self, BufRead, BufReader, Lines}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+/// OBO 1.2/1.4 ontology files, as GO and its relatives are published. Each stanza of a type in
+/// `stanzas` (by default just `[Term]`) is stored whole, from its `[Term]` line on, keyed by its
+/// `id` tag. The header before the first stanza, `!` comment lines and blank lines are left out.
+#[derive(Clone,Debug)]
+pub struct OboConfig {
+    stanzas: Vec<String>
+}
+
+impl OboConfig {
+    pub fn new() -> OboConfig {
+        OboConfig {
+            stanzas: vec!["Term".to_string()]
+        }
+    }
+}
+
+chain!(stanzas,get_stanzas,Vec<String>,OboConfig);
+
+pub struct OboSource {
+    path: PathBuf,
+    config: OboConfig
+}
+
+impl OboSource {
+    pub fn new(path: &Path, config: &OboConfig) -> io::Result<OboSource> {
+        check_exists(path)?;
+        Ok(OboSource { path: path.to_path_buf(), config: config.clone() })
+    }
+}
+
+/* a `[Type]` line's type */
+fn stanza_type(line: &str) -> Option<&str> {
+    line.trim().strip_prefix('[')?.strip_suffix(']').map(|t| t.trim())
+}
+
+/* the id tag's value, less any trailing {modifiers} and ! comment */
+fn stanza_id(lines: &[String]) -> Option<String> {
+    lines.iter().find_map(|line| {
+        let value = line.strip_prefix("id:")?;
+        let value = value.split(" !").next().unwrap_or(value);
+        let value = if value.trim_end().ends_with('}') { value.rsplit_once('{').map(|(v,_)| v).unwrap_or(value) } else { value };
+        Some(value.trim().to_string()).filter(|v| !v.is_empty())
+    })
+}
+
+struct OboIterator<'a> {
+    lines: Lines<BufReader<File>>,
+    line: usize,
+    /* the `[Type]` line which ended the last stanza, and its number */
+    next: Option<(String,usize)>,
+    config: &'a OboConfig
+}
+
+impl<'a> OboIterator<'a> {
+    /* the next stanza's lines, less comments and blanks, and the line number it starts on */
+    fn stanza(&mut self) -> io::Result<Option<(Vec<String>,usize)>> {
+        let mut out = vec![];
+        let mut start = 0;
+        if let Some((line,number)) = self.next.take() {
+            out.push(line);
+            start = number;
+        }
+        for line in self.lines.by_ref() {
+            let line = line?;
+            self.line += 1;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('!') { continue; }
+            if stanza_type(line).is_some() {
+                if out.is_empty() {
+                    start = self.line;
+                } else {
+                    self.next = Some((line.to_string(),self.line));
+                    break;
+                }
+            } else if out.is_empty() {
+                /* the header */
+                continue;
+            }
+            out.push(line.to_string());
+        }
+        Ok(if out.is_empty() { None } else { Some((out,start)) })
+    }
+}
+
+impl<'a> Iterator for OboIterator<'a> {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (lines,start) = match self.stanza() {
+                Ok(Some(stanza)) => stanza,
+                Ok(None) => { return None; },
+                Err(e) => { return Some(Err(e)); }
+            };
+            let wanted = stanza_type(&lines[0]).map(|t| self.config.stanzas.iter().any(|s| s == t)).unwrap_or(false);
+            if !wanted { continue; }
+            return Some(match stanza_id(&lines) {
+                Some(id) => Ok((id.into_bytes(),lines.join("\n").into_bytes())),
+                None => Err(invalid_data(format!("line {}: {} stanza with no id",start,lines[0].trim())))
+            });
+        }
+    }
+}
+
+impl NCDValueSource for OboSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match File::open(&self.path) {
+            Ok(file) => Box::new(OboIterator { lines: BufReader::new(file).lines(), line: 0, next: None, config: &self.config }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sources::fixture;
+    use super::{OboConfig, OboSource};
+
+    fn parse(data: &str, stanzas: &[&str]) -> Result<Vec<(String,String)>,String> {
+        let config = OboConfig::new().stanzas(stanzas.iter().map(|s| s.to_string()).collect());
+        fixture::parse(data.as_bytes(),|path| OboSource::new(path,&config))
+    }
+
+    #[test]
+    fn test_obo() {
+        let data = "format-version: 1.2\nontology: go\n\n[Term]\nid: GO:0000001\nname: mitochondrion inheritance\n\
+                    ! a comment\nis_a: GO:0048308 ! organelle inheritance\n\n[Term]\r\nid: GO:0000002 {source=\"x\"}\r\n\
+                    name: mitochondrial genome maintenance\r\n\n[Typedef]\nid: part_of\nname: part of\n";
+        assert_eq!(Ok(vec![
+            ("GO:0000001".to_string(),"[Term]\nid: GO:0000001\nname: mitochondrion inheritance\nis_a: GO:0048308 ! organelle inheritance".to_string()),
+            ("GO:0000002".to_string(),"[Term]\nid: GO:0000002 {source=\"x\"}\nname: mitochondrial genome maintenance".to_string())
+        ]),parse(data,&["Term"]));
+        assert_eq!(Ok(vec!["part_of".to_string()]),parse(data,&["Typedef"]).map(|v| v.into_iter().map(|(k,_)| k).collect()));
+        assert_eq!(Err("line 3: [Term] stanza with no id".to_string()),parse("x: y\n\n[Term]\nname: z\n",&["Term"]));
+        assert_eq!(Ok(vec![]),parse("format-version: 1.2\n",&["Term"]));
+    }
+}
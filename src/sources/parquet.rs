@@ -0,0 +1,138 @@
+use std::{fs::File, io, path::{Path, PathBuf}, sync::Arc};
+
+use ncd::NCDValueSource;
+use parquet::{file::reader::{FileReader, SerializedFileReader}, record::{reader::RowIter, Field, Row}};
+
+use super::{check_exists, counting::{report_skip, Tally}, invalid_data, SourceIter};
+
+/// Rows of a parquet file, read a row group at a time. The key is taken from column `key_col`
+/// and the value from `value_col`. String and binary columns are stored as their bytes, anything
+/// else as its display form. Rows with a null key are skipped (and reported to any `tally`), and
+/// a null value is stored empty.
+#[derive(Clone,Debug)]
+pub struct ParquetConfig {
+    key_col: String,
+    value_col: String,
+    tally: Option<Arc<Tally>>
+}
+
+impl ParquetConfig {
+    pub fn new() -> ParquetConfig {
+        ParquetConfig {
+            key_col: "key".to_string(),
+            value_col: "value".to_string(),
+            tally: None
+        }
+    }
+
+    /// Report rows skipped for a null key to `tally`.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> ParquetConfig {
+        self.tally = Some(tally.clone());
+        self
+    }
+}
+
+chain!(key_col,get_key_col,String,ParquetConfig);
+chain!(value_col,get_value_col,String,ParquetConfig);
+
+pub struct ParquetSource {
+    path: PathBuf,
+    config: ParquetConfig
+}
+
+impl ParquetSource {
+    pub fn new(path: &Path, config: &ParquetConfig) -> io::Result<ParquetSource> {
+        check_exists(path)?;
+        Ok(ParquetSource { path: path.to_path_buf(), config: config.clone() })
+    }
+
+    fn rows(&self) -> io::Result<RowIter<'static>> {
+        let reader = SerializedFileReader::new(File::open(&self.path)?).map_err(invalid_data)?;
+        let columns = reader.metadata().file_metadata().schema_descr().columns().iter().map(|c| c.name().to_string()).collect::<Vec<_>>();
+        for column in &[&self.config.key_col,&self.config.value_col] {
+            if !columns.contains(*column) {
+                return Err(invalid_data(format!("no such column: {} (have {})",column,columns.join(", "))));
+            }
+        }
+        Ok(RowIter::from_file_into(Box::new(reader)))
+    }
+}
+
+fn field_bytes(field: &Field) -> Option<Vec<u8>> {
+    match field {
+        Field::Null => None,
+        Field::Str(s) => Some(s.as_bytes().to_vec()),
+        Field::Bytes(b) => Some(b.data().to_vec()),
+        other => Some(other.to_string().into_bytes())
+    }
+}
+
+fn column<'a>(row: &'a Row, name: &str) -> Option<&'a Field> {
+    row.get_column_iter().find(|(n,_)| *n == name).map(|(_,f)| f)
+}
+
+fn row_entry(row: &Row, config: &ParquetConfig) -> Option<(Vec<u8>,Vec<u8>)> {
+    let key = column(row,&config.key_col).and_then(field_bytes)?;
+    let value = column(row,&config.value_col).and_then(field_bytes).unwrap_or_default();
+    Some((key,value))
+}
+
+impl NCDValueSource for ParquetSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let rows = match self.rows() {
+            Ok(rows) => rows,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        Box::new(rows.enumerate().filter_map(move |(i,row)| {
+            match row.map(|row| row_entry(&row,&self.config)) {
+                Ok(Some(entry)) => Some(Ok(entry)),
+                Ok(None) => report_skip(&self.config.tally,format!("row {}: null {}",i+1,self.config.key_col)).err().map(Err),
+                Err(e) => Some(Err(invalid_data(e)))
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use ncd::NCDValueSource;
+    use parquet::{data_type::{ByteArray, ByteArrayType, Int64Type}, file::{properties::WriterProperties, writer::SerializedFileWriter}, schema::parser::parse_message_type};
+
+    use crate::sources::counting::Tally;
+    use super::{ParquetConfig, ParquetSource};
+
+    #[test]
+    fn test_parquet() {
+        let schema = Arc::new(parse_message_type("message t { required binary id (UTF8); optional int64 count; optional binary blob; }").unwrap());
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut writer = SerializedFileWriter::new(file.reopen().unwrap(),schema,Arc::new(WriterProperties::builder().build())).unwrap();
+        let mut group = writer.next_row_group().unwrap();
+        let mut column = group.next_column().unwrap().unwrap();
+        column.typed::<ByteArrayType>().write_batch(&[ByteArray::from("a"),ByteArray::from("b")],None,None).unwrap();
+        column.close().unwrap();
+        let mut column = group.next_column().unwrap().unwrap();
+        column.typed::<Int64Type>().write_batch(&[42],Some(&[1,0]),None).unwrap();
+        column.close().unwrap();
+        let mut column = group.next_column().unwrap().unwrap();
+        column.typed::<ByteArrayType>().write_batch(&[ByteArray::from(vec![0xFF,0x00])],Some(&[0,1]),None).unwrap();
+        column.close().unwrap();
+        group.close().unwrap();
+        writer.close().unwrap();
+        let source = ParquetSource::new(file.path(),&ParquetConfig::new().key_col("id".to_string()).value_col("count".to_string())).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"a".to_vec(),b"42".to_vec()),(b"b".to_vec(),vec![])],out);
+        let source = ParquetSource::new(file.path(),&ParquetConfig::new().key_col("id".to_string()).value_col("blob".to_string())).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"a".to_vec(),vec![]),(b"b".to_vec(),vec![0xFF,0x00])],out);
+        let tally = Tally::new();
+        let source = ParquetSource::new(file.path(),&ParquetConfig::new().key_col("count".to_string()).value_col("id".to_string()).tally(&tally)).unwrap();
+        assert_eq!(1,source.iter().count());
+        assert_eq!(1,tally.skipped());
+        tally.set_strict(true);
+        assert_eq!("strict: row 2: null count",source.iter().find_map(|e| e.err()).unwrap().to_string());
+        let source = ParquetSource::new(file.path(),&ParquetConfig::new()).unwrap();
+        assert!(source.iter().next().unwrap().is_err());
+    }
+}
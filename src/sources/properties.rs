@@ -0,0 +1,141 @@
+use std::{fs::File, io::{self, BufRead, BufReader, Lines}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+/// Java `.properties` (and plain `.env`) files. Follows the `.properties` rules: lines ending
+/// with an odd number of `\` run on into the next line, lines starting `#` or `!` are comments,
+/// the key ends at the first unescaped `=`, `:` or whitespace, and `\t`, `\n`, `\r`, `\f`,
+/// `\uXXXX` and `\`-anything escapes are decoded in both key and value.
+pub struct PropertiesSource {
+    path: PathBuf
+}
+
+impl PropertiesSource {
+    pub fn new(path: &Path) -> io::Result<PropertiesSource> {
+        check_exists(path)?;
+        Ok(PropertiesSource { path: path.to_path_buf() })
+    }
+}
+
+fn continues(line: &str) -> bool {
+    line.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1
+}
+
+fn is_separator(c: char) -> bool {
+    c == '=' || c == ':' || c.is_whitespace()
+}
+
+/* decodes escapes up to the first unescaped character `stop` accepts, returning the rest */
+fn unescape<F: Fn(char) -> bool>(text: &str, number: usize, stop: F) -> io::Result<(String,&str)> {
+    let mut out = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i,c)) = chars.next() {
+        if c != '\\' {
+            if stop(c) { return Ok((out,&text[i..])); }
+            out.push(c);
+            continue;
+        }
+        match chars.next().map(|(_,c)| c) {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('f') => out.push('\x0C'),
+            Some('u') => {
+                let hex : String = chars.by_ref().take(4).map(|(_,c)| c).collect();
+                let code = u32::from_str_radix(&hex,16).ok().filter(|_| hex.len() == 4).and_then(char::from_u32);
+                out.push(code.ok_or_else(|| invalid_data(format!("line {}: bad \\u escape: \\u{}",number,hex)))?);
+            },
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    Ok((out,""))
+}
+
+fn parse_property(line: &str, number: usize) -> io::Result<(Vec<u8>,Vec<u8>)> {
+    let (key,rest) = unescape(line,number,is_separator)?;
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix(|c: char| c == '=' || c == ':').unwrap_or(rest).trim_start();
+    let (value,_) = unescape(rest,number,|_| false)?;
+    Ok((key.into_bytes(),value.into_bytes()))
+}
+
+struct PropertiesIterator {
+    lines: Lines<BufReader<File>>,
+    line: usize
+}
+
+impl PropertiesIterator {
+    /* the joined logical line and the number of the physical line it started on */
+    fn logical_line(&mut self) -> Option<io::Result<(String,usize)>> {
+        let mut out = String::new();
+        let mut start = None;
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => { return Some(Err(e)); },
+                None => { return start.map(|s| Ok((out,s))); }
+            };
+            self.line += 1;
+            let line = line.trim_end_matches('\r');
+            let line = if start.is_some() { line.trim_start() } else { line };
+            if start.is_none() {
+                let trimmed = line.trim_start();
+                if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') { continue; }
+                start = Some(self.line);
+            }
+            if continues(line) {
+                out.push_str(&line[..line.len()-1]);
+            } else {
+                out.push_str(line);
+                return Some(Ok((out,start.unwrap())));
+            }
+        }
+    }
+}
+
+impl Iterator for PropertiesIterator {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.logical_line().map(|line| {
+            line.and_then(|(line,number)| parse_property(line.trim_start(),number))
+        })
+    }
+}
+
+impl NCDValueSource for PropertiesSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match File::open(&self.path) {
+            Ok(file) => Box::new(PropertiesIterator { lines: BufReader::new(file).lines(), line: 0 }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sources::fixture;
+    use super::PropertiesSource;
+
+    fn parse(data: &str) -> Result<Vec<(String,String)>,String> {
+        fixture::parse(data.as_bytes(),|path| PropertiesSource::new(path))
+    }
+
+    #[test]
+    fn test_properties() {
+        let data = "# comment\n  ! also\n\na=1\nb : 2\nc 3\nfruits = apple, \\\n    banana\nkey\\ with\\=odd = x\\ty\\u00e9\\\\\nempty\n";
+        assert_eq!(Ok(vec![
+            ("a".to_string(),"1".to_string()),
+            ("b".to_string(),"2".to_string()),
+            ("c".to_string(),"3".to_string()),
+            ("fruits".to_string(),"apple, banana".to_string()),
+            ("key with=odd".to_string(),"x\ty\u{e9}\\".to_string()),
+            ("empty".to_string(),"".to_string())
+        ]),parse(data));
+        assert_eq!(Ok(vec![("a".to_string(),"b".to_string())]),parse("a=\\\nb"));
+        assert_eq!(Err("line 2: bad \\u escape: \\uzz".to_string()),parse("a=1\nb=\\uzz\n"));
+    }
+}
@@ -0,0 +1,419 @@
+use std::{collections::HashMap, fs::{self, File}, io::{self, BufReader, Read}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+/* FieldDescriptorProto.Type values needed to walk and render keys */
+const TYPE_STRING : u32 = 9;
+const TYPE_MESSAGE : u32 = 11;
+const TYPE_BYTES : u32 = 12;
+
+/// A stream of protobuf messages, each preceded by its length as a varint (as written by
+/// `writeDelimitedTo` and friends). Messages are stored raw, keyed by the field at `key`, which
+/// is a field name or a dotted path through nested messages (eg `locus.start`), and must be a
+/// scalar. As protobuf merges repeated fields, the last occurrence of a field is the one used.
+/// The message type comes from the `FileDescriptorSet` in `descriptors` (from `protoc
+/// --descriptor_set_out`), and can be left out if the set only contains one.
+#[derive(Clone,Debug)]
+pub struct ProtobufConfig {
+    descriptors: PathBuf,
+    message: Option<String>,
+    key: String
+}
+
+impl ProtobufConfig {
+    pub fn new() -> ProtobufConfig {
+        ProtobufConfig {
+            descriptors: PathBuf::new(),
+            message: None,
+            key: "id".to_string()
+        }
+    }
+}
+
+chain!(descriptors,get_descriptors,PathBuf,ProtobufConfig);
+chain!(message,get_message,Option<String>,ProtobufConfig);
+chain!(key,get_key,String,ProtobufConfig);
+
+enum Wire<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+    Group,
+    GroupEnd
+}
+
+fn varint(data: &[u8], pos: &mut usize) -> io::Result<u64> {
+    let mut out = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos).ok_or_else(|| invalid_data("truncated protobuf varint"))?;
+        *pos += 1;
+        out |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 { return Ok(out); }
+    }
+    Err(invalid_data("protobuf varint too long"))
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> io::Result<&'a [u8]> {
+    let end = pos.checked_add(len).filter(|end| *end <= data.len()).ok_or_else(|| invalid_data("truncated protobuf field"))?;
+    let out = &data[*pos..end];
+    *pos = end;
+    Ok(out)
+}
+
+fn next_field<'a>(data: &'a [u8], pos: &mut usize) -> io::Result<(u32,Wire<'a>)> {
+    let tag = varint(data,pos)?;
+    let number = (tag >> 3) as u32;
+    let wire = match tag & 7 {
+        0 => Wire::Varint(varint(data,pos)?),
+        1 => {
+            let mut bytes = [0;8];
+            bytes.copy_from_slice(take(data,pos,8)?);
+            Wire::Fixed64(u64::from_le_bytes(bytes))
+        },
+        2 => {
+            let len = varint(data,pos)? as usize;
+            Wire::Bytes(take(data,pos,len)?)
+        },
+        3 => {
+            /* deprecated groups: skip to the matching end */
+            loop {
+                if let (end,Wire::GroupEnd) = next_field(data,pos)? {
+                    if end != number { return Err(invalid_data("mismatched protobuf group")); }
+                    break;
+                }
+            }
+            Wire::Group
+        },
+        4 => Wire::GroupEnd,
+        5 => {
+            let mut bytes = [0;4];
+            bytes.copy_from_slice(take(data,pos,4)?);
+            Wire::Fixed32(u32::from_le_bytes(bytes))
+        },
+        other => { return Err(invalid_data(format!("unknown protobuf wire type {}",other))); }
+    };
+    Ok((number,wire))
+}
+
+fn each_field<'a, F>(data: &'a [u8], mut cb: F) -> io::Result<()> where F: FnMut(u32,Wire<'a>) -> io::Result<()> {
+    let mut pos = 0;
+    while pos < data.len() {
+        match next_field(data,&mut pos)? {
+            (_,Wire::GroupEnd) => { return Err(invalid_data("unexpected protobuf group end")); },
+            (number,wire) => { cb(number,wire)?; }
+        }
+    }
+    Ok(())
+}
+
+struct FieldType {
+    name: String,
+    number: u32,
+    kind: u32,
+    type_name: String
+}
+
+fn parse_field(data: &[u8]) -> io::Result<FieldType> {
+    let mut out = FieldType { name: String::new(), number: 0, kind: 0, type_name: String::new() };
+    each_field(data,|number,wire| {
+        match (number,wire) {
+            (1,Wire::Bytes(b)) => { out.name = String::from_utf8_lossy(b).to_string(); },
+            (3,Wire::Varint(v)) => { out.number = v as u32; },
+            (5,Wire::Varint(v)) => { out.kind = v as u32; },
+            (6,Wire::Bytes(b)) => { out.type_name = String::from_utf8_lossy(b).trim_start_matches('.').to_string(); },
+            _ => {}
+        }
+        Ok(())
+    })?;
+    Ok(out)
+}
+
+/* DescriptorProto: name 1, field 2, nested_type 3 */
+fn parse_message(data: &[u8], prefix: &str, out: &mut HashMap<String,Vec<FieldType>>) -> io::Result<()> {
+    let (mut name,mut fields,mut nested) = (String::new(),vec![],vec![]);
+    each_field(data,|number,wire| {
+        match (number,wire) {
+            (1,Wire::Bytes(b)) => { name = String::from_utf8_lossy(b).to_string(); },
+            (2,Wire::Bytes(b)) => { fields.push(parse_field(b)?); },
+            (3,Wire::Bytes(b)) => { nested.push(b); },
+            _ => {}
+        }
+        Ok(())
+    })?;
+    let full_name = if prefix.is_empty() { name } else { format!("{}.{}",prefix,name) };
+    for data in nested {
+        parse_message(data,&full_name,out)?;
+    }
+    out.insert(full_name,fields);
+    Ok(())
+}
+
+/* FileDescriptorSet: file 1; FileDescriptorProto: package 2, message_type 4 */
+fn parse_descriptors(data: &[u8]) -> io::Result<HashMap<String,Vec<FieldType>>> {
+    let mut out = HashMap::new();
+    each_field(data,|number,wire| {
+        if let (1,Wire::Bytes(file)) = (number,wire) {
+            let (mut package,mut messages) = (String::new(),vec![]);
+            each_field(file,|number,wire| {
+                match (number,wire) {
+                    (2,Wire::Bytes(b)) => { package = String::from_utf8_lossy(b).to_string(); },
+                    (4,Wire::Bytes(b)) => { messages.push(b); },
+                    _ => {}
+                }
+                Ok(())
+            })?;
+            for message in messages {
+                parse_message(message,&package,&mut out)?;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(out)
+}
+
+/* full name, or an unambiguous short name */
+fn find_message<'a>(types: &'a HashMap<String,Vec<FieldType>>, name: Option<&str>) -> Result<&'a str,String> {
+    let mut names : Vec<_> = types.keys().map(|k| k.as_str()).collect();
+    names.sort();
+    let candidates : Vec<_> = match name {
+        Some(name) => names.iter().cloned().filter(|n| *n == name || n.ends_with(&format!(".{}",name))).collect(),
+        None => names.clone()
+    };
+    match (candidates.as_slice(),name) {
+        ([one],_) => Ok(*one),
+        (many,Some(name)) if many.contains(&name) => Ok(types.get_key_value(name).unwrap().0.as_str()),
+        ([],None) => Err("no message types in the descriptors".to_string()),
+        ([],Some(name)) => Err(format!("no message type {} in the descriptors (there are: {})",name,names.join(", "))),
+        (_,name) => Err(format!("{} message types in the descriptors: choose one of {}",
+            if name.is_some() { "several matching" } else { "several" },candidates.join(", ")))
+    }
+}
+
+#[derive(Clone,Debug)]
+struct KeyField {
+    name: String,
+    number: u32,
+    kind: u32
+}
+
+fn resolve_key(types: &HashMap<String,Vec<FieldType>>, message: &str, key: &str) -> Result<Vec<KeyField>,String> {
+    let mut out = vec![];
+    let mut message = message.to_string();
+    let segments : Vec<_> = key.split('.').collect();
+    for (i,segment) in segments.iter().enumerate() {
+        let fields = types.get(&message).ok_or_else(|| format!("no message type {} in the descriptors",message))?;
+        let field = fields.iter().find(|f| f.name == *segment)
+            .ok_or_else(|| format!("no field {} in message {}",segment,message))?;
+        let last = i == segments.len()-1;
+        if last && field.kind == TYPE_MESSAGE {
+            return Err(format!("key field {} is a message, not a scalar",key));
+        } else if !last && field.kind != TYPE_MESSAGE {
+            return Err(format!("field {} in {} is not a message",segment,key));
+        }
+        out.push(KeyField { name: key.to_string(), number: field.number, kind: field.kind });
+        message = field.type_name.clone();
+    }
+    Ok(out)
+}
+
+fn zigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+fn render_key(field: &KeyField, wire: Wire) -> io::Result<Vec<u8>> {
+    let text = match (field.kind,wire) {
+        (TYPE_STRING,Wire::Bytes(b)) | (TYPE_BYTES,Wire::Bytes(b)) => { return Ok(b.to_vec()); },
+        (1,Wire::Fixed64(v)) => f64::from_bits(v).to_string(),
+        (2,Wire::Fixed32(v)) => f32::from_bits(v).to_string(),
+        (3,Wire::Varint(v)) => (v as i64).to_string(),
+        (4,Wire::Varint(v)) | (13,Wire::Varint(v)) => v.to_string(),
+        (5,Wire::Varint(v)) | (14,Wire::Varint(v)) => (v as i32).to_string(),
+        (6,Wire::Fixed64(v)) => v.to_string(),
+        (7,Wire::Fixed32(v)) => v.to_string(),
+        (8,Wire::Varint(v)) => (v != 0).to_string(),
+        (15,Wire::Fixed32(v)) => (v as i32).to_string(),
+        (16,Wire::Fixed64(v)) => (v as i64).to_string(),
+        (17,Wire::Varint(v)) | (18,Wire::Varint(v)) => zigzag(v).to_string(),
+        _ => { return Err(invalid_data(format!("key field {} has an unsupported type or doesn't match the descriptors",field.name))); }
+    };
+    Ok(text.into_bytes())
+}
+
+fn find_key(data: &[u8], path: &[KeyField]) -> io::Result<Option<Vec<u8>>> {
+    let (field,rest) = match path.split_first() { Some(x) => x, None => { return Ok(None); } };
+    let mut found = None;
+    each_field(data,|number,wire| {
+        if number == field.number { found = Some(wire); }
+        Ok(())
+    })?;
+    match (found,rest.is_empty()) {
+        (None,_) => Ok(None),
+        (Some(wire),true) => render_key(field,wire).map(Some),
+        (Some(Wire::Bytes(inner)),false) => find_key(inner,rest),
+        (Some(_),false) => Err(invalid_data(format!("key field {} doesn't match the descriptors",field.name)))
+    }
+}
+
+pub struct ProtobufSource {
+    path: PathBuf,
+    key: Vec<KeyField>,
+    config: ProtobufConfig
+}
+
+impl ProtobufSource {
+    pub fn new(path: &Path, config: &ProtobufConfig) -> io::Result<ProtobufSource> {
+        for path in &[path,config.descriptors.as_path()] {
+            check_exists(path)?;
+        }
+        let types = parse_descriptors(&fs::read(&config.descriptors)?)
+            .map_err(|e| invalid_data(format!("{}: {}",config.descriptors.display(),e)))?;
+        let message = find_message(&types,config.message.as_deref()).map_err(invalid_data)?;
+        let key = resolve_key(&types,message,&config.key).map_err(invalid_data)?;
+        Ok(ProtobufSource { path: path.to_path_buf(), key, config: config.clone() })
+    }
+}
+
+/* None at a clean end of file */
+fn read_message(reader: &mut BufReader<File>) -> io::Result<Option<Vec<u8>>> {
+    let mut len = 0u64;
+    for i in 0.. {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            if i == 0 { return Ok(None); }
+            return Err(invalid_data("truncated protobuf length"));
+        }
+        if i >= 10 { return Err(invalid_data("protobuf length too long")); }
+        len |= ((byte[0] & 0x7F) as u64) << (7*i);
+        if byte[0] & 0x80 == 0 { break; }
+    }
+    let mut out = vec![];
+    reader.by_ref().take(len).read_to_end(&mut out)?;
+    if (out.len() as u64) < len {
+        return Err(invalid_data("truncated protobuf message"));
+    }
+    Ok(Some(out))
+}
+
+struct ProtobufIterator<'a> {
+    reader: BufReader<File>,
+    messages: usize,
+    source: &'a ProtobufSource
+}
+
+impl<'a> ProtobufIterator<'a> {
+    fn message(&mut self) -> io::Result<Option<(Vec<u8>,Vec<u8>)>> {
+        let message = match read_message(&mut self.reader)? {
+            Some(message) => message,
+            None => { return Ok(None); }
+        };
+        self.messages += 1;
+        let number = self.messages;
+        let key = find_key(&message,&self.source.key)
+            .map_err(|e| invalid_data(format!("message {}: {}",number,e)))?
+            .ok_or_else(|| invalid_data(format!("message {}: no {} field",number,self.source.config.key)))?;
+        Ok(Some((key,message)))
+    }
+}
+
+impl<'a> Iterator for ProtobufIterator<'a> {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.message().transpose()
+    }
+}
+
+impl NCDValueSource for ProtobufSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match File::open(&self.path) {
+            Ok(file) => Box::new(ProtobufIterator { reader: BufReader::new(file), messages: 0, source: self }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use ncd::NCDValueSource;
+
+    use super::{ProtobufConfig, ProtobufSource};
+
+    fn varint(mut v: u64) -> Vec<u8> {
+        let mut out = vec![];
+        loop {
+            if v < 0x80 { out.push(v as u8); return out; }
+            out.push((v as u8 & 0x7F) | 0x80);
+            v >>= 7;
+        }
+    }
+
+    fn bytes_field(number: u64, data: &[u8]) -> Vec<u8> {
+        let mut out = varint(number << 3 | 2);
+        out.extend(varint(data.len() as u64));
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn varint_field(number: u64, v: u64) -> Vec<u8> {
+        let mut out = varint(number << 3);
+        out.extend(varint(v));
+        out
+    }
+
+    fn field(name: &str, number: u64, kind: u64, type_name: &str) -> Vec<u8> {
+        [bytes_field(1,name.as_bytes()),varint_field(3,number),varint_field(5,kind),bytes_field(6,type_name.as_bytes())].concat()
+    }
+
+    fn descriptors() -> Vec<u8> {
+        let gene = [bytes_field(1,b"Gene"),bytes_field(2,&field("id",1,9,"")),bytes_field(2,&field("locus",2,11,".test.Locus"))].concat();
+        let locus = [bytes_field(1,b"Locus"),bytes_field(2,&field("start",1,3,"")),bytes_field(2,&field("strand",3,17,""))].concat();
+        let file = [bytes_field(1,b"test.proto"),bytes_field(2,b"test"),bytes_field(4,&gene),bytes_field(4,&locus)].concat();
+        bytes_field(1,&file)
+    }
+
+    fn config(key: &str) -> (tempfile::NamedTempFile,tempfile::NamedTempFile,ProtobufConfig) {
+        let mut desc = tempfile::NamedTempFile::new().unwrap();
+        desc.write_all(&descriptors()).unwrap();
+        let locus = [varint_field(1,(-5i64) as u64),varint_field(3,1),varint_field(1,100)].concat();
+        let messages = vec![
+            [bytes_field(1,b"BRCA2"),bytes_field(2,&locus),vec![3<<3|5,0,0,0,0]].concat(),
+            [bytes_field(2,&varint_field(3,3)),bytes_field(1,b"TP53")].concat(),
+            varint_field(99,1)
+        ];
+        let mut stream = tempfile::NamedTempFile::new().unwrap();
+        for message in &messages {
+            stream.write_all(&varint(message.len() as u64)).unwrap();
+            stream.write_all(message).unwrap();
+        }
+        let config = ProtobufConfig::new().descriptors(desc.path().to_path_buf()).message(Some("Gene".to_string())).key(key.to_string());
+        (desc,stream,config)
+    }
+
+    fn keys(key: &str) -> Vec<Result<String,String>> {
+        let (_desc,stream,config) = config(key);
+        let source = ProtobufSource::new(stream.path(),&config).unwrap();
+        source.iter().map(|e| e.map(|(k,_)| String::from_utf8(k).unwrap()).map_err(|e| e.to_string())).collect()
+    }
+
+    #[test]
+    fn test_protobuf() {
+        assert_eq!(vec![Ok("BRCA2".to_string()),Ok("TP53".to_string()),Err("message 3: no id field".to_string())],keys("id"));
+        assert_eq!(vec![Ok("100".to_string())],keys("locus.start")[..1].to_vec());
+        assert_eq!(vec![Ok("-1".to_string()),Ok("-2".to_string())],keys("locus.strand")[..2].to_vec());
+        let (_desc,stream,config) = config("id");
+        let source = ProtobufSource::new(stream.path(),&config).unwrap();
+        let first = source.iter().next().unwrap().unwrap();
+        assert_eq!(b"BRCA2"[..],first.1[2..7]);
+        for (message,key) in &[(None,"id"),(Some("Gene"),"missing"),(Some("Gene"),"locus"),(Some("Gene"),"id.x"),(Some("Nope"),"id")] {
+            let config = config.clone().message(message.map(|s| s.to_string())).key(key.to_string());
+            assert!(ProtobufSource::new(stream.path(),&config).is_err(),"{:?} {}",message,key);
+        }
+        let config = config.clone().message(Some("test.Locus".to_string())).key("start".to_string());
+        assert!(ProtobufSource::new(stream.path(),&config).is_ok());
+    }
+}
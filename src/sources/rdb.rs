@@ -0,0 +1,730 @@
+use std::{fs::File, io::{self, BufReader, Read}, path::{Path, PathBuf}, sync::Arc, time::{SystemTime, UNIX_EPOCH}};
+
+use ncd::NCDValueSource;
+use serde_json::{Map, Number, Value};
+
+use super::{check_exists, counting::{report_skip, Tally}, invalid_data, SourceIter};
+
+const MAX_VERSION : u32 = 12;
+
+const OP_SLOT_INFO : u8 = 0xF4;
+const OP_FUNCTION2 : u8 = 0xF5;
+const OP_MODULE_AUX : u8 = 0xF7;
+const OP_IDLE : u8 = 0xF8;
+const OP_FREQ : u8 = 0xF9;
+const OP_AUX : u8 = 0xFA;
+const OP_RESIZEDB : u8 = 0xFB;
+const OP_EXPIRETIME_MS : u8 = 0xFC;
+const OP_EXPIRETIME : u8 = 0xFD;
+const OP_SELECTDB : u8 = 0xFE;
+const OP_EOF : u8 = 0xFF;
+
+const TYPE_STRING : u8 = 0;
+const TYPE_LIST : u8 = 1;
+const TYPE_SET : u8 = 2;
+const TYPE_ZSET : u8 = 3;
+const TYPE_HASH : u8 = 4;
+const TYPE_ZSET_2 : u8 = 5;
+const TYPE_MODULE_2 : u8 = 7;
+const TYPE_HASH_ZIPMAP : u8 = 9;
+const TYPE_LIST_ZIPLIST : u8 = 10;
+const TYPE_SET_INTSET : u8 = 11;
+const TYPE_ZSET_ZIPLIST : u8 = 12;
+const TYPE_HASH_ZIPLIST : u8 = 13;
+const TYPE_LIST_QUICKLIST : u8 = 14;
+const TYPE_STREAM_LISTPACKS : u8 = 15;
+const TYPE_HASH_LISTPACK : u8 = 16;
+const TYPE_ZSET_LISTPACK : u8 = 17;
+const TYPE_LIST_QUICKLIST_2 : u8 = 18;
+const TYPE_STREAM_LISTPACKS_2 : u8 = 19;
+const TYPE_SET_LISTPACK : u8 = 20;
+const TYPE_STREAM_LISTPACKS_3 : u8 = 21;
+const TYPE_HASH_METADATA_PRE_GA : u8 = 22;
+const TYPE_HASH_LISTPACK_EX_PRE_GA : u8 = 23;
+const TYPE_HASH_METADATA : u8 = 24;
+const TYPE_HASH_LISTPACK_EX : u8 = 25;
+
+/* a hash field's ttl in a listpack, when it has none */
+const HASH_NO_TTL : u64 = 0;
+
+/// What to do with keys which don't hold strings: lists, sets, sorted sets and hashes.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum RdbOther {
+    Skip,
+    /// Lists and sets become JSON arrays, hashes objects and sorted sets objects of scores.
+    Json
+}
+
+#[derive(Clone,Debug)]
+pub struct RdbConfig {
+    other: RdbOther,
+    tally: Option<Arc<Tally>>
+}
+
+impl RdbConfig {
+    pub fn new() -> RdbConfig {
+        RdbConfig {
+            other: RdbOther::Skip,
+            tally: None
+        }
+    }
+
+    /// Report keys skipped for what they hold to `tally`.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> RdbConfig {
+        self.tally = Some(tally.clone());
+        self
+    }
+}
+
+chain!(other,get_other,RdbOther,RdbConfig);
+
+/// A Redis RDB dump (up to RDB version 12), read without a server. Every database in the file
+/// is read, and keys and hash fields (Redis 7.4 on) which had already expired when the build
+/// started are left out, as Redis itself would. Streams and module values can't be represented,
+/// so are always skipped. Skipped keys, though not expired ones, are reported to any tally.
+pub struct RdbSource {
+    path: PathBuf,
+    config: RdbConfig
+}
+
+impl RdbSource {
+    pub fn new(path: &Path, config: &RdbConfig) -> io::Result<RdbSource> {
+        check_exists(path)?;
+        Ok(RdbSource { path: path.to_path_buf(), config: config.clone() })
+    }
+}
+
+enum Length {
+    Plain(u64),
+    Encoded(u8)
+}
+
+fn slice(blob: &[u8], start: usize, len: usize) -> io::Result<&[u8]> {
+    blob.get(start..start+len).ok_or_else(|| invalid_data("rdb: encoded value runs off its end"))
+}
+
+fn byte(blob: &[u8], at: usize) -> io::Result<u8> {
+    slice(blob,at,1).map(|b| b[0])
+}
+
+fn lzf_decompress(input: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let bad = || invalid_data("rdb: bad lzf data");
+    let mut out = vec![];
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            if out.len()+ctrl+1 > len { return Err(bad()); }
+            out.extend_from_slice(input.get(i..i+ctrl+1).ok_or_else(bad)?);
+            i += ctrl+1;
+        } else {
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i).ok_or_else(bad)? as usize;
+                i += 1;
+            }
+            let back = ((ctrl & 0x1F) << 8) + *input.get(i).ok_or_else(bad)? as usize + 1;
+            i += 1;
+            let start = out.len().checked_sub(back).ok_or_else(bad)?;
+            if out.len()+run+2 > len { return Err(bad()); }
+            for j in 0..run+2 { out.push(out[start+j]); }
+        }
+    }
+    if out.len() != len { return Err(bad()); }
+    Ok(out)
+}
+
+fn int_entry(value: i64) -> Vec<u8> { value.to_string().into_bytes() }
+
+fn ziplist(blob: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut out = vec![];
+    let mut at = 10;
+    loop {
+        let prev = byte(blob,at)?;
+        if prev == 0xFF { break; }
+        at += if prev == 0xFE { 5 } else { 1 };
+        let enc = byte(blob,at)?;
+        let (entry,len) = match enc >> 6 {
+            0 => { let n = (enc & 0x3F) as usize; (slice(blob,at+1,n)?.to_vec(),1+n) },
+            1 => { let n = (((enc & 0x3F) as usize) << 8) | byte(blob,at+1)? as usize; (slice(blob,at+2,n)?.to_vec(),2+n) },
+            2 => {
+                let b = slice(blob,at+1,4)?;
+                let n = u32::from_be_bytes([b[0],b[1],b[2],b[3]]) as usize;
+                (slice(blob,at+5,n)?.to_vec(),5+n)
+            },
+            _ => match enc {
+                0xC0 => { let b = slice(blob,at+1,2)?; (int_entry(i16::from_le_bytes([b[0],b[1]]) as i64),3) },
+                0xD0 => { let b = slice(blob,at+1,4)?; (int_entry(i32::from_le_bytes([b[0],b[1],b[2],b[3]]) as i64),5) },
+                0xE0 => {
+                    let b = slice(blob,at+1,8)?;
+                    (int_entry(i64::from_le_bytes([b[0],b[1],b[2],b[3],b[4],b[5],b[6],b[7]])),9)
+                },
+                0xF0 => { let b = slice(blob,at+1,3)?; (int_entry((i32::from_le_bytes([0,b[0],b[1],b[2]]) >> 8) as i64),4) },
+                0xFE => (int_entry(byte(blob,at+1)? as i8 as i64),2),
+                0xF1..=0xFD => (int_entry((enc & 0x0F) as i64 - 1),1),
+                _ => { return Err(invalid_data(format!("rdb: bad ziplist encoding {:#x}",enc))); }
+            }
+        };
+        out.push(entry);
+        at += len;
+    }
+    Ok(out)
+}
+
+fn listpack_backlen(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5
+    }
+}
+
+fn listpack(blob: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut out = vec![];
+    let mut at = 6;
+    loop {
+        let b = byte(blob,at)?;
+        if b == 0xFF { break; }
+        let (entry,len) = if b & 0x80 == 0 {
+            (int_entry(b as i64),1)
+        } else if b & 0xC0 == 0x80 {
+            let n = (b & 0x3F) as usize;
+            (slice(blob,at+1,n)?.to_vec(),1+n)
+        } else if b & 0xE0 == 0xC0 {
+            let v = (((b & 0x1F) as i64) << 8) | byte(blob,at+1)? as i64;
+            (int_entry(if v >= 1 << 12 { v - (1 << 13) } else { v }),2)
+        } else if b & 0xF0 == 0xE0 {
+            let n = (((b & 0x0F) as usize) << 8) | byte(blob,at+1)? as usize;
+            (slice(blob,at+2,n)?.to_vec(),2+n)
+        } else {
+            match b {
+                0xF0 => {
+                    let l = slice(blob,at+1,4)?;
+                    let n = u32::from_le_bytes([l[0],l[1],l[2],l[3]]) as usize;
+                    (slice(blob,at+5,n)?.to_vec(),5+n)
+                },
+                0xF1 => { let v = slice(blob,at+1,2)?; (int_entry(i16::from_le_bytes([v[0],v[1]]) as i64),3) },
+                0xF2 => { let v = slice(blob,at+1,3)?; (int_entry((i32::from_le_bytes([0,v[0],v[1],v[2]]) >> 8) as i64),4) },
+                0xF3 => { let v = slice(blob,at+1,4)?; (int_entry(i32::from_le_bytes([v[0],v[1],v[2],v[3]]) as i64),5) },
+                0xF4 => {
+                    let v = slice(blob,at+1,8)?;
+                    (int_entry(i64::from_le_bytes([v[0],v[1],v[2],v[3],v[4],v[5],v[6],v[7]])),9)
+                },
+                _ => { return Err(invalid_data(format!("rdb: bad listpack encoding {:#x}",b))); }
+            }
+        };
+        out.push(entry);
+        at += len + listpack_backlen(len);
+    }
+    Ok(out)
+}
+
+fn intset(blob: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let header = slice(blob,0,8)?;
+    let width = u32::from_le_bytes([header[0],header[1],header[2],header[3]]) as usize;
+    let count = u32::from_le_bytes([header[4],header[5],header[6],header[7]]) as usize;
+    (0..count).map(|i| {
+        let b = slice(blob,8+i*width,width)?;
+        Ok(int_entry(match width {
+            2 => i16::from_le_bytes([b[0],b[1]]) as i64,
+            4 => i32::from_le_bytes([b[0],b[1],b[2],b[3]]) as i64,
+            8 => i64::from_le_bytes([b[0],b[1],b[2],b[3],b[4],b[5],b[6],b[7]]),
+            _ => { return Err(invalid_data(format!("rdb: bad intset width {}",width))); }
+        }))
+    }).collect()
+}
+
+fn zipmap(blob: &[u8]) -> io::Result<Vec<(Vec<u8>,Vec<u8>)>> {
+    let length = |at: usize| -> io::Result<(usize,usize)> {
+        match byte(blob,at)? {
+            254 => { let b = slice(blob,at+1,4)?; Ok((u32::from_le_bytes([b[0],b[1],b[2],b[3]]) as usize,5)) },
+            255 => Err(invalid_data("rdb: zipmap ends mid-entry")),
+            n => Ok((n as usize,1))
+        }
+    };
+    let mut out = vec![];
+    let mut at = 1;
+    while byte(blob,at)? != 0xFF {
+        let (n,size) = length(at)?;
+        let key = slice(blob,at+size,n)?.to_vec();
+        at += size+n;
+        let (n,size) = length(at)?;
+        let free = byte(blob,at+size)? as usize;
+        let value = slice(blob,at+size+1,n)?.to_vec();
+        at += size+1+n+free;
+        out.push((key,value));
+    }
+    Ok(out)
+}
+
+fn string_json(bytes: Vec<u8>) -> Value {
+    Value::String(String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).to_string()))
+}
+
+fn score_json(score: f64) -> Value {
+    Number::from_f64(score).map(Value::Number).unwrap_or_else(|| Value::String(score.to_string()))
+}
+
+fn array_json(items: Vec<Vec<u8>>) -> Value {
+    Value::Array(items.into_iter().map(string_json).collect())
+}
+
+fn pairs(items: Vec<Vec<u8>>) -> io::Result<Vec<(Vec<u8>,Vec<u8>)>> {
+    if items.len() % 2 != 0 { return Err(invalid_data("rdb: odd number of entries in a hash or sorted set")); }
+    let mut out = vec![];
+    let mut items = items.into_iter();
+    while let (Some(a),Some(b)) = (items.next(),items.next()) { out.push((a,b)); }
+    Ok(out)
+}
+
+fn hash_json(pairs: Vec<(Vec<u8>,Vec<u8>)>) -> Value {
+    let mut map = Map::new();
+    for (field,value) in pairs {
+        map.insert(String::from_utf8_lossy(&field).to_string(),string_json(value));
+    }
+    Value::Object(map)
+}
+
+fn zset_json(pairs: Vec<(Vec<u8>,f64)>) -> Value {
+    let mut map = Map::new();
+    for (member,score) in pairs {
+        map.insert(String::from_utf8_lossy(&member).to_string(),score_json(score));
+    }
+    Value::Object(map)
+}
+
+fn parse_score(bytes: &[u8]) -> io::Result<f64> {
+    String::from_utf8_lossy(bytes).parse::<f64>().map_err(|_| invalid_data("rdb: bad sorted set score"))
+}
+
+enum RdbValue {
+    String(Vec<u8>),
+    Other(Value),
+    Unrepresentable,
+    /* a hash all of whose fields had expired */
+    Expired
+}
+
+/* the fields of a hash with field expiry which are yet to expire, or Expired if none are */
+fn live_hash(fields: Vec<(Vec<u8>,Vec<u8>,u64)>, now_ms: u64) -> RdbValue {
+    let live = fields.into_iter().filter(|(_,_,at)| *at == HASH_NO_TTL || *at > now_ms).map(|(f,v,_)| (f,v)).collect::<Vec<_>>();
+    if live.is_empty() { RdbValue::Expired } else { RdbValue::Other(hash_json(live)) }
+}
+
+struct Rdb<R: Read> {
+    input: R
+}
+
+impl<R: Read> Rdb<R> {
+    fn bytes(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        /* lengths come from the file, so only allocate for what is actually there */
+        let mut out = vec![];
+        (&mut self.input).take(len as u64).read_to_end(&mut out)?;
+        if out.len() < len { return Err(invalid_data("rdb: truncated file")); }
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> { Ok(self.bytes(1)?[0]) }
+
+    fn u32_le(&mut self) -> io::Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0],b[1],b[2],b[3]]))
+    }
+
+    fn u64_le(&mut self) -> io::Result<u64> {
+        let b = self.bytes(8)?;
+        Ok(u64::from_le_bytes([b[0],b[1],b[2],b[3],b[4],b[5],b[6],b[7]]))
+    }
+
+    fn length_or_encoding(&mut self) -> io::Result<Length> {
+        let first = self.u8()?;
+        Ok(match first >> 6 {
+            0 => Length::Plain((first & 0x3F) as u64),
+            1 => Length::Plain((((first & 0x3F) as u64) << 8) | self.u8()? as u64),
+            3 => Length::Encoded(first & 0x3F),
+            _ => match first {
+                0x80 => { let b = self.bytes(4)?; Length::Plain(u32::from_be_bytes([b[0],b[1],b[2],b[3]]) as u64) },
+                0x81 => { let b = self.bytes(8)?; Length::Plain(u64::from_be_bytes([b[0],b[1],b[2],b[3],b[4],b[5],b[6],b[7]])) },
+                _ => { return Err(invalid_data(format!("rdb: bad length encoding {:#x}",first))); }
+            }
+        })
+    }
+
+    fn length(&mut self) -> io::Result<u64> {
+        match self.length_or_encoding()? {
+            Length::Plain(n) => Ok(n),
+            Length::Encoded(_) => Err(invalid_data("rdb: expected a length, found an encoded string"))
+        }
+    }
+
+    fn string(&mut self) -> io::Result<Vec<u8>> {
+        match self.length_or_encoding()? {
+            Length::Plain(n) => self.bytes(n as usize),
+            Length::Encoded(0) => Ok(int_entry(self.u8()? as i8 as i64)),
+            Length::Encoded(1) => { let b = self.bytes(2)?; Ok(int_entry(i16::from_le_bytes([b[0],b[1]]) as i64)) },
+            Length::Encoded(2) => Ok(int_entry(self.u32_le()? as i32 as i64)),
+            Length::Encoded(3) => {
+                let compressed = self.length()? as usize;
+                let len = self.length()? as usize;
+                let data = self.bytes(compressed)?;
+                lzf_decompress(&data,len)
+            },
+            Length::Encoded(other) => Err(invalid_data(format!("rdb: unknown string encoding {}",other)))
+        }
+    }
+
+    fn strings(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let count = self.length()?;
+        (0..count).map(|_| self.string()).collect()
+    }
+
+    fn string_pairs(&mut self) -> io::Result<Vec<(Vec<u8>,Vec<u8>)>> {
+        let count = self.length()?;
+        (0..count).map(|_| Ok((self.string()?,self.string()?))).collect()
+    }
+
+    /* module data is a sequence of typed items ending with a zero opcode */
+    fn skip_module_data(&mut self) -> io::Result<()> {
+        loop {
+            match self.length()? {
+                0 => { return Ok(()); },
+                1 | 2 => { self.length()?; },
+                3 => { self.bytes(4)?; },
+                4 => { self.bytes(8)?; },
+                5 => { self.string()?; },
+                other => { return Err(invalid_data(format!("rdb: bad module opcode {}",other))); }
+            }
+        }
+    }
+
+    fn skip_stream(&mut self, kind: u8) -> io::Result<()> {
+        for _ in 0..self.length()? {
+            self.string()?;
+            self.string()?;
+        }
+        self.length()?;
+        self.length()?;
+        self.length()?;
+        if kind != TYPE_STREAM_LISTPACKS {
+            for _ in 0..5 { self.length()?; }
+        }
+        for _ in 0..self.length()? {
+            self.string()?;
+            self.length()?;
+            self.length()?;
+            if kind != TYPE_STREAM_LISTPACKS { self.length()?; }
+            for _ in 0..self.length()? {
+                self.bytes(16+8)?;
+                self.length()?;
+            }
+            for _ in 0..self.length()? {
+                self.string()?;
+                self.bytes(8)?;
+                if kind == TYPE_STREAM_LISTPACKS_3 { self.bytes(8)?; }
+                for _ in 0..self.length()? { self.bytes(16)?; }
+            }
+        }
+        Ok(())
+    }
+
+    /* each field with its expiry time, in ms */
+    fn hash_metadata(&mut self, kind: u8) -> io::Result<Vec<(Vec<u8>,Vec<u8>,u64)>> {
+        /* since 7.4 proper, ttls are offsets from the hash's earliest expiry */
+        let min_expiry = if kind == TYPE_HASH_METADATA { Some(self.u64_le()?) } else { None };
+        let mut fields = vec![];
+        for _ in 0..self.length()? {
+            let ttl = self.length()?;
+            let field = self.string()?;
+            let value = self.string()?;
+            let at = match min_expiry {
+                Some(min) if ttl != HASH_NO_TTL => ttl.saturating_add(min).saturating_sub(1),
+                _ => ttl
+            };
+            fields.push((field,value,at));
+        }
+        Ok(fields)
+    }
+
+    fn hash_listpack_ex(&mut self, kind: u8) -> io::Result<Vec<(Vec<u8>,Vec<u8>,u64)>> {
+        if kind == TYPE_HASH_LISTPACK_EX { self.u64_le()?; }
+        let entries = listpack(&self.string()?)?;
+        if entries.len() % 3 != 0 { return Err(invalid_data("rdb: hash listpack entries don't come in threes")); }
+        entries.chunks(3).map(|entry| {
+            let at = String::from_utf8_lossy(&entry[2]).parse::<u64>().map_err(|_| invalid_data("rdb: bad hash field ttl"))?;
+            Ok((entry[0].clone(),entry[1].clone(),at))
+        }).collect()
+    }
+
+    fn value(&mut self, kind: u8, now_ms: u64) -> io::Result<RdbValue> {
+        Ok(match kind {
+            TYPE_STRING => RdbValue::String(self.string()?),
+            TYPE_LIST | TYPE_SET => RdbValue::Other(array_json(self.strings()?)),
+            TYPE_ZSET => {
+                let count = self.length()?;
+                let mut members = vec![];
+                for _ in 0..count {
+                    let member = self.string()?;
+                    let score = match self.u8()? {
+                        253 => f64::NAN,
+                        254 => f64::INFINITY,
+                        255 => f64::NEG_INFINITY,
+                        n => parse_score(&self.bytes(n as usize)?)?
+                    };
+                    members.push((member,score));
+                }
+                RdbValue::Other(zset_json(members))
+            },
+            TYPE_ZSET_2 => {
+                let count = self.length()?;
+                let mut members = vec![];
+                for _ in 0..count {
+                    let member = self.string()?;
+                    members.push((member,f64::from_bits(self.u64_le()?)));
+                }
+                RdbValue::Other(zset_json(members))
+            },
+            TYPE_HASH => RdbValue::Other(hash_json(self.string_pairs()?)),
+            TYPE_HASH_ZIPMAP => RdbValue::Other(hash_json(zipmap(&self.string()?)?)),
+            TYPE_LIST_ZIPLIST => RdbValue::Other(array_json(ziplist(&self.string()?)?)),
+            TYPE_SET_INTSET => RdbValue::Other(array_json(intset(&self.string()?)?)),
+            TYPE_SET_LISTPACK => RdbValue::Other(array_json(listpack(&self.string()?)?)),
+            TYPE_HASH_ZIPLIST => RdbValue::Other(hash_json(pairs(ziplist(&self.string()?)?)?)),
+            TYPE_HASH_LISTPACK => RdbValue::Other(hash_json(pairs(listpack(&self.string()?)?)?)),
+            TYPE_HASH_METADATA | TYPE_HASH_METADATA_PRE_GA => live_hash(self.hash_metadata(kind)?,now_ms),
+            TYPE_HASH_LISTPACK_EX | TYPE_HASH_LISTPACK_EX_PRE_GA => live_hash(self.hash_listpack_ex(kind)?,now_ms),
+            TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => {
+                let blob = self.string()?;
+                let entries = if kind == TYPE_ZSET_ZIPLIST { ziplist(&blob)? } else { listpack(&blob)? };
+                let members = pairs(entries)?.into_iter().map(|(member,score)| Ok((member,parse_score(&score)?))).collect::<io::Result<_>>()?;
+                RdbValue::Other(zset_json(members))
+            },
+            TYPE_LIST_QUICKLIST => {
+                let mut items = vec![];
+                for _ in 0..self.length()? { items.extend(ziplist(&self.string()?)?); }
+                RdbValue::Other(array_json(items))
+            },
+            TYPE_LIST_QUICKLIST_2 => {
+                let mut items = vec![];
+                for _ in 0..self.length()? {
+                    let container = self.length()?;
+                    let blob = self.string()?;
+                    if container == 1 { items.push(blob); } else { items.extend(listpack(&blob)?); }
+                }
+                RdbValue::Other(array_json(items))
+            },
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                self.skip_stream(kind)?;
+                RdbValue::Unrepresentable
+            },
+            TYPE_MODULE_2 => {
+                self.length()?;
+                self.skip_module_data()?;
+                RdbValue::Unrepresentable
+            },
+            other => { return Err(invalid_data(format!("rdb: unsupported value type {}",other))); }
+        })
+    }
+}
+
+struct RdbIterator {
+    rdb: Rdb<BufReader<File>>,
+    other: RdbOther,
+    tally: Option<Arc<Tally>>,
+    now_ms: u64,
+    done: bool
+}
+
+impl RdbIterator {
+    fn open(path: &Path, config: &RdbConfig) -> io::Result<RdbIterator> {
+        let mut rdb = Rdb { input: BufReader::new(File::open(path)?) };
+        let header = rdb.bytes(9).map_err(|_| invalid_data("rdb: not an RDB file"))?;
+        if &header[..5] != b"REDIS" {
+            return Err(invalid_data("rdb: not an RDB file"));
+        }
+        match String::from_utf8_lossy(&header[5..]).parse::<u32>() {
+            Ok(version) if version <= MAX_VERSION => {},
+            _ => { return Err(invalid_data(format!("rdb: unsupported version {}",String::from_utf8_lossy(&header[5..])))); }
+        }
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        Ok(RdbIterator { rdb, other: config.other, tally: config.tally.clone(), now_ms, done: false })
+    }
+
+    fn record(&mut self) -> io::Result<Option<(Vec<u8>,Vec<u8>)>> {
+        let mut expiry = None;
+        loop {
+            let kind = match self.rdb.u8()? {
+                OP_EOF => { return Ok(None); },
+                OP_AUX => { self.rdb.string()?; self.rdb.string()?; continue; },
+                OP_RESIZEDB => { self.rdb.length()?; self.rdb.length()?; continue; },
+                OP_SELECTDB | OP_IDLE => { self.rdb.length()?; continue; },
+                OP_FREQ => { self.rdb.u8()?; continue; },
+                OP_SLOT_INFO => { for _ in 0..3 { self.rdb.length()?; } continue; },
+                OP_EXPIRETIME => { expiry = Some(self.rdb.u32_le()? as u64 * 1000); continue; },
+                OP_EXPIRETIME_MS => { expiry = Some(self.rdb.u64_le()?); continue; },
+                OP_FUNCTION2 => { self.rdb.string()?; continue; },
+                OP_MODULE_AUX => {
+                    for _ in 0..3 { self.rdb.length()?; }
+                    self.rdb.skip_module_data()?;
+                    continue;
+                },
+                kind => kind
+            };
+            let key = self.rdb.string()?;
+            let value = self.rdb.value(kind,self.now_ms)?;
+            if expiry.take().map(|at| at <= self.now_ms).unwrap_or(false) { continue; }
+            match (value,self.other) {
+                (RdbValue::String(value),_) => { return Ok(Some((key,value))); },
+                (RdbValue::Other(value),RdbOther::Json) => {
+                    return Ok(Some((key,serde_json::to_vec(&value).map_err(invalid_data)?)));
+                },
+                (RdbValue::Other(_),RdbOther::Skip) => {
+                    report_skip(&self.tally,format!("rdb: key {} doesn't hold a string",String::from_utf8_lossy(&key)))?;
+                },
+                (RdbValue::Unrepresentable,_) => {
+                    report_skip(&self.tally,format!("rdb: key {} holds a stream or module value",String::from_utf8_lossy(&key)))?;
+                },
+                (RdbValue::Expired,_) => {}
+            }
+        }
+    }
+}
+
+impl Iterator for RdbIterator {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done { return None; }
+        let record = self.record().transpose();
+        if !matches!(record,Some(Ok(_))) { self.done = true; }
+        record
+    }
+}
+
+impl NCDValueSource for RdbSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match RdbIterator::open(&self.path,&self.config) {
+            Ok(records) => Box::new(records),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sources::{counting::Tally, fixture};
+    use super::{listpack, lzf_decompress, ziplist, RdbConfig, RdbOther, RdbSource};
+
+    fn string(out: &mut Vec<u8>, s: &[u8]) {
+        out.push(s.len() as u8);
+        out.extend_from_slice(s);
+    }
+
+    /* a listpack of short strings and small ints */
+    fn small_listpack(entries: &[&[u8]]) -> Vec<u8> {
+        let mut body = vec![];
+        for entry in entries {
+            match std::str::from_utf8(entry).ok().and_then(|s| s.parse::<u8>().ok()).filter(|n| *n < 128) {
+                Some(n) => { body.push(n); body.push(1); },
+                None => { body.push(0x80 | entry.len() as u8); body.extend_from_slice(entry); body.push(1+entry.len() as u8); }
+            }
+        }
+        let mut out = ((7+body.len()) as u32).to_le_bytes().to_vec();
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend(body);
+        out.push(0xFF);
+        out
+    }
+
+    fn rdb() -> Vec<u8> {
+        let mut out = b"REDIS0011".to_vec();
+        out.push(0xFA); string(&mut out,b"redis-ver"); string(&mut out,b"7.0.0");
+        out.extend_from_slice(&[0xFE,0x00,0xFB,0x04,0x01]);
+        out.push(0); string(&mut out,b"gene:BRCA2"); string(&mut out,b"13q13.1");
+        out.push(0); string(&mut out,b"count"); out.extend_from_slice(&[0xC0,0xF6]);
+        out.push(0xFC); out.extend_from_slice(&1000u64.to_le_bytes());
+        out.push(0); string(&mut out,b"expired"); string(&mut out,b"x");
+        out.push(1); string(&mut out,b"list"); out.push(2); string(&mut out,b"a"); string(&mut out,b"b");
+        out.push(16); string(&mut out,b"hash"); string(&mut out,&small_listpack(&[b"f",b"7"]));
+        out.push(0xFF);
+        out.extend_from_slice(&[0;8]);
+        out
+    }
+
+    fn read(data: &[u8], other: RdbOther) -> Result<Vec<(String,String)>,String> {
+        fixture::parse(data,|path| RdbSource::new(path,&RdbConfig::new().other(other)))
+    }
+
+    #[test]
+    fn test_skips() {
+        let tally = Tally::new();
+        let config = RdbConfig::new().tally(&tally);
+        assert_eq!(2,fixture::parse(&rdb(),|path| RdbSource::new(path,&config)).unwrap().len());
+        assert_eq!(2,tally.skipped());
+        tally.set_strict(true);
+        assert_eq!(Err("strict: rdb: key list doesn't hold a string".to_string()),fixture::parse(&rdb(),|path| RdbSource::new(path,&config)));
+        assert!(fixture::parse(&rdb(),|path| RdbSource::new(path,&config.clone().other(RdbOther::Json))).is_ok());
+    }
+
+    #[test]
+    fn test_rdb() {
+        assert_eq!(Ok(vec![
+            ("gene:BRCA2".to_string(),"13q13.1".to_string()),
+            ("count".to_string(),"-10".to_string())
+        ]),read(&rdb(),RdbOther::Skip));
+        assert_eq!(Ok(vec![
+            ("gene:BRCA2".to_string(),"13q13.1".to_string()),
+            ("count".to_string(),"-10".to_string()),
+            ("list".to_string(),r#"["a","b"]"#.to_string()),
+            ("hash".to_string(),r#"{"f":"7"}"#.to_string())
+        ]),read(&rdb(),RdbOther::Json));
+        let data = rdb();
+        assert_eq!(Err("rdb: truncated file".to_string()),read(&data[..data.len()-12],RdbOther::Skip));
+        assert!(read(b"REDIS0099\xFF",RdbOther::Skip).is_err());
+        /* a string claiming to be 2^62 bytes long fails as truncated without allocating it */
+        let mut huge = b"REDIS0011\xFE\x00\x00\x81".to_vec();
+        huge.extend_from_slice(&(1u64 << 62).to_be_bytes());
+        huge.extend_from_slice(b"short");
+        assert_eq!(Err("rdb: truncated file".to_string()),read(&huge,RdbOther::Skip));
+    }
+
+    #[test]
+    fn test_field_expiry() {
+        let mut data = b"REDIS0012".to_vec();
+        data.extend_from_slice(&[0xFE,0x00]);
+        data.extend_from_slice(&[0xF4,0x05,0x02,0x01]);
+        /* field ttls are offsets from the earliest, so "old" expired at 1000 */
+        data.push(24); string(&mut data,b"meta"); data.extend_from_slice(&1000u64.to_le_bytes());
+        data.push(2);
+        data.push(1); string(&mut data,b"old"); string(&mut data,b"x");
+        data.push(0); string(&mut data,b"kept"); string(&mut data,b"y");
+        data.push(23); string(&mut data,b"lp"); string(&mut data,&small_listpack(&[b"a",b"1",b"0",b"b",b"2",b"5"]));
+        data.push(25); string(&mut data,b"gone"); data.extend_from_slice(&5u64.to_le_bytes());
+        string(&mut data,&small_listpack(&[b"a",b"1",b"5"]));
+        data.push(0); string(&mut data,b"after"); string(&mut data,b"z");
+        data.push(0xFF);
+        data.extend_from_slice(&[0;8]);
+        assert_eq!(Ok(vec![
+            ("meta".to_string(),r#"{"kept":"y"}"#.to_string()),
+            ("lp".to_string(),r#"{"a":"1"}"#.to_string()),
+            ("after".to_string(),"z".to_string())
+        ]),read(&data,RdbOther::Json));
+        assert!(read(b"NOTREDIS!",RdbOther::Skip).is_err());
+    }
+
+    #[test]
+    fn test_encodings() {
+        /* "abcabcabc": three literals, then a back-reference of six */
+        assert_eq!(b"abcabcabc".to_vec(),lzf_decompress(&[0x02,b'a',b'b',b'c',0x80,0x02],9).unwrap());
+        assert!(lzf_decompress(&[0x80,0x05],4).is_err());
+        /* output past the stated length is refused rather than grown */
+        assert!(lzf_decompress(&[0x02,b'a',b'b',b'c',0x80,0x02],6).is_err());
+        assert!(lzf_decompress(&[0x02,b'a',b'b',b'c'],2).is_err());
+        let mut zl = vec![0;10];
+        zl.extend_from_slice(&[0x00,0x02,b'h',b'i',0x04,0xF3,0x02,0xC0,0x39,0x30,0xFF]);
+        assert_eq!(vec![b"hi".to_vec(),b"2".to_vec(),b"12345".to_vec()],ziplist(&zl).unwrap());
+        assert_eq!(vec![b"ab".to_vec(),b"5".to_vec()],listpack(&small_listpack(&[b"ab",b"5"])).unwrap());
+    }
+}
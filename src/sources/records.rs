@@ -0,0 +1,195 @@
+use std::{fs::File, io::{self, BufRead, BufReader}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+
+use crate::prepare::{split_fields, split_quoted_fields};
+use super::{check_exists, invalid_data, SourceIter};
+
+/// Separated records ended by `terminator` rather than a newline, so a record can hold newlines
+/// itself (eg NUL from `find -print0`). The fields are split as for the flat source: the key is
+/// field `index` (first is 1) and the value is the rest of the record as it was, less the key
+/// and the separator before it (after it, for the first field). Blank records are skipped
+/// unless `skip_blank` is off, and records starting with `comment` always are; with
+/// `inline_comments` a record also ends at a `comment` within it. Trailing whitespace is
+/// stripped unless `trim_tail` is off. With `quote_aware`, fields are split by
+/// `split_quoted_fields`, so a quoted field can hold the separator (or comment), and the value's
+/// fields lose their quotes as the key does.
+#[derive(Clone,Debug)]
+pub struct RecordsConfig {
+    terminator: u8,
+    separator: Option<String>,
+    index: usize,
+    skip_blank: bool,
+    comment: Option<String>,
+    inline_comments: bool,
+    trim_tail: bool,
+    quote_aware: bool
+}
+
+impl RecordsConfig {
+    pub fn new() -> RecordsConfig {
+        RecordsConfig {
+            terminator: 0,
+            separator: None,
+            index: 1,
+            skip_blank: true,
+            comment: None,
+            inline_comments: false,
+            trim_tail: true,
+            quote_aware: false
+        }
+    }
+}
+
+chain!(terminator,get_terminator,u8,RecordsConfig);
+chain!(separator,get_separator,Option<String>,RecordsConfig);
+chain!(index,get_index,usize,RecordsConfig);
+chain!(skip_blank,get_skip_blank,bool,RecordsConfig);
+chain!(comment,get_comment,Option<String>,RecordsConfig);
+chain!(inline_comments,get_inline_comments,bool,RecordsConfig);
+chain!(trim_tail,get_trim_tail,bool,RecordsConfig);
+chain!(quote_aware,get_quote_aware,bool,RecordsConfig);
+
+pub struct RecordsSource {
+    path: PathBuf,
+    config: RecordsConfig
+}
+
+impl RecordsSource {
+    pub fn new(path: &Path, config: &RecordsConfig) -> io::Result<RecordsSource> {
+        check_exists(path)?;
+        Ok(RecordsSource { path: path.to_path_buf(), config: config.clone() })
+    }
+}
+
+/* where an inline comment starts, outside quotes when they're honoured */
+fn comment_start(record: &[u8], comment: &[u8], quote_aware: bool) -> Option<usize> {
+    let mut quoted = false;
+    for (at,b) in record.iter().enumerate() {
+        if quote_aware && *b == b'"' { quoted = !quoted; }
+        if !quoted && record[at..].starts_with(comment) { return Some(at); }
+    }
+    None
+}
+
+/* the bytes of `record` a field of it covers, with the quotes it lost */
+fn span(record: &[u8], field: &[u8], quote_aware: bool) -> (usize,usize) {
+    let start = field.as_ptr() as usize - record.as_ptr() as usize;
+    let end = start + field.len();
+    if quote_aware && start > 0 && record.get(end) == Some(&b'"') && record[start-1] == b'"' {
+        (start-1,end+1)
+    } else {
+        (start,end)
+    }
+}
+
+fn parse_record(record: &[u8], number: usize, config: &RecordsConfig) -> io::Result<(Vec<u8>,Vec<u8>)> {
+    let mut record = record;
+    if let Some(comment) = config.comment.as_ref().filter(|_| config.inline_comments) {
+        if let Some(at) = comment_start(record,comment.as_bytes(),config.quote_aware) {
+            record = &record[..at];
+        }
+    }
+    if config.trim_tail {
+        let end = record.iter().rposition(|b| !b.is_ascii_whitespace()).map(|i| i+1).unwrap_or(0);
+        record = &record[..end];
+    }
+    let fields = if config.quote_aware { split_quoted_fields(record,&config.separator) } else { split_fields(record,&config.separator) };
+    if config.index == 0 || config.index > fields.len() {
+        return Err(invalid_data(format!("record {}: no field {} (has {})",number,config.index,fields.len())));
+    }
+    /* the other fields with the original bytes between them; past the key, what followed it */
+    let spans : Vec<_> = fields.iter().map(|f| span(record,f,config.quote_aware)).collect();
+    let mut value = vec![];
+    for (i,field) in fields.iter().enumerate().filter(|(i,_)| i+1 != config.index) {
+        if i > 0 && !(i == 1 && config.index == 1) {
+            value.extend_from_slice(&record[spans[i-1].1..spans[i].0]);
+        }
+        value.extend_from_slice(field);
+    }
+    if let Some(last) = spans.last() {
+        if spans.len() > 1 { value.extend_from_slice(&record[last.1..]); }
+    }
+    Ok((fields[config.index-1].to_vec(),value))
+}
+
+impl NCDValueSource for RecordsSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        let mut number = 0;
+        Box::new(BufReader::new(file).split(self.config.terminator).filter_map(move |record| {
+            let record = match record { Ok(r) => r, Err(e) => { return Some(Err(e)); } };
+            number += 1;
+            if self.config.skip_blank && record.iter().all(|b| b.is_ascii_whitespace()) { return None; }
+            if let Some(comment) = &self.config.comment {
+                if record.starts_with(comment.as_bytes()) { return None; }
+            }
+            Some(parse_record(&record,number,&self.config))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use ncd::NCDValueSource;
+
+    use super::{RecordsConfig, RecordsSource};
+
+    #[test]
+    fn test_records() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"a\tline one\nline two\x00#skipped\x00\x00b\tx\ty\x00c").unwrap();
+        let config = RecordsConfig::new().separator(Some("\t".to_string())).comment(Some("#".to_string()));
+        let source = RecordsSource::new(file.path(),&config).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![
+            (b"a".to_vec(),b"line one\nline two".to_vec()),
+            (b"b".to_vec(),b"x\ty".to_vec()),
+            (b"c".to_vec(),vec![])
+        ],out);
+        let source = RecordsSource::new(file.path(),&config.index(2)).unwrap();
+        let out : Vec<_> = source.iter().collect();
+        assert_eq!(b"line one\nline two".to_vec(),out[0].as_ref().unwrap().0);
+        assert_eq!(b"a".to_vec(),out[0].as_ref().unwrap().1);
+        assert_eq!(b"b\ty".to_vec(),out[1].as_ref().unwrap().1);
+        assert!(out[2].is_err());
+    }
+
+    /* as the flat source: what follows the key is kept byte for byte, bar -T and -I */
+    #[test]
+    fn test_value_bytes() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"BRCA2  chr13   13q13.1 # band \t\x00TP53\tchr17 \x00").unwrap();
+        let config = RecordsConfig::new().comment(Some("#".to_string()));
+        let get = |config: &RecordsConfig| -> Vec<_> { RecordsSource::new(file.path(),config).unwrap().iter().map(|e| e.unwrap()).collect() };
+        assert_eq!(vec![
+            (b"BRCA2".to_vec(),b"chr13   13q13.1 # band".to_vec()),
+            (b"TP53".to_vec(),b"chr17".to_vec())
+        ],get(&config));
+        assert_eq!(b"chr13   13q13.1".to_vec(),get(&config.clone().inline_comments(true))[0].1);
+        assert_eq!(b"chr13   13q13.1 # band \t".to_vec(),get(&config.clone().trim_tail(false))[0].1);
+        assert_eq!(b"chr13 ".to_vec(),get(&config.clone().trim_tail(false))[1].1);
+        assert_eq!((b"chr13".to_vec(),b"BRCA2   13q13.1".to_vec()),get(&config.clone().index(2).inline_comments(true))[0]);
+    }
+
+    #[test]
+    fn test_quote_aware() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"\"BRCA2 gene\" chr13 \"13q13.1 band\"\n").unwrap();
+        let config = RecordsConfig::new().terminator(b'\n').quote_aware(true);
+        let out : Vec<_> = RecordsSource::new(file.path(),&config).unwrap().iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"BRCA2 gene".to_vec(),b"chr13 13q13.1 band".to_vec())],out);
+        let commented = config.clone().comment(Some("#".to_string())).inline_comments(true);
+        let mut file2 = tempfile::NamedTempFile::new().unwrap();
+        file2.write_all(b"CFTR \"chr7 #q31.2\" # band\n").unwrap();
+        let out : Vec<_> = RecordsSource::new(file2.path(),&commented).unwrap().iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"CFTR".to_vec(),b"chr7 #q31.2".to_vec())],out);
+        let out : Vec<_> = RecordsSource::new(file.path(),&config.quote_aware(false)).unwrap().iter().map(|e| e.unwrap()).collect();
+        assert_eq!(b"\"BRCA2".to_vec(),out[0].0);
+    }
+}
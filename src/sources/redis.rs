@@ -0,0 +1,132 @@
+use std::{collections::HashSet, io, sync::Arc};
+
+use ncd::NCDValueSource;
+use tempfile::NamedTempFile;
+
+use crate::error::remove_on_exit;
+use super::{counting::{report_skip, Tally}, invalid_data, spool::{SpoolSource, Spooler}, SourceIter};
+
+/// Which keys to take, and how many to ask for per `SCAN`/`MGET` round trip.
+#[derive(Clone,Debug)]
+pub struct RedisConfig {
+    pattern: String,
+    batch: usize,
+    tally: Option<Arc<Tally>>
+}
+
+impl RedisConfig {
+    pub fn new() -> RedisConfig {
+        RedisConfig {
+            pattern: "*".to_string(),
+            batch: 1000,
+            tally: None
+        }
+    }
+
+    /// Report keys left out of the snapshot to `tally`.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> RedisConfig {
+        self.tally = Some(tally.clone());
+        self
+    }
+}
+
+chain!(pattern,get_pattern,String,RedisConfig);
+chain!(batch,get_batch,usize,RedisConfig);
+
+/// A snapshot of the string keys of a live Redis matching a `SCAN` pattern. The snapshot is
+/// taken once, into a spool, so every build attempt sees the same data even if the server moves
+/// on. Keys which aren't strings, or which vanish mid-scan, are left out (and reported to any
+/// tally on each pass), and a key `SCAN` gives more than once (as it may when the keyspace is
+/// rehashed) is only taken once.
+pub struct RedisSource {
+    _spool: NamedTempFile,
+    records: SpoolSource,
+    skipped: Vec<String>,
+    tally: Option<Arc<Tally>>
+}
+
+fn redis_error(e: redis::RedisError) -> io::Error {
+    invalid_data(format!("redis: {}",e))
+}
+
+/* the keys of a SCAN batch not already spooled */
+fn new_keys(seen: &mut HashSet<Vec<u8>>, keys: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    keys.into_iter().filter(|key| seen.insert(key.clone())).collect()
+}
+
+/* MGET answers nil for keys holding something other than a string */
+fn spool_batch(spooler: &mut Spooler, keys: &[Vec<u8>], values: Vec<Option<Vec<u8>>>, skipped: &mut Vec<String>) -> io::Result<()> {
+    for (key,value) in keys.iter().zip(values) {
+        match value {
+            Some(value) => { spooler.add(key,&value)?; },
+            None => { skipped.push(format!("redis: key {} doesn't hold a string, or has gone",String::from_utf8_lossy(key))); }
+        }
+    }
+    Ok(())
+}
+
+impl RedisSource {
+    pub fn new(url: &str, config: &RedisConfig) -> io::Result<RedisSource> {
+        let client = redis::Client::open(url).map_err(redis_error)?;
+        let mut connection = client.get_connection().map_err(redis_error)?;
+        let spool = NamedTempFile::new()?;
+        remove_on_exit(spool.path());
+        let mut spooler = Spooler::new(spool.reopen()?);
+        let mut seen = HashSet::new();
+        let mut skipped = vec![];
+        let mut cursor = 0u64;
+        loop {
+            let (next,keys) : (u64,Vec<Vec<u8>>) = redis::cmd("SCAN").arg(cursor)
+                .arg("MATCH").arg(&config.pattern).arg("COUNT").arg(config.batch)
+                .query(&mut connection).map_err(redis_error)?;
+            let keys = new_keys(&mut seen,keys);
+            if !keys.is_empty() {
+                let values : Vec<Option<Vec<u8>>> = redis::cmd("MGET").arg(&keys).query(&mut connection).map_err(redis_error)?;
+                spool_batch(&mut spooler,&keys,values,&mut skipped)?;
+            }
+            if next == 0 { break; }
+            cursor = next;
+        }
+        spooler.finish()?;
+        let records = SpoolSource::new(spool.path());
+        Ok(RedisSource { _spool: spool, records, skipped, tally: config.tally.clone() })
+    }
+}
+
+impl NCDValueSource for RedisSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        /* found taking the snapshot, but counted on each pass like any other source's skips */
+        if let Err(e) = self.skipped.iter().try_for_each(|what| report_skip(&self.tally,what.clone())) {
+            return Box::new(std::iter::once(Err(e)));
+        }
+        self.records.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use ncd::NCDValueSource;
+
+    use crate::sources::spool::{SpoolSource, Spooler};
+    use super::{new_keys, spool_batch};
+
+    #[test]
+    fn test_repeated_keys() {
+        let spool = tempfile::NamedTempFile::new().unwrap();
+        let mut spooler = Spooler::new(spool.reopen().unwrap());
+        let mut seen = HashSet::new();
+        let keys = new_keys(&mut seen,vec![b"a".to_vec(),b"b".to_vec()]);
+        let mut skipped = vec![];
+        spool_batch(&mut spooler,&keys,vec![Some(b"1".to_vec()),Some(b"2".to_vec())],&mut skipped).unwrap();
+        /* a later batch repeating b, and c holding a list */
+        let keys = new_keys(&mut seen,vec![b"b".to_vec(),b"c".to_vec(),b"a".to_vec()]);
+        assert_eq!(vec![b"c".to_vec()],keys);
+        spool_batch(&mut spooler,&keys,vec![None],&mut skipped).unwrap();
+        assert_eq!(vec!["redis: key c doesn't hold a string, or has gone".to_string()],skipped);
+        spooler.finish().unwrap();
+        let records : Vec<_> = SpoolSource::new(spool.path()).iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"a".to_vec(),b"1".to_vec()),(b"b".to_vec(),b"2".to_vec())],records);
+    }
+}
@@ -0,0 +1,21 @@
+use ncd::NCDValueSource;
+
+use super::SourceIter;
+
+/// Leaves out the first `count` records of `inner`, such as a header line read as a record.
+pub struct SkipSource {
+    inner: Box<dyn NCDValueSource>,
+    count: usize
+}
+
+impl SkipSource {
+    pub fn new(inner: Box<dyn NCDValueSource>, count: usize) -> SkipSource {
+        SkipSource { inner, count }
+    }
+}
+
+impl NCDValueSource for SkipSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        Box::new(self.inner.iter().skip(self.count))
+    }
+}
@@ -0,0 +1,126 @@
+use std::{fs::File, io::{self, BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+
+use super::SourceIter;
+
+/* records are a little-endian u64 key length, u64 value length, then the bytes of each */
+
+pub fn write_record<W: Write>(out: &mut W, key: &[u8], value: &[u8]) -> io::Result<()> {
+    out.write_all(&(key.len() as u64).to_le_bytes())?;
+    out.write_all(&(value.len() as u64).to_le_bytes())?;
+    out.write_all(key)?;
+    out.write_all(value)
+}
+
+fn read_u64<R: Read>(input: &mut R) -> io::Result<Option<u64>> {
+    let mut buffer = [0;8];
+    let mut got = 0;
+    while got < 8 {
+        let n = input.read(&mut buffer[got..])?;
+        if n == 0 {
+            if got == 0 { return Ok(None); }
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,"truncated spool file"));
+        }
+        got += n;
+    }
+    Ok(Some(u64::from_le_bytes(buffer)))
+}
+
+/* a damaged length mustn't be allocated up front */
+fn read_bytes<R: Read>(input: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    input.by_ref().take(len).read_to_end(&mut out)?;
+    if (out.len() as u64) < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,"truncated spool file"));
+    }
+    Ok(out)
+}
+
+pub fn read_record<R: Read>(input: &mut R) -> io::Result<Option<(Vec<u8>,Vec<u8>)>> {
+    let key_len = match read_u64(input)? { Some(n) => n, None => { return Ok(None); } };
+    let value_len = read_u64(input)?.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof,"truncated spool file"))?;
+    let key = read_bytes(input,key_len)?;
+    let value = read_bytes(input,value_len)?;
+    Ok(Some((key,value)))
+}
+
+/// Source reading back records written with `write_record`.
+pub struct SpoolSource {
+    path: PathBuf
+}
+
+impl SpoolSource {
+    pub fn new(path: &Path) -> SpoolSource {
+        SpoolSource { path: path.to_path_buf() }
+    }
+}
+
+struct SpoolIterator {
+    input: Option<BufReader<File>>
+}
+
+impl Iterator for SpoolIterator {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let out = read_record(self.input.as_mut()?).transpose();
+        if !matches!(out,Some(Ok(_))) { self.input = None; }
+        out
+    }
+}
+
+impl NCDValueSource for SpoolSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match File::open(&self.path) {
+            Ok(file) => Box::new(SpoolIterator { input: Some(BufReader::new(file)) }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+/// Writes records for a `SpoolSource`, flushing on `finish`.
+pub struct Spooler {
+    out: BufWriter<File>
+}
+
+impl Spooler {
+    pub fn new(file: File) -> Spooler {
+        Spooler { out: BufWriter::new(file) }
+    }
+
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        write_record(&mut self.out,key,value)
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use super::{SpoolSource, Spooler};
+
+    #[test]
+    fn test_spool() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut spooler = Spooler::new(file.reopen().unwrap());
+        spooler.add(b"a",b"").unwrap();
+        spooler.add(b"",b"\x00\n\xFF").unwrap();
+        spooler.finish().unwrap();
+        let source = SpoolSource::new(file.path());
+        for _ in 0..2 {
+            let records : Vec<_> = source.iter().map(|r| r.unwrap()).collect();
+            assert_eq!(vec![(b"a".to_vec(),vec![]),(vec![],b"\x00\n\xFF".to_vec())],records);
+        }
+        std::fs::write(file.path(),&[1,0,0,0]).unwrap();
+        assert!(source.iter().next().unwrap().is_err());
+        let mut huge = u64::MAX.to_le_bytes().to_vec();
+        huge.extend_from_slice(&[0;8]);
+        std::fs::write(file.path(),&huge).unwrap();
+        assert_eq!("truncated spool file",source.iter().next().unwrap().unwrap_err().to_string());
+    }
+}
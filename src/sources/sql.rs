@@ -0,0 +1,196 @@
+use std::{io, sync::Arc};
+
+use mysql::{prelude::Queryable, Value};
+use ncd::NCDValueSource;
+use postgres::{types::Type, NoTls, Row};
+use tempfile::NamedTempFile;
+
+use crate::error::remove_on_exit;
+use super::{counting::{report_skip, Tally}, invalid_data, spool::{SpoolSource, Spooler}, SourceIter};
+
+/// The query giving the records, and how many rows to fetch per round trip of the cursor.
+#[derive(Clone,Debug)]
+pub struct SqlConfig {
+    query: String,
+    batch: usize,
+    tally: Option<Arc<Tally>>
+}
+
+impl SqlConfig {
+    pub fn new() -> SqlConfig {
+        SqlConfig {
+            query: String::new(),
+            batch: 1000,
+            tally: None
+        }
+    }
+
+    /// Report rows left out of the snapshot to `tally`.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> SqlConfig {
+        self.tally = Some(tally.clone());
+        self
+    }
+}
+
+chain!(query,get_query,String,SqlConfig);
+chain!(batch,get_batch,usize,SqlConfig);
+
+/// A snapshot of the rows of a query on a live PostgreSQL or MySQL database: the first column
+/// is the key and the second the value. Rows are streamed from the server, by a cursor on
+/// PostgreSQL and unbuffered on MySQL, into a spool, so the build's passes all see the same
+/// data. Rows with a NULL key or value are left out, and reported to any tally on each pass.
+pub struct SqlSource {
+    _spool: NamedTempFile,
+    records: SpoolSource,
+    skipped: Vec<String>,
+    tally: Option<Arc<Tally>>
+}
+
+/* a row is spooled, or noted as skipped if it has a NULL */
+fn spool_row(spooler: &mut Spooler, row: usize, key: Option<Vec<u8>>, value: Option<Vec<u8>>, skipped: &mut Vec<String>) -> io::Result<()> {
+    match (key,value) {
+        (Some(key),Some(value)) => spooler.add(&key,&value),
+        (None,_) => { skipped.push(format!("sql: row {}: NULL key",row)); Ok(()) },
+        (Some(key),None) => { skipped.push(format!("sql: row {}: NULL value for {}",row,String::from_utf8_lossy(&key))); Ok(()) }
+    }
+}
+
+fn two_columns(columns: usize) -> io::Result<()> {
+    if columns != 2 {
+        return Err(invalid_data(format!("sql: the query must give two columns, key and value, not {}",columns)));
+    }
+    Ok(())
+}
+
+/* text and bytea as they are, and numbers and booleans as postgres would print them: anything
+ * else wants a cast to text in the query */
+fn postgres_bytes(row: &Row, index: usize) -> io::Result<Option<Vec<u8>>> {
+    let column = &row.columns()[index];
+    let get_error = |e: postgres::Error| invalid_data(format!("postgres: column {}: {}",column.name(),e));
+    let type_ = column.type_();
+    Ok(if *type_ == Type::BYTEA {
+        row.try_get::<_,Option<&[u8]>>(index).map_err(get_error)?.map(|v| v.to_vec())
+    } else if *type_ == Type::INT2 {
+        row.try_get::<_,Option<i16>>(index).map_err(get_error)?.map(|v| v.to_string().into_bytes())
+    } else if *type_ == Type::INT4 {
+        row.try_get::<_,Option<i32>>(index).map_err(get_error)?.map(|v| v.to_string().into_bytes())
+    } else if *type_ == Type::INT8 {
+        row.try_get::<_,Option<i64>>(index).map_err(get_error)?.map(|v| v.to_string().into_bytes())
+    } else if *type_ == Type::FLOAT4 {
+        row.try_get::<_,Option<f32>>(index).map_err(get_error)?.map(|v| v.to_string().into_bytes())
+    } else if *type_ == Type::FLOAT8 {
+        row.try_get::<_,Option<f64>>(index).map_err(get_error)?.map(|v| v.to_string().into_bytes())
+    } else if *type_ == Type::BOOL {
+        row.try_get::<_,Option<bool>>(index).map_err(get_error)?.map(|v| (if v { "t" } else { "f" }).as_bytes().to_vec())
+    } else {
+        row.try_get::<_,Option<&str>>(index)
+            .map_err(|_| invalid_data(format!("postgres: column {} is {}: cast it to text in the query",column.name(),type_)))?
+            .map(|v| v.as_bytes().to_vec())
+    })
+}
+
+/* the text protocol sends every non-NULL value as bytes, but be ready for the others */
+fn mysql_bytes(value: &Value) -> io::Result<Option<Vec<u8>>> {
+    Ok(match value {
+        Value::NULL => None,
+        Value::Bytes(bytes) => Some(bytes.clone()),
+        Value::Int(v) => Some(v.to_string().into_bytes()),
+        Value::UInt(v) => Some(v.to_string().into_bytes()),
+        Value::Float(v) => Some(v.to_string().into_bytes()),
+        Value::Double(v) => Some(v.to_string().into_bytes()),
+        other => { return Err(invalid_data(format!("mysql: can't store {:?}: cast it to a string in the query",other))); }
+    })
+}
+
+fn spool_postgres(dsn: &str, config: &SqlConfig, spooler: &mut Spooler, skipped: &mut Vec<String>) -> io::Result<()> {
+    let postgres_error = |e: postgres::Error| invalid_data(format!("postgres: {}",e));
+    let mut client = postgres::Client::connect(dsn,NoTls).map_err(postgres_error)?;
+    /* a portal is only good inside a transaction */
+    let mut transaction = client.transaction().map_err(postgres_error)?;
+    let portal = transaction.bind(config.query.as_str(),&[]).map_err(postgres_error)?;
+    let mut number = 0;
+    loop {
+        let rows = transaction.query_portal(&portal,config.batch as i32).map_err(postgres_error)?;
+        if rows.is_empty() { break; }
+        for row in &rows {
+            two_columns(row.len())?;
+            number += 1;
+            spool_row(spooler,number,postgres_bytes(row,0)?,postgres_bytes(row,1)?,skipped)?;
+        }
+    }
+    Ok(())
+}
+
+fn spool_mysql(dsn: &str, config: &SqlConfig, spooler: &mut Spooler, skipped: &mut Vec<String>) -> io::Result<()> {
+    let mysql_error = |e: mysql::Error| invalid_data(format!("mysql: {}",e));
+    let opts = mysql::Opts::from_url(dsn).map_err(|e| invalid_data(format!("mysql: {}",e)))?;
+    let mut connection = mysql::Conn::new(opts).map_err(mysql_error)?;
+    for (i,row) in connection.query_iter(&config.query).map_err(mysql_error)?.enumerate() {
+        let row = row.map_err(mysql_error)?;
+        two_columns(row.len())?;
+        let value = |index| row.as_ref(index).map(mysql_bytes).unwrap_or(Ok(None));
+        spool_row(spooler,i+1,value(0)?,value(1)?,skipped)?;
+    }
+    Ok(())
+}
+
+impl SqlSource {
+    pub fn new(dsn: &str, config: &SqlConfig) -> io::Result<SqlSource> {
+        if config.query.trim().is_empty() {
+            return Err(invalid_data("sql: no query given"));
+        }
+        let spool = NamedTempFile::new()?;
+        remove_on_exit(spool.path());
+        let mut spooler = Spooler::new(spool.reopen()?);
+        let mut skipped = vec![];
+        if dsn.starts_with("mysql://") {
+            spool_mysql(dsn,config,&mut spooler,&mut skipped)?;
+        } else {
+            spool_postgres(dsn,config,&mut spooler,&mut skipped)?;
+        }
+        spooler.finish()?;
+        let records = SpoolSource::new(spool.path());
+        Ok(SqlSource { _spool: spool, records, skipped, tally: config.tally.clone() })
+    }
+}
+
+impl NCDValueSource for SqlSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        /* as for redis, skips found taking the snapshot count on each pass */
+        if let Err(e) = self.skipped.iter().try_for_each(|what| report_skip(&self.tally,what.clone())) {
+            return Box::new(std::iter::once(Err(e)));
+        }
+        self.records.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mysql::Value;
+
+    use ncd::NCDValueSource;
+
+    use crate::sources::spool::{SpoolSource, Spooler};
+    use super::{mysql_bytes, spool_row};
+
+    #[test]
+    fn test_mysql_bytes() {
+        assert_eq!(Some(b"abc".to_vec()),mysql_bytes(&Value::Bytes(b"abc".to_vec())).unwrap());
+        assert_eq!(Some(b"-3".to_vec()),mysql_bytes(&Value::Int(-3)).unwrap());
+        assert_eq!(None,mysql_bytes(&Value::NULL).unwrap());
+        assert!(mysql_bytes(&Value::Date(2024,1,2,0,0,0,0)).is_err());
+    }
+
+    #[test]
+    fn test_null_rows() {
+        let spool = tempfile::NamedTempFile::new().unwrap();
+        let mut spooler = Spooler::new(spool.reopen().unwrap());
+        let mut skipped = vec![];
+        spool_row(&mut spooler,1,Some(b"a".to_vec()),Some(b"1".to_vec()),&mut skipped).unwrap();
+        spool_row(&mut spooler,2,None,Some(b"2".to_vec()),&mut skipped).unwrap();
+        spool_row(&mut spooler,3,Some(b"c".to_vec()),None,&mut skipped).unwrap();
+        spooler.finish().unwrap();
+        assert_eq!(vec!["sql: row 2: NULL key".to_string(),"sql: row 3: NULL value for c".to_string()],skipped);
+        assert_eq!(1,SpoolSource::new(spool.path()).iter().count());
+    }
+}
@@ -0,0 +1,358 @@
+use std::{collections::VecDeque, convert::TryInto, fs::File, io::{self, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use flate2::read::DeflateDecoder;
+use ncd::NCDValueSource;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+const LEVELDB_MAGIC : u64 = 0xdb4775248b80fb57;
+const ROCKSDB_MAGIC : u64 = 0x88e241b785f4cff7;
+const LEVELDB_FOOTER : usize = 48;
+const ROCKSDB_FOOTER : usize = 53;
+const BLOCK_TRAILER : u64 = 5;
+
+const TYPE_DELETION : u8 = 0;
+const TYPE_VALUE : u8 = 1;
+const TYPE_SINGLE_DELETION : u8 = 7;
+
+/// LevelDB and RocksDB block-based SST tables (RocksDB footer versions up to 5), read without
+/// either library. Entries come out in key order with the newest version of a key winning, and
+/// deleted keys left out. Blocks may be uncompressed or compressed with snappy, zlib or zstd.
+/// Merge operands, blob references and partitioned indexes aren't supported.
+pub struct SstSource {
+    path: PathBuf
+}
+
+impl SstSource {
+    pub fn new(path: &Path) -> io::Result<SstSource> {
+        check_exists(path)?;
+        Ok(SstSource { path: path.to_path_buf() })
+    }
+}
+
+fn varint(data: &[u8], at: &mut usize) -> io::Result<u64> {
+    let mut out = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = *data.get(*at).ok_or_else(|| invalid_data("sst: truncated varint"))?;
+        *at += 1;
+        out |= ((b & 0x7F) as u64) << shift;
+        if b & 0x80 == 0 { return Ok(out); }
+    }
+    Err(invalid_data("sst: varint too long"))
+}
+
+fn signed_varint(data: &[u8], at: &mut usize) -> io::Result<i64> {
+    let n = varint(data,at)?;
+    Ok(((n >> 1) as i64) ^ -((n & 1) as i64))
+}
+
+fn u32_at(data: &[u8], at: usize) -> io::Result<u32> {
+    let b = data.get(at..at+4).ok_or_else(|| invalid_data("sst: truncated block"))?;
+    Ok(u32::from_le_bytes([b[0],b[1],b[2],b[3]]))
+}
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+struct Handle {
+    offset: u64,
+    size: u64
+}
+
+impl Handle {
+    fn parse(data: &[u8], at: &mut usize) -> io::Result<Handle> {
+        Ok(Handle { offset: varint(data,at)?, size: varint(data,at)? })
+    }
+}
+
+/* where a block's entries end, allowing for its restart array and any RocksDB hash index */
+fn entries_end(block: &[u8]) -> io::Result<usize> {
+    if block.len() < 4 { return Err(invalid_data("sst: block too short")); }
+    let mut end = block.len()-4;
+    let mut packed = u32_at(block,end)?;
+    if packed & (1 << 31) != 0 {
+        packed &= !(1 << 31);
+        let buckets = block.get(end.saturating_sub(2)..end).ok_or_else(|| invalid_data("sst: truncated block"))?;
+        end = end.checked_sub(2 + u16::from_le_bytes([buckets[0],buckets[1]]) as usize).ok_or_else(|| invalid_data("sst: bad hash index"))?;
+    }
+    end.checked_sub(4*packed as usize).ok_or_else(|| invalid_data("sst: bad restart count"))
+}
+
+fn block_entries(block: &[u8]) -> io::Result<Vec<(Vec<u8>,Vec<u8>)>> {
+    let end = entries_end(block)?;
+    let mut out = vec![];
+    let mut key : Vec<u8> = vec![];
+    let mut at = 0;
+    while at < end {
+        let shared = varint(block,&mut at)? as usize;
+        let unshared = varint(block,&mut at)? as usize;
+        let value_len = varint(block,&mut at)? as usize;
+        if shared > key.len() || at.checked_add(unshared).and_then(|n| n.checked_add(value_len)).map(|n| n > end).unwrap_or(true) {
+            return Err(invalid_data("sst: bad block entry"));
+        }
+        key.truncate(shared);
+        key.extend_from_slice(&block[at..at+unshared]);
+        at += unshared;
+        out.push((key.clone(),block[at..at+value_len].to_vec()));
+        at += value_len;
+    }
+    Ok(out)
+}
+
+/* from RocksDB format version 4, index entries after a restart carry only a size delta */
+fn index_handles(block: &[u8], delta_encoded: bool) -> io::Result<Vec<Handle>> {
+    if !delta_encoded {
+        return block_entries(block)?.iter().map(|(_,value)| Handle::parse(value,&mut 0)).collect();
+    }
+    let end = entries_end(block)?;
+    let mut out : Vec<Handle> = vec![];
+    let mut at = 0;
+    while at < end {
+        let shared = varint(block,&mut at)?;
+        let unshared = varint(block,&mut at)? as usize;
+        at = at.checked_add(unshared).ok_or_else(|| invalid_data("sst: bad index entry"))?;
+        let handle = match (shared,out.last()) {
+            (0,_) | (_,None) => Handle::parse(block,&mut at)?,
+            (_,Some(prev)) => {
+                let delta = signed_varint(block,&mut at)?;
+                let size = (prev.size as i64).checked_add(delta).filter(|size| *size >= 0 && prev.size <= i64::MAX as u64);
+                let offset = prev.offset.checked_add(prev.size).and_then(|n| n.checked_add(BLOCK_TRAILER));
+                match (offset,size) {
+                    (Some(offset),Some(size)) => Handle { offset, size: size as u64 },
+                    _ => { return Err(invalid_data("sst: bad index entry")); }
+                }
+            }
+        };
+        out.push(handle);
+    }
+    Ok(out)
+}
+
+struct Table {
+    file: File,
+    len: u64,
+    format_version: u32
+}
+
+impl Table {
+    fn open(path: &Path) -> io::Result<(Table,Vec<Handle>)> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if len < LEVELDB_FOOTER as u64 { return Err(invalid_data("sst: file too short")); }
+        let mut footer = vec![0;ROCKSDB_FOOTER.min(len as usize)];
+        file.seek(SeekFrom::End(-(footer.len() as i64)))?;
+        file.read_exact(&mut footer)?;
+        let magic = u64::from_le_bytes(footer[footer.len()-8..].try_into().unwrap());
+        let (handles,format_version) = match magic {
+            LEVELDB_MAGIC => (&footer[footer.len()-LEVELDB_FOOTER..],0),
+            ROCKSDB_MAGIC if footer.len() == ROCKSDB_FOOTER => {
+                let version = u32_at(&footer,ROCKSDB_FOOTER-12)?;
+                if version > 5 { return Err(invalid_data(format!("sst: unsupported RocksDB format version {}",version))); }
+                (&footer[1..],version)
+            },
+            _ => { return Err(invalid_data("sst: not a LevelDB or RocksDB block-based table")); }
+        };
+        let mut at = 0;
+        Handle::parse(handles,&mut at)?;
+        let index = Handle::parse(handles,&mut at)?;
+        let mut table = Table { file, len, format_version };
+        let index_block = table.block(index)?;
+        let data = index_handles(&index_block,format_version >= 4)?;
+        Ok((table,data))
+    }
+
+    fn block(&mut self, handle: Handle) -> io::Result<Vec<u8>> {
+        /* check the handle against the file before trusting its size with an allocation */
+        let end = handle.size.checked_add(BLOCK_TRAILER).and_then(|size| handle.offset.checked_add(size));
+        if end.map(|end| end > self.len).unwrap_or(true) {
+            return Err(invalid_data("sst: block runs off the end of the file"));
+        }
+        let mut raw = vec![0;(handle.size+BLOCK_TRAILER) as usize];
+        self.file.seek(SeekFrom::Start(handle.offset))?;
+        self.file.read_exact(&mut raw).map_err(|_| invalid_data("sst: block runs off the end of the file"))?;
+        let compression = raw[handle.size as usize];
+        raw.truncate(handle.size as usize);
+        if compression == 0 { return Ok(raw); }
+        /* from format version 2, RocksDB prefixes non-snappy compressed blocks with their size */
+        let mut at = 0;
+        if compression != 1 && self.format_version >= 2 { varint(&raw,&mut at)?; }
+        let body = &raw[at..];
+        match compression {
+            1 => snap::raw::Decoder::new().decompress_vec(body).map_err(|e| invalid_data(format!("sst: snappy: {}",e))),
+            2 => {
+                let mut out = vec![];
+                DeflateDecoder::new(body).read_to_end(&mut out).map_err(|e| invalid_data(format!("sst: zlib: {}",e)))?;
+                Ok(out)
+            },
+            7 => zstd::decode_all(body).map_err(|e| invalid_data(format!("sst: zstd: {}",e))),
+            other => Err(invalid_data(format!("sst: unsupported block compression {}",other)))
+        }
+    }
+}
+
+struct SstIterator {
+    table: Table,
+    blocks: VecDeque<Handle>,
+    pending: VecDeque<(Vec<u8>,Vec<u8>)>,
+    last_key: Option<Vec<u8>>
+}
+
+impl SstIterator {
+    /* the user key and value of the next live entry of this block, newest version first */
+    fn take(&mut self, internal_key: Vec<u8>, value: Vec<u8>) -> io::Result<Option<(Vec<u8>,Vec<u8>)>> {
+        if internal_key.len() < 8 { return Err(invalid_data("sst: internal key too short")); }
+        let split = internal_key.len()-8;
+        let kind = internal_key[split];
+        let mut key = internal_key;
+        key.truncate(split);
+        if self.last_key.as_ref() == Some(&key) { return Ok(None); }
+        self.last_key = Some(key.clone());
+        match kind {
+            TYPE_VALUE => Ok(Some((key,value))),
+            TYPE_DELETION | TYPE_SINGLE_DELETION => Ok(None),
+            other => Err(invalid_data(format!("sst: unsupported entry type {} for key {}",other,String::from_utf8_lossy(&key))))
+        }
+    }
+
+    fn advance(&mut self) -> io::Result<Option<(Vec<u8>,Vec<u8>)>> {
+        loop {
+            while let Some((key,value)) = self.pending.pop_front() {
+                if let Some(record) = self.take(key,value)? { return Ok(Some(record)); }
+            }
+            let handle = match self.blocks.pop_front() { Some(h) => h, None => { return Ok(None); } };
+            let block = self.table.block(handle)?;
+            self.pending.extend(block_entries(&block)?);
+        }
+    }
+}
+
+impl Iterator for SstIterator {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.advance().transpose();
+        if let Some(Err(_)) = record { self.blocks.clear(); self.pending.clear(); }
+        record
+    }
+}
+
+impl NCDValueSource for SstSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match Table::open(&self.path) {
+            Ok((table,blocks)) => Box::new(SstIterator { table, blocks: blocks.into(), pending: VecDeque::new(), last_key: None }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use super::{index_handles, Handle, SstSource, LEVELDB_MAGIC};
+
+    fn varint(out: &mut Vec<u8>, mut n: u64) {
+        while n >= 0x80 { out.push((n as u8) | 0x80); n >>= 7; }
+        out.push(n as u8);
+    }
+
+    /* one restart point, so every key after the first is prefix-compressed */
+    fn block(entries: &[(Vec<u8>,Vec<u8>)]) -> Vec<u8> {
+        let mut out = vec![];
+        let mut prev : &[u8] = &[];
+        for (key,value) in entries {
+            let shared = key.iter().zip(prev.iter()).take_while(|(a,b)| a == b).count();
+            varint(&mut out,shared as u64);
+            varint(&mut out,(key.len()-shared) as u64);
+            varint(&mut out,value.len() as u64);
+            out.extend_from_slice(&key[shared..]);
+            out.extend_from_slice(value);
+            prev = &key[..];
+        }
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out
+    }
+
+    fn internal(key: &[u8], seq: u64, kind: u8) -> Vec<u8> {
+        let mut out = key.to_vec();
+        out.extend_from_slice(&((seq << 8) | kind as u64).to_le_bytes());
+        out
+    }
+
+    fn handle(offset: usize, size: usize) -> Vec<u8> {
+        let mut out = vec![];
+        varint(&mut out,offset as u64);
+        varint(&mut out,size as u64);
+        out
+    }
+
+    fn table() -> Vec<u8> {
+        let first = block(&[
+            (internal(b"gene:A",9,1),b"new".to_vec()),
+            (internal(b"gene:A",3,1),b"old".to_vec()),
+            (internal(b"gene:B",5,0),vec![])
+        ]);
+        let second = block(&[(internal(b"gene:C",4,1),b"c".to_vec())]);
+        let mut out = vec![];
+        let mut handles = vec![];
+        for data in &[first,second] {
+            handles.push(handle(out.len(),data.len()));
+            out.extend_from_slice(data);
+            out.extend_from_slice(&[0;5]);
+        }
+        let index = block(&[(internal(b"gene:B",0,1),handles[0].clone()),(internal(b"gene:D",0,1),handles[1].clone())]);
+        let index_handle = handle(out.len(),index.len());
+        out.extend_from_slice(&index);
+        out.extend_from_slice(&[0;5]);
+        let meta_handle = handle(out.len(),8);
+        out.extend_from_slice(&block(&[]));
+        out.extend_from_slice(&[0;5]);
+        let mut footer = [meta_handle,index_handle].concat();
+        footer.resize(40,0);
+        footer.extend_from_slice(&LEVELDB_MAGIC.to_le_bytes());
+        out.extend(footer);
+        out
+    }
+
+    /* delta-encoded: a full handle after each restart, then only the change in size */
+    #[test]
+    fn test_index_handles() {
+        let index = |first: Vec<u8>, delta: u64| {
+            let mut second = vec![];
+            varint(&mut second,delta);
+            let mut out = vec![0,1,b'a'];
+            out.extend(first);
+            out.extend_from_slice(&[1,1,b'b']);
+            out.extend(second);
+            out.extend_from_slice(&0u32.to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+            out
+        };
+        /* zigzag: 4 is +2 */
+        assert_eq!(vec![Handle { offset: 10, size: 20 },Handle { offset: 35, size: 22 }],index_handles(&index(handle(10,20),4),true).unwrap());
+        /* zigzag: 41 is -21, shrinking 20 below nothing */
+        assert!(index_handles(&index(handle(10,20),41),true).is_err());
+        let mut huge = vec![];
+        varint(&mut huge,u64::MAX-2);
+        varint(&mut huge,1);
+        assert!(index_handles(&index(huge,0),true).is_err());
+    }
+
+    #[test]
+    fn test_sst() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(),table()).unwrap();
+        let source = SstSource::new(file.path()).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"gene:A".to_vec(),b"new".to_vec()),(b"gene:C".to_vec(),b"c".to_vec())],out);
+        let mut data = table();
+        let len = data.len();
+        data[len-1] ^= 0xFF;
+        std::fs::write(file.path(),data).unwrap();
+        assert!(source.iter().next().unwrap().is_err());
+        let mut footer = [handle(0,0),handle(0,1<<40)].concat();
+        footer.resize(40,0);
+        footer.extend_from_slice(&LEVELDB_MAGIC.to_le_bytes());
+        std::fs::write(file.path(),footer).unwrap();
+        assert_eq!("sst: block runs off the end of the file",source.iter().next().unwrap().unwrap_err().to_string());
+    }
+}
@@ -0,0 +1,66 @@
+use std::{collections::HashSet, io};
+
+use ncd::NCDValueSource;
+
+use super::{invalid_data, SourceIter};
+
+/// Wraps a source, failing the build on records which would otherwise quietly go in: duplicate
+/// keys and empty values. Records a source would quietly leave out are failed by its `Tally`,
+/// and UTF-8 is left to `ValidateSource`, as keys and values needn't be text.
+pub struct StrictSource {
+    inner: Box<dyn NCDValueSource>
+}
+
+impl StrictSource {
+    pub fn new(inner: Box<dyn NCDValueSource>) -> StrictSource {
+        StrictSource { inner }
+    }
+}
+
+fn check(seen: &mut HashSet<Vec<u8>>, record: u64, key: &[u8], value: &[u8]) -> io::Result<()> {
+    let show = String::from_utf8_lossy(key);
+    if value.is_empty() {
+        return Err(invalid_data(format!("strict: record {}: empty value for {}",record,show)));
+    }
+    if !seen.insert(key.to_vec()) {
+        return Err(invalid_data(format!("strict: record {}: duplicate key {}",record,show)));
+    }
+    Ok(())
+}
+
+impl NCDValueSource for StrictSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let mut seen = HashSet::new();
+        let mut record = 0;
+        Box::new(self.inner.iter().map(move |entry| {
+            let (key,value) = entry?;
+            record += 1;
+            check(&mut seen,record,&key,&value)?;
+            Ok((key,value))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use crate::sources::memory::MemorySource;
+    use super::StrictSource;
+
+    fn run(entries: &[(&[u8],&[u8])]) -> Result<usize,String> {
+        let entries = entries.iter().map(|(k,v)| (k.to_vec(),v.to_vec())).collect();
+        let source = StrictSource::new(Box::new(MemorySource::new(entries)));
+        let out : Result<Vec<_>,_> = source.iter().collect();
+        out.map(|v| v.len()).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_strict() {
+        assert_eq!(Ok(2),run(&[(b"a",b"1"),(b"b",b"2")]));
+        assert_eq!(Err("strict: record 3: duplicate key a".to_string()),run(&[(b"a",b"1"),(b"b",b"2"),(b"a",b"3")]));
+        assert_eq!(Err("strict: record 1: empty value for a".to_string()),run(&[(b"a",b"")]));
+        /* binary formats are fine as they are */
+        assert_eq!(Ok(2),run(&[(b"a\xFF",b"1"),(b"a",b"\xC0")]));
+    }
+}
@@ -0,0 +1,77 @@
+use std::{fs::File, io::{self, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+/// Each regular file in an uncompressed tar archive is stored whole, keyed by its member path.
+/// Compressed tarballs are decompressed by `Input` before they get here.
+pub struct TarSource {
+    path: PathBuf
+}
+
+impl TarSource {
+    pub fn new(path: &Path) -> io::Result<TarSource> {
+        check_exists(path)?;
+        Ok(TarSource { path: path.to_path_buf() })
+    }
+}
+
+/* the entries iterator borrows the archive, so find the members first and read them after */
+fn tar_members(path: &Path) -> io::Result<Vec<(String,u64,u64)>> {
+    let mut tar = tar::Archive::new(File::open(path)?);
+    let mut members = vec![];
+    for entry in tar.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() { continue; }
+        let name = entry.path()?;
+        let name = name.to_str().ok_or_else(|| invalid_data("non-UTF-8 member path"))?.to_string();
+        members.push((name,entry.raw_file_position(),entry.size()));
+    }
+    Ok(members)
+}
+
+impl NCDValueSource for TarSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let (members,mut file) = match tar_members(&self.path).and_then(|m| Ok((m,File::open(&self.path)?))) {
+            Ok(x) => x,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        Box::new(members.into_iter().map(move |(name,start,len)| {
+            let mut value = vec![0;len as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(&mut value)?;
+            Ok((name.into_bytes(),value))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use super::TarSource;
+
+    #[test]
+    fn test_tar() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.tar");
+        let mut tar = tar::Builder::new(std::fs::File::create(&path).unwrap());
+        for (name,data) in &[("docs/a.txt",&b"hello"[..]),("b.bin",&b"\x00\x01"[..])] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            tar.append_data(&mut header,name,*data).unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_cksum();
+        tar.append_data(&mut header,"docs/",&b""[..]).unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+        let source = TarSource::new(&path).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"docs/a.txt".to_vec(),b"hello".to_vec()),(b"b.bin".to_vec(),b"\x00\x01".to_vec())],out);
+    }
+}
@@ -0,0 +1,92 @@
+use std::{io, sync::Arc};
+
+use ncd::NCDValueSource;
+
+use super::{counting::Tally, SourceIter};
+
+/// Given a record's key and value, the replacement for one of them, or `None` to drop the record.
+pub type Transform = Box<dyn Fn(&[u8],&[u8]) -> io::Result<Option<Vec<u8>>>>;
+
+/// Wraps a source, passing each record through key and value transforms in the order they were
+/// added. Value transforms see the already-transformed key.
+pub struct TransformSource {
+    inner: Box<dyn NCDValueSource>,
+    key: Vec<Transform>,
+    value: Vec<Transform>,
+    tally: Option<Arc<Tally>>
+}
+
+impl TransformSource {
+    pub fn new(inner: Box<dyn NCDValueSource>) -> TransformSource {
+        TransformSource { inner, key: vec![], value: vec![], tally: None }
+    }
+
+    pub fn map_key(mut self, transform: Transform) -> TransformSource {
+        self.key.push(transform);
+        self
+    }
+
+    pub fn map_value(mut self, transform: Transform) -> TransformSource {
+        self.value.push(transform);
+        self
+    }
+
+    /// Count dropped records in `tally`.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> TransformSource {
+        self.tally = Some(tally.clone());
+        self
+    }
+
+    fn apply(&self, mut key: Vec<u8>, mut value: Vec<u8>) -> io::Result<Option<(Vec<u8>,Vec<u8>)>> {
+        for transform in &self.key {
+            match transform(&key,&value)? {
+                Some(k) => { key = k; },
+                None => { return Ok(None); }
+            }
+        }
+        for transform in &self.value {
+            match transform(&key,&value)? {
+                Some(v) => { value = v; },
+                None => { return Ok(None); }
+            }
+        }
+        Ok(Some((key,value)))
+    }
+}
+
+impl NCDValueSource for TransformSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        Box::new(self.inner.iter().filter_map(move |entry| {
+            let (key,value) = match entry { Ok(e) => e, Err(e) => { return Some(Err(e)); } };
+            let out = self.apply(key,value).transpose();
+            if out.is_none() {
+                if let Some(tally) = &self.tally { tally.add_dropped(); }
+            }
+            out
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use crate::sources::{counting::Tally, memory::MemorySource};
+    use super::TransformSource;
+
+    #[test]
+    fn test_transform() {
+        let tally = Tally::new();
+        let source = TransformSource::new(Box::new(MemorySource::new(vec![
+            (b"a".to_vec(),b"1".to_vec()),
+            (b"skip".to_vec(),b"2".to_vec()),
+            (b"c".to_vec(),b"3".to_vec())
+        ])))
+            .map_key(Box::new(|k,_| Ok(if k == b"skip" { None } else { Some(k.to_ascii_uppercase()) })))
+            .map_value(Box::new(|k,v| Ok(Some([k,b"=",v].concat()))))
+            .tally(&tally);
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"A".to_vec(),b"A=1".to_vec()),(b"C".to_vec(),b"C=3".to_vec())],out);
+        assert_eq!(1,tally.dropped());
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use ncd::NCDValueSource;
+
+use super::{counting::Tally, invalid_data, SourceIter};
+
+/// Checks a record's value, saying what is wrong with it if it fails.
+pub type Validator = Box<dyn Fn(&[u8]) -> Result<(),String>>;
+
+/// What to do with a record whose value fails a validator.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum InvalidPolicy {
+    /// Fail the build, naming the record.
+    Error,
+    /// Leave it out, counted as dropped.
+    Skip
+}
+
+/// Values must each be a complete JSON document.
+pub fn json_validator() -> Validator {
+    Box::new(|value| serde_json::from_slice::<serde_json::Value>(value).map(|_| ()).map_err(|e| e.to_string()))
+}
+
+/// Values must be valid UTF-8.
+pub fn utf8_validator() -> Validator {
+    Box::new(|value| std::str::from_utf8(value).map(|_| ()).map_err(|e| e.to_string()))
+}
+
+/// Wraps a source, passing every value through each named validator in the order they were
+/// added and applying an `InvalidPolicy` to those which fail.
+pub struct ValidateSource {
+    inner: Box<dyn NCDValueSource>,
+    validators: Vec<(String,Validator)>,
+    policy: InvalidPolicy,
+    tally: Option<Arc<Tally>>
+}
+
+impl ValidateSource {
+    pub fn new(inner: Box<dyn NCDValueSource>, policy: InvalidPolicy) -> ValidateSource {
+        ValidateSource { inner, validators: vec![], policy, tally: None }
+    }
+
+    pub fn validator(mut self, name: &str, validator: Validator) -> ValidateSource {
+        self.validators.push((name.to_string(),validator));
+        self
+    }
+
+    /// Count skipped records in `tally`.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> ValidateSource {
+        self.tally = Some(tally.clone());
+        self
+    }
+
+    fn failure(&self, value: &[u8]) -> Option<(&str,String)> {
+        self.validators.iter().find_map(|(name,validator)| validator(value).err().map(|e| (name.as_str(),e)))
+    }
+}
+
+impl NCDValueSource for ValidateSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let mut record = 0;
+        Box::new(self.inner.iter().filter_map(move |entry| {
+            let (key,value) = match entry { Ok(e) => e, Err(e) => { return Some(Err(e)); } };
+            record += 1;
+            let (name,error) = match self.failure(&value) {
+                Some(failure) => failure,
+                None => { return Some(Ok((key,value))); }
+            };
+            match self.policy {
+                InvalidPolicy::Skip => {
+                    if let Some(tally) = &self.tally { tally.add_dropped(); }
+                    None
+                },
+                InvalidPolicy::Error => {
+                    Some(Err(invalid_data(format!("record {}: value for {} failed {} validation: {}",
+                        record,String::from_utf8_lossy(&key),name,error))))
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDValueSource;
+
+    use crate::sources::{counting::Tally, memory::MemorySource};
+    use super::{json_validator, utf8_validator, InvalidPolicy, ValidateSource};
+
+    fn source(policy: InvalidPolicy) -> ValidateSource {
+        let entries = vec![
+            (b"a".to_vec(),b"{\"x\":1}".to_vec()),
+            (b"b".to_vec(),b"{\"x\":".to_vec()),
+            (b"c".to_vec(),b"\"\xFF\"".to_vec()),
+            (b"d".to_vec(),b"[]".to_vec())
+        ];
+        ValidateSource::new(Box::new(MemorySource::new(entries)),policy)
+            .validator("UTF-8",utf8_validator())
+            .validator("JSON",json_validator())
+    }
+
+    #[test]
+    fn test_validate() {
+        let tally = Tally::new();
+        let out : Vec<_> = source(InvalidPolicy::Skip).tally(&tally).iter().map(|e| e.unwrap().0).collect();
+        assert_eq!(vec![b"a".to_vec(),b"d".to_vec()],out);
+        assert_eq!(2,tally.dropped());
+        let out : Result<Vec<_>,_> = source(InvalidPolicy::Error).iter().collect();
+        assert!(out.unwrap_err().to_string().starts_with("record 2: value for b failed JSON validation: "));
+        let custom = ValidateSource::new(Box::new(MemorySource::new(vec![(b"k".to_vec(),b"".to_vec())])),InvalidPolicy::Error)
+            .validator("non-empty",Box::new(|v| if v.is_empty() { Err("empty".to_string()) } else { Ok(()) }));
+        let out : Result<Vec<_>,_> = custom.iter().collect();
+        assert_eq!("record 1: value for k failed non-empty validation: empty",out.unwrap_err().to_string());
+    }
+}
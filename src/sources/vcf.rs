@@ -0,0 +1,113 @@
+use std::{fs::File, io::{self, BufRead, BufReader}, path::{Path, PathBuf}, sync::Arc};
+
+use ncd::NCDValueSource;
+
+use super::{check_exists, counting::{report_tolerated, Tally}, invalid_data, SourceIter};
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum VcfKey {
+    /// The ID column, one entry per `;`-separated ID, falling back to `Locus` for `.`.
+    Id,
+    /// `chrom:pos:ref:alt`.
+    Locus
+}
+
+/// Each data line is stored whole, keyed as chosen by `key`. `#` header lines are skipped. With
+/// a `tally`, falling back to the locus for a line without an ID is reported to it.
+#[derive(Clone,Debug)]
+pub struct VcfConfig {
+    key: VcfKey,
+    tally: Option<Arc<Tally>>
+}
+
+impl VcfConfig {
+    pub fn new() -> VcfConfig {
+        VcfConfig { key: VcfKey::Id, tally: None }
+    }
+
+    /// Report lines keyed by locus for want of an ID to `tally`.
+    pub fn tally(mut self, tally: &Arc<Tally>) -> VcfConfig {
+        self.tally = Some(tally.clone());
+        self
+    }
+}
+
+chain!(key,get_key,VcfKey,VcfConfig);
+
+pub struct VcfSource {
+    path: PathBuf,
+    config: VcfConfig
+}
+
+impl VcfSource {
+    pub fn new(path: &Path, config: &VcfConfig) -> io::Result<VcfSource> {
+        check_exists(path)?;
+        Ok(VcfSource { path: path.to_path_buf(), config: config.clone() })
+    }
+}
+
+fn vcf_keys(line: &str, number: usize, key: VcfKey, tally: &Option<Arc<Tally>>) -> io::Result<Vec<String>> {
+    let columns : Vec<&str> = line.splitn(6,'\t').collect();
+    if columns.len() < 5 {
+        return Err(invalid_data(format!("line {}: expected at least 5 tab-separated columns",number)));
+    }
+    let locus = format!("{}:{}:{}:{}",columns[0],columns[1],columns[3],columns[4]);
+    if key == VcfKey::Id && columns[2] == "." {
+        report_tolerated(tally,format!("line {}: no ID, keyed by locus {}",number,locus))?;
+    }
+    Ok(match key {
+        VcfKey::Id if columns[2] != "." => columns[2].split(';').map(|s| s.to_string()).collect(),
+        _ => vec![locus]
+    })
+}
+
+impl NCDValueSource for VcfSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        let key = self.config.key;
+        let tally = &self.config.tally;
+        Box::new(BufReader::new(file).lines().enumerate().flat_map(move |(i,line)| {
+            let line = match line { Ok(l) => l, Err(e) => { return vec![Err(e)]; } };
+            let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+            if line.starts_with('#') || line.is_empty() { return vec![]; }
+            match vcf_keys(line,i+1,key,tally) {
+                Ok(keys) => keys.into_iter().map(|k| Ok((k.into_bytes(),line.as_bytes().to_vec()))).collect(),
+                Err(e) => vec![Err(e)]
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use ncd::NCDValueSource;
+
+    use crate::sources::{counting::Tally, fixture};
+    use super::{VcfConfig, VcfKey, VcfSource};
+
+    fn keys(data: &str, config: &VcfConfig) -> Result<Vec<String>,String> {
+        fixture::parse(data.as_bytes(),|path| VcfSource::new(path,config)).map(|records| records.into_iter().map(|(k,_)| k).collect())
+    }
+
+    #[test]
+    fn test_vcf() {
+        let data = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\n13\t32315474\trs1;rs2\tG\tA\t.\n17\t7676154\t.\tG\tC\t50\n";
+        assert_eq!(Ok(vec!["rs1".to_string(),"rs2".to_string(),"17:7676154:G:C".to_string()]),keys(data,&VcfConfig::new()));
+        assert_eq!(Ok(vec!["13:32315474:G:A".to_string(),"17:7676154:G:C".to_string()]),keys(data,&VcfConfig::new().key(VcfKey::Locus)));
+        assert_eq!(Err("line 1: expected at least 5 tab-separated columns".to_string()),keys("13 1 . G A\n",&VcfConfig::new()));
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+        let source = VcfSource::new(file.path(),&VcfConfig::new()).unwrap();
+        let (_,value) = source.iter().last().unwrap().unwrap();
+        assert_eq!(b"17\t7676154\t.\tG\tC\t50".to_vec(),value);
+        let tally = Tally::new();
+        tally.set_strict(true);
+        assert_eq!(Err("strict: line 4: no ID, keyed by locus 17:7676154:G:C".to_string()),keys(data,&VcfConfig::new().tally(&tally)));
+        assert!(keys(data,&VcfConfig::new().key(VcfKey::Locus).tally(&tally)).is_ok());
+    }
+}
@@ -0,0 +1,277 @@
+use std::{fs::File, io::{self, BufReader}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+use quick_xml::{escape::unescape, events::{BytesStart, Event}, Reader, Writer};
+
+use super::{check_exists, invalid_data, SourceIter};
+
+/// Where in a record element its key is: `@accession` (an attribute of the record), `name`
+/// (the text of the first `name` child), `a/b/@c` (an attribute further down) or `.` (the
+/// record's own text). Elements match on their local name, ignoring any namespace prefix.
+#[derive(Clone,Debug,PartialEq)]
+pub struct KeyPath {
+    elements: Vec<Vec<u8>>,
+    attribute: Option<String>
+}
+
+impl KeyPath {
+    pub fn parse(s: &str) -> Result<KeyPath,String> {
+        let path = s.trim();
+        let path = path.strip_prefix("./").unwrap_or(path);
+        let mut out = KeyPath { elements: vec![], attribute: None };
+        if path == "." || path.is_empty() { return Ok(out); }
+        let segments : Vec<_> = path.split('/').collect();
+        for (i,segment) in segments.iter().enumerate() {
+            if segment.is_empty() || *segment == "@" {
+                return Err(format!("Invalid key path: {}",s));
+            } else if let Some(name) = segment.strip_prefix('@') {
+                if i != segments.len()-1 {
+                    return Err(format!("Invalid key path (attribute must come last): {}",s));
+                }
+                out.attribute = Some(name.to_string());
+            } else {
+                out.elements.push(segment.as_bytes().to_vec());
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Repeated `record` elements anywhere in an XML file (matched on local name, and not looked
+/// for inside each other), each stored as its serialized XML under the key found at `key`.
+/// Namespace declarations on ancestors of the records are not copied into the values.
+#[derive(Clone,Debug)]
+pub struct XmlConfig {
+    record: String,
+    key: KeyPath
+}
+
+impl XmlConfig {
+    pub fn new() -> XmlConfig {
+        XmlConfig {
+            record: "entry".to_string(),
+            key: KeyPath { elements: vec![], attribute: Some("id".to_string()) }
+        }
+    }
+}
+
+chain!(record,get_record,String,XmlConfig);
+chain!(key,get_key,KeyPath,XmlConfig);
+
+pub struct XmlSource {
+    path: PathBuf,
+    config: XmlConfig
+}
+
+impl XmlSource {
+    pub fn new(path: &Path, config: &XmlConfig) -> io::Result<XmlSource> {
+        check_exists(path)?;
+        Ok(XmlSource { path: path.to_path_buf(), config: config.clone() })
+    }
+}
+
+fn xml_error<E: std::fmt::Display>(e: E) -> io::Error {
+    invalid_data(e.to_string())
+}
+
+fn xml_text(bytes: &[u8]) -> io::Result<String> {
+    let text = std::str::from_utf8(bytes).map_err(xml_error)?;
+    Ok(unescape(text).map_err(xml_error)?.to_string())
+}
+
+fn attribute(start: &BytesStart, name: &str) -> io::Result<Option<String>> {
+    for attr in start.attributes() {
+        let attr = attr.map_err(xml_error)?;
+        if attr.key.as_ref() == name.as_bytes() {
+            return Ok(Some(xml_text(&attr.value)?));
+        }
+    }
+    Ok(None)
+}
+
+/* finds the key while a record streams past, from the stack of element names inside it */
+struct KeyFinder<'a> {
+    path: &'a KeyPath,
+    key: Option<String>,
+    text: Option<String>
+}
+
+impl<'a> KeyFinder<'a> {
+    fn start(&mut self, stack: &[Vec<u8>], start: &BytesStart) -> io::Result<()> {
+        if self.key.is_some() || self.text.is_some() || stack != &self.path.elements[..] { return Ok(()); }
+        match &self.path.attribute {
+            Some(name) => { self.key = attribute(start,name)?; },
+            None => { self.text = Some(String::new()); }
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, stack: &[Vec<u8>], text: &str) {
+        if let Some(out) = self.text.as_mut() {
+            if stack == &self.path.elements[..] { out.push_str(text); }
+        }
+    }
+
+    fn end(&mut self, stack: &[Vec<u8>]) {
+        if stack == &self.path.elements[..] {
+            if let Some(text) = self.text.take() {
+                self.key = Some(text.trim().to_string());
+            }
+        }
+    }
+}
+
+struct XmlIterator<'a> {
+    reader: Reader<BufReader<File>>,
+    buf: Vec<u8>,
+    records: usize,
+    config: &'a XmlConfig
+}
+
+impl<'a> XmlIterator<'a> {
+    /* skips to the next record and reads it */
+    fn find_record(&mut self) -> io::Result<Option<(Writer<Vec<u8>>,KeyFinder<'a>)>> {
+        let config : &'a XmlConfig = self.config;
+        loop {
+            self.buf.clear();
+            let event = self.reader.read_event_into(&mut self.buf).map_err(xml_error)?;
+            let (start,empty) = match &event {
+                Event::Start(start) => (start,false),
+                Event::Empty(start) => (start,true),
+                Event::Eof => { return Ok(None); },
+                _ => { continue; }
+            };
+            if start.local_name().as_ref() != config.record.as_bytes() { continue; }
+            let mut finder = KeyFinder { path: &config.key, key: None, text: None };
+            finder.start(&[],start)?;
+            if empty { finder.end(&[]); }
+            let mut writer = Writer::new(vec![]);
+            writer.write_event(event).map_err(xml_error)?;
+            if empty {
+                return Ok(Some((writer,finder)));
+            }
+            self.read_record(&mut writer,&mut finder)?;
+            return Ok(Some((writer,finder)));
+        }
+    }
+
+    fn read_record(&mut self, writer: &mut Writer<Vec<u8>>, finder: &mut KeyFinder) -> io::Result<()> {
+        let mut stack : Vec<Vec<u8>> = vec![];
+        loop {
+            self.buf.clear();
+            let event = self.reader.read_event_into(&mut self.buf).map_err(xml_error)?;
+            let mut finished = false;
+            match &event {
+                Event::Start(start) => {
+                    stack.push(start.local_name().as_ref().to_vec());
+                    finder.start(&stack,start)?;
+                },
+                Event::Empty(start) => {
+                    stack.push(start.local_name().as_ref().to_vec());
+                    finder.start(&stack,start)?;
+                    finder.end(&stack);
+                    stack.pop();
+                },
+                Event::Text(text) => { finder.text(&stack,&xml_text(text)?); },
+                Event::CData(text) => { finder.text(&stack,&String::from_utf8_lossy(text)); },
+                Event::End(_) => {
+                    finder.end(&stack);
+                    finished = stack.pop().is_none();
+                },
+                Event::Eof => {
+                    return Err(invalid_data(format!("unexpected end of file inside <{}>",self.config.record)));
+                },
+                _ => {}
+            }
+            writer.write_event(event).map_err(xml_error)?;
+            if finished { return Ok(()); }
+        }
+    }
+
+    fn record(&mut self) -> io::Result<Option<(Vec<u8>,Vec<u8>)>> {
+        let (writer,finder) = match self.find_record()? {
+            Some(record) => record,
+            None => { return Ok(None); }
+        };
+        self.records += 1;
+        let key = finder.key.ok_or_else(|| {
+            invalid_data(format!("record {}: no key in <{}>",self.records,self.config.record))
+        })?;
+        Ok(Some((key.into_bytes(),writer.into_inner())))
+    }
+}
+
+impl<'a> Iterator for XmlIterator<'a> {
+    type Item = io::Result<(Vec<u8>,Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.record().transpose()
+    }
+}
+
+impl NCDValueSource for XmlSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        match File::open(&self.path) {
+            Ok(file) => Box::new(XmlIterator {
+                reader: Reader::from_reader(BufReader::new(file)),
+                buf: vec![],
+                records: 0,
+                config: &self.config
+            }),
+            Err(e) => Box::new(std::iter::once(Err(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use ncd::NCDValueSource;
+
+    use crate::sources::fixture;
+    use super::{KeyPath, XmlConfig, XmlSource};
+
+    const DATA : &str = r#"<?xml version="1.0"?>
+<uniprot xmlns="http://uniprot.org/uniprot">
+<entry dataset="Swiss-Prot"><accession>P04637</accession><name>P53_HUMAN</name><gene><name type="primary">TP53</name></gene></entry>
+<entry dataset="TrEMBL"><accession><![CDATA[Q00]]>001 &amp; more</accession></entry>
+<entry dataset="Empty"/>
+</uniprot>"#;
+
+    fn parse(record: &str, key: &str) -> Result<Vec<(String,String)>,String> {
+        let config = XmlConfig::new().record(record.to_string()).key(KeyPath::parse(key).unwrap());
+        fixture::parse(DATA.as_bytes(),|path| XmlSource::new(path,&config))
+    }
+
+    fn keys(record: &str, key: &str) -> Result<Vec<String>,String> {
+        parse(record,key).map(|v| v.into_iter().map(|(k,_)| k).collect())
+    }
+
+    #[test]
+    fn test_key_path() {
+        assert_eq!(KeyPath { elements: vec![], attribute: Some("id".to_string()) },KeyPath::parse("@id").unwrap());
+        assert_eq!(KeyPath { elements: vec![b"a".to_vec(),b"b".to_vec()], attribute: None },KeyPath::parse("./a/b").unwrap());
+        assert_eq!(KeyPath { elements: vec![], attribute: None },KeyPath::parse(".").unwrap());
+        assert!(KeyPath::parse("@a/b").is_err());
+        assert!(KeyPath::parse("a//b").is_err());
+    }
+
+    #[test]
+    fn test_xml() {
+        let out = parse("entry","@dataset").unwrap();
+        assert_eq!(3,out.len());
+        assert_eq!(("Swiss-Prot".to_string(),r#"<entry dataset="Swiss-Prot"><accession>P04637</accession><name>P53_HUMAN</name><gene><name type="primary">TP53</name></gene></entry>"#.to_string()),out[0]);
+        assert_eq!(r#"<entry dataset="Empty"/>"#,out[2].1);
+        assert_eq!(Ok(vec!["P53_HUMAN".to_string(),"TP53".to_string()]),keys("name","."));
+        assert_eq!(Ok(vec!["primary".to_string()]),keys("gene","name/@type"));
+        assert_eq!(Err("record 3: no key in <entry>".to_string()),keys("entry","accession"));
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(DATA.as_bytes()).unwrap();
+        let config = XmlConfig::new().key(KeyPath::parse("accession").unwrap());
+        let source = XmlSource::new(file.path(),&config).unwrap();
+        let found : Vec<_> = source.iter().take(2).map(|e| String::from_utf8(e.unwrap().0).unwrap()).collect();
+        assert_eq!(vec!["P04637".to_string(),"Q00001 & more".to_string()],found);
+        assert_eq!(Ok(vec!["TP53".to_string()]),keys("gene","name"));
+    }
+}
@@ -0,0 +1,105 @@
+use std::{fs::File, io::{self, BufReader}, path::Path};
+
+use ncd::NCDValueSource;
+use serde_yaml::{Mapping, Value};
+
+use super::{invalid_data, memory::MemorySource, SourceIter};
+
+/// How sequences and mappings are stored.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum YamlNested {
+    /// As compact JSON.
+    Json,
+    /// As YAML, without the trailing newline.
+    Yaml
+}
+
+/// Scalar values are stored as their text (null as empty), nested values as chosen by `nested`.
+#[derive(Clone,Debug)]
+pub struct YamlConfig {
+    nested: YamlNested
+}
+
+impl YamlConfig {
+    pub fn new() -> YamlConfig {
+        YamlConfig { nested: YamlNested::Json }
+    }
+}
+
+chain!(nested,get_nested,YamlNested,YamlConfig);
+
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Null => Some(String::new()),
+        _ => None
+    }
+}
+
+fn yaml_value(value: Value, nested: YamlNested) -> io::Result<Vec<u8>> {
+    if let Some(text) = scalar_text(&value) {
+        return Ok(text.into_bytes());
+    }
+    match nested {
+        YamlNested::Json => serde_json::to_vec(&value).map_err(invalid_data),
+        YamlNested::Yaml => {
+            let text = serde_yaml::to_string(&value).map_err(invalid_data)?;
+            Ok(text.trim_end_matches('\n').as_bytes().to_vec())
+        }
+    }
+}
+
+/// Source over the entries of a YAML file's top-level mapping.
+pub struct YamlSource {
+    entries: MemorySource
+}
+
+impl YamlSource {
+    pub fn new(path: &Path, config: &YamlConfig) -> io::Result<YamlSource> {
+        let mapping : Mapping = serde_yaml::from_reader(BufReader::new(File::open(path)?))
+            .map_err(|e| invalid_data(format!("{}: {}",path.display(),e)))?;
+        let mut entries = vec![];
+        for (key,value) in mapping {
+            let key = match &key {
+                Value::Null => None,
+                k => scalar_text(k)
+            }.ok_or_else(|| invalid_data(format!("{}: keys must be scalars",path.display())))?;
+            entries.push((key.into_bytes(),yaml_value(value,config.nested)?));
+        }
+        Ok(YamlSource { entries: MemorySource::new(entries) })
+    }
+}
+
+impl NCDValueSource for YamlSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sources::fixture;
+    use super::{YamlConfig, YamlNested, YamlSource};
+
+    fn parse(data: &str, config: &YamlConfig) -> Result<Vec<(String,String)>,String> {
+        fixture::parse(data.as_bytes(),|path| YamlSource::new(path,config))
+    }
+
+    #[test]
+    fn test_yaml() {
+        let data = "name: ncd\nport: 8080\ndebug: false\nunset:\nhosts:\n  - a\n  - b\n";
+        assert_eq!(Ok(vec![
+            ("name".to_string(),"ncd".to_string()),
+            ("port".to_string(),"8080".to_string()),
+            ("debug".to_string(),"false".to_string()),
+            ("unset".to_string(),"".to_string()),
+            ("hosts".to_string(),"[\"a\",\"b\"]".to_string())
+        ]),parse(data,&YamlConfig::new()));
+        let out = parse("hosts:\n  - a\n  - b\n",&YamlConfig::new().nested(YamlNested::Yaml)).unwrap();
+        assert_eq!(vec![("hosts".to_string(),"- a\n- b".to_string())],out);
+        assert!(parse("- a\n- b\n",&YamlConfig::new()).is_err());
+        assert!(parse("[a]: 1\n",&YamlConfig::new()).is_err());
+    }
+}
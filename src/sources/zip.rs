@@ -0,0 +1,65 @@
+use std::{fs::File, io::{self, Read}, path::{Path, PathBuf}};
+
+use ncd::NCDValueSource;
+use zip::ZipArchive;
+
+use super::{check_exists, invalid_data, SourceIter};
+
+/// Each file in a zip archive is stored decompressed, keyed by its member path. Members are read
+/// one at a time in central-directory order.
+pub struct ZipSource {
+    path: PathBuf
+}
+
+impl ZipSource {
+    pub fn new(path: &Path) -> io::Result<ZipSource> {
+        check_exists(path)?;
+        Ok(ZipSource { path: path.to_path_buf() })
+    }
+}
+
+fn zip_member(archive: &mut ZipArchive<File>, index: usize) -> io::Result<Option<(Vec<u8>,Vec<u8>)>> {
+    let mut member = archive.by_index(index).map_err(invalid_data)?;
+    if !member.is_file() { return Ok(None); }
+    let name = member.name().as_bytes().to_vec();
+    /* the size is the header's word for it, so grow with what is actually there */
+    let mut value = vec![];
+    member.read_to_end(&mut value)?;
+    Ok(Some((name,value)))
+}
+
+impl NCDValueSource for ZipSource {
+    fn iter<'a>(&'a self) -> SourceIter<'a> {
+        let mut archive = match File::open(&self.path).and_then(|f| ZipArchive::new(f).map_err(invalid_data)) {
+            Ok(a) => a,
+            Err(e) => { return Box::new(std::iter::once(Err(e))); }
+        };
+        Box::new((0..archive.len()).filter_map(move |i| zip_member(&mut archive,i).transpose()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use ncd::NCDValueSource;
+
+    use super::ZipSource;
+
+    #[test]
+    fn test_zip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.add_directory("docs/",options).unwrap();
+        zip.start_file("docs/a.txt",options).unwrap();
+        zip.write_all(b"hello hello hello").unwrap();
+        zip.start_file("b.bin",options.compression_method(zip::CompressionMethod::Stored)).unwrap();
+        zip.write_all(b"\x00\x01").unwrap();
+        zip.finish().unwrap();
+        let source = ZipSource::new(&path).unwrap();
+        let out : Vec<_> = source.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![(b"docs/a.txt".to_vec(),b"hello hello hello".to_vec()),(b"b.bin".to_vec(),b"\x00\x01".to_vec())],out);
+    }
+}
@@ -0,0 +1,9 @@
+/* builder-style setter plus getter for a config field, as used by the configs in ncd */
+macro_rules! chain {
+    ($name:ident,$getter:ident,$type:ty,$struct:ty) => {
+        impl $struct {
+            pub fn $name(mut self, value: $type) -> Self { self.$name = value; self }
+            pub fn $getter(&self) -> &$type { &self.$name }
+        }
+    }
+}
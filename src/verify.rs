@@ -0,0 +1,254 @@
+use std::{collections::HashSet, fs::{self, File}, io, path::Path, time::{SystemTime, UNIX_EPOCH}};
+
+use hmac::{Hmac, Mac};
+use ncd::{NCDReader, StdNCDReadAccessor};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::{shard::{sha256_file, shard_of, to_hex, Manifest, ShardEntry}, sources::invalid_data};
+
+/// What was wrong with one shard of a manifest, if anything.
+pub struct ShardCheck {
+    pub path: String,
+    pub problems: Vec<String>
+}
+
+impl ShardCheck {
+    pub fn ok(&self) -> bool { self.problems.is_empty() }
+}
+
+/// What was wrong with a manifest as a whole, and with each of its shards.
+pub struct ManifestCheck {
+    pub problems: Vec<String>,
+    pub shards: Vec<ShardCheck>
+}
+
+impl ManifestCheck {
+    pub fn ok(&self) -> bool { self.problems.is_empty() && self.shards.iter().all(|s| s.ok()) }
+}
+
+fn check_shard(dir: &Path, shard: &ShardEntry, shard_count: usize) -> Vec<String> {
+    let path = dir.join(&shard.path);
+    let size = match std::fs::metadata(&path) {
+        Ok(m) => m.len(),
+        Err(e) => { return vec![format!("cannot open: {}",e)]; }
+    };
+    let mut problems = vec![];
+    if size != shard.size {
+        problems.push(format!("size is {}, manifest says {}",size,shard.size));
+    }
+    match sha256_file(&path) {
+        Ok(sha256) if sha256 != shard.sha256 => { problems.push(format!("sha256 is {}, manifest says {}",sha256,shard.sha256)); },
+        Ok(_) => {},
+        Err(e) => { problems.push(format!("cannot read: {}",e)); }
+    }
+    let reader = File::open(&path).map_err(|e| e.to_string())
+        .and_then(|file| StdNCDReadAccessor::new(file).map_err(|e| io::Error::from(e).to_string()))
+        .and_then(|accessor| NCDReader::new_box(Box::new(accessor)).map_err(|e| e.to_string()));
+    let mut reader = match reader {
+        Ok(reader) => reader,
+        Err(e) => {
+            problems.push(format!("not a readable ncd file: {}",e));
+            return problems;
+        }
+    };
+    /* the keys it was built with must route here and be found here */
+    for key in &shard.samples {
+        let name = String::from_utf8_lossy(key);
+        let routed = shard_of(key,shard_count);
+        if routed != shard.index {
+            problems.push(format!("sample key {} hashes to shard {}, not {}",name,routed,shard.index));
+        }
+        match reader.get(key) {
+            Ok(Some(_)) => {},
+            Ok(None) => { problems.push(format!("sample key {} is missing",name)); },
+            Err(e) => { problems.push(format!("sample key {} cannot be read: {}",name,e)); }
+        }
+    }
+    problems
+}
+
+/* the same file however the manifest spells it, where it exists to be resolved */
+fn same_file_key(dir: &Path, shard: &ShardEntry) -> String {
+    let path = dir.join(&shard.path);
+    fs::canonicalize(&path).unwrap_or(path).to_string_lossy().to_string()
+}
+
+/// Checks a manifest and every shard listed in it against the files next to it: size, sha256,
+/// that it opens as an ncd file of a version this build understands, and that the manifest's
+/// sample keys for it hash to it and are in it. Shards partition keys by hash, so coverage is
+/// complete and non-overlapping exactly when each index below `shard_count` is listed once,
+/// in order, and no two entries are the same file.
+pub fn verify_manifest(path: &Path) -> io::Result<(Manifest,ManifestCheck)> {
+    let manifest = Manifest::load(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut problems = vec![];
+    if manifest.shards.len() != manifest.shard_count {
+        problems.push(format!("lists {} shards, but keys are spread over {}",manifest.shards.len(),manifest.shard_count));
+    }
+    for index in 0..manifest.shard_count {
+        if !manifest.shards.iter().any(|s| s.index == index) {
+            problems.push(format!("shard {} is missing, so its keys can't be found",index));
+        }
+    }
+    let mut seen_index = HashSet::new();
+    let mut seen_file = HashSet::new();
+    let shards = manifest.shards.iter().enumerate().map(|(position,shard)| {
+        let mut problems = check_shard(dir,shard,manifest.shard_count);
+        if shard.index != position {
+            problems.push(format!("listed at position {} with index {}",position,shard.index));
+        }
+        if shard.index >= manifest.shard_count {
+            problems.push(format!("index {} is beyond the shard count of {}",shard.index,manifest.shard_count));
+        }
+        if !seen_index.insert(shard.index) || !seen_file.insert(same_file_key(dir,shard)) {
+            problems.push("listed more than once, so shards overlap".to_string());
+        }
+        ShardCheck { path: shard.path.clone(), problems }
+    }).collect();
+    Ok((manifest,ManifestCheck { problems, shards }))
+}
+
+/// A record that `manifest_path` was verified, pinning the manifest and every shard by sha256.
+pub fn attestation(manifest_path: &Path, manifest: &Manifest) -> io::Result<Value> {
+    let verified_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    Ok(json!({
+        "manifest": manifest_path.file_name().map(|n| n.to_string_lossy().to_string()),
+        "manifest_sha256": sha256_file(manifest_path)?,
+        "verified_at": verified_at,
+        "shard_count": manifest.shard_count,
+        "shards": manifest.shards.iter().map(|s| json!({ "index": s.index, "path": s.path, "sha256": s.sha256 })).collect::<Vec<_>>()
+    }))
+}
+
+fn push_field(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u64).to_be_bytes());
+    out.extend_from_slice(field);
+}
+
+/// The bytes an attestation's signature covers, independent of how its JSON is laid out: the
+/// tag `ncd-attestation-v1`, then the manifest name, manifest sha256, verified_at and
+/// shard_count, then the index, path and sha256 of each shard in order, each field as a u64
+/// big-endian length then its UTF-8 (numbers in decimal, a missing manifest name empty).
+pub fn canonical_bytes(attestation: &Value) -> io::Result<Vec<u8>> {
+    let bad = || invalid_data("attestation is missing a field");
+    let text = |value: &Value, name: &str| -> io::Result<String> {
+        match value.get(name) {
+            Some(Value::String(s)) => Ok(s.clone()),
+            Some(Value::Number(n)) => Ok(n.to_string()),
+            Some(Value::Null) => Ok(String::new()),
+            _ => Err(bad())
+        }
+    };
+    let mut out = vec![];
+    push_field(&mut out,b"ncd-attestation-v1");
+    for name in &["manifest","manifest_sha256","verified_at","shard_count"] {
+        push_field(&mut out,text(attestation,name)?.as_bytes());
+    }
+    for shard in attestation.get("shards").and_then(|s| s.as_array()).ok_or_else(bad)? {
+        for name in &["index","path","sha256"] {
+            push_field(&mut out,text(shard,name)?.as_bytes());
+        }
+    }
+    Ok(out)
+}
+
+/// Adds an HMAC-SHA256 `signature` over the `canonical_bytes` of the attestation.
+pub fn sign(attestation: &mut Value, key: &[u8]) -> io::Result<()> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).map_err(|e| invalid_data(e.to_string()))?;
+    mac.update(&canonical_bytes(attestation)?);
+    let signature = to_hex(&mac.finalize().into_bytes());
+    attestation.as_object_mut().ok_or_else(|| invalid_data("attestation is not an object"))?
+        .insert("signature".to_string(),Value::String(signature));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use ncd::NCDBuildConfig;
+    use serde_json::Value;
+
+    use crate::{shard::{manifest_path, sha256_file, shard_of, shard_path, Manifest, ShardEntry}, writer::NCDWriter};
+    use super::{attestation, canonical_bytes, sign, verify_manifest};
+
+    /* a key for each shard of two */
+    fn key_for(index: usize) -> Vec<u8> {
+        (0..).map(|i| format!("k{}",i).into_bytes()).find(|k| shard_of(k,2) == index).unwrap()
+    }
+
+    fn build(output: &std::path::Path) -> Manifest {
+        let mut shards = vec![];
+        for index in 0..2 {
+            let path = shard_path(output,index);
+            let key = key_for(index);
+            let mut writer = NCDWriter::create(&path,NCDBuildConfig::new()).unwrap();
+            writer.insert(&key,b"v").unwrap();
+            writer.finish().unwrap();
+            let size = std::fs::metadata(&path).unwrap().len();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            shards.push(ShardEntry { index, path: name, size, records: 1, sha256: sha256_file(&path).unwrap(), samples: vec![key] });
+        }
+        Manifest { shard_count: 2, shards }
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.ncd");
+        let manifest = build(&output);
+        let path = manifest_path(&output);
+        manifest.write(&path).unwrap();
+        let (_,check) = verify_manifest(&path).unwrap();
+        assert!(check.ok());
+        let mut record = attestation(&path,&manifest).unwrap();
+        sign(&mut record,b"secret").unwrap();
+        assert_eq!(64,record["signature"].as_str().unwrap().len());
+        std::fs::write(shard_path(&output,1),b"corrupt").unwrap();
+        let (_,check) = verify_manifest(&path).unwrap();
+        assert!(check.problems.is_empty());
+        assert!(check.shards[0].ok());
+        assert_eq!(3,check.shards[1].problems.len());
+    }
+
+    #[test]
+    fn test_lost_shard() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.ncd");
+        let mut manifest = build(&output);
+        let path = manifest_path(&output);
+        manifest.shards.remove(0);
+        manifest.write(&path).unwrap();
+        let (_,check) = verify_manifest(&path).unwrap();
+        assert!(!check.ok());
+        assert_eq!(2,check.problems.len());
+        assert!(check.shards[0].problems.iter().any(|p| p.contains("position 0 with index 1")));
+        /* shards renumbered into each other's places: their sample keys no longer route to them */
+        let mut manifest = build(&output);
+        manifest.shards.swap(0,1);
+        manifest.shards[0].index = 0;
+        manifest.shards[1].index = 1;
+        manifest.write(&path).unwrap();
+        let (_,check) = verify_manifest(&path).unwrap();
+        assert!(check.problems.is_empty());
+        assert!(check.shards.iter().all(|s| s.problems.iter().any(|p| p.contains("hashes to shard"))));
+        /* the same file under two names overlaps */
+        let mut manifest = build(&output);
+        manifest.shards[1].path = format!("./{}",manifest.shards[0].path);
+        manifest.write(&path).unwrap();
+        let (_,check) = verify_manifest(&path).unwrap();
+        assert!(check.shards[1].problems.iter().any(|p| p.contains("more than once")));
+    }
+
+    #[test]
+    fn test_canonical_bytes() {
+        let a : Value = serde_json::from_str(r#"{"manifest":"m","manifest_sha256":"00","verified_at":5,"shard_count":1,"shards":[{"index":0,"path":"p","sha256":"11"}]}"#).unwrap();
+        let b : Value = serde_json::from_str(r#"{"shards":[{"sha256":"11","path":"p","index":0}],"shard_count":1,"verified_at":5,"manifest_sha256":"00","manifest":"m"}"#).unwrap();
+        assert_eq!(canonical_bytes(&a).unwrap(),canonical_bytes(&b).unwrap());
+        let (mut a,mut b) = (a,b);
+        sign(&mut a,b"secret").unwrap();
+        sign(&mut b,b"secret").unwrap();
+        assert_eq!(a["signature"],b["signature"]);
+        b["shards"][0]["path"] = Value::String("q".to_string());
+        assert_ne!(canonical_bytes(&a).unwrap(),canonical_bytes(&b).unwrap());
+    }
+}
@@ -0,0 +1,168 @@
+use std::{fmt, process};
+
+use clap::{Arg, ArgMatches};
+use serde_json::{json, Value};
+
+/// The `-t` formats ncd-build reads, and so its possible values besides `guess`.
+pub const INPUT_FORMATS : &[&str] = &[
+    "flat", "csv", "json", "jsonl", "kvjson", "fasta", "vcf", "gff", "dir", "tar", "zip", "msgpack", "cbor",
+    "parquet", "avro", "yaml", "properties", "cdb", "bdb", "lmdb", "redis", "sql", "rdb", "sst",
+    "http-json", "fixed", "blocks", "obo", "xml", "protobuf", "arrow", "ncd-dump"
+];
+
+/// The `-t` formats which need a cargo feature, and which feature.
+pub const FORMAT_FEATURES : &[(&str,&str)] = &[
+    ("parquet","parquet"), ("avro","avro"), ("lmdb","lmdb"), ("redis","redis"), ("sql","sql"), ("arrow","arrow")
+];
+
+/// Versioned formats of this crate's own, with the versions read and written. ncd-dump files are
+/// written by the library's `DumpWriter` rather than by any of the tools.
+pub const OWN_FORMATS : &[(&str,&[u32],&[u32])] = &[
+    ("ncd-dump",&[1],&[1]),
+    ("shard-manifest",&[1],&[1])
+];
+
+/// Compressed input which is decompressed on the way in.
+pub const COMPRESSION : &[&str] = &["gzip", "zstd", "xz"];
+
+/// What a build of ncd-tools can do, for `--version` and bug reports.
+#[derive(Clone,Debug,PartialEq)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    pub git_hash: Option<&'static str>,
+    /// The ncd crate which reads and writes the files themselves.
+    pub ncd_version: Option<&'static str>,
+    pub features: Vec<&'static str>,
+    /// libcurl and its TLS library, for remote lookups and http-json.
+    pub curl: String
+}
+
+/// The optional cargo features this build has, including the input formats behind them.
+pub fn features() -> Vec<&'static str> {
+    let mut features = vec![];
+    if cfg!(feature="arrow") { features.push("arrow"); }
+    if cfg!(feature="avro") { features.push("avro"); }
+    if cfg!(feature="expr") { features.push("expr"); }
+    if cfg!(feature="lmdb") { features.push("lmdb"); }
+    if cfg!(feature="parquet") { features.push("parquet"); }
+    if cfg!(feature="redis") { features.push("redis"); }
+    if cfg!(feature="sql") { features.push("sql"); }
+    if cfg!(feature="trace") { features.push("trace"); }
+    features
+}
+
+/// The `-t` formats this build can read.
+pub fn input_formats() -> Vec<&'static str> {
+    let features = features();
+    INPUT_FORMATS.iter().filter(|format| {
+        FORMAT_FEATURES.iter().all(|(name,feature)| name != *format || features.contains(feature))
+    }).cloned().collect()
+}
+
+pub fn version_info() -> VersionInfo {
+    let curl = curl::Version::get();
+    let curl = match curl.ssl_version() {
+        Some(ssl) => format!("libcurl {} ({})",curl.version(),ssl),
+        None => format!("libcurl {}",curl.version())
+    };
+    VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: option_env!("NCD_TOOLS_GIT_HASH"),
+        ncd_version: option_env!("NCD_VERSION"),
+        features: features(),
+        curl
+    }
+}
+
+fn versions(versions: &[u32]) -> String {
+    versions.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn own_formats(write: bool) -> Value {
+    Value::Object(OWN_FORMATS.iter().map(|(name,read,written)| {
+        (name.to_string(),json!(if write { *written } else { *read }))
+    }).collect())
+}
+
+impl VersionInfo {
+    pub fn to_json(&self, program: &str) -> Value {
+        json!({
+            "program": program,
+            "version": self.version,
+            "git": self.git_hash,
+            "ncd": self.ncd_version,
+            "read": { "inputs": input_formats(), "formats": own_formats(false) },
+            "write": { "formats": own_formats(true) },
+            "compression": COMPRESSION,
+            "features": self.features,
+            "curl": self.curl
+        })
+    }
+}
+
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,"{}",self.version)?;
+        if let Some(hash) = self.git_hash { write!(f," ({})",hash)?; }
+        writeln!(f)?;
+        writeln!(f,"ncd: {}",self.ncd_version.unwrap_or("unknown"))?;
+        writeln!(f,"inputs: {}",input_formats().join(", "))?;
+        for (name,read,write) in OWN_FORMATS {
+            writeln!(f,"{}: reads version {}, writes version {}",name,versions(read),versions(write))?;
+        }
+        writeln!(f,"compression: {}",COMPRESSION.join(", "))?;
+        let features = if self.features.is_empty() { "none".to_string() } else { self.features.join(", ") };
+        writeln!(f,"features: {}",features)?;
+        write!(f,"curl: {}",self.curl)
+    }
+}
+
+/// `-V`/`--version` and `--json`, to stand in for clap's own version flag (so the app wants
+/// `AppSettings::DisableVersion`). Arguments which are otherwise required must be
+/// `required_unless("version")`.
+pub fn version_args() -> Vec<Arg<'static,'static>> {
+    vec![
+        Arg::with_name("version")
+            .short("-V")
+            .long("--version")
+            .help("print the version and what this build can read and write, then exit"),
+        Arg::with_name("json")
+            .long("--json")
+            .requires("version")
+            .help("print the version as json")
+    ]
+}
+
+/// Answers `--version`, with `--json` for the machine-readable form, and exits.
+pub fn exit_if_version(program: &str, matches: &ArgMatches) {
+    if !matches.is_present("version") { return; }
+    let info = version_info();
+    if matches.is_present("json") {
+        println!("{}",info.to_json(program));
+    } else {
+        println!("{} {}",program,info);
+    }
+    process::exit(0);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{features, input_formats, version_info, INPUT_FORMATS};
+
+    #[test]
+    fn test_version_info() {
+        let info = version_info();
+        assert_eq!(env!("CARGO_PKG_VERSION"),info.version);
+        let json = info.to_json("ncd-build");
+        assert_eq!("ncd-build",json["program"]);
+        assert_eq!(input_formats().len(),json["read"]["inputs"].as_array().unwrap().len());
+        assert_eq!(cfg!(feature="parquet"),input_formats().contains(&"parquet"));
+        assert_eq!(cfg!(feature="sql"),features().contains(&"sql"));
+        assert!(input_formats().contains(&"flat"));
+        assert!(input_formats().len() <= INPUT_FORMATS.len());
+        assert_eq!(1,json["write"]["formats"]["ncd-dump"][0]);
+        let text = info.to_string();
+        assert!(text.starts_with(info.version));
+        assert!(text.contains("ncd-dump: reads version 1, writes version 1"));
+    }
+}
@@ -0,0 +1,82 @@
+use std::{fmt::Display, io, path::{Path, PathBuf}};
+
+use ncd::{NCDBuild, NCDBuildConfig};
+use tempfile::NamedTempFile;
+
+use crate::sources::spool::{SpoolSource, Spooler};
+
+fn build_error<E: Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other,e.to_string())
+}
+
+/// Push-style alternative to implementing `NCDValueSource`. Entries are spooled to a temporary
+/// file as they are inserted and the usual (possibly multi-attempt) build runs on `finish`.
+pub struct NCDWriter {
+    path: PathBuf,
+    config: NCDBuildConfig,
+    spool_file: NamedTempFile,
+    spooler: Spooler
+}
+
+impl NCDWriter {
+    pub fn create(path: &Path, config: NCDBuildConfig) -> io::Result<NCDWriter> {
+        let spool_file = NamedTempFile::new()?;
+        let spooler = Spooler::new(spool_file.reopen()?);
+        Ok(NCDWriter { path: path.to_path_buf(), config, spool_file, spooler })
+    }
+
+    #[cfg_attr(feature="trace",tracing::instrument(level="trace",skip_all))]
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.spooler.add(key,value)
+    }
+
+    #[cfg_attr(feature="trace",tracing::instrument(skip_all,fields(path=%self.path.display())))]
+    pub fn finish(self) -> io::Result<()> {
+        self.spooler.finish()?;
+        let source = SpoolSource::new(self.spool_file.path());
+        let mut builder = NCDBuild::new(&self.config,&source,&self.path).map_err(build_error)?;
+        loop {
+            #[cfg(feature="trace")]
+            let _span = tracing::info_span!("attempt",config=%builder.describe_attempt()).entered();
+            if builder.attempt(|_,_| {}).map_err(build_error)? { break; }
+        }
+        #[cfg(feature="trace")]
+        tracing::info!(result=%builder.result());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use ncd::{NCDBuildConfig, NCDReader, StdNCDReadAccessor};
+
+    use super::NCDWriter;
+
+    #[test]
+    fn test_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.ncd");
+        let mut writer = NCDWriter::create(&path,NCDBuildConfig::new()).unwrap();
+        for i in 0..1000 {
+            writer.insert(format!("key{}",i).as_bytes(),format!("value {}",i).as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+        let accessor = StdNCDReadAccessor::new(File::open(&path).unwrap()).unwrap();
+        let mut reader = NCDReader::new_box(Box::new(accessor)).unwrap();
+        assert_eq!(Some(b"value 17".to_vec()),reader.get(b"key17").unwrap());
+        assert_eq!(None,reader.get(b"key1000").unwrap());
+    }
+
+    #[test]
+    fn test_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.ncd");
+        NCDWriter::create(&path,NCDBuildConfig::new()).unwrap().finish().unwrap();
+        let accessor = StdNCDReadAccessor::new(File::open(&path).unwrap()).unwrap();
+        let mut reader = NCDReader::new_box(Box::new(accessor)).unwrap();
+        assert_eq!(None,reader.get(b"anything").unwrap());
+        assert_eq!(None,reader.get(b"").unwrap());
+    }
+}
@@ -0,0 +1,493 @@
+mod common;
+
+use std::{fs, io::Write, path::{Path, PathBuf}};
+
+use assert_cmd::Command;
+use common::{MockConfig, MockServer};
+
+const CSV : &str = "BRCA2,chr13\nTP53,chr17\nCFTR,chr7\n";
+
+fn write_input(dir: &Path, name: &str, data: &str) -> PathBuf {
+    let path = dir.join(name);
+    fs::write(&path,data).unwrap();
+    path
+}
+
+fn stdout(output: &std::process::Output) -> String { String::from_utf8_lossy(&output.stdout).to_string() }
+fn stderr(output: &std::process::Output) -> String { String::from_utf8_lossy(&output.stderr).to_string() }
+
+/* builds genes.csv into genes.ncd, returning the directory to keep it alive */
+fn build_genes() -> (tempfile::TempDir,PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.csv",CSV);
+    let output = dir.path().join("genes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert!(stdout(&out).contains("Attempting to build"));
+    (dir,output)
+}
+
+fn lookup(key: &str, path: &str, extra: &[&str]) -> std::process::Output {
+    Command::cargo_bin("ncd-lookup").unwrap().arg(key).arg(path).args(extra).output().unwrap()
+}
+
+#[test]
+fn test_build_and_lookup() {
+    let (_dir,path) = build_genes();
+    let path = path.to_string_lossy();
+    for (key,value) in &[("BRCA2","chr13"),("TP53","chr17"),("CFTR","chr7")] {
+        let out = lookup(key,&path,&[]);
+        assert!(out.status.success(),"{}",stderr(&out));
+        assert_eq!(*value,stdout(&out));
+    }
+}
+
+#[test]
+fn test_missing_key() {
+    let (_dir,path) = build_genes();
+    let out = lookup("MISSING",&path.to_string_lossy(),&[]);
+    assert_eq!(Some(1),out.status.code());
+    assert_eq!("",stdout(&out));
+    assert_eq!("",stderr(&out));
+}
+
+/* the ncd file at `path` stored uncompressed in a zip, as genes.ncd */
+fn zip_genes(dir: &Path, path: &Path) -> PathBuf {
+    let zip_path = dir.join("genes.zip");
+    let mut zip = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+    let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    zip.start_file("genes.ncd",options).unwrap();
+    zip.write_all(&fs::read(path).unwrap()).unwrap();
+    zip.finish().unwrap();
+    zip_path
+}
+
+#[test]
+fn test_archive_lookup() {
+    let (dir,path) = build_genes();
+    let zip_path = zip_genes(dir.path(),&path);
+    let out = lookup("TP53",&format!("{}!genes.ncd",zip_path.to_string_lossy()),&[]);
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert_eq!("chr17",stdout(&out));
+    for config in vec![MockConfig::new(),MockConfig::new().ranges(false)] {
+        let server = MockServer::start(&zip_path,config);
+        let out = lookup("TP53",&format!("{}!genes.ncd",server.url()),&["--source","zip"]);
+        assert!(out.status.success(),"{}",stderr(&out));
+        assert_eq!("chr17",stdout(&out));
+        let out = lookup("TP53",&format!("{}!other.ncd",server.url()),&["--source","zip"]);
+        assert!(stderr(&out).contains("No member other.ncd"),"{}",stderr(&out));
+    }
+}
+
+#[test]
+fn test_remote_lookup() {
+    let (_dir,path) = build_genes();
+    let server = MockServer::start(&path,MockConfig::new());
+    let out = lookup("CFTR",&server.url(),&[]);
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert_eq!("chr7",stdout(&out));
+    let out = lookup("MISSING",&server.url(),&[]);
+    assert_eq!(Some(1),out.status.code());
+    assert!(server.requests() > 0);
+}
+
+#[test]
+fn test_remote_failure() {
+    let (_dir,path) = build_genes();
+    let server = MockServer::start(&path,MockConfig::new().fail_first(usize::MAX));
+    let out = lookup("CFTR",&server.url(),&["--error-format","json"]);
+    assert_eq!(Some(1),out.status.code());
+    assert_eq!("",stdout(&out));
+    assert!(stderr(&out).starts_with("{\"error\":"),"{}",stderr(&out));
+}
+
+#[test]
+fn test_missing_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing.ncd");
+    let out = lookup("TP53",&missing.to_string_lossy(),&["--error-format","json"]);
+    assert_eq!(Some(1),out.status.code());
+    let err = stderr(&out);
+    assert!(err.starts_with("{\"error\":\"No such file: "),"{}",err);
+    assert!(err.contains("\"kind\":\"open\""),"{}",err);
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(dir.path().join("missing.csv")).arg(dir.path().join("out.ncd")).output().unwrap();
+    assert!(!out.status.success());
+    assert!(!stderr(&out).is_empty());
+}
+
+#[test]
+fn test_bad_flags() {
+    let out = Command::cargo_bin("ncd-build").unwrap().args(&["in.csv","out.ncd","--no-such-flag"]).output().unwrap();
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("--no-such-flag"),"{}",stderr(&out));
+    let out = Command::cargo_bin("ncd-lookup").unwrap().args(&["key","x.ncd","--no-such-flag","--error-format","json"]).output().unwrap();
+    assert_eq!(Some(1),out.status.code());
+    assert!(stderr(&out).starts_with("{\"error\":"),"{}",stderr(&out));
+    let out = Command::cargo_bin("ncd-build").unwrap().args(&["in.csv","out.ncd","-t","no-such-format"]).output().unwrap();
+    assert!(!out.status.success());
+}
+
+#[test]
+fn test_empty_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"empty.csv","");
+    let output = dir.path().join("empty.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert!(stdout(&out).contains("Warning: NCD-W001: no records"));
+    assert_eq!(Some(1),lookup("TP53",&output.to_string_lossy(),&[]).status.code());
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).arg("--fail-if-empty").output().unwrap();
+    assert_eq!(Some(1),out.status.code());
+    assert!(stderr(&out).starts_with("NCD-E009: No records to build from"),"{}",stderr(&out));
+    let catalog = write_input(dir.path(),"de.tsv","NCD-E009\tKeine Eintr\u{e4}ge in {}\n");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).arg("--fail-if-empty")
+        .args(&["--error-format","json"]).env("NCD_MESSAGES",&catalog).output().unwrap();
+    assert!(stderr(&out).starts_with("{\"error\":\"Keine Eintr\u{e4}ge in "),"{}",stderr(&out));
+    assert!(stderr(&out).contains("\"id\":\"NCD-E009\""),"{}",stderr(&out));
+}
+
+#[test]
+fn test_strict() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"dups.csv","TP53,chr17\nTP53,chr17\n");
+    let output = dir.path().join("dups.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).arg("--strict").output().unwrap();
+    assert_eq!(Some(1),out.status.code());
+    assert!(!stderr(&out).is_empty());
+    /* a feature the source would skip for want of an ID */
+    let gff = write_input(dir.path(),"genes.gff3","chr13\tens\tgene\t1\t9\t.\t+\t.\tID=gene1\nchr13\tens\tregion\t1\t9\t.\t+\t.\tNote=x\n");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&gff).arg(&output).args(&["-t","gff"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&gff).arg(&output).args(&["-t","gff","--strict"]).output().unwrap();
+    assert_eq!(Some(1),out.status.code());
+    assert!(stderr(&out).contains("strict: line 2: no ID attribute"),"{}",stderr(&out));
+}
+
+#[test]
+fn test_output_is_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.csv",CSV);
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    let output = dir.path().join("sub/../genes.csv");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).output().unwrap();
+    assert_eq!(Some(1),out.status.code());
+    assert!(stderr(&out).contains("same file as input"),"{}",stderr(&out));
+    assert_eq!(CSV,fs::read_to_string(&input).unwrap());
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).arg("--force").output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert_eq!("chr17",stdout(&lookup("TP53",&input.to_string_lossy(),&[])));
+}
+
+#[test]
+fn test_several_inputs() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = write_input(dir.path(),"part1.csv","BRCA2,chr13\nTP53,chr17\n");
+    let second = write_input(dir.path(),"part2.csv","CFTR,chr7\nTP53,chr17p\n");
+    let output = dir.path().join("genes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&first).arg(&output).arg("--input").arg(&second)
+        .args(&["--dup-policy","first"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let path = output.to_string_lossy();
+    for (key,value) in &[("BRCA2","chr13"),("TP53","chr17"),("CFTR","chr7")] {
+        assert_eq!(*value,stdout(&lookup(key,&path,&[])));
+    }
+    let out = Command::cargo_bin("ncd-build").unwrap().arg("--input").arg(&second).arg(&first).arg(&output)
+        .args(&["--dup-policy","last"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert_eq!("chr17p",stdout(&lookup("TP53",&path,&[])));
+}
+
+#[test]
+fn test_quote_aware() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.txt","\"BRCA2 gene\" chr13\nTP53 \"chr17 p13.1\"\n");
+    let output = dir.path().join("genes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).arg("--quote-aware").output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let path = output.to_string_lossy();
+    assert_eq!("chr13",stdout(&lookup("BRCA2 gene",&path,&[])));
+    assert_eq!("chr17 p13.1",stdout(&lookup("TP53",&path,&[])));
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).args(&["--quote-aware","--skip-lines","1"]).output().unwrap();
+    assert!(stderr(&out).contains("NCD-E024"),"{}",stderr(&out));
+}
+
+#[test]
+fn test_options_between_positionals() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.tsv","BRCA2 chr13\nTP53 chr17\n");
+    let output = dir.path().join("genes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).args(&["-t","flat"]).arg(&output).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert_eq!("chr17",stdout(&lookup("TP53",&output.to_string_lossy(),&[])));
+}
+
+#[test]
+fn test_input_prefixes() {
+    let dir = tempfile::tempdir().unwrap();
+    let genes = write_input(dir.path(),"genes.csv","BRCA2,chr13\n");
+    let transcripts = write_input(dir.path(),"transcripts.csv","BRCA2,ENST00000380152\n");
+    let aliases = write_input(dir.path(),"aliases.csv","FANCD1,BRCA2\n");
+    let output = dir.path().join("all.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&aliases)
+        .arg("--input").arg(format!("{}=gene:",genes.display()))
+        .arg("--input").arg(format!("{}=tx:",transcripts.display()))
+        .arg(&output).arg("--strict").output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let path = output.to_string_lossy();
+    assert_eq!("chr13",stdout(&lookup("gene:BRCA2",&path,&[])));
+    assert_eq!("ENST00000380152",stdout(&lookup("tx:BRCA2",&path,&[])));
+    assert_eq!("BRCA2",stdout(&lookup("FANCD1",&path,&[])));
+    assert_eq!(Some(1),lookup("BRCA2",&path,&[]).status.code());
+}
+
+#[test]
+fn test_header_names_field() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"variants.tsv","## exported\n## v2\nchrom\tid\tref\nchr1\trs1\tA\nchr2\trs2\tG\n");
+    let output = dir.path().join("variants.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output)
+        .args(&["-d","\t","--skip-lines","2","--header","-f","id"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let path = output.to_string_lossy();
+    assert!(lookup("rs1",&path,&[]).status.success());
+    assert_eq!(Some(1),lookup("id",&path,&[]).status.code());
+    assert_eq!(Some(1),lookup("chr1",&path,&[]).status.code());
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output)
+        .args(&["-d","\t","-f","id"]).output().unwrap();
+    assert!(stderr(&out).contains("NCD-E016"),"{}",stderr(&out));
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output)
+        .args(&["-d","\t","--header","-f","name"]).output().unwrap();
+    assert!(stderr(&out).contains("NCD-E017"),"{}",stderr(&out));
+    /* picking value fields, the named key is found as the header goes through */
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output)
+        .args(&["-d","\t","--skip-lines","2","--header","-f","id","--value-fields","3"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert_eq!("G",stdout(&lookup("rs2",&path,&[])));
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output)
+        .args(&["-d","\t","--skip-lines","2","--header","-f","name","--value-fields","3"]).output().unwrap();
+    assert!(stderr(&out).contains("NCD-E017"),"{}",stderr(&out));
+}
+
+#[test]
+fn test_null_separated() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"notes.txt","a\tfirst line\nsecond line\0b\tone\0");
+    let output = dir.path().join("notes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output)
+        .args(&["-d","\t","-0"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let path = output.to_string_lossy();
+    assert_eq!("first line\nsecond line",stdout(&lookup("a",&path,&[])));
+    assert_eq!("one",stdout(&lookup("b",&path,&[])));
+    assert_eq!(Some(1),lookup("second line",&path,&[]).status.code());
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output)
+        .args(&["-0","--filter","a"]).output().unwrap();
+    assert!(stderr(&out).contains("NCD-E019"),"{}",stderr(&out));
+    let input = write_input(dir.path(),"commented.txt","a  first  # note\0b one \0");
+    for (extra,a,b) in &[(&["-C","#","-I"][..],"first","one"),(&["-T"][..],"first  # note","one ")] {
+        let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).arg("-0").args(*extra).output().unwrap();
+        assert!(out.status.success(),"{}",stderr(&out));
+        assert_eq!(*a,stdout(&lookup("a",&path,&[])));
+        assert_eq!(*b,stdout(&lookup("b",&path,&[])));
+    }
+}
+
+#[test]
+fn test_version() {
+    let out = Command::cargo_bin("ncd-lookup").unwrap().arg("--version").output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert!(stdout(&out).starts_with(&format!("ncd-lookup {}",env!("CARGO_PKG_VERSION"))));
+    let out = Command::cargo_bin("ncd-build").unwrap().args(&["--version","--json"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let info : serde_json::Value = serde_json::from_str(&stdout(&out)).unwrap();
+    assert_eq!("ncd-build",info["program"]);
+    assert!(info["read"]["inputs"].as_array().unwrap().iter().any(|f| f == "csv"));
+    /* after --, -V is a key like any other */
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"flags.csv","-V,minus vee\n");
+    let output = dir.path().join("flags.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let out = Command::cargo_bin("ncd-lookup").unwrap().arg("--").arg("-V").arg(&output).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert_eq!("minus vee",stdout(&out));
+}
+
+#[test]
+fn test_windows_line_endings() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.txt","\u{FEFF}BRCA2 chr13\r\nTP53\r\n");
+    let output = dir.path().join("genes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let path = output.to_string_lossy();
+    assert_eq!("chr13",stdout(&lookup("BRCA2",&path,&[])));
+    assert!(lookup("TP53",&path,&[]).status.success());
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).args(&["--crlf","keep"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert_eq!(Some(1),lookup("TP53",&path,&[]).status.code());
+}
+
+#[test]
+fn test_input_encoding() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("towns.txt");
+    fs::write(&input,b"Z\xFCrich CH\nK\xF8benhavn DK\n").unwrap();
+    let output = dir.path().join("towns.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).args(&["--input-encoding","latin1"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let path = output.to_string_lossy();
+    assert_eq!("CH",stdout(&lookup("Z\u{FC}rich",&path,&[])));
+    assert_eq!("DK",stdout(&lookup("K\u{F8}benhavn",&path,&[])));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_value_pipe() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.csv",CSV);
+    let output = dir.path().join("genes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output)
+        .args(&["--value-pipe","tr a-z A-Z","--value-pipe","base64"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert_eq!("Q0hSMTM=",stdout(&lookup("BRCA2",&output.to_string_lossy(),&[])));
+}
+
+#[test]
+fn test_set_mode() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"allow.txt","alice\nbob\nalice\n");
+    let output = dir.path().join("allow.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).args(&["--set-mode","--set-marker","1"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let path = output.to_string_lossy();
+    assert_eq!("1",stdout(&lookup("alice",&path,&[])));
+    assert_eq!("1",stdout(&lookup("bob",&path,&[])));
+    assert_eq!(Some(1),lookup("carol",&path,&[]).status.code());
+}
+
+#[test]
+fn test_http_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.txt","BRCA2 chr13\nTP53 chr17\nCFTR chr7\n");
+    for config in vec![MockConfig::new(),MockConfig::new().truncate_first(2),MockConfig::new().ranges(false).truncate_first(1),MockConfig::new().fail_first(1)] {
+        let server = MockServer::start(&input,config);
+        let output = dir.path().join("genes.ncd");
+        let out = Command::cargo_bin("ncd-build").unwrap().arg(server.url()).arg(&output).args(&["-t","flat"]).output().unwrap();
+        assert!(out.status.success(),"{}",stderr(&out));
+        assert_eq!("chr7",stdout(&lookup("CFTR",&output.to_string_lossy(),&[])));
+    }
+    let server = MockServer::start(&input,MockConfig::new().fail_first(5));
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(server.url()).arg(dir.path().join("x.ncd")).args(&["-t","flat","--retries","1"]).output().unwrap();
+    assert!(!out.status.success());
+    assert_eq!(2,server.requests());
+}
+
+#[test]
+fn test_row_filter() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"variants.tsv","rs1\tchr1\tA\nrs2\tchr2\t.\nrs3\tchrUn_alt\tC\n");
+    let output = dir.path().join("variants.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output)
+        .args(&["-d","\t","--where","field3 != \".\"","--filter","chr[0-9]+\t"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let path = output.to_string_lossy();
+    assert_eq!("chr1\tA",stdout(&lookup("rs1",&path,&[])));
+    assert_eq!(Some(1),lookup("rs2",&path,&[]).status.code());
+    assert_eq!(Some(1),lookup("rs3",&path,&[]).status.code());
+}
+
+#[test]
+fn test_kvjson() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.kvjson","ENSG1\t{ \"name\": \"BRCA2\" }\nENSG2\t[1, 2]\n");
+    let output = dir.path().join("genes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let path = output.to_string_lossy();
+    assert_eq!("{\"name\":\"BRCA2\"}",stdout(&lookup("ENSG1",&path,&[])));
+    assert_eq!("[1,2]",stdout(&lookup("ENSG2",&path,&[])));
+    let input = write_input(dir.path(),"bad.txt","ENSG1\t{}\nENSG2\t{\n");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).args(&["-t","kvjson"]).output().unwrap();
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("line 2: value is not JSON"),"{}",stderr(&out));
+}
+
+#[test]
+fn test_progress() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.tsv","a\t1\nb\t2\n");
+    let output = dir.path().join("genes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).args(&["-d","\t","--progress"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    assert!(stdout(&out).contains("Attempting to build"));
+    assert_eq!("2",stdout(&lookup("b",&output.to_string_lossy(),&[])));
+}
+
+#[test]
+fn test_filter_needs_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.json","{\n  \"BRCA2\": \"chr13\",\n  \"TP53\": \"chr17\"\n}\n");
+    let output = dir.path().join("genes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).args(&["-t","json","--filter","BRCA2"]).output().unwrap();
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("NCD-E014"),"{}",stderr(&out));
+}
+
+#[test]
+#[cfg(not(feature="parquet"))]
+fn test_format_needs_feature() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.parquet","PAR1");
+    let output = dir.path().join("genes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).output().unwrap();
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("NCD-E022"),"{}",stderr(&out));
+    assert!(!output.exists());
+}
+
+#[test]
+fn test_accounting_multi_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(),"genes.json","{\n  \"BRCA2\": \"chr13\",\n  \"TP53\": \"chr17\"\n}\n");
+    let output = dir.path().join("genes.ncd");
+    let accounting = dir.path().join("accounting.tsv");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).arg("--accounting").arg(&accounting).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    let report = fs::read_to_string(&accounting).unwrap();
+    assert_eq!(format!("{}\t2\t0\t2\t0\t0\t2",input.display()),report.lines().nth(1).unwrap());
+}
+
+#[test]
+fn test_max_output_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let data : String = (0..500).map(|i| format!("GENE{},chr{}\n",i,i%23)).collect();
+    let input = write_input(dir.path(),"genes.csv",&data);
+    let output = dir.path().join("genes.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).args(&["--max-output-size","4000"]).output().unwrap();
+    assert!(out.status.success(),"{}",stderr(&out));
+    /* sharded straight from the estimate: no unsharded file is built on the way */
+    assert!(stdout(&out).contains("shards"),"{}",stdout(&out));
+    assert!(!output.exists());
+    let manifest = dir.path().join("genes.ncd.manifest.json");
+    assert!(dir.path().join("genes.ncd.1").exists());
+    for key in &["GENE0","GENE250","GENE499"] {
+        let out = lookup(key,&manifest.to_string_lossy(),&[]);
+        assert!(out.status.success(),"{}",stderr(&out));
+    }
+    assert_eq!("chr20",stdout(&lookup("GENE250",&manifest.to_string_lossy(),&[])));
+    let out = Command::cargo_bin("ncd-verify").unwrap().arg("--manifest").arg(&manifest).output().unwrap();
+    assert!(out.status.success(),"{}{}",stdout(&out),stderr(&out));
+    /* one record too big for any shard stops the build rather than splitting forever */
+    let data = format!("SMALL,chr1\nHUGE,{}\n","x".repeat(10000));
+    let input = write_input(dir.path(),"huge.csv",&data);
+    let output = dir.path().join("huge.ncd");
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).args(&["--max-output-size","4000"]).output().unwrap();
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("NCD-E023") && stderr(&out).contains("HUGE"),"{}",stderr(&out));
+    assert!(!dir.path().join("huge.ncd.0").exists());
+    let out = Command::cargo_bin("ncd-build").unwrap().arg(&input).arg(&output).args(&["--max-output-size","0"]).output().unwrap();
+    assert!(!out.status.success());
+}
@@ -0,0 +1,133 @@
+#![allow(dead_code)]
+
+use std::{fs::{self, File}, io::{BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, path::{Path, PathBuf}, sync::{Arc, Mutex}, thread, time::Duration};
+
+use ncd::{NCDBuild, NCDBuildConfig, NCDFlatConfig, NCDFlatSource};
+
+/// Behaviour of a `MockServer`. Everything is well-behaved by default.
+#[derive(Clone)]
+pub struct MockConfig {
+    pub latency: Duration,
+    pub ranges: bool,
+    pub fail_first: usize,
+    pub truncate_first: usize,
+    pub corrupt: bool
+}
+
+impl MockConfig {
+    pub fn new() -> MockConfig {
+        MockConfig {
+            latency: Duration::from_millis(0),
+            ranges: true,
+            fail_first: 0,
+            truncate_first: 0,
+            corrupt: false
+        }
+    }
+
+    pub fn latency(mut self, latency: Duration) -> MockConfig { self.latency = latency; self }
+    pub fn ranges(mut self, ranges: bool) -> MockConfig { self.ranges = ranges; self }
+    pub fn fail_first(mut self, count: usize) -> MockConfig { self.fail_first = count; self }
+    pub fn truncate_first(mut self, count: usize) -> MockConfig { self.truncate_first = count; self }
+    pub fn corrupt(mut self, corrupt: bool) -> MockConfig { self.corrupt = corrupt; self }
+}
+
+/// A local HTTP server serving a single file, answering each request on its own connection.
+pub struct MockServer {
+    port: u16,
+    requests: Arc<Mutex<usize>>,
+    /* ranged requests answered with the whole file */
+    whole_bodies: Arc<Mutex<usize>>
+}
+
+/* an open-ended range runs to the end */
+fn parse_range(header: &str) -> Option<(usize,usize)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    let (start,end) = spec.split_once('-')?;
+    let end = if end.trim().is_empty() { usize::MAX-1 } else { end.trim().parse().ok()? };
+    Some((start.trim().parse().ok()?,end))
+}
+
+fn serve(mut stream: TcpStream, data: &[u8], config: &MockConfig, attempt: usize, whole_bodies: &Mutex<usize>) -> std::io::Result<()> {
+    let mut range = None;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" { break; }
+        if let Some((name,value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("range") { range = parse_range(value); }
+        }
+    }
+    thread::sleep(config.latency);
+    if attempt < config.fail_first {
+        return stream.write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    }
+    let mut body = match (range,config.ranges) {
+        (Some((start,end)),true) => data[start.min(data.len())..(end+1).min(data.len())].to_vec(),
+        _ => data.to_vec()
+    };
+    if config.corrupt {
+        for b in body.iter_mut() { *b ^= 0xFF; }
+    }
+    let head = match (range,config.ranges) {
+        (Some((start,_)),true) => format!("HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",start,start+body.len().max(1)-1,data.len()),
+        _ => {
+            if range.is_some() { *whole_bodies.lock().unwrap() += 1; }
+            "HTTP/1.1 200 OK\r\n".to_string()
+        }
+    };
+    stream.write_all(format!("{}Content-Length: {}\r\nConnection: close\r\n\r\n",head,body.len()).as_bytes())?;
+    /* the full length is promised, but the connection drops halfway */
+    if attempt < config.fail_first + config.truncate_first {
+        return stream.write_all(&body[..body.len()/2]);
+    }
+    stream.write_all(&body)
+}
+
+impl MockServer {
+    pub fn start(path: &Path, config: MockConfig) -> MockServer {
+        let data = Arc::new(fs::read(path).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(0));
+        let requests2 = requests.clone();
+        let whole_bodies = Arc::new(Mutex::new(0));
+        let whole_bodies2 = whole_bodies.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream { Ok(s) => s, Err(_) => break };
+                let attempt = {
+                    let mut requests = requests2.lock().unwrap();
+                    *requests += 1;
+                    *requests - 1
+                };
+                let data = data.clone();
+                let config = config.clone();
+                let whole_bodies = whole_bodies2.clone();
+                thread::spawn(move || { serve(stream,&data,&config,attempt,&whole_bodies).ok(); });
+            }
+        });
+        MockServer { port, requests, whole_bodies }
+    }
+
+    pub fn url(&self) -> String { format!("http://127.0.0.1:{}/test.ncd",self.port) }
+
+    pub fn requests(&self) -> usize { *self.requests.lock().unwrap() }
+
+    pub fn whole_bodies(&self) -> usize { *self.whole_bodies.lock().unwrap() }
+}
+
+/// Builds an ncd file from whitespace-separated `key value` lines in a fresh temporary directory.
+pub fn build_fixture(name: &str, lines: &[&str]) -> (tempfile::TempDir,PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join(format!("{}.txt",name));
+    let output = dir.path().join(format!("{}.ncd",name));
+    let mut file = File::create(&input).unwrap();
+    for line in lines {
+        writeln!(file,"{}",line).unwrap();
+    }
+    let source = NCDFlatSource::new(&input,&NCDFlatConfig::new()).unwrap();
+    let mut builder = NCDBuild::new(&NCDBuildConfig::new(),&source,&output).unwrap();
+    while !builder.attempt(|_,_| {}).unwrap() {}
+    (dir,output)
+}
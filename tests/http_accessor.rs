@@ -0,0 +1,109 @@
+mod common;
+
+use std::{fs::{self, File}, io::{Read, Seek, SeekFrom}, path::Path, time::{Duration, Instant}};
+
+use common::{build_fixture, MockConfig, MockServer};
+use ncd::{CurlConfig, CurlNCDReadAccessor, NCDReader, StdNCDReadAccessor};
+use ncd_tools::archive::{locate_tar_member, HttpFile, Window};
+
+const LINES : &[&str] = &[
+    "BRCA2 chr13",
+    "TP53 chr17",
+    "CFTR chr7"
+];
+
+fn local_get(path: &Path, key: &[u8]) -> Option<Vec<u8>> {
+    let accessor = StdNCDReadAccessor::new(File::open(path).unwrap()).unwrap();
+    NCDReader::new_box(Box::new(accessor)).unwrap().get(key).unwrap()
+}
+
+fn remote_get(url: &str, config: &CurlConfig, key: &[u8]) -> Result<Option<Vec<u8>>,String> {
+    let accessor = CurlNCDReadAccessor::new(config,url).map_err(|e| e.to_string())?;
+    let mut reader = NCDReader::new_box(Box::new(accessor)).map_err(|e| e.to_string())?;
+    reader.get(key).map_err(|e| e.to_string())
+}
+
+#[test]
+fn test_remote_matches_local() {
+    let (_dir,path) = build_fixture("remote",LINES);
+    let server = MockServer::start(&path,MockConfig::new());
+    for key in &["BRCA2","TP53","CFTR","MISSING"] {
+        let expected = local_get(&path,key.as_bytes());
+        assert_eq!(Ok(expected),remote_get(&server.url(),&CurlConfig::new(),key.as_bytes()));
+    }
+    assert!(server.requests() > 0);
+}
+
+#[test]
+fn test_latency() {
+    let (_dir,path) = build_fixture("latency",LINES);
+    let server = MockServer::start(&path,MockConfig::new().latency(Duration::from_millis(200)));
+    let start = Instant::now();
+    let expected = local_get(&path,b"TP53");
+    assert_eq!(Ok(expected),remote_get(&server.url(),&CurlConfig::new(),b"TP53"));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn test_server_error() {
+    let (_dir,path) = build_fixture("error",LINES);
+    let server = MockServer::start(&path,MockConfig::new().fail_first(usize::MAX));
+    assert!(remote_get(&server.url(),&CurlConfig::new(),b"TP53").is_err());
+}
+
+#[test]
+fn test_corruption() {
+    let (_dir,path) = build_fixture("corrupt",LINES);
+    let server = MockServer::start(&path,MockConfig::new().corrupt(true));
+    assert!(remote_get(&server.url(),&CurlConfig::new(),b"TP53").is_err());
+}
+
+/* a server which ignores Range sends the whole file, which must be cut down, not misread */
+#[test]
+fn test_no_ranges_falls_back_to_whole_body() {
+    let (_dir,path) = build_fixture("noranges",LINES);
+    let server = MockServer::start(&path,MockConfig::new().ranges(false));
+    for key in &["BRCA2","CFTR","MISSING"] {
+        let expected = local_get(&path,key.as_bytes());
+        assert_eq!(Ok(expected),remote_get(&server.url(),&CurlConfig::new(),key.as_bytes()));
+    }
+    assert_eq!(Some(b"chr7".to_vec()),local_get(&path,b"CFTR"));
+    assert!(server.whole_bodies() > 0);
+}
+
+#[test]
+fn test_http_file() {
+    let (_dir,path) = build_fixture("httpfile",LINES);
+    let data = fs::read(&path).unwrap();
+    for config in vec![MockConfig::new(),MockConfig::new().ranges(false)] {
+        let server = MockServer::start(&path,config);
+        let mut file = HttpFile::open(&server.url(),None).unwrap();
+        assert_eq!(data.len() as u64,file.seek(SeekFrom::End(0)).unwrap());
+        file.seek(SeekFrom::Start(3)).unwrap();
+        let mut out = vec![];
+        file.read_to_end(&mut out).unwrap();
+        assert_eq!(&data[3..],&out[..]);
+    }
+    let server = MockServer::start(&path,MockConfig::new().fail_first(usize::MAX));
+    assert!(HttpFile::open(&server.url(),None).is_err());
+}
+
+/* a member of a tar on the server is found and read without fetching the whole archive */
+#[test]
+fn test_remote_tar_member() {
+    let (dir,path) = build_fixture("tarred",LINES);
+    let tar_path = dir.path().join("tarred.tar");
+    let mut tar = tar::Builder::new(File::create(&tar_path).unwrap());
+    tar.append_path_with_name(&path,"tarred.ncd").unwrap();
+    tar.finish().unwrap();
+    drop(tar);
+    let server = MockServer::start(&tar_path,MockConfig::new());
+    let mut input = HttpFile::open(&server.url(),None).unwrap();
+    let (start,len) = locate_tar_member(&mut input,"tarred.tar","tarred.ncd").unwrap();
+    let accessor = StdNCDReadAccessor::new(Window::new(input,start,len).unwrap()).unwrap();
+    let mut reader = NCDReader::new_box(Box::new(accessor)).unwrap();
+    for key in &["BRCA2","TP53","CFTR","MISSING"] {
+        assert_eq!(local_get(&path,key.as_bytes()),reader.get(key.as_bytes()).unwrap());
+    }
+    assert_eq!(0,server.whole_bodies());
+}